@@ -282,7 +282,7 @@ impl AddressSpaceMgr {
             if start_addr > dbs_boot::layout::MMIO_LOW_END
                 || start_addr + size <= dbs_boot::layout::MMIO_LOW_START
             {
-                let region = self.create_region(start_addr, size, info, &mut param)?;
+                let region = self.create_region(start_addr, size, info, &mut param, false)?;
                 regions.push(region);
                 start_addr = start_addr
                     .checked_add(size)
@@ -296,7 +296,8 @@ impl AddressSpaceMgr {
                 if below_size < (MINIMAL_SPLIT_SPACE) {
                     below_size = 0;
                 } else {
-                    let region = self.create_region(start_addr, below_size, info, &mut param)?;
+                    let region =
+                        self.create_region(start_addr, below_size, info, &mut param, false)?;
                     regions.push(region);
                 }
 
@@ -305,7 +306,8 @@ impl AddressSpaceMgr {
                 let above_size = size
                     .checked_sub(below_size)
                     .ok_or(AddressManagerError::InvalidOperation)?;
-                let region = self.create_region(above_start, above_size, info, &mut param)?;
+                let region =
+                    self.create_region(above_start, above_size, info, &mut param, false)?;
                 regions.push(region);
                 start_addr = above_start
                     .checked_add(above_size)
@@ -351,6 +353,81 @@ impl AddressSpaceMgr {
         Ok(())
     }
 
+    /// Hot-add a guest memory region of `size` bytes above the existing guest memory, and
+    /// return it.
+    ///
+    /// The region starts at a multiple of `align` bytes and is mapped into KVM and the guest
+    /// memory object right away, devices see it on their next access to the guest memory.
+    #[cfg(feature = "atomic-guest-memory")]
+    pub fn insert_memory_region(
+        &mut self,
+        res_mgr: &ResourceManager,
+        size: u64,
+        align: u64,
+        info: &NumaRegionInfo,
+        mut param: AddressSpaceMgrBuilder,
+    ) -> Result<Arc<AddressSpaceRegion>> {
+        let min_addr = self
+            .address_space
+            .as_ref()
+            .ok_or(AddressManagerError::GuestMemoryNotInitialized)?
+            .last_addr()
+            .raw_value()
+            .checked_add(1)
+            .ok_or(AddressManagerError::InvalidOperation)?;
+        let constraint = Constraint::new(size)
+            .min(min_addr)
+            .max(*dbs_boot::layout::GUEST_MEM_END)
+            .align(align);
+        let start_addr = res_mgr
+            .allocate_mem_address(&constraint)
+            .ok_or(AddressManagerError::NoAvailableMemAddress)?;
+
+        let result = self.map_memory_region(res_mgr, start_addr, size, info, &mut param);
+        if result.is_err() {
+            if let Err(e) = res_mgr.free_mem_address(start_addr, size) {
+                warn!(
+                    "failed to free guest memory address 0x{:x}: {:?}",
+                    start_addr, e
+                );
+            }
+        }
+        result
+    }
+
+    #[cfg(feature = "atomic-guest-memory")]
+    fn map_memory_region(
+        &mut self,
+        res_mgr: &ResourceManager,
+        start_addr: u64,
+        size: u64,
+        info: &NumaRegionInfo,
+        param: &mut AddressSpaceMgrBuilder,
+    ) -> Result<Arc<AddressSpaceRegion>> {
+        let region = self.create_region(start_addr, size, info, param, true)?;
+        let mmap_reg = self.create_mmap_region(region.clone())?;
+
+        let vm_as = self
+            .vm_as
+            .clone()
+            .ok_or(AddressManagerError::GuestMemoryNotInitialized)?;
+        let guard = vm_as.lock().unwrap();
+        let vm_memory = vm_as
+            .memory()
+            .insert_region(mmap_reg.clone())
+            .map_err(AddressManagerError::CreateGuestMemory)?;
+        self.map_to_kvm(res_mgr, param, &region, mmap_reg)?;
+        guard.replace(vm_memory);
+
+        self.address_space
+            .as_mut()
+            .ok_or(AddressManagerError::GuestMemoryNotInitialized)?
+            .insert_region(region.clone())
+            .map_err(AddressManagerError::CreateAddressSpaceRegion)?;
+
+        Ok(region)
+    }
+
     // size unit: Byte
     fn create_region(
         &mut self,
@@ -358,6 +435,7 @@ impl AddressSpaceMgr {
         size_bytes: u64,
         info: &NumaRegionInfo,
         param: &mut AddressSpaceMgrBuilder,
+        is_hotplug: bool,
     ) -> Result<Arc<AddressSpaceRegion>> {
         let mem_file_path = param.get_next_mem_file();
         let region = AddressSpaceRegion::create_default_memory_region(
@@ -367,7 +445,7 @@ impl AddressSpaceMgr {
             param.mem_type,
             &mem_file_path,
             param.mem_prealloc,
-            false,
+            is_hotplug,
         )
         .map_err(AddressManagerError::CreateAddressSpaceRegion)?;
         let region = Arc::new(region);
@@ -790,6 +868,51 @@ mod tests {
         assert!(fd < 1000);
     }
 
+    #[cfg(feature = "atomic-guest-memory")]
+    #[test]
+    fn test_insert_memory_region() {
+        let res_mgr = ResourceManager::new(None);
+        let numa_region_info = NumaRegionInfo {
+            size: 100,
+            host_numa_node_id: None,
+            guest_numa_node_id: Some(0),
+            vcpu_ids: vec![1, 2],
+        };
+        let builder = AddressSpaceMgrBuilder::new("shmem", "").unwrap();
+        let mut as_mgr = builder
+            .build(&res_mgr, &[numa_region_info.clone()])
+            .unwrap();
+
+        let size = 128 << 20;
+        let builder = AddressSpaceMgrBuilder::new("shmem", "").unwrap();
+        let region = as_mgr
+            .insert_memory_region(&res_mgr, size, size, &numa_region_info, builder)
+            .unwrap();
+        let start = GUEST_MEM_START + size;
+        assert_eq!(region.start_addr(), GuestAddress(start));
+        assert_eq!(region.len(), size);
+        assert_eq!(
+            as_mgr.get_address_space().unwrap().last_addr(),
+            GuestAddress(start + size - 1)
+        );
+
+        let gmem = as_mgr.vm_memory().unwrap();
+        assert_eq!(gmem.num_regions(), 2);
+        gmem.write_obj(0xa5u8, GuestAddress(start + size - 1))
+            .unwrap();
+        assert_eq!(
+            gmem.read_obj::<u8>(GuestAddress(start + size - 1)).unwrap(),
+            0xa5
+        );
+        assert_eq!(as_mgr.get_base_to_slot_map().lock().unwrap().len(), 2);
+
+        // Memory is hot-added after the last region, below the end of the guest memory.
+        let builder = AddressSpaceMgrBuilder::new("shmem", "").unwrap();
+        as_mgr
+            .insert_memory_region(&res_mgr, u64::MAX >> 1, size, &numa_region_info, builder)
+            .unwrap_err();
+    }
+
     #[test]
     fn test_address_space_mgr_get_boundary() {
         let layout = AddressSpaceLayout::new(
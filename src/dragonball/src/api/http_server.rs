@@ -0,0 +1,738 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal REST API server, in the spirit of the Firecracker one, to drive a standalone
+//! Dragonball instance over a unix domain socket.
+//!
+//! Each HTTP request is translated into a `VmmAction`, which is sent to the `VmmService` through
+//! the same channels runtime-rs uses when it embeds Dragonball. Requests are served one at a time,
+//! as the VMM handles actions sequentially anyway.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crossbeam_channel::{Receiver, Sender};
+use log::{debug, error, info, warn};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use vmm_sys_util::eventfd::EventFd;
+
+#[cfg(feature = "virtio-fs")]
+use crate::api::v1::FsDeviceConfigInfo;
+#[cfg(feature = "hotplug")]
+use crate::api::v1::VcpuResizeInfo;
+#[cfg(feature = "virtio-vsock")]
+use crate::api::v1::VsockDeviceConfigInfo;
+#[cfg(feature = "virtio-balloon")]
+use crate::api::v1::{BalloonDeviceConfigInfo, BalloonDeviceConfigUpdateInfo};
+#[cfg(feature = "virtio-blk")]
+use crate::api::v1::{BlockDeviceConfigInfo, BlockDeviceConfigUpdateInfo};
+use crate::api::v1::{
+    BootSourceConfig, InstanceInfo, VmmAction, VmmData, VmmRequest, VmmRequestResult, VmmResponse,
+    HOTUNPLUG_TIMEOUT,
+};
+#[cfg(feature = "virtio-net")]
+use crate::api::v1::{VirtioNetDeviceConfigInfo, VirtioNetDeviceConfigUpdateInfo};
+use crate::vm::VmConfigInfo;
+
+/// Maximum size of the headers of a request.
+const MAX_HEADERS_SIZE: usize = 8192;
+/// Maximum size of the body of a request.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Errors associated with the HTTP API server.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiServerError {
+    /// Failed to bind the API socket.
+    #[error("failed to bind API socket {0:?}: {1}")]
+    BindSocket(PathBuf, #[source] io::Error),
+
+    /// Failed to read a request or write a response.
+    #[error("API connection error: {0}")]
+    Connection(#[source] io::Error),
+
+    /// The VMM service went away.
+    #[error("the VMM service is not available")]
+    VmmServiceUnavailable,
+}
+
+/// Errors generated while parsing a request, reported to the client with a 4xx status.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum RequestError {
+    /// The request line or a header is malformed.
+    #[error("malformed request: {0}")]
+    Malformed(String),
+
+    /// The request body is too large.
+    #[error("request body too large ({0} bytes)")]
+    BodyTooLarge(usize),
+
+    /// The request body could not be deserialized.
+    #[error("invalid request body: {0}")]
+    InvalidBody(String),
+
+    /// The id given in the path doesn't match the one of the body.
+    #[error("the id from the path ({0}) doesn't match the one of the body ({1})")]
+    IdMismatch(String, String),
+
+    /// No such resource.
+    #[error("invalid path: {0}")]
+    InvalidPath(String),
+
+    /// The method isn't allowed for the resource.
+    #[error("method {0} not allowed on {1}")]
+    MethodNotAllowed(String, String),
+}
+
+/// HTTP request received on the API socket.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Request {
+    /// Request method, e.g. `PUT`.
+    pub method: String,
+    /// Request path, e.g. `/drives/rootfs`.
+    pub path: String,
+    /// Request body, if any.
+    pub body: Vec<u8>,
+}
+
+/// HTTP response sent back on the API socket.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    /// HTTP status code.
+    pub status: u16,
+    /// JSON body, if any.
+    pub body: Option<String>,
+}
+
+impl Response {
+    fn no_content() -> Self {
+        Response {
+            status: 204,
+            body: None,
+        }
+    }
+
+    fn ok<T: serde::Serialize>(data: &T) -> Self {
+        match serde_json::to_string(data) {
+            Ok(body) => Response {
+                status: 200,
+                body: Some(body),
+            },
+            Err(e) => Response::fault(500, &e.to_string()),
+        }
+    }
+
+    fn fault(status: u16, message: &str) -> Self {
+        let fault = Fault {
+            fault_message: message.to_string(),
+        };
+        Response {
+            status,
+            body: serde_json::to_string(&fault).ok(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "HTTP/1.1 {} {}\r\n", self.status, self.reason())?;
+        match &self.body {
+            Some(body) => write!(
+                out,
+                "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )?,
+            None => write!(out, "\r\n")?,
+        }
+        out.flush()
+    }
+}
+
+impl From<RequestError> for Response {
+    fn from(e: RequestError) -> Self {
+        let status = match e {
+            RequestError::BodyTooLarge(_) => 413,
+            RequestError::InvalidPath(_) => 404,
+            RequestError::MethodNotAllowed(_, _) => 405,
+            _ => 400,
+        };
+        Response::fault(status, &e.to_string())
+    }
+}
+
+/// Error message sent to the client, like the Firecracker API does.
+#[derive(Debug, Serialize)]
+struct Fault {
+    fault_message: String,
+}
+
+/// Actions triggered through `PUT /actions`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+enum ActionType {
+    InstanceStart,
+    InstanceStop,
+    Pause,
+    Resume,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActionBody {
+    action_type: ActionType,
+}
+
+/// What a request translates to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParsedRequest {
+    /// Read the shared instance information, without going through the VMM.
+    GetInstanceInfo,
+    /// Send an action to the VMM.
+    Action(VmmAction),
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> std::result::Result<T, RequestError> {
+    serde_json::from_slice(body).map_err(|e| RequestError::InvalidBody(e.to_string()))
+}
+
+fn check_id(path_id: &str, body_id: &str) -> std::result::Result<(), RequestError> {
+    if path_id != body_id {
+        return Err(RequestError::IdMismatch(
+            path_id.to_string(),
+            body_id.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl ParsedRequest {
+    /// Translate a HTTP request into a `ParsedRequest`.
+    pub fn try_from_request(req: &Request) -> std::result::Result<Self, RequestError> {
+        let segments: Vec<&str> = req
+            .path
+            .trim_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+        let method = req.method.as_str();
+        let body = req.body.as_slice();
+        let not_allowed = || RequestError::MethodNotAllowed(req.method.clone(), req.path.clone());
+
+        let action = match (segments.as_slice(), method) {
+            ([], "GET") => return Ok(ParsedRequest::GetInstanceInfo),
+            ([], _) => return Err(not_allowed()),
+
+            (["actions"], "PUT") => match parse_body::<ActionBody>(body)?.action_type {
+                ActionType::InstanceStart => VmmAction::StartMicroVm,
+                ActionType::InstanceStop => VmmAction::ShutdownMicroVm,
+                ActionType::Pause => VmmAction::PauseVm,
+                ActionType::Resume => VmmAction::ResumeVm,
+            },
+
+            (["boot-source"], "PUT") => {
+                VmmAction::ConfigureBootSource(parse_body::<BootSourceConfig>(body)?)
+            }
+
+            (["machine-config"], "GET") => VmmAction::GetVmConfiguration,
+            (["machine-config"], "PUT") => {
+                VmmAction::SetVmConfiguration(parse_body::<VmConfigInfo>(body)?)
+            }
+
+            #[cfg(feature = "virtio-blk")]
+            (["drives", id], "PUT") => {
+                let cfg = parse_body::<BlockDeviceConfigInfo>(body)?;
+                check_id(id, &cfg.drive_id)?;
+                VmmAction::InsertBlockDevice(cfg)
+            }
+            #[cfg(feature = "virtio-blk")]
+            (["drives", id], "PATCH") => {
+                let cfg = parse_body::<BlockDeviceConfigUpdateInfo>(body)?;
+                check_id(id, &cfg.drive_id)?;
+                VmmAction::UpdateBlockDevice(cfg)
+            }
+            #[cfg(feature = "virtio-blk")]
+            (["drives", id], "DELETE") => VmmAction::RemoveBlockDevice(id.to_string()),
+
+            #[cfg(feature = "virtio-net")]
+            (["network-interfaces", id], "PUT") => {
+                let cfg = parse_body::<VirtioNetDeviceConfigInfo>(body)?;
+                check_id(id, &cfg.iface_id)?;
+                VmmAction::InsertNetworkDevice(cfg)
+            }
+            #[cfg(feature = "virtio-net")]
+            (["network-interfaces", id], "PATCH") => {
+                let cfg = parse_body::<VirtioNetDeviceConfigUpdateInfo>(body)?;
+                check_id(id, &cfg.iface_id)?;
+                VmmAction::UpdateNetworkInterface(cfg)
+            }
+            #[cfg(feature = "virtio-net")]
+            (["network-interfaces", id], "DELETE") => {
+                VmmAction::RemoveNetworkDevice(id.to_string())
+            }
+
+            #[cfg(feature = "virtio-fs")]
+            (["fs", tag], "PUT") => {
+                let cfg = parse_body::<FsDeviceConfigInfo>(body)?;
+                check_id(tag, &cfg.tag)?;
+                VmmAction::InsertFsDevice(cfg)
+            }
+            #[cfg(feature = "virtio-fs")]
+            (["fs", tag], "DELETE") => VmmAction::RemoveFsDevice(tag.to_string()),
+
+            #[cfg(feature = "virtio-vsock")]
+            (["vsock"], "PUT") => {
+                VmmAction::InsertVsockDevice(parse_body::<VsockDeviceConfigInfo>(body)?)
+            }
+            #[cfg(feature = "virtio-vsock")]
+            (["vsock", id], "DELETE") => VmmAction::RemoveVsockDevice(id.to_string()),
+
+            #[cfg(feature = "virtio-balloon")]
+            (["balloon"], "PUT") => {
+                VmmAction::InsertBalloonDevice(parse_body::<BalloonDeviceConfigInfo>(body)?)
+            }
+            #[cfg(feature = "virtio-balloon")]
+            (["balloon"], "PATCH") => {
+                VmmAction::UpdateBalloon(parse_body::<BalloonDeviceConfigUpdateInfo>(body)?)
+            }
+            #[cfg(feature = "virtio-balloon")]
+            (["balloon", id, "statistics"], "GET") => VmmAction::GetBalloonStats(id.to_string()),
+
+            #[cfg(feature = "hotplug")]
+            (["vcpus"], "PUT") => VmmAction::ResizeVcpu(parse_body::<VcpuResizeInfo>(body)?),
+
+            (["actions"], _)
+            | (["boot-source"], _)
+            | (["machine-config"], _)
+            | (["drives", _], _)
+            | (["network-interfaces", _], _)
+            | (["fs", _], _)
+            | (["vsock"], _)
+            | (["vsock", _], _)
+            | (["balloon"], _)
+            | (["balloon", _, "statistics"], _)
+            | (["vcpus"], _) => return Err(not_allowed()),
+
+            _ => return Err(RequestError::InvalidPath(req.path.clone())),
+        };
+
+        Ok(ParsedRequest::Action(action))
+    }
+}
+
+/// Convert the outcome of a VMM action into a HTTP response.
+fn vmm_result_to_response(result: VmmRequestResult) -> Response {
+    match result {
+        Ok(VmmData::Empty) => Response::no_content(),
+        Ok(VmmData::MachineConfiguration(cfg)) => Response::ok(&cfg),
+        #[cfg(feature = "hotplug")]
+        Ok(VmmData::MemoryRegion(info)) => Response::ok(&info),
+        #[cfg(feature = "virtio-balloon")]
+        Ok(VmmData::BalloonStats(stats)) => Response::ok(&stats),
+        // The API thread waits for the guest, the VMM thread has to keep serving the upcall.
+        Ok(data @ VmmData::Hotunplug(_)) => match data.wait_hotunplug(HOTUNPLUG_TIMEOUT) {
+            Ok(()) => Response::no_content(),
+            Err(e) => Response::fault(500, &e.to_string()),
+        },
+        Err(e) => Response::fault(400, &e.to_string()),
+    }
+}
+
+/// Read a HTTP request from the stream, `Ok(None)` is returned when the peer closed the
+/// connection.
+fn read_request<R: BufRead>(
+    reader: &mut R,
+) -> io::Result<Option<std::result::Result<Request, RequestError>>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => {
+            return Ok(Some(Err(RequestError::Malformed(
+                line.trim_end().to_string(),
+            ))))
+        }
+    };
+
+    let mut content_length = 0;
+    let mut headers_size = line.len();
+    loop {
+        line.clear();
+        let count = reader.read_line(&mut line)?;
+        if count == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        headers_size += count;
+        if headers_size > MAX_HEADERS_SIZE {
+            return Ok(Some(Err(RequestError::Malformed(
+                "headers too large".to_string(),
+            ))));
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = match value.trim().parse::<usize>() {
+                    Ok(len) => len,
+                    Err(_) => {
+                        return Ok(Some(Err(RequestError::Malformed(header.to_string()))));
+                    }
+                };
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        return Ok(Some(Err(RequestError::BodyTooLarge(content_length))));
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(Ok(Request { method, path, body })))
+}
+
+/// HTTP API server forwarding requests to a `VmmService`.
+pub struct ApiServer {
+    vmm_shared_info: Arc<RwLock<InstanceInfo>>,
+    to_vmm: Sender<VmmRequest>,
+    from_vmm: Receiver<VmmResponse>,
+    to_vmm_fd: EventFd,
+}
+
+impl ApiServer {
+    /// Create a new API server, talking to the `VmmService` whose channels are given.
+    ///
+    /// `to_vmm_fd` must be the event fd the `Vmm` was created with, it notifies the VMM event loop
+    /// of pending actions.
+    pub fn new(
+        vmm_shared_info: Arc<RwLock<InstanceInfo>>,
+        to_vmm: Sender<VmmRequest>,
+        from_vmm: Receiver<VmmResponse>,
+        to_vmm_fd: EventFd,
+    ) -> Self {
+        ApiServer {
+            vmm_shared_info,
+            to_vmm,
+            from_vmm,
+            to_vmm_fd,
+        }
+    }
+
+    /// Bind the unix socket at `path` and serve requests, until the VMM service goes away.
+    pub fn run<P: AsRef<Path>>(&mut self, path: P) -> std::result::Result<(), ApiServerError> {
+        let path = path.as_ref();
+        // Remove the socket of a previous instance, if any.
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)
+            .map_err(|e| ApiServerError::BindSocket(path.to_path_buf(), e))?;
+        info!("API server listening on {:?}", path);
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => match self.handle_connection(stream) {
+                    Err(ApiServerError::VmmServiceUnavailable) => {
+                        return Err(ApiServerError::VmmServiceUnavailable)
+                    }
+                    Err(e) => warn!("{}", e),
+                    Ok(()) => {}
+                },
+                Err(e) => error!("failed to accept API connection: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_connection(&mut self, stream: UnixStream) -> std::result::Result<(), ApiServerError> {
+        let mut writer = stream.try_clone().map_err(ApiServerError::Connection)?;
+        let mut reader = BufReader::new(stream);
+
+        loop {
+            let response = match read_request(&mut reader).map_err(ApiServerError::Connection)? {
+                None => return Ok(()),
+                Some(Ok(req)) => {
+                    debug!("API request: {} {}", req.method, req.path);
+                    self.handle_request(&req)?
+                }
+                Some(Err(e)) => {
+                    // The stream can't be trusted anymore, reply and close the connection.
+                    Response::from(e)
+                        .write_to(&mut writer)
+                        .map_err(ApiServerError::Connection)?;
+                    return Ok(());
+                }
+            };
+            response
+                .write_to(&mut writer)
+                .map_err(ApiServerError::Connection)?;
+        }
+    }
+
+    fn handle_request(&mut self, req: &Request) -> std::result::Result<Response, ApiServerError> {
+        match ParsedRequest::try_from_request(req) {
+            Ok(ParsedRequest::GetInstanceInfo) => {
+                let info = self
+                    .vmm_shared_info
+                    .read()
+                    .expect("Failed to read shared info due to poisoned lock");
+                Ok(Response::ok(&*info))
+            }
+            Ok(ParsedRequest::Action(action)) => {
+                let result = self.send_action(action)?;
+                Ok(vmm_result_to_response(result))
+            }
+            Err(e) => Ok(Response::from(e)),
+        }
+    }
+
+    fn send_action(
+        &mut self,
+        action: VmmAction,
+    ) -> std::result::Result<VmmRequestResult, ApiServerError> {
+        self.to_vmm
+            .send(Box::new(action))
+            .map_err(|_| ApiServerError::VmmServiceUnavailable)?;
+        self.to_vmm_fd
+            .write(1)
+            .map_err(|_| ApiServerError::VmmServiceUnavailable)?;
+        let response = self
+            .from_vmm
+            .recv()
+            .map_err(|_| ApiServerError::VmmServiceUnavailable)?;
+        Ok(*response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_read_request() {
+        let raw = "PUT /boot-source HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n{}";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let req = read_request(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(req, request("PUT", "/boot-source", "{}"));
+        assert!(read_request(&mut reader).unwrap().is_none());
+
+        let raw = "GET / HTTP/1.1\r\n\r\nGET /machine-config HTTP/1.1\r\n\r\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let req = read_request(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(req, request("GET", "/", ""));
+        let req = read_request(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(req, request("GET", "/machine-config", ""));
+
+        let mut reader = BufReader::new("garbage\r\n\r\n".as_bytes());
+        assert!(matches!(
+            read_request(&mut reader).unwrap().unwrap(),
+            Err(RequestError::Malformed(_))
+        ));
+
+        let raw = format!(
+            "PUT /vsock HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        let mut reader = BufReader::new(raw.as_bytes());
+        assert_eq!(
+            read_request(&mut reader).unwrap().unwrap(),
+            Err(RequestError::BodyTooLarge(MAX_BODY_SIZE + 1))
+        );
+
+        // truncated body
+        let raw = "PUT /actions HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}";
+        let mut reader = BufReader::new(raw.as_bytes());
+        assert!(read_request(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("GET", "/", "")),
+            Ok(ParsedRequest::GetInstanceInfo)
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request(
+                "PUT",
+                "/actions",
+                r#"{"action_type": "InstanceStart"}"#
+            )),
+            Ok(ParsedRequest::Action(VmmAction::StartMicroVm))
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request(
+                "PUT",
+                "/actions",
+                r#"{"action_type": "InstanceStop"}"#
+            )),
+            Ok(ParsedRequest::Action(VmmAction::ShutdownMicroVm))
+        );
+        assert!(matches!(
+            ParsedRequest::try_from_request(&request(
+                "PUT",
+                "/actions",
+                r#"{"action_type": "Reboot"}"#
+            )),
+            Err(RequestError::InvalidBody(_))
+        ));
+        assert_eq!(
+            ParsedRequest::try_from_request(&request(
+                "PUT",
+                "/boot-source",
+                r#"{"kernel_path": "/vmlinux", "boot_args": "console=ttyS0"}"#
+            )),
+            Ok(ParsedRequest::Action(VmmAction::ConfigureBootSource(
+                BootSourceConfig {
+                    kernel_path: "/vmlinux".to_string(),
+                    initrd_path: None,
+                    boot_args: Some("console=ttyS0".to_string()),
+                }
+            )))
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request(
+                "PUT",
+                "/machine-config",
+                r#"{"vcpu_count": 2, "max_vcpu_count": 4, "mem_size_mib": 512}"#
+            )),
+            Ok(ParsedRequest::Action(VmmAction::SetVmConfiguration(
+                VmConfigInfo {
+                    vcpu_count: 2,
+                    max_vcpu_count: 4,
+                    mem_size_mib: 512,
+                    ..Default::default()
+                }
+            )))
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("GET", "/machine-config", "")),
+            Ok(ParsedRequest::Action(VmmAction::GetVmConfiguration))
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("DELETE", "/machine-config", "")),
+            Err(RequestError::MethodNotAllowed(
+                "DELETE".to_string(),
+                "/machine-config".to_string()
+            ))
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("GET", "/foo", "")),
+            Err(RequestError::InvalidPath("/foo".to_string()))
+        );
+    }
+
+    #[cfg(feature = "virtio-vsock")]
+    #[test]
+    fn test_parse_vsock_request() {
+        assert_eq!(
+            ParsedRequest::try_from_request(&request(
+                "PUT",
+                "/vsock",
+                r#"{"id": "vsock0", "guest_cid": 3, "uds_path": "/tmp/vsock.sock",
+                    "tcp_addr": null, "queue_size": [128], "use_shared_irq": null,
+                    "use_generic_irq": null}"#
+            ))
+            .map(|r| matches!(r, ParsedRequest::Action(VmmAction::InsertVsockDevice(_)))),
+            Ok(true)
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("DELETE", "/vsock/vsock0", "")),
+            Ok(ParsedRequest::Action(VmmAction::RemoveVsockDevice(
+                "vsock0".to_string()
+            )))
+        );
+    }
+
+    #[cfg(feature = "virtio-net")]
+    #[test]
+    fn test_parse_network_interface_request() {
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("DELETE", "/network-interfaces/eth0", "")),
+            Ok(ParsedRequest::Action(VmmAction::RemoveNetworkDevice(
+                "eth0".to_string()
+            )))
+        );
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    #[test]
+    fn test_parse_fs_request() {
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("DELETE", "/fs/kataShared", "")),
+            Ok(ParsedRequest::Action(VmmAction::RemoveFsDevice(
+                "kataShared".to_string()
+            )))
+        );
+    }
+
+    #[cfg(feature = "virtio-blk")]
+    #[test]
+    fn test_parse_drive_request() {
+        assert_eq!(
+            ParsedRequest::try_from_request(&request("DELETE", "/drives/rootfs", "")),
+            Ok(ParsedRequest::Action(VmmAction::RemoveBlockDevice(
+                "rootfs".to_string()
+            )))
+        );
+        assert_eq!(
+            ParsedRequest::try_from_request(&request(
+                "PATCH",
+                "/drives/rootfs",
+                r#"{"drive_id": "data", "rate_limiter": null}"#
+            )),
+            Err(RequestError::IdMismatch(
+                "rootfs".to_string(),
+                "data".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let mut out = Vec::new();
+        Response::no_content().write_to(&mut out).unwrap();
+        assert_eq!(out, b"HTTP/1.1 204 No Content\r\n\r\n");
+
+        let mut out = Vec::new();
+        Response::from(RequestError::InvalidPath("/foo".to_string()))
+            .write_to(&mut out)
+            .unwrap();
+        let body = r#"{"fault_message":"invalid path: /foo"}"#;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        );
+    }
+}
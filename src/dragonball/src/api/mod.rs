@@ -4,3 +4,7 @@
 //! API related data structures to configure the vmm.
 
 pub mod v1;
+
+/// HTTP API server to drive a standalone Dragonball instance.
+#[cfg(feature = "api-server")]
+pub mod http_server;
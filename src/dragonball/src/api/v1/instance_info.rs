@@ -9,7 +9,8 @@ use serde_derive::{Deserialize, Serialize};
 ///
 /// When Dragonball starts, the instance state is Uninitialized. Once start_microvm method is
 /// called, the state goes from Uninitialized to Starting. The state is changed to Running until
-/// the start_microvm method ends. Halting and Halted are currently unsupported.
+/// the start_microvm method ends. The PauseVm and ResumeVm actions switch a running instance
+/// between Running and Paused. Halting and Halted are currently unsupported.
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub enum InstanceState {
     /// Microvm is not initialized.
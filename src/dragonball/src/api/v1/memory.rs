@@ -0,0 +1,52 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::address_space_manager::AddressManagerError;
+
+/// Size in MiB of the guest memory blocks, i.e. of the sparse memory sections of x86_64 and
+/// aarch64 Linux guests. Hot-added memory is probed and onlined by the guest one block at a time.
+pub const MEMORY_BLOCK_SIZE_MIB: u64 = 128;
+
+/// Configuration information to hot-add guest memory to a running microVM.
+///
+/// The memory is mapped right after the existing guest memory, aligned to the guest memory
+/// block size. The guest is not notified of it: it's up to the caller to have the guest probe
+/// and online the new memory blocks, e.g. through `/sys/devices/system/memory/probe`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryRegionConfigInfo {
+    /// Size of the memory to hot-add, in MiB. Must be a multiple of `MEMORY_BLOCK_SIZE_MIB`.
+    pub size_mib: u64,
+}
+
+/// Guest memory hot-added by a `InsertMemoryRegion` action.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+pub struct MemoryRegionInfo {
+    /// Guest physical address of the memory.
+    pub guest_addr: u64,
+    /// Size of the memory, in MiB.
+    pub size_mib: u64,
+}
+
+/// Errors associated with guest memory hotplug.
+#[derive(Debug, thiserror::Error)]
+pub enum MemoryRegionError {
+    /// Guest memory can only be hot-added to a running microVM.
+    #[error("memory hotplug is not allowed pre boot")]
+    UpdateNotAllowedPreBoot,
+
+    /// The memory size is not a non-zero multiple of the guest memory block size.
+    #[error("invalid memory size {0} MiB, not a multiple of the memory block size")]
+    InvalidSize(u64),
+
+    /// Failed to map the memory into the guest.
+    #[error("failed to hot-add guest memory: {0}")]
+    AddressManager(#[source] AddressManagerError),
+
+    #[cfg(feature = "host-device")]
+    /// Failed to map the memory for the DMA of the host devices.
+    #[error("failed to map hot-added memory for host devices: {0}")]
+    HostDevice(#[source] crate::device_manager::vfio_dev_mgr::VfioDeviceError),
+}
@@ -0,0 +1,38 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Configuration information to dump the guest memory of a microVM into an ELF core file.
+///
+/// The core file has one loadable segment per guest memory region, at the guest physical
+/// address of the region. It has no note segment with the vCPU registers or the guest
+/// `VMCOREINFO`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GuestMemoryDumpConfigInfo {
+    /// Path of the core file to create.
+    pub dump_path: PathBuf,
+}
+
+/// Errors associated with guest memory dump operations.
+#[derive(Debug, thiserror::Error)]
+pub enum GuestMemoryDumpError {
+    /// The guest memory isn't initialized yet.
+    #[error("the guest memory is not initialized")]
+    NoGuestMemory,
+
+    /// Failed to write the core file.
+    #[error("failed to write core file {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    /// Not enough space for the core file, of the given size in bytes.
+    #[error("not enough space for core file {0}: {1} bytes needed, {2} bytes available")]
+    NoSpace(PathBuf, u64, u64),
+
+    /// Failed to copy guest memory into the core file.
+    #[error("failed to dump guest memory: {0}")]
+    GuestMemory(#[source] vm_memory::GuestMemoryError),
+}
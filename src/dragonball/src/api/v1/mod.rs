@@ -17,3 +17,19 @@ pub use self::instance_info::{InstanceInfo, InstanceState};
 /// Wrapper for configuring the memory and CPU of the microVM.
 mod machine_config;
 pub use self::machine_config::{VmConfigError, MAX_SUPPORTED_VCPUS};
+
+/// Wrapper for creating and loading snapshots of the microVM.
+mod snapshot;
+pub use self::snapshot::{SnapshotConfigInfo, SnapshotError};
+
+/// Wrapper for dumping the guest memory of the microVM.
+mod memory_dump;
+pub use self::memory_dump::{GuestMemoryDumpConfigInfo, GuestMemoryDumpError};
+
+/// Wrapper for hot-adding guest memory to the microVM.
+#[cfg(feature = "hotplug")]
+mod memory;
+#[cfg(feature = "hotplug")]
+pub use self::memory::{
+    MemoryRegionConfigInfo, MemoryRegionError, MemoryRegionInfo, MEMORY_BLOCK_SIZE_MIB,
+};
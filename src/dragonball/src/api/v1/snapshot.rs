@@ -0,0 +1,64 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::StartMicroVmError;
+use crate::vcpu::VcpuManagerError;
+
+/// Configuration information to create a snapshot of, or restore a microVM from, a directory.
+///
+/// The directory holds the vCPU state, the in-kernel device state and one file per guest memory
+/// region, plus a `snapshot.json` metadata file which is written last.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotConfigInfo {
+    /// Directory to store the snapshot files into, or to load them from.
+    pub snapshot_path: PathBuf,
+}
+
+/// Errors associated with snapshot operations.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    /// Snapshots are only supported on x86_64.
+    #[error("snapshot is not supported on this architecture")]
+    NotSupported,
+
+    /// A snapshot can only be created from a paused microVM.
+    #[error("the microVM must be paused to create a snapshot")]
+    MicroVMNotPaused,
+
+    /// A snapshot can only be loaded into a microVM which has not been started.
+    #[error("a snapshot can only be loaded before the microVM is started")]
+    LoadNotAllowedPostBoot,
+
+    /// The snapshot doesn't match the configuration of the microVM.
+    #[error("the snapshot doesn't match the microVM configuration: {0}")]
+    ConfigMismatch(String),
+
+    /// Failed to access the snapshot files.
+    #[error("failed to access snapshot file {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    /// Failed to encode or decode the snapshot metadata.
+    #[error("invalid snapshot metadata: {0}")]
+    Metadata(#[source] serde_json::Error),
+
+    /// Failed to save or restore the state through KVM.
+    #[error("failed to save or restore KVM state: {0}")]
+    Kvm(#[source] kvm_ioctls::Error),
+
+    /// Failed to copy guest memory from or to the snapshot.
+    #[error("failed to save or restore guest memory: {0}")]
+    GuestMemory(#[source] vm_memory::GuestMemoryError),
+
+    /// The vCPU manager failed.
+    #[error("vCPU manager error: {0}")]
+    Vcpu(#[source] VcpuManagerError),
+
+    /// Failed to set up the microVM the snapshot is loaded into.
+    #[error("failed to set up the microVM: {0}")]
+    StartMicroVm(#[source] StartMicroVmError),
+}
@@ -7,26 +7,41 @@
 // found in the THIRD-PARTY file.
 
 use std::fs::File;
+use std::time::Duration;
 
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use log::{debug, error, info, warn};
 
+use crate::device_manager::HotunplugReceiver;
 use crate::error::{Result, StartMicroVmError, StopMicrovmError};
 use crate::event_manager::EventManager;
-use crate::vm::{CpuTopology, KernelConfigInfo, VmConfigInfo};
+#[cfg(any(feature = "virtio-blk", feature = "virtio-net", feature = "virtio-fs"))]
+use crate::metric::METRICS;
+use crate::vcpu::VcpuManagerError;
+use crate::vm::{CpuTopology, KernelConfigInfo, NumaRegionInfo, VmConfigInfo};
 use crate::vmm::Vmm;
 
 use self::VmConfigError::*;
 use self::VmmActionError::MachineConfig;
 
+#[cfg(feature = "virtio-balloon")]
+pub use crate::device_manager::balloon_dev_mgr::{
+    BalloonDeviceConfigInfo, BalloonDeviceConfigUpdateInfo, BalloonDeviceError, BalloonDeviceMgr,
+    BalloonGuestStats, BalloonStatsInfo,
+};
 #[cfg(feature = "virtio-blk")]
 pub use crate::device_manager::blk_dev_mgr::{
     BlockDeviceConfigInfo, BlockDeviceConfigUpdateInfo, BlockDeviceError, BlockDeviceMgr,
+    BlockDeviceType,
 };
 #[cfg(feature = "virtio-fs")]
 pub use crate::device_manager::fs_dev_mgr::{
     FsDeviceConfigInfo, FsDeviceConfigUpdateInfo, FsDeviceError, FsDeviceMgr, FsMountConfigInfo,
 };
+#[cfg(feature = "host-device")]
+pub use crate::device_manager::vfio_dev_mgr::{
+    HostDeviceConfig, VfioDeviceError, VfioDeviceMgr, VfioPciDeviceConfig,
+};
 #[cfg(feature = "virtio-net")]
 pub use crate::device_manager::virtio_net_dev_mgr::{
     VirtioNetDeviceConfigInfo, VirtioNetDeviceConfigUpdateInfo, VirtioNetDeviceError,
@@ -74,7 +89,8 @@ pub enum VmmActionError {
     MachineConfig(#[source] VmConfigError),
 
     #[cfg(feature = "virtio-vsock")]
-    /// The action `InsertVsockDevice` failed either because of bad user input or an internal error.
+    /// The action `InsertVsockDevice` or `RemoveVsockDevice` failed either because of bad user
+    /// input or an internal error.
     #[error("failed to add virtio-vsock device: {0}")]
     Vsock(#[source] VsockDeviceError),
 
@@ -93,10 +109,53 @@ pub enum VmmActionError {
     #[error("virtio-fs device error: {0}")]
     FsDevice(#[source] FsDeviceError),
 
+    #[cfg(feature = "virtio-balloon")]
+    /// Balloon device related errors.
+    #[error("virtio-balloon device error: {0}")]
+    Balloon(#[source] BalloonDeviceError),
+
+    #[cfg(feature = "host-device")]
+    /// Host device related errors.
+    #[error("host device error: {0}")]
+    HostDevice(#[source] VfioDeviceError),
+
     #[cfg(feature = "hotplug")]
     /// The action `ResizeVcpu` Failed
     #[error("vcpu resize error : {0}")]
     ResizeVcpu(#[source] VcpuResizeError),
+
+    #[cfg(feature = "hotplug")]
+    /// The action `InsertMemoryRegion` failed.
+    #[error("memory hotplug error: {0}")]
+    InsertMemoryRegion(#[source] MemoryRegionError),
+
+    /// The action `PauseVm` or `ResumeVm` isn't allowed in the current VM state.
+    #[error("the action is not allowed while the VM is {0:?}")]
+    InvalidVmState(InstanceState),
+
+    /// The action `PauseVm` failed.
+    #[error("failed to pause the VM: {0}")]
+    PauseVm(#[source] VcpuManagerError),
+
+    /// The action `ResumeVm` failed.
+    #[error("failed to resume the VM: {0}")]
+    ResumeVm(#[source] VcpuManagerError),
+
+    /// The action `CreateSnapshot` or `LoadSnapshot` failed.
+    #[error("snapshot error: {0}")]
+    Snapshot(#[source] SnapshotError),
+
+    /// The action `DumpGuestMemory` failed.
+    #[error("guest memory dump error: {0}")]
+    GuestMemoryDump(#[source] GuestMemoryDumpError),
+
+    /// The guest failed to release a hot-unplugged device.
+    #[error("the guest failed to release the hot-unplugged device")]
+    HotunplugFailed,
+
+    /// The guest didn't release a hot-unplugged device in time.
+    #[error("timed out waiting for the guest to release the hot-unplugged device")]
+    HotunplugTimeout,
 }
 
 /// This enum represents the public interface of the VMM. Each action contains various
@@ -128,6 +187,11 @@ pub enum VmmAction {
     /// booted. The response is sent using the `OutcomeSender`.
     InsertVsockDevice(VsockDeviceConfigInfo),
 
+    #[cfg(feature = "virtio-vsock")]
+    /// Remove a vsock device according to the given id. This action can only be called before the
+    /// microVM has booted.
+    RemoveVsockDevice(String),
+
     #[cfg(feature = "virtio-blk")]
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
     /// input. This action can only be called before the microVM has booted.
@@ -153,6 +217,11 @@ pub enum VmmAction {
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(VirtioNetDeviceConfigUpdateInfo),
 
+    #[cfg(feature = "virtio-net")]
+    /// Remove a network interface according to the given iface_id. The device is hot-unplugged
+    /// from the guest if the microVM is running.
+    RemoveNetworkDevice(String),
+
     #[cfg(feature = "virtio-fs")]
     /// Add a new shared fs device or update one that already exists using the
     /// `FsDeviceConfig` as input. This action can only be called before the microVM has
@@ -169,9 +238,63 @@ pub enum VmmAction {
     /// Update fs rate limiter, after microVM start.
     UpdateFsDevice(FsDeviceConfigUpdateInfo),
 
+    #[cfg(feature = "virtio-fs")]
+    /// Remove a shared fs device according to the given tag. The device is hot-unplugged from
+    /// the guest if the microVM is running.
+    RemoveFsDevice(String),
+
+    #[cfg(feature = "virtio-balloon")]
+    /// Add a new balloon device or update one that already exists using the
+    /// `BalloonDeviceConfig` as input. Only one balloon device is allowed per microVM.
+    InsertBalloonDevice(BalloonDeviceConfigInfo),
+
+    #[cfg(feature = "virtio-balloon")]
+    /// Set the target size of a balloon device, before or after the microVM has booted.
+    UpdateBalloon(BalloonDeviceConfigUpdateInfo),
+
+    #[cfg(feature = "virtio-balloon")]
+    /// Get the target and actual size of the balloon device given by its id. This action can
+    /// only be called after the microVM has booted.
+    GetBalloonStats(String),
+
+    #[cfg(feature = "host-device")]
+    /// Add a host PCI device passed through with VFIO, using the `HostDeviceConfig` as input.
+    /// The device is hot-plugged into the guest if the microVM is running.
+    InsertHostDevice(HostDeviceConfig),
+
+    #[cfg(feature = "host-device")]
+    /// Remove a host device according to the given id. The device is hot-unplugged from the
+    /// guest if the microVM is running.
+    RemoveHostDevice(String),
+
     #[cfg(feature = "hotplug")]
     /// Resize Vcpu number in the guest.
     ResizeVcpu(VcpuResizeInfo),
+
+    #[cfg(feature = "hotplug")]
+    /// Hot-add guest memory to the microVM, above the existing guest memory. The guest is not
+    /// notified of the new memory, it has to probe it.
+    InsertMemoryRegion(MemoryRegionConfigInfo),
+
+    /// Pause all vCPUs of the microVM. This action can only be called after the microVM has
+    /// booted.
+    PauseVm,
+
+    /// Resume all vCPUs of a paused microVM.
+    ResumeVm,
+
+    /// Save the vCPU state, the device state and the guest memory of the microVM into the
+    /// directory given by `SnapshotConfigInfo`. A running microVM is paused during the save.
+    CreateSnapshot(SnapshotConfigInfo),
+
+    /// Restore the microVM from the snapshot given by `SnapshotConfigInfo` and start it. This
+    /// action replaces `StartMicroVm`, after the microVM has been configured the same way as
+    /// the one the snapshot was created from.
+    LoadSnapshot(SnapshotConfigInfo),
+
+    /// Dump the guest memory of the microVM into the ELF core file given by
+    /// `GuestMemoryDumpConfigInfo`. The microVM should be paused for the dump to be consistent.
+    DumpGuestMemory(GuestMemoryDumpConfigInfo),
 }
 
 /// The enum represents the response sent by the VMM in case of success. The response is either
@@ -182,8 +305,38 @@ pub enum VmmData {
     Empty,
     /// The microVM configuration represented by `VmConfigInfo`.
     MachineConfiguration(Box<VmConfigInfo>),
+    #[cfg(feature = "hotplug")]
+    /// The guest memory hot-added by `InsertMemoryRegion`.
+    MemoryRegion(MemoryRegionInfo),
+    #[cfg(feature = "virtio-balloon")]
+    /// The balloon statistics returned by `GetBalloonStats`.
+    BalloonStats(BalloonStatsInfo),
+    /// The device removed by `RemoveNetworkDevice` or `RemoveFsDevice` is being hot-unplugged,
+    /// see `VmmData::wait_hotunplug`.
+    Hotunplug(HotunplugReceiver),
+}
+
+impl VmmData {
+    /// Wait for the guest to release the device removed by the action, if it is being
+    /// hot-unplugged.
+    ///
+    /// The host resources of the device, like its tap interface or shared directory, shouldn't
+    /// be reused before the guest released it.
+    pub fn wait_hotunplug(&self, timeout: Duration) -> std::result::Result<(), VmmActionError> {
+        match self {
+            VmmData::Hotunplug(receiver) => match receiver.recv_timeout(timeout) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(VmmActionError::HotunplugFailed),
+                Err(_) => Err(VmmActionError::HotunplugTimeout),
+            },
+            _ => Ok(()),
+        }
+    }
 }
 
+/// Time the API clients wait for the guest to release a hot-unplugged device.
+pub const HOTUNPLUG_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Request data type used to communicate between the API and the VMM.
 pub type VmmRequest = Box<VmmAction>;
 
@@ -241,6 +394,8 @@ impl VmmService {
             }
             #[cfg(feature = "virtio-vsock")]
             VmmAction::InsertVsockDevice(vsock_cfg) => self.add_vsock_device(vmm, vsock_cfg),
+            #[cfg(feature = "virtio-vsock")]
+            VmmAction::RemoveVsockDevice(id) => self.remove_vsock_device(vmm, &id),
             #[cfg(feature = "virtio-blk")]
             VmmAction::InsertBlockDevice(block_device_config) => {
                 self.add_block_device(vmm, event_mgr, block_device_config)
@@ -261,6 +416,10 @@ impl VmmService {
             VmmAction::UpdateNetworkInterface(netif_update) => {
                 self.update_net_rate_limiters(vmm, netif_update)
             }
+            #[cfg(feature = "virtio-net")]
+            VmmAction::RemoveNetworkDevice(iface_id) => {
+                self.remove_virtio_net_device(vmm, event_mgr, &iface_id)
+            }
             #[cfg(feature = "virtio-fs")]
             VmmAction::InsertFsDevice(fs_cfg) => self.add_fs_device(vmm, fs_cfg),
 
@@ -272,8 +431,39 @@ impl VmmService {
             VmmAction::UpdateFsDevice(fs_update_cfg) => {
                 self.update_fs_rate_limiters(vmm, fs_update_cfg)
             }
+            #[cfg(feature = "virtio-fs")]
+            VmmAction::RemoveFsDevice(tag) => self.remove_fs_device(vmm, event_mgr, &tag),
+            #[cfg(feature = "virtio-balloon")]
+            VmmAction::InsertBalloonDevice(balloon_cfg) => {
+                self.add_balloon_device(vmm, event_mgr, balloon_cfg)
+            }
+            #[cfg(feature = "virtio-balloon")]
+            VmmAction::UpdateBalloon(balloon_update) => {
+                self.update_balloon_size(vmm, balloon_update)
+            }
+            #[cfg(feature = "virtio-balloon")]
+            VmmAction::GetBalloonStats(balloon_id) => self.get_balloon_stats(vmm, &balloon_id),
+            #[cfg(feature = "host-device")]
+            VmmAction::InsertHostDevice(hostdev_cfg) => {
+                self.add_host_device(vmm, event_mgr, hostdev_cfg)
+            }
+            #[cfg(feature = "host-device")]
+            VmmAction::RemoveHostDevice(hostdev_id) => {
+                self.remove_host_device(vmm, event_mgr, &hostdev_id)
+            }
             #[cfg(feature = "hotplug")]
             VmmAction::ResizeVcpu(vcpu_resize_cfg) => self.resize_vcpu(vmm, vcpu_resize_cfg),
+            #[cfg(feature = "hotplug")]
+            VmmAction::InsertMemoryRegion(mem_region_cfg) => {
+                self.insert_memory_region(vmm, mem_region_cfg)
+            }
+            VmmAction::PauseVm => self.pause_vm(vmm),
+            VmmAction::ResumeVm => self.resume_vm(vmm),
+            VmmAction::CreateSnapshot(snapshot_cfg) => self.create_snapshot(vmm, snapshot_cfg),
+            VmmAction::LoadSnapshot(snapshot_cfg) => {
+                self.load_snapshot(vmm, event_mgr, snapshot_cfg)
+            }
+            VmmAction::DumpGuestMemory(dump_cfg) => self.dump_guest_memory(vmm, dump_cfg),
         };
 
         debug!("send vmm response: {:?}", response);
@@ -424,10 +614,23 @@ impl VmmService {
         }
         config.vpmu_feature = machine_config.vpmu_feature;
 
+        if !machine_config.numa_regions.is_empty() {
+            handle_numa_regions(
+                &machine_config.numa_regions,
+                config.mem_size_mib,
+                config.max_vcpu_count,
+            )?;
+        }
+        config.numa_regions = machine_config.numa_regions;
+
         // If serial_path is:
-        // - None, legacy_manager will create_stdio_console.
-        // - Some(path), legacy_manager will create_socket_console on that path.
+        // - None, legacy_manager will create_stdio_console, or create_file_console if a console
+        //   log file is configured.
+        // - Some(path), legacy_manager will create_socket_console on that path, the console
+        //   output is also written to the console log file if configured.
         config.serial_path = machine_config.serial_path;
+        config.console_log = machine_config.console_log;
+        config.guest_memory_dump_path = machine_config.guest_memory_dump_path;
 
         vm.set_vm_config(config.clone());
         self.machine_config = config;
@@ -467,6 +670,28 @@ impl VmmService {
             .map_err(VmmActionError::Vsock)
     }
 
+    #[cfg(feature = "virtio-vsock")]
+    fn remove_vsock_device(&self, vmm: &mut Vmm, id: &str) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        if vm.is_vm_initialized() {
+            return Err(VmmActionError::Vsock(
+                VsockDeviceError::UpdateNotAllowedPostBoot,
+            ));
+        }
+
+        info!("remove_vsock_device: {}", id);
+        let ctx = vm.create_device_op_context(None).map_err(|e| {
+            info!("create device op context error: {:?}", e);
+            VmmActionError::Vsock(VsockDeviceError::UpdateNotAllowedPostBoot)
+        })?;
+
+        vm.device_manager_mut()
+            .vsock_manager
+            .remove_device(ctx, id)
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::Vsock)
+    }
+
     #[cfg(feature = "virtio-blk")]
     // Only call this function as part of the API.
     // If the drive_id does not exist, a new Block Device Config is added to the list.
@@ -486,7 +711,9 @@ impl VmmService {
                 VmmActionError::Block(BlockDeviceError::UpdateNotAllowedPostBoot)
             })?;
 
-        BlockDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config)
+        let result = BlockDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config);
+        METRICS.block.record_attach(&result);
+        result
             .map(|_| VmmData::Empty)
             .map_err(VmmActionError::Block)
     }
@@ -518,7 +745,9 @@ impl VmmService {
             .create_device_op_context(Some(event_mgr.epoll_manager()))
             .map_err(|_| VmmActionError::Block(BlockDeviceError::UpdateNotAllowedPostBoot))?;
 
-        BlockDeviceMgr::remove_device(vm.device_manager_mut(), ctx, drive_id)
+        let result = BlockDeviceMgr::remove_device(vm.device_manager_mut(), ctx, drive_id);
+        METRICS.block.record_detach(&result);
+        result
             .map(|_| VmmData::Empty)
             .map_err(VmmActionError::Block)
     }
@@ -543,11 +772,38 @@ impl VmmService {
                 }
             })?;
 
-        VirtioNetDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config)
+        let result = VirtioNetDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config);
+        METRICS.net.record_attach(&result);
+        result
             .map(|_| VmmData::Empty)
             .map_err(VmmActionError::VirtioNet)
     }
 
+    #[cfg(feature = "virtio-net")]
+    fn remove_virtio_net_device(
+        &mut self,
+        vmm: &mut Vmm,
+        event_mgr: &mut EventManager,
+        iface_id: &str,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        let ctx = vm
+            .create_device_op_context(Some(event_mgr.epoll_manager()))
+            .map_err(|e| {
+                if let StartMicroVmError::UpcallServerNotReady = e {
+                    VmmActionError::UpcallServerNotReady
+                } else {
+                    VmmActionError::StartMicroVm(e)
+                }
+            })?;
+
+        let result = VirtioNetDeviceMgr::remove_device(vm.device_manager_mut(), ctx, iface_id);
+        METRICS.net.record_detach(&result);
+        result
+            .map(|hotunplug| hotunplug.map_or(VmmData::Empty, VmmData::Hotunplug))
+            .map_err(VmmActionError::VirtioNet)
+    }
+
     #[cfg(feature = "virtio-net")]
     fn update_net_rate_limiters(
         &mut self,
@@ -575,11 +831,38 @@ impl VmmService {
             info!("create device op context error: {:?}", e);
             VmmActionError::FsDevice(FsDeviceError::UpdateNotAllowedPostBoot)
         })?;
-        FsDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config)
+        let result = FsDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config);
+        METRICS.fs.record_attach(&result);
+        result
             .map(|_| VmmData::Empty)
             .map_err(VmmActionError::FsDevice)
     }
 
+    #[cfg(feature = "virtio-fs")]
+    fn remove_fs_device(
+        &mut self,
+        vmm: &mut Vmm,
+        event_mgr: &mut EventManager,
+        tag: &str,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        let ctx = vm
+            .create_device_op_context(Some(event_mgr.epoll_manager()))
+            .map_err(|e| {
+                if let StartMicroVmError::UpcallServerNotReady = e {
+                    VmmActionError::UpcallServerNotReady
+                } else {
+                    VmmActionError::StartMicroVm(e)
+                }
+            })?;
+
+        let result = FsDeviceMgr::remove_device(vm.device_manager_mut(), ctx, tag);
+        METRICS.fs.record_detach(&result);
+        result
+            .map(|hotunplug| hotunplug.map_or(VmmData::Empty, VmmData::Hotunplug))
+            .map_err(VmmActionError::FsDevice)
+    }
+
     #[cfg(feature = "virtio-fs")]
     fn manipulate_fs_backend_fs(
         &self,
@@ -614,6 +897,101 @@ impl VmmService {
             .map_err(VmmActionError::FsDevice)
     }
 
+    #[cfg(feature = "virtio-balloon")]
+    fn add_balloon_device(
+        &mut self,
+        vmm: &mut Vmm,
+        event_mgr: &mut EventManager,
+        config: BalloonDeviceConfigInfo,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        let ctx = vm
+            .create_device_op_context(Some(event_mgr.epoll_manager()))
+            .map_err(|e| {
+                if let StartMicroVmError::MicroVMAlreadyRunning = e {
+                    VmmActionError::Balloon(BalloonDeviceError::UpdateNotAllowedPostBoot)
+                } else if let StartMicroVmError::UpcallServerNotReady = e {
+                    VmmActionError::UpcallServerNotReady
+                } else {
+                    VmmActionError::StartMicroVm(e)
+                }
+            })?;
+
+        BalloonDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config)
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::Balloon)
+    }
+
+    #[cfg(feature = "virtio-balloon")]
+    fn update_balloon_size(
+        &mut self,
+        vmm: &mut Vmm,
+        config: BalloonDeviceConfigUpdateInfo,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+
+        BalloonDeviceMgr::update_balloon_size(vm.device_manager_mut(), config)
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::Balloon)
+    }
+
+    #[cfg(feature = "virtio-balloon")]
+    fn get_balloon_stats(&mut self, vmm: &mut Vmm, balloon_id: &str) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+
+        BalloonDeviceMgr::get_balloon_stats(vm.device_manager(), balloon_id)
+            .map(VmmData::BalloonStats)
+            .map_err(VmmActionError::Balloon)
+    }
+
+    #[cfg(feature = "host-device")]
+    fn add_host_device(
+        &mut self,
+        vmm: &mut Vmm,
+        event_mgr: &mut EventManager,
+        config: HostDeviceConfig,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        let ctx = vm
+            .create_device_op_context(Some(event_mgr.epoll_manager()))
+            .map_err(|e| {
+                if let StartMicroVmError::MicroVMAlreadyRunning = e {
+                    VmmActionError::HostDevice(VfioDeviceError::UpdateNotAllowedPostBoot)
+                } else if let StartMicroVmError::UpcallServerNotReady = e {
+                    VmmActionError::UpcallServerNotReady
+                } else {
+                    VmmActionError::StartMicroVm(e)
+                }
+            })?;
+
+        VfioDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config)
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::HostDevice)
+    }
+
+    #[cfg(feature = "host-device")]
+    fn remove_host_device(
+        &mut self,
+        vmm: &mut Vmm,
+        event_mgr: &mut EventManager,
+        hostdev_id: &str,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        let ctx = vm
+            .create_device_op_context(Some(event_mgr.epoll_manager()))
+            .map_err(|e| {
+                if let StartMicroVmError::UpcallServerNotReady = e {
+                    VmmActionError::UpcallServerNotReady
+                } else {
+                    VmmActionError::StartMicroVm(e)
+                }
+            })?;
+
+        VfioDeviceMgr::remove_device(vm.device_manager_mut(), ctx, hostdev_id)
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::HostDevice)
+    }
+
     #[cfg(feature = "hotplug")]
     fn resize_vcpu(&mut self, vmm: &mut Vmm, config: VcpuResizeInfo) -> VmmRequestResult {
         if !cfg!(target_arch = "x86_64") {
@@ -644,6 +1022,123 @@ impl VmmService {
 
         Ok(VmmData::Empty)
     }
+
+    #[cfg(feature = "hotplug")]
+    fn insert_memory_region(
+        &mut self,
+        vmm: &mut Vmm,
+        config: MemoryRegionConfigInfo,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::VmNotExist)?;
+        vm.insert_memory_region(config)
+            .map(VmmData::MemoryRegion)
+            .map_err(VmmActionError::InsertMemoryRegion)
+    }
+
+    fn pause_vm(&mut self, vmm: &mut Vmm) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        match vm.shared_info().read().unwrap().state {
+            InstanceState::Running => {}
+            InstanceState::Paused => return Ok(VmmData::Empty),
+            state => return Err(VmmActionError::InvalidVmState(state)),
+        }
+
+        vm.pause_all_vcpus_with_downtime()
+            .map_err(VmmActionError::PauseVm)?;
+        vm.shared_info().write().unwrap().state = InstanceState::Paused;
+
+        Ok(VmmData::Empty)
+    }
+
+    fn resume_vm(&mut self, vmm: &mut Vmm) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        match vm.shared_info().read().unwrap().state {
+            InstanceState::Paused => {}
+            InstanceState::Running => return Ok(VmmData::Empty),
+            state => return Err(VmmActionError::InvalidVmState(state)),
+        }
+
+        vm.resume_all_vcpus_with_downtime()
+            .map_err(VmmActionError::ResumeVm)?;
+        vm.shared_info().write().unwrap().state = InstanceState::Running;
+
+        Ok(VmmData::Empty)
+    }
+
+    fn dump_guest_memory(
+        &mut self,
+        vmm: &mut Vmm,
+        config: GuestMemoryDumpConfigInfo,
+    ) -> VmmRequestResult {
+        let vm = vmm.get_vm().ok_or(VmmActionError::InvalidVMID)?;
+
+        vm.dump_guest_memory(&config)
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::GuestMemoryDump)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn create_snapshot(&mut self, vmm: &mut Vmm, config: SnapshotConfigInfo) -> VmmRequestResult {
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        // A running VM is paused while its state is saved, and resumed afterwards.
+        let running = match vm.shared_info().read().unwrap().state {
+            InstanceState::Running => true,
+            InstanceState::Paused => false,
+            state => return Err(VmmActionError::InvalidVmState(state)),
+        };
+        if running {
+            vm.pause_all_vcpus_with_downtime()
+                .map_err(VmmActionError::PauseVm)?;
+            vm.shared_info().write().unwrap().state = InstanceState::Paused;
+        }
+
+        let result = vm.create_snapshot(&config);
+
+        if running {
+            vm.resume_all_vcpus_with_downtime()
+                .map_err(VmmActionError::ResumeVm)?;
+            vm.shared_info().write().unwrap().state = InstanceState::Running;
+        }
+        result
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::Snapshot)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn load_snapshot(
+        &mut self,
+        vmm: &mut Vmm,
+        event_mgr: &mut EventManager,
+        config: SnapshotConfigInfo,
+    ) -> VmmRequestResult {
+        let vmm_seccomp_filter = vmm.vmm_seccomp_filter();
+        let vcpu_seccomp_filter = vmm.vcpu_seccomp_filter();
+        let vm = vmm.get_vm_mut().ok_or(VmmActionError::InvalidVMID)?;
+        if vm.is_vm_initialized() {
+            return Err(VmmActionError::Snapshot(
+                SnapshotError::LoadNotAllowedPostBoot,
+            ));
+        }
+
+        vm.restore_microvm(&config, event_mgr, vmm_seccomp_filter, vcpu_seccomp_filter)
+            .map(|_| VmmData::Empty)
+            .map_err(VmmActionError::Snapshot)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn create_snapshot(&mut self, _vmm: &mut Vmm, _config: SnapshotConfigInfo) -> VmmRequestResult {
+        Err(VmmActionError::Snapshot(SnapshotError::NotSupported))
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn load_snapshot(
+        &mut self,
+        _vmm: &mut Vmm,
+        _event_mgr: &mut EventManager,
+        _config: SnapshotConfigInfo,
+    ) -> VmmRequestResult {
+        Err(VmmActionError::Snapshot(SnapshotError::NotSupported))
+    }
 }
 
 fn handle_cpu_topology(
@@ -674,6 +1169,35 @@ fn handle_cpu_topology(
     Ok(cpu_topology)
 }
 
+fn handle_numa_regions(
+    numa_regions: &[NumaRegionInfo],
+    mem_size_mib: usize,
+    max_vcpu_count: u8,
+) -> std::result::Result<(), VmmActionError> {
+    // The NUMA regions must cover the whole guest memory and each vCPU exactly once.
+    let mem_size: u64 = numa_regions.iter().map(|r| r.size).sum();
+    if mem_size != mem_size_mib as u64 {
+        return Err(MachineConfig(InvalidNumaRegionMemorySize(
+            mem_size as usize,
+        )));
+    }
+    let vcpu_count: usize = numa_regions.iter().map(|r| r.vcpu_ids.len()).sum();
+    if vcpu_count != max_vcpu_count as usize {
+        return Err(MachineConfig(InvalidNumaRegionCpuCount(vcpu_count as u16)));
+    }
+    let max_vcpu_id = numa_regions
+        .iter()
+        .flat_map(|r| r.vcpu_ids.iter())
+        .max()
+        .copied()
+        .unwrap_or(0);
+    if max_vcpu_id >= max_vcpu_count as u32 {
+        return Err(MachineConfig(InvalidNumaRegionCpuMaxId(max_vcpu_id as u16)));
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::{Arc, Mutex};
@@ -1008,23 +1532,65 @@ mod tests {
                     assert_eq!(err_string, expected_err);
                 },
             ),
-            // success
+            // NUMA regions not covering the guest memory
             TestData::new(
-                VmmAction::SetVmConfiguration(VmConfigInfo::default()),
+                VmmAction::SetVmConfiguration(VmConfigInfo {
+                    numa_regions: vec![NumaRegionInfo {
+                        size: 64,
+                        host_numa_node_id: Some(0),
+                        guest_numa_node_id: Some(0),
+                        vcpu_ids: vec![0],
+                    }],
+                    ..Default::default()
+                }),
                 InstanceState::Uninitialized,
                 &|result| {
-                    assert!(result.is_ok());
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::MachineConfig(
+                            VmConfigError::InvalidNumaRegionMemorySize(64)
+                        ))
+                    ));
                 },
             ),
-        ];
-
-        for t in tests.iter_mut() {
-            t.check_request();
-        }
-    }
-
-    #[test]
-    fn test_vmm_action_start_microvm() {
+            // NUMA regions with an out of range vCPU
+            TestData::new(
+                VmmAction::SetVmConfiguration(VmConfigInfo {
+                    numa_regions: vec![NumaRegionInfo {
+                        size: 128,
+                        host_numa_node_id: Some(0),
+                        guest_numa_node_id: Some(0),
+                        vcpu_ids: vec![1],
+                    }],
+                    ..Default::default()
+                }),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::MachineConfig(
+                            VmConfigError::InvalidNumaRegionCpuMaxId(1)
+                        ))
+                    ));
+                },
+            ),
+            // success
+            TestData::new(
+                VmmAction::SetVmConfiguration(VmConfigInfo::default()),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(result.is_ok());
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[test]
+    fn test_vmm_action_start_microvm() {
         skip_if_not_root!();
 
         let tests = &mut [
@@ -1069,6 +1635,159 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_vmm_action_pause_resume_vm() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // invalid state (not started)
+            TestData::new(
+                VmmAction::PauseVm,
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::InvalidVmState(InstanceState::Uninitialized))
+                    ));
+                },
+            ),
+            TestData::new(VmmAction::ResumeVm, InstanceState::Starting, &|result| {
+                assert!(matches!(
+                    result,
+                    Err(VmmActionError::InvalidVmState(InstanceState::Starting))
+                ));
+            }),
+            // already paused
+            TestData::new(VmmAction::PauseVm, InstanceState::Paused, &|result| {
+                assert!(matches!(result, Ok(VmmData::Empty)));
+            }),
+            // already running
+            TestData::new(VmmAction::ResumeVm, InstanceState::Running, &|result| {
+                assert!(matches!(result, Ok(VmmData::Empty)));
+            }),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_vmm_action_snapshot() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // invalid state (not started)
+            TestData::new(
+                VmmAction::CreateSnapshot(SnapshotConfigInfo::default()),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::InvalidVmState(InstanceState::Uninitialized))
+                    ));
+                },
+            ),
+            // invalid state (already started)
+            TestData::new(
+                VmmAction::LoadSnapshot(SnapshotConfigInfo::default()),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::Snapshot(
+                            SnapshotError::LoadNotAllowedPostBoot
+                        ))
+                    ));
+                },
+            ),
+            // missing snapshot metadata
+            TestData::new(
+                VmmAction::LoadSnapshot(SnapshotConfigInfo {
+                    snapshot_path: "/nonexistent".into(),
+                }),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::Snapshot(SnapshotError::Io(_, _)))
+                    ));
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[test]
+    fn test_vmm_action_dump_guest_memory() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // no guest memory
+            TestData::new(
+                VmmAction::DumpGuestMemory(GuestMemoryDumpConfigInfo::default()),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::GuestMemoryDump(
+                            GuestMemoryDumpError::NoGuestMemory
+                        ))
+                    ));
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[cfg(feature = "hotplug")]
+    #[test]
+    fn test_vmm_action_insert_memory_region() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // invalid size
+            TestData::new(
+                VmmAction::InsertMemoryRegion(MemoryRegionConfigInfo { size_mib: 100 }),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::InsertMemoryRegion(
+                            MemoryRegionError::InvalidSize(100)
+                        ))
+                    ));
+                },
+            ),
+            // invalid state (not started)
+            TestData::new(
+                VmmAction::InsertMemoryRegion(MemoryRegionConfigInfo {
+                    size_mib: MEMORY_BLOCK_SIZE_MIB,
+                }),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::InsertMemoryRegion(
+                            MemoryRegionError::UpdateNotAllowedPreBoot
+                        ))
+                    ));
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
     #[test]
     fn test_vmm_action_shutdown_microvm() {
         skip_if_not_root!();
@@ -1317,6 +2036,49 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "virtio-fs")]
+    #[test]
+    fn test_vmm_action_remove_fs_device() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // hotplug unready
+            TestData::new(
+                VmmAction::RemoveFsDevice(String::from("kataShared")),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::StartMicroVm(
+                            StartMicroVmError::UpcallMissVsock
+                        ))
+                    ));
+                },
+            ),
+            // invalid tag
+            TestData::new(
+                VmmAction::RemoveFsDevice(String::from("kataShared")),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::FsDevice(FsDeviceError::TagNotExists(_)))
+                    ));
+                    let err_string = format!("{}", result.unwrap_err());
+                    let expected_err = String::from(
+                        "virtio-fs device error: \
+                    fs tag'kataShared' doesn't exist",
+                    );
+                    assert_eq!(err_string, expected_err);
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
     #[cfg(feature = "virtio-net")]
     #[test]
     fn test_vmm_action_insert_network_device() {
@@ -1393,6 +2155,208 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "virtio-net")]
+    #[test]
+    fn test_vmm_action_remove_network_device() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // hotplug unready
+            TestData::new(
+                VmmAction::RemoveNetworkDevice(String::from("1")),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::StartMicroVm(
+                            StartMicroVmError::UpcallMissVsock
+                        ))
+                    ));
+                },
+            ),
+            // invalid id
+            TestData::new(
+                VmmAction::RemoveNetworkDevice(String::from("1")),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::VirtioNet(
+                            VirtioNetDeviceError::InvalidIfaceId(_)
+                        ))
+                    ));
+                    let err_string = format!("{}", result.unwrap_err());
+                    let expected_err = String::from(
+                        "virtio-net device error: \
+                    invalid virtio-net iface id '1'",
+                    );
+                    assert_eq!(err_string, expected_err);
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[cfg(feature = "virtio-balloon")]
+    #[test]
+    fn test_vmm_action_balloon() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // invalid id
+            TestData::new(
+                VmmAction::UpdateBalloon(BalloonDeviceConfigUpdateInfo {
+                    balloon_id: String::from("balloon0"),
+                    new_size_mib: 128,
+                }),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::Balloon(
+                            BalloonDeviceError::InvalidDeviceId(_)
+                        ))
+                    ));
+                    let err_string = format!("{}", result.unwrap_err());
+                    let expected_err = String::from(
+                        "virtio-balloon device error: \
+                    invalid balloon device id 'balloon0'",
+                    );
+                    assert_eq!(err_string, expected_err);
+                },
+            ),
+            TestData::new(
+                VmmAction::GetBalloonStats(String::from("balloon0")),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::Balloon(
+                            BalloonDeviceError::InvalidDeviceId(_)
+                        ))
+                    ));
+                },
+            ),
+            // success
+            TestData::new(
+                VmmAction::InsertBalloonDevice(BalloonDeviceConfigInfo {
+                    balloon_id: String::from("balloon0"),
+                    f_reporting: true,
+                    ..Default::default()
+                }),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(result.is_ok());
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[cfg(feature = "host-device")]
+    #[test]
+    fn test_vmm_action_insert_host_device() {
+        skip_if_not_root!();
+
+        let sysfs_path = std::env::temp_dir().to_str().unwrap().to_string();
+        let tests = &mut [
+            // invalid sysfs path
+            TestData::new(
+                VmmAction::InsertHostDevice(HostDeviceConfig {
+                    hostdev_id: String::from("hostdev0"),
+                    sysfs_path: String::from("/sys/bus/pci/devices/ffff:ff:1f.7"),
+                    dev_config: VfioPciDeviceConfig {
+                        bus_slot_func: String::from("ffff:ff:1f.7"),
+                        guest_dev_id: None,
+                    },
+                }),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::HostDevice(
+                            VfioDeviceError::InvalidSysfsPath(_)
+                        ))
+                    ));
+                    let err_string = format!("{}", result.unwrap_err());
+                    let expected_err = String::from(
+                        "host device error: \
+                    invalid host device sysfs path /sys/bus/pci/devices/ffff:ff:1f.7",
+                    );
+                    assert_eq!(err_string, expected_err);
+                },
+            ),
+            // success, the device is opened when the microVM boots
+            TestData::new(
+                VmmAction::InsertHostDevice(HostDeviceConfig {
+                    hostdev_id: String::from("hostdev0"),
+                    sysfs_path: sysfs_path.clone(),
+                    dev_config: VfioPciDeviceConfig {
+                        bus_slot_func: String::from("0000:01:00.0"),
+                        guest_dev_id: Some(2),
+                    },
+                }),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(result.is_ok());
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[cfg(feature = "host-device")]
+    #[test]
+    fn test_vmm_action_remove_host_device() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // hotplug unready
+            TestData::new(
+                VmmAction::RemoveHostDevice(String::from("hostdev0")),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::StartMicroVm(
+                            StartMicroVmError::UpcallMissVsock
+                        ))
+                    ));
+                },
+            ),
+            // invalid id
+            TestData::new(
+                VmmAction::RemoveHostDevice(String::from("hostdev0")),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::HostDevice(
+                            VfioDeviceError::InvalidDeviceId(_)
+                        ))
+                    ));
+                    let err_string = format!("{}", result.unwrap_err());
+                    let expected_err =
+                        String::from("host device error: invalid host device id 'hostdev0'");
+                    assert_eq!(err_string, expected_err);
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
     #[cfg(feature = "virtio-vsock")]
     #[test]
     fn test_vmm_action_insert_vsock_device() {
@@ -1452,4 +2416,61 @@ mod tests {
             t.check_request();
         }
     }
+
+    #[cfg(feature = "virtio-vsock")]
+    #[test]
+    fn test_vmm_action_remove_vsock_device() {
+        skip_if_not_root!();
+
+        let tests = &mut [
+            // invalid state
+            TestData::new(
+                VmmAction::RemoveVsockDevice(String::from("root")),
+                InstanceState::Running,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::Vsock(
+                            VsockDeviceError::UpdateNotAllowedPostBoot
+                        ))
+                    ));
+                },
+            ),
+            // invalid id
+            TestData::new(
+                VmmAction::RemoveVsockDevice(String::from("root")),
+                InstanceState::Uninitialized,
+                &|result| {
+                    assert!(matches!(
+                        result,
+                        Err(VmmActionError::Vsock(VsockDeviceError::VsockIdNotExists(_)))
+                    ));
+                },
+            ),
+        ];
+
+        for t in tests.iter_mut() {
+            t.check_request();
+        }
+    }
+
+    #[test]
+    fn test_vmm_data_wait_hotunplug() {
+        let timeout = Duration::from_millis(10);
+        assert!(VmmData::Empty.wait_hotunplug(timeout).is_ok());
+
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let data = VmmData::Hotunplug(receiver);
+        sender.send(true).unwrap();
+        assert!(data.wait_hotunplug(timeout).is_ok());
+        sender.send(false).unwrap();
+        assert!(matches!(
+            data.wait_hotunplug(timeout),
+            Err(VmmActionError::HotunplugFailed)
+        ));
+        assert!(matches!(
+            data.wait_hotunplug(timeout),
+            Err(VmmActionError::HotunplugTimeout)
+        ));
+    }
 }
@@ -0,0 +1,158 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Standalone Dragonball VMM, driven through the HTTP API served on a unix socket.
+//!
+//! Usage: `dragonball-vmm --api-sock <path> [--id <instance id>] [--log-level <level>]
+//! [--seccomp-filter <path> | --no-seccomp]`
+
+use std::process;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+
+use crossbeam_channel::unbounded;
+use dragonball::api::http_server::ApiServer;
+use dragonball::api::v1::{InstanceInfo, VmmService};
+use dragonball::seccomp::SeccompFilters;
+use dragonball::signal_handler::register_signal_handlers;
+use dragonball::{Vmm, EXIT_CODE_ARG_PARSING, EXIT_CODE_GENERIC_ERROR};
+use vmm_sys_util::eventfd::EventFd;
+
+const DRAGONBALL_VERSION: &str = env!("CARGO_PKG_VERSION");
+const DEFAULT_INSTANCE_ID: &str = "dragonball";
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+struct Args {
+    api_sock: String,
+    id: String,
+    log_level: slog::Level,
+    seccomp_filter: Option<String>,
+    no_seccomp: bool,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: dragonball-vmm --api-sock <path> [--id <instance id>] [--log-level <level>] \
+         [--seccomp-filter <path> | --no-seccomp]"
+    );
+    process::exit(EXIT_CODE_ARG_PARSING as i32);
+}
+
+fn parse_args() -> Args {
+    let mut api_sock = None;
+    let mut id = DEFAULT_INSTANCE_ID.to_string();
+    let mut log_level = DEFAULT_LOG_LEVEL.to_string();
+    let mut seccomp_filter = None;
+    let mut no_seccomp = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--api-sock" => api_sock = Some(args.next().unwrap_or_else(|| usage())),
+            "--id" => id = args.next().unwrap_or_else(|| usage()),
+            "--log-level" => log_level = args.next().unwrap_or_else(|| usage()),
+            "--seccomp-filter" => seccomp_filter = Some(args.next().unwrap_or_else(|| usage())),
+            "--no-seccomp" => no_seccomp = true,
+            "--version" => {
+                println!("dragonball-vmm {}", DRAGONBALL_VERSION);
+                process::exit(0);
+            }
+            _ => usage(),
+        }
+    }
+
+    if no_seccomp && seccomp_filter.is_some() {
+        usage();
+    }
+
+    let log_level = logging::level_name_to_slog_level(&log_level).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        usage();
+    });
+
+    Args {
+        api_sock: api_sock.unwrap_or_else(|| usage()),
+        id,
+        log_level,
+        seccomp_filter,
+        no_seccomp,
+    }
+}
+
+fn seccomp_filters(args: &Args) -> SeccompFilters {
+    if args.no_seccomp {
+        return SeccompFilters::default();
+    }
+
+    let filters = match &args.seccomp_filter {
+        Some(path) => SeccompFilters::from_json_file(path),
+        None => SeccompFilters::new_default(),
+    };
+    filters.unwrap_or_else(|e| {
+        eprintln!("failed to load the seccomp filters: {}", e);
+        process::exit(EXIT_CODE_ARG_PARSING as i32);
+    })
+}
+
+fn main() {
+    let args = parse_args();
+
+    // Log to stderr, for both the slog and the log records of the VMM.
+    let (logger, _log_guard) = logging::create_logger(
+        "dragonball-vmm",
+        &args.id,
+        args.log_level,
+        std::io::stderr(),
+    );
+    slog_scope::set_global_logger(logger).cancel_reset();
+    if let Err(e) = slog_stdlog::init() {
+        eprintln!("failed to initialize the logger: {}", e);
+        process::exit(EXIT_CODE_GENERIC_ERROR as i32);
+    }
+
+    let seccomp_filters = seccomp_filters(&args);
+
+    if let Err(e) = register_signal_handlers() {
+        eprintln!("failed to register signal handlers: {}", e);
+        process::exit(EXIT_CODE_GENERIC_ERROR as i32);
+    }
+
+    let shared_info = Arc::new(RwLock::new(InstanceInfo::new(
+        args.id,
+        DRAGONBALL_VERSION.to_string(),
+    )));
+    let (to_vmm, from_api) = unbounded();
+    let (to_api, from_vmm) = unbounded();
+    let api_event_fd =
+        EventFd::new(libc::EFD_NONBLOCK).expect("Failed to create eventfd for the API server");
+
+    let vmm = match Vmm::new(
+        shared_info.clone(),
+        api_event_fd.try_clone().expect("Failed to dup eventfd"),
+        seccomp_filters.vmm,
+        seccomp_filters.vcpu,
+        None,
+    ) {
+        Ok(vmm) => vmm,
+        Err(e) => {
+            eprintln!("failed to create the VMM: {}", e);
+            process::exit(EXIT_CODE_GENERIC_ERROR as i32);
+        }
+    };
+
+    let mut api_server = ApiServer::new(shared_info, to_vmm, from_vmm, api_event_fd);
+    let api_sock = args.api_sock;
+    thread::Builder::new()
+        .name("api_server".to_owned())
+        .spawn(move || {
+            if let Err(e) = api_server.run(&api_sock) {
+                eprintln!("API server exited: {}", e);
+                process::exit(EXIT_CODE_GENERIC_ERROR as i32);
+            }
+        })
+        .expect("Failed to start the API server");
+
+    let exit_code =
+        Vmm::run_vmm_event_loop(Arc::new(Mutex::new(vmm)), VmmService::new(from_api, to_api));
+    process::exit(exit_code);
+}
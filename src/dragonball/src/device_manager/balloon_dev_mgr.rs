@@ -0,0 +1,473 @@
+// Copyright 2020-2022 Alibaba Cloud. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use dbs_virtio_devices as virtio;
+use dbs_virtio_devices::balloon::{Balloon, BalloonConfig};
+use dbs_virtio_devices::Error as VirtioError;
+use serde_derive::{Deserialize, Serialize};
+use slog::info;
+
+use crate::config_manager::{ConfigItem, DeviceConfigInfo, DeviceConfigInfos};
+use crate::device_manager::balloon_stats::StatsBalloon;
+use crate::device_manager::{DbsMmioV2Device, DeviceManager, DeviceMgrError, DeviceOpContext};
+
+// The flag of whether to use the shared irq.
+const USE_SHARED_IRQ: bool = true;
+// The flag of whether to use the generic irq.
+const USE_GENERIC_IRQ: bool = false;
+// Offset of the `actual` field, the number of pages the guest gave back, in the
+// virtio-balloon configuration space.
+const BALLOON_CONFIG_ACTUAL_OFFSET: u64 = 4;
+// Balloon pages are always 4KiB, whatever the guest page size.
+const BALLOON_PAGES_PER_MIB: u64 = 256;
+
+/// Errors associated with virtio-balloon device operations.
+#[derive(Debug, thiserror::Error)]
+pub enum BalloonDeviceError {
+    /// The balloon device ID is invalid.
+    #[error("invalid balloon device id '{0}'")]
+    InvalidDeviceId(String),
+
+    /// The balloon device ID is already in use.
+    #[error("the balloon device id {0} already exists")]
+    DeviceIdAlreadyExist(String),
+
+    /// Only one balloon device is allowed per virtual machine.
+    #[error("a balloon device already exists")]
+    BalloonDeviceAlreadyExists,
+
+    /// The balloon device hasn't been created yet.
+    #[error("the balloon device {0} hasn't been created")]
+    BalloonDeviceNotCreated(String),
+
+    /// The update is not allowed after booting the microvm.
+    #[error("update operation is not allowed after boot")]
+    UpdateNotAllowedPostBoot,
+
+    /// Cannot create the virtio-balloon device.
+    #[error("cannot create virtio-balloon device: {0}")]
+    CreateBalloonDevice(#[source] VirtioError),
+
+    /// Cannot initialize a MMIO balloon device or add a device to the MMIO Bus.
+    #[error("failure while registering balloon device: {0}")]
+    RegisterBalloonDevice(#[source] DeviceMgrError),
+
+    /// Failed to hotplug the balloon device.
+    #[error("failure while hotplugging balloon device: {0}")]
+    HotplugDeviceFailed(#[source] DeviceMgrError),
+
+    /// Failed to change the balloon size.
+    #[error("failed to set the balloon size to {0}MiB: {1}")]
+    SetBalloonSize(u64, #[source] VirtioError),
+}
+
+/// Configuration information for a virtio-balloon device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+pub struct BalloonDeviceConfigInfo {
+    /// Unique identifier of the balloon device.
+    pub balloon_id: String,
+    /// Target size of the balloon, in MiB.
+    pub size_mib: u64,
+    /// Use shared irq
+    pub use_shared_irq: Option<bool>,
+    /// Use generic irq
+    pub use_generic_irq: Option<bool>,
+    /// Deflate the balloon when the guest runs out of memory.
+    pub f_deflate_on_oom: bool,
+    /// Let the guest report its free pages, so that they are returned to the host.
+    pub f_reporting: bool,
+    /// Interval in seconds to refresh the memory statistics of the guest, 0 to disable the
+    /// statistics queue.
+    #[serde(default)]
+    pub stats_polling_interval_s: u32,
+}
+
+impl ConfigItem for BalloonDeviceConfigInfo {
+    type Err = BalloonDeviceError;
+
+    fn id(&self) -> &str {
+        &self.balloon_id
+    }
+
+    fn check_conflicts(&self, other: &Self) -> Result<(), BalloonDeviceError> {
+        if self.balloon_id == other.balloon_id {
+            Err(BalloonDeviceError::DeviceIdAlreadyExist(
+                self.balloon_id.clone(),
+            ))
+        } else {
+            Err(BalloonDeviceError::BalloonDeviceAlreadyExists)
+        }
+    }
+}
+
+/// Target size update for a virtio-balloon device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BalloonDeviceConfigUpdateInfo {
+    /// Unique identifier of the balloon device.
+    pub balloon_id: String,
+    /// New target size of the balloon, in MiB.
+    pub new_size_mib: u64,
+}
+
+/// Statistics of a virtio-balloon device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+pub struct BalloonStatsInfo {
+    /// Unique identifier of the balloon device.
+    pub balloon_id: String,
+    /// Target size of the balloon, in MiB.
+    pub target_size_mib: u64,
+    /// Memory the guest has given to the balloon so far, in MiB.
+    pub actual_size_mib: u64,
+    /// Whether the guest reports its free pages.
+    pub free_page_reporting: bool,
+    /// Latest memory statistics reported by the guest through the statistics queue.
+    pub guest_stats: BalloonGuestStats,
+}
+
+/// Memory statistics reported by the guest through the statistics queue of the balloon, as
+/// defined by the virtio specification. Sizes are in bytes, the statistics the guest doesn't
+/// support are `None`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+pub struct BalloonGuestStats {
+    /// Memory swapped in.
+    pub swap_in: Option<u64>,
+    /// Memory swapped out.
+    pub swap_out: Option<u64>,
+    /// Number of major page faults.
+    pub major_faults: Option<u64>,
+    /// Number of minor page faults.
+    pub minor_faults: Option<u64>,
+    /// Memory not used at all.
+    pub free_memory: Option<u64>,
+    /// Total memory available to the guest.
+    pub total_memory: Option<u64>,
+    /// Estimation of the memory available to start new applications.
+    pub available_memory: Option<u64>,
+    /// Memory used by the disk caches, which can be reclaimed.
+    pub disk_caches: Option<u64>,
+    /// Number of successful hugetlb page allocations.
+    pub hugetlb_allocations: Option<u64>,
+    /// Number of failed hugetlb page allocations.
+    pub hugetlb_failures: Option<u64>,
+}
+
+/// Balloon Device Info
+pub type BalloonDeviceInfo = DeviceConfigInfo<BalloonDeviceConfigInfo>;
+
+/// Device manager to manage the virtio-balloon device.
+pub struct BalloonDeviceMgr {
+    pub(crate) info_list: DeviceConfigInfos<BalloonDeviceConfigInfo>,
+    pub(crate) use_shared_irq: bool,
+}
+
+impl BalloonDeviceMgr {
+    /// Gets the index of the device with the specified `balloon_id` if it exists in the list.
+    pub fn get_index_of_balloon_id(&self, balloon_id: &str) -> Option<usize> {
+        self.info_list
+            .iter()
+            .position(|info| info.config.balloon_id.eq(balloon_id))
+    }
+
+    /// Insert a virtio-balloon device into the manager, attaching it if the microvm is running.
+    pub fn insert_device(
+        device_mgr: &mut DeviceManager,
+        mut ctx: DeviceOpContext,
+        config: BalloonDeviceConfigInfo,
+    ) -> std::result::Result<(), BalloonDeviceError> {
+        if !cfg!(feature = "hotplug") && ctx.is_hotplug {
+            return Err(BalloonDeviceError::UpdateNotAllowedPostBoot);
+        }
+
+        let mgr = &mut device_mgr.balloon_manager;
+
+        info!(
+            ctx.logger(),
+            "add virtio-balloon device configuration";
+            "subsystem" => "balloon_dev_mgr",
+            "id" => &config.balloon_id,
+            "size_mib" => config.size_mib,
+            "f_reporting" => config.f_reporting,
+        );
+
+        // The configuration of a created balloon device can't be updated, and inserting it again
+        // would create a second balloon device.
+        if let Some(index) = mgr.get_index_of_balloon_id(&config.balloon_id) {
+            if mgr.info_list[index].device.is_some() {
+                return Err(BalloonDeviceError::BalloonDeviceAlreadyExists);
+            }
+        }
+        let device_index = mgr.info_list.insert_or_update(&config)?;
+
+        if ctx.is_hotplug {
+            let dev = match Self::create_device(&config, &mut ctx) {
+                Ok(device) => DeviceManager::create_mmio_virtio_device(
+                    device,
+                    &mut ctx,
+                    config.use_shared_irq.unwrap_or(mgr.use_shared_irq),
+                    config.use_generic_irq.unwrap_or(USE_GENERIC_IRQ),
+                )
+                .map_err(BalloonDeviceError::RegisterBalloonDevice),
+                Err(e) => Err(BalloonDeviceError::CreateBalloonDevice(e)),
+            };
+            let dev = match dev {
+                Ok(dev) => dev,
+                Err(e) => {
+                    mgr.info_list.remove(device_index);
+                    return Err(e);
+                }
+            };
+            ctx.insert_hotplug_mmio_device(&dev, None)
+                .map_err(BalloonDeviceError::HotplugDeviceFailed)?;
+            mgr.info_list[device_index].set_device(dev);
+        }
+
+        Ok(())
+    }
+
+    /// Attach the configured balloon device to the virtual machine instance.
+    pub fn attach_devices(
+        &mut self,
+        ctx: &mut DeviceOpContext,
+    ) -> std::result::Result<(), BalloonDeviceError> {
+        for info in self.info_list.iter_mut() {
+            info!(
+                ctx.logger(),
+                "attach virtio-balloon device";
+                "subsystem" => "balloon_dev_mgr",
+                "id" => &info.config.balloon_id,
+                "size_mib" => info.config.size_mib,
+            );
+
+            let device = Self::create_device(&info.config, ctx)
+                .map_err(BalloonDeviceError::CreateBalloonDevice)?;
+            let device = DeviceManager::create_mmio_virtio_device(
+                device,
+                ctx,
+                info.config.use_shared_irq.unwrap_or(self.use_shared_irq),
+                info.config.use_generic_irq.unwrap_or(USE_GENERIC_IRQ),
+            )
+            .map_err(BalloonDeviceError::RegisterBalloonDevice)?;
+            info.set_device(device);
+        }
+
+        Ok(())
+    }
+
+    /// Change the target size of the balloon, the guest inflates or deflates the balloon
+    /// accordingly.
+    pub fn update_balloon_size(
+        device_mgr: &mut DeviceManager,
+        new_cfg: BalloonDeviceConfigUpdateInfo,
+    ) -> std::result::Result<(), BalloonDeviceError> {
+        let mgr = &mut device_mgr.balloon_manager;
+        let index = mgr
+            .get_index_of_balloon_id(&new_cfg.balloon_id)
+            .ok_or_else(|| BalloonDeviceError::InvalidDeviceId(new_cfg.balloon_id.clone()))?;
+
+        let info = &mut mgr.info_list[index];
+        if let Some(device) = info.device.as_ref() {
+            if let Some(mmio_dev) = device.as_any().downcast_ref::<DbsMmioV2Device>() {
+                let guard = mmio_dev.state();
+                let inner_dev = guard.get_inner_device();
+                if let Some(balloon_dev) = inner_dev.as_any().downcast_ref::<StatsBalloon>() {
+                    balloon_dev
+                        .balloon()
+                        .set_size(new_cfg.new_size_mib)
+                        .map_err(|e| BalloonDeviceError::SetBalloonSize(new_cfg.new_size_mib, e))?;
+                }
+            }
+        }
+        // Before boot, the new size is applied once the device is created.
+        info.config.size_mib = new_cfg.new_size_mib;
+
+        Ok(())
+    }
+
+    /// Get the target and actual size of the balloon, and the memory statistics of the guest.
+    pub fn get_balloon_stats(
+        device_mgr: &DeviceManager,
+        balloon_id: &str,
+    ) -> std::result::Result<BalloonStatsInfo, BalloonDeviceError> {
+        let mgr = &device_mgr.balloon_manager;
+        let index = mgr
+            .get_index_of_balloon_id(balloon_id)
+            .ok_or_else(|| BalloonDeviceError::InvalidDeviceId(balloon_id.to_string()))?;
+        let info = &mgr.info_list[index];
+        let device = info
+            .device
+            .as_ref()
+            .ok_or_else(|| BalloonDeviceError::BalloonDeviceNotCreated(balloon_id.to_string()))?;
+
+        let mut actual_pages = [0u8; 4];
+        let mut guest_stats = BalloonGuestStats::default();
+        if let Some(mmio_dev) = device.as_any().downcast_ref::<DbsMmioV2Device>() {
+            let mut guard = mmio_dev.state();
+            let inner_dev = guard.get_inner_device_mut();
+            // The configuration space of the balloon is always large enough.
+            let _ = inner_dev.read_config(BALLOON_CONFIG_ACTUAL_OFFSET, &mut actual_pages);
+            if let Some(balloon_dev) = inner_dev.as_any().downcast_ref::<StatsBalloon>() {
+                guest_stats = balloon_dev.guest_stats();
+            }
+        }
+
+        Ok(BalloonStatsInfo {
+            balloon_id: balloon_id.to_string(),
+            target_size_mib: info.config.size_mib,
+            actual_size_mib: u32::from_le_bytes(actual_pages) as u64 / BALLOON_PAGES_PER_MIB,
+            free_page_reporting: info.config.f_reporting,
+            guest_stats,
+        })
+    }
+
+    fn create_device(
+        cfg: &BalloonDeviceConfigInfo,
+        ctx: &mut DeviceOpContext,
+    ) -> std::result::Result<Box<StatsBalloon>, virtio::Error> {
+        let epoll_mgr = ctx.epoll_mgr.clone().ok_or(virtio::Error::InvalidInput)?;
+        let balloon_device = Balloon::new(
+            epoll_mgr.clone(),
+            BalloonConfig {
+                f_deflate_on_oom: cfg.f_deflate_on_oom,
+                f_reporting: cfg.f_reporting,
+            },
+        )?;
+        if cfg.size_mib > 0 {
+            balloon_device.set_size(cfg.size_mib)?;
+        }
+
+        let logger = ctx.logger().new(slog::o!("subsystem" => "balloon_dev_mgr"));
+
+        Ok(Box::new(StatsBalloon::new(
+            Box::new(balloon_device),
+            epoll_mgr,
+            logger,
+            cfg.stats_polling_interval_s,
+        )))
+    }
+}
+
+impl Default for BalloonDeviceMgr {
+    /// Create a new virtio-balloon device manager.
+    fn default() -> Self {
+        BalloonDeviceMgr {
+            info_list: DeviceConfigInfos::new(),
+            use_shared_irq: USE_SHARED_IRQ,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::sync::Arc;
+
+    use dbs_device::DeviceIo;
+    use test_utils::skip_if_not_root;
+
+    use super::*;
+    use crate::test_utils::tests::create_vm_for_test;
+
+    #[test]
+    fn test_balloon_config_check_conflicts() {
+        let config = BalloonDeviceConfigInfo {
+            balloon_id: "balloon0".to_string(),
+            ..Default::default()
+        };
+        let same_id = config.clone();
+        let other_id = BalloonDeviceConfigInfo {
+            balloon_id: "balloon1".to_string(),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            config.check_conflicts(&same_id),
+            Err(BalloonDeviceError::DeviceIdAlreadyExist(_))
+        ));
+        assert!(matches!(
+            config.check_conflicts(&other_id),
+            Err(BalloonDeviceError::BalloonDeviceAlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn test_balloon_insert_and_update_before_boot() {
+        skip_if_not_root!();
+
+        let mut vm = create_vm_for_test();
+        let config = BalloonDeviceConfigInfo {
+            balloon_id: "balloon0".to_string(),
+            size_mib: 0,
+            f_reporting: true,
+            ..Default::default()
+        };
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        BalloonDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config).unwrap();
+
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        let second = BalloonDeviceConfigInfo {
+            balloon_id: "balloon1".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            BalloonDeviceMgr::insert_device(vm.device_manager_mut(), ctx, second),
+            Err(BalloonDeviceError::BalloonDeviceAlreadyExists)
+        ));
+
+        BalloonDeviceMgr::update_balloon_size(
+            vm.device_manager_mut(),
+            BalloonDeviceConfigUpdateInfo {
+                balloon_id: "balloon0".to_string(),
+                new_size_mib: 128,
+            },
+        )
+        .unwrap();
+        let mgr = &vm.device_manager().balloon_manager;
+        assert_eq!(mgr.info_list[0].config.size_mib, 128);
+
+        assert!(matches!(
+            BalloonDeviceMgr::get_balloon_stats(vm.device_manager(), "balloon0"),
+            Err(BalloonDeviceError::BalloonDeviceNotCreated(_))
+        ));
+        assert!(matches!(
+            BalloonDeviceMgr::update_balloon_size(
+                vm.device_manager_mut(),
+                BalloonDeviceConfigUpdateInfo {
+                    balloon_id: "balloon1".to_string(),
+                    new_size_mib: 128,
+                },
+            ),
+            Err(BalloonDeviceError::InvalidDeviceId(_))
+        ));
+    }
+
+    struct DummyDevice;
+
+    impl DeviceIo for DummyDevice {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_balloon_insert_after_creation() {
+        skip_if_not_root!();
+
+        let mut vm = create_vm_for_test();
+        let config = BalloonDeviceConfigInfo {
+            balloon_id: "balloon0".to_string(),
+            ..Default::default()
+        };
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        BalloonDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config.clone()).unwrap();
+        vm.device_manager_mut().balloon_manager.info_list[0].set_device(Arc::new(DummyDevice));
+
+        // The same balloon can't be inserted again once its device is created.
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        assert!(matches!(
+            BalloonDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config),
+            Err(BalloonDeviceError::BalloonDeviceAlreadyExists)
+        ));
+        assert_eq!(vm.device_manager().balloon_manager.info_list.len(), 1);
+    }
+}
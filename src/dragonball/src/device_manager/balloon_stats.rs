@@ -0,0 +1,384 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Statistics virtqueue of the virtio-balloon device.
+//!
+//! The virtio-balloon device of `dbs-virtio-devices` only has the inflate, deflate and free page
+//! reporting queues, so it is wrapped to add the statistics queue. The guest driver keeps a single
+//! buffer in that queue, which it fills with its memory statistics each time the device gives the
+//! buffer back. The device gives the buffer back periodically to refresh the statistics.
+
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dbs_device::resources::{DeviceResources, ResourceConstraint};
+use dbs_utils::epoll_manager::{
+    EpollManager, EventOps, EventSet, Events, MutEventSubscriber, SubscriberId,
+};
+use dbs_virtio_devices::balloon::Balloon;
+use dbs_virtio_devices::{
+    ActivateError, ActivateResult, ConfigResult, Result as VirtioResult, VirtioDevice,
+    VirtioDeviceConfig, VirtioQueueConfig, VirtioSharedMemoryList,
+};
+use kvm_ioctls::VmFd;
+use virtio_queue::QueueSync;
+use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestRegionMmap};
+use vmm_sys_util::timerfd::TimerFd;
+
+use super::balloon_dev_mgr::BalloonGuestStats;
+use crate::address_space_manager::GuestAddressSpaceImpl;
+
+// Feature bit of the statistics queue.
+const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1;
+// The statistics queue follows the inflate and deflate queues.
+const STATS_QUEUE_INDEX: usize = 2;
+// The guest driver only queues one statistics buffer.
+const STATS_QUEUE_SIZE: u16 = 2;
+// A statistics entry is a little endian u16 tag followed by a u64 value, packed.
+const STATS_ENTRY_SIZE: u64 = 10;
+const STATS_QUEUE_EVENT: u32 = 0;
+const STATS_TIMER_EVENT: u32 = 1;
+
+impl BalloonGuestStats {
+    fn update(&mut self, tag: u16, value: u64) {
+        let field = match tag {
+            0 => &mut self.swap_in,
+            1 => &mut self.swap_out,
+            2 => &mut self.major_faults,
+            3 => &mut self.minor_faults,
+            4 => &mut self.free_memory,
+            5 => &mut self.total_memory,
+            6 => &mut self.available_memory,
+            7 => &mut self.disk_caches,
+            8 => &mut self.hugetlb_allocations,
+            9 => &mut self.hugetlb_failures,
+            _ => return,
+        };
+        *field = Some(value);
+    }
+}
+
+/// Virtio-balloon device with a statistics queue.
+pub(crate) struct StatsBalloon {
+    balloon: Box<Balloon<GuestAddressSpaceImpl>>,
+    epoll_mgr: EpollManager,
+    logger: slog::Logger,
+    // Interval to refresh the statistics, the statistics queue is disabled if it's `None`.
+    polling_interval: Option<Duration>,
+    queue_sizes: Vec<u16>,
+    stats_acked: bool,
+    stats: Arc<Mutex<BalloonGuestStats>>,
+    subscriber_id: Option<SubscriberId>,
+}
+
+impl StatsBalloon {
+    /// Add a statistics queue refreshed every `polling_interval_s` seconds to the balloon device,
+    /// or none if `polling_interval_s` is 0.
+    pub fn new(
+        balloon: Box<Balloon<GuestAddressSpaceImpl>>,
+        epoll_mgr: EpollManager,
+        logger: slog::Logger,
+        polling_interval_s: u32,
+    ) -> Self {
+        let polling_interval = if polling_interval_s > 0 {
+            Some(Duration::from_secs(polling_interval_s as u64))
+        } else {
+            None
+        };
+        let mut queue_sizes = balloon.queue_max_sizes().to_vec();
+        if polling_interval.is_some() {
+            queue_sizes.insert(STATS_QUEUE_INDEX, STATS_QUEUE_SIZE);
+        }
+
+        StatsBalloon {
+            balloon,
+            epoll_mgr,
+            logger,
+            polling_interval,
+            queue_sizes,
+            stats_acked: false,
+            stats: Arc::new(Mutex::new(BalloonGuestStats::default())),
+            subscriber_id: None,
+        }
+    }
+
+    /// Get the wrapped balloon device.
+    pub fn balloon(&self) -> &Balloon<GuestAddressSpaceImpl> {
+        &self.balloon
+    }
+
+    /// Get the latest memory statistics reported by the guest.
+    pub fn guest_stats(&self) -> BalloonGuestStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    fn remove_stats_handler(&mut self) {
+        if let Some(id) = self.subscriber_id.take() {
+            if let Err(e) = self.epoll_mgr.remove_subscriber(id) {
+                slog::warn!(
+                    self.logger,
+                    "failed to remove balloon statistics handler, {:?}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+impl VirtioDevice<GuestAddressSpaceImpl, QueueSync, GuestRegionMmap> for StatsBalloon {
+    fn device_type(&self) -> u32 {
+        self.balloon.device_type()
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.queue_sizes
+    }
+
+    fn get_avail_features(&self, page: u32) -> u32 {
+        let mut features = self.balloon.get_avail_features(page);
+        if page == 0 && self.polling_interval.is_some() {
+            features |= 1 << VIRTIO_BALLOON_F_STATS_VQ;
+        }
+        features
+    }
+
+    fn set_acked_features(&mut self, page: u32, value: u32) {
+        let mut value = value;
+        if page == 0 && self.polling_interval.is_some() {
+            self.stats_acked = value & (1 << VIRTIO_BALLOON_F_STATS_VQ) != 0;
+            value &= !(1 << VIRTIO_BALLOON_F_STATS_VQ);
+        }
+        self.balloon.set_acked_features(page, value)
+    }
+
+    fn read_config(&mut self, offset: u64, data: &mut [u8]) -> ConfigResult {
+        self.balloon.read_config(offset, data)
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> ConfigResult {
+        self.balloon.write_config(offset, data)
+    }
+
+    fn activate(
+        &mut self,
+        mut config: VirtioDeviceConfig<GuestAddressSpaceImpl, QueueSync, GuestRegionMmap>,
+    ) -> ActivateResult {
+        if let (true, Some(interval)) = (self.stats_acked, self.polling_interval) {
+            if config.queues.len() <= STATS_QUEUE_INDEX {
+                return Err(ActivateError::InvalidParam);
+            }
+            let timer = TimerFd::new().map_err(|_| ActivateError::InternalError)?;
+            let handler = StatsHandler {
+                queue: config.queues.remove(STATS_QUEUE_INDEX),
+                vm_as: config.vm_as.clone(),
+                timer,
+                interval,
+                stats: self.stats.clone(),
+                stats_desc: None,
+                logger: self.logger.clone(),
+            };
+            self.subscriber_id = Some(self.epoll_mgr.add_subscriber(Box::new(handler)));
+        }
+        self.balloon.activate(config)
+    }
+
+    fn reset(&mut self) -> ActivateResult {
+        self.remove_stats_handler();
+        self.balloon.reset()
+    }
+
+    fn remove(&mut self) {
+        self.remove_stats_handler();
+        self.balloon.remove()
+    }
+
+    fn get_resource_requirements(
+        &self,
+        requests: &mut Vec<ResourceConstraint>,
+        use_generic_irq: bool,
+    ) {
+        self.balloon
+            .get_resource_requirements(requests, use_generic_irq)
+    }
+
+    fn set_resource(
+        &mut self,
+        vm_fd: Arc<VmFd>,
+        resource: DeviceResources,
+    ) -> VirtioResult<Option<VirtioSharedMemoryList<GuestRegionMmap>>> {
+        self.balloon.set_resource(vm_fd, resource)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Handler of the statistics queue, running on the epoll thread.
+struct StatsHandler {
+    queue: VirtioQueueConfig<QueueSync>,
+    vm_as: GuestAddressSpaceImpl,
+    timer: TimerFd,
+    interval: Duration,
+    stats: Arc<Mutex<BalloonGuestStats>>,
+    // Head of the statistics buffer held by the device.
+    stats_desc: Option<u16>,
+    logger: slog::Logger,
+}
+
+impl StatsHandler {
+    // Read the statistics from the buffer queued by the guest, and keep the buffer until the
+    // next refresh.
+    fn process_queue(&mut self) {
+        if let Err(e) = self.queue.consume_event() {
+            slog::error!(
+                self.logger,
+                "failed to read balloon statistics queue event, {:?}",
+                e
+            );
+            return;
+        }
+        let mem = self.vm_as.memory();
+        loop {
+            let chain = match self.queue.get_next_descriptor(mem.clone()) {
+                Ok(Some(chain)) => chain,
+                Ok(None) => break,
+                Err(e) => {
+                    slog::error!(
+                        self.logger,
+                        "invalid balloon statistics descriptor, {:?}",
+                        e
+                    );
+                    break;
+                }
+            };
+            // The guest is not supposed to queue another buffer before getting back the previous
+            // one, give the previous one back anyway.
+            if let Some(head) = self.stats_desc.take() {
+                self.queue.add_used(&*mem, head, 0);
+            }
+            self.stats_desc = Some(chain.head_index());
+            if let Some(desc) = chain.clone().next() {
+                let stats = read_stats(&*mem, desc.addr(), desc.len());
+                *self.stats.lock().unwrap() = stats;
+            }
+        }
+    }
+
+    // Give the statistics buffer back for the guest to fill it with fresh statistics.
+    fn refresh_stats(&mut self) {
+        if let Err(e) = self.timer.wait() {
+            slog::error!(
+                self.logger,
+                "failed to read balloon statistics timer, {:?}",
+                e
+            );
+            return;
+        }
+        if let Some(head) = self.stats_desc.take() {
+            let mem = self.vm_as.memory();
+            self.queue.add_used(&*mem, head, 0);
+            if let Err(e) = self.queue.notify() {
+                slog::error!(
+                    self.logger,
+                    "failed to notify balloon statistics queue, {:?}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+impl MutEventSubscriber for StatsHandler {
+    fn process(&mut self, events: Events, _ops: &mut EventOps) {
+        match events.data() {
+            STATS_QUEUE_EVENT => self.process_queue(),
+            STATS_TIMER_EVENT => self.refresh_stats(),
+            slot => slog::error!(self.logger, "unknown epoll slot number {}", slot),
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        let events =
+            Events::with_data(self.queue.eventfd.as_ref(), STATS_QUEUE_EVENT, EventSet::IN);
+        if let Err(e) = ops.add(events) {
+            slog::error!(
+                self.logger,
+                "failed to register balloon statistics queue event, {:?}",
+                e
+            );
+        }
+        if let Err(e) = self.timer.reset(self.interval, Some(self.interval)) {
+            slog::error!(
+                self.logger,
+                "failed to arm balloon statistics timer, {:?}",
+                e
+            );
+            return;
+        }
+        let events = Events::with_data(&self.timer, STATS_TIMER_EVENT, EventSet::IN);
+        if let Err(e) = ops.add(events) {
+            slog::error!(
+                self.logger,
+                "failed to register balloon statistics timer, {:?}",
+                e
+            );
+        }
+    }
+}
+
+// Read the statistics entries from a buffer of the statistics queue, unknown tags are skipped.
+fn read_stats<M: GuestMemory>(mem: &M, addr: GuestAddress, len: u32) -> BalloonGuestStats {
+    let mut stats = BalloonGuestStats::default();
+    let mut offset = 0;
+    while offset + STATS_ENTRY_SIZE <= len as u64 {
+        let entry = addr.unchecked_add(offset);
+        let tag: Result<u16, _> = mem.read_obj(entry);
+        let value: Result<u64, _> = mem.read_obj(entry.unchecked_add(2));
+        match (tag, value) {
+            (Ok(tag), Ok(value)) => {
+                stats.update(u16::from_le(tag), u64::from_le(value));
+            }
+            _ => break,
+        }
+        offset += STATS_ENTRY_SIZE;
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_memory::GuestMemoryMmap;
+
+    use super::*;
+
+    #[test]
+    fn test_balloon_read_stats() {
+        let mem: GuestMemoryMmap =
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let addr = GuestAddress(0x100);
+        // free memory, an unknown tag and total memory.
+        let entries: &[(u16, u64)] = &[(4, 0x1000), (0xff, 1), (5, 0x4000)];
+        for (i, (tag, value)) in entries.iter().enumerate() {
+            let entry = addr.unchecked_add(i as u64 * STATS_ENTRY_SIZE);
+            mem.write_obj(tag.to_le(), entry).unwrap();
+            mem.write_obj(value.to_le(), entry.unchecked_add(2))
+                .unwrap();
+        }
+
+        let stats = read_stats(&mem, addr, 3 * STATS_ENTRY_SIZE as u32);
+        assert_eq!(stats.free_memory, Some(0x1000));
+        assert_eq!(stats.total_memory, Some(0x4000));
+        assert_eq!(stats.available_memory, None);
+
+        // A truncated entry is ignored.
+        let stats = read_stats(&mem, addr, STATS_ENTRY_SIZE as u32 + 4);
+        assert_eq!(stats.free_memory, Some(0x1000));
+        assert_eq!(stats.total_memory, None);
+    }
+}
@@ -0,0 +1,957 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio-blk device serving a [`DiskFile`] synchronously.
+//!
+//! Each queue is served by its own worker thread, so the queues access the disk image
+//! concurrently. On top of the read, write, flush and get id commands, the device offers the
+//! discard and write zeroes commands when the disk is writable.
+
+use std::any::Any;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use dbs_utils::epoll_manager::{
+    EpollManager, Error as EpollError, EventOps, EventSet, Events, MutEventSubscriber,
+};
+use dbs_utils::rate_limiter::{BucketUpdate, RateLimiter, TokenType};
+use dbs_virtio_devices::{
+    ActivateError, ActivateResult, ConfigResult, VirtioDevice, VirtioDeviceConfig,
+    VirtioQueueConfig,
+};
+use virtio_queue::{Descriptor, QueueSync};
+use vm_memory::{
+    Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryError, GuestRegionMmap,
+};
+use vmm_sys_util::eventfd::EventFd;
+
+use super::DiskFile;
+use crate::address_space_manager::GuestAddressSpaceImpl;
+
+const VIRTIO_ID_BLOCK: u32 = 2;
+
+// Feature bits of virtio-blk devices, from the virtio 1.1 specification.
+const VIRTIO_BLK_F_SEG_MAX: u32 = 2;
+const VIRTIO_BLK_F_RO: u32 = 5;
+const VIRTIO_BLK_F_FLUSH: u32 = 9;
+const VIRTIO_BLK_F_MQ: u32 = 12;
+const VIRTIO_BLK_F_DISCARD: u32 = 13;
+const VIRTIO_BLK_F_WRITE_ZEROES: u32 = 14;
+const VIRTIO_F_VERSION_1: u32 = 32;
+
+// Request types.
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+
+// Request status.
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+// Flag of the write zeroes segments allowing to deallocate the range.
+const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1;
+
+// Offsets of the fields of the configuration space, which is little endian.
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_SEG_MAX: usize = 12;
+const CONFIG_NUM_QUEUES: usize = 34;
+const CONFIG_MAX_DISCARD_SECTORS: usize = 36;
+const CONFIG_MAX_DISCARD_SEG: usize = 40;
+const CONFIG_DISCARD_SECTOR_ALIGNMENT: usize = 44;
+const CONFIG_MAX_WRITE_ZEROES_SECTORS: usize = 48;
+const CONFIG_MAX_WRITE_ZEROES_SEG: usize = 52;
+const CONFIG_WRITE_ZEROES_MAY_UNMAP: usize = 56;
+const CONFIG_SPACE_SIZE: usize = 60;
+
+const SECTOR_SHIFT: u32 = 9;
+const SECTOR_SIZE: u64 = 1 << SECTOR_SHIFT;
+// Size of the request header, the type, a reserved field and the sector.
+const REQUEST_HEADER_SIZE: u32 = 16;
+// Size of the discard and write zeroes segments, the sector, the number of sectors and flags.
+const SEGMENT_SIZE: u32 = 16;
+// The requests are served synchronously, bound the time a queue spends on a single one.
+const MAX_DISCARD_SECTORS: u32 = 1 << 21;
+const MAX_WRITE_ZEROES_SECTORS: u32 = 1 << 17;
+// Size of the serial number of virtio-blk devices.
+const VIRTIO_BLK_ID_BYTES: usize = 20;
+
+const QUEUE_EVENT: u32 = 0;
+const RATE_LIMITER_EVENT: u32 = 1;
+const KILL_EVENT: u32 = 2;
+
+/// Virtio-blk device serving a disk image shared by its queues.
+pub(crate) struct SyncBlock {
+    disk: Arc<dyn DiskFile>,
+    device_id: [u8; VIRTIO_BLK_ID_BYTES],
+    read_only: bool,
+    queue_sizes: Vec<u16>,
+    avail_features: u64,
+    acked_features: u64,
+    config_space: Vec<u8>,
+    rate_limiters: Vec<Arc<Mutex<RateLimiter>>>,
+    workers: Vec<QueueWorker>,
+    logger: slog::Logger,
+}
+
+impl SyncBlock {
+    /// Create a device with queues of `queue_sizes`, each throttled by the rate limiter of the
+    /// same index if any.
+    pub(crate) fn new(
+        disk: Arc<dyn DiskFile>,
+        read_only: bool,
+        queue_sizes: Vec<u16>,
+        mut rate_limiters: Vec<RateLimiter>,
+        logger: slog::Logger,
+    ) -> io::Result<Self> {
+        let metadata = disk.file().metadata()?;
+        let serial = format!("{}{}{}", metadata.dev(), metadata.rdev(), metadata.ino());
+        let mut device_id = [0u8; VIRTIO_BLK_ID_BYTES];
+        let len = serial.len().min(VIRTIO_BLK_ID_BYTES);
+        device_id[..len].copy_from_slice(&serial.as_bytes()[..len]);
+
+        let mut avail_features =
+            1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_BLK_F_SEG_MAX | 1u64 << VIRTIO_BLK_F_FLUSH;
+        if read_only {
+            avail_features |= 1u64 << VIRTIO_BLK_F_RO;
+        } else {
+            avail_features |= 1u64 << VIRTIO_BLK_F_DISCARD | 1u64 << VIRTIO_BLK_F_WRITE_ZEROES;
+        }
+        if queue_sizes.len() > 1 {
+            avail_features |= 1u64 << VIRTIO_BLK_F_MQ;
+        }
+
+        let config_space = build_config_space(disk.as_ref(), &queue_sizes);
+        rate_limiters.resize_with(queue_sizes.len(), RateLimiter::default);
+
+        Ok(SyncBlock {
+            disk,
+            device_id,
+            read_only,
+            queue_sizes,
+            avail_features,
+            acked_features: 0,
+            config_space,
+            rate_limiters: rate_limiters
+                .into_iter()
+                .map(|limiter| Arc::new(Mutex::new(limiter)))
+                .collect(),
+            workers: Vec::new(),
+            logger,
+        })
+    }
+
+    /// Update the rate limiters of the queues.
+    pub(crate) fn set_patch_rate_limiters(&self, bytes: BucketUpdate, ops: BucketUpdate) {
+        for limiter in self.rate_limiters.iter() {
+            limiter
+                .lock()
+                .unwrap()
+                .update_buckets(bytes.clone(), ops.clone());
+        }
+    }
+
+    fn stop_workers(&mut self) {
+        for worker in self.workers.drain(..) {
+            if let Err(e) = worker.stop() {
+                slog::warn!(
+                    self.logger,
+                    "failed to stop virtio-blk queue worker, {:?}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn build_config_space(disk: &dyn DiskFile, queue_sizes: &[u16]) -> Vec<u8> {
+    let mut config = vec![0u8; CONFIG_SPACE_SIZE];
+    let mut put32 = |offset: usize, value: u32| {
+        config[offset..offset + 4].copy_from_slice(&value.to_le_bytes())
+    };
+    // The header and status descriptors are not counted as segments.
+    let seg_max = queue_sizes.iter().min().copied().unwrap_or(0).max(3) - 2;
+    put32(CONFIG_SEG_MAX, seg_max as u32);
+    put32(CONFIG_MAX_DISCARD_SECTORS, MAX_DISCARD_SECTORS);
+    put32(CONFIG_MAX_DISCARD_SEG, 1);
+    let alignment = (disk.discard_granularity() >> SECTOR_SHIFT).max(1);
+    put32(CONFIG_DISCARD_SECTOR_ALIGNMENT, alignment as u32);
+    put32(CONFIG_MAX_WRITE_ZEROES_SECTORS, MAX_WRITE_ZEROES_SECTORS);
+    put32(CONFIG_MAX_WRITE_ZEROES_SEG, 1);
+
+    let capacity = disk.virtual_size() >> SECTOR_SHIFT;
+    config[CONFIG_CAPACITY..CONFIG_CAPACITY + 8].copy_from_slice(&capacity.to_le_bytes());
+    config[CONFIG_NUM_QUEUES..CONFIG_NUM_QUEUES + 2]
+        .copy_from_slice(&(queue_sizes.len() as u16).to_le_bytes());
+    config[CONFIG_WRITE_ZEROES_MAY_UNMAP] = 1;
+    config
+}
+
+impl VirtioDevice<GuestAddressSpaceImpl, QueueSync, GuestRegionMmap> for SyncBlock {
+    fn device_type(&self) -> u32 {
+        VIRTIO_ID_BLOCK
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.queue_sizes
+    }
+
+    fn get_avail_features(&self, page: u32) -> u32 {
+        match page {
+            0 => self.avail_features as u32,
+            1 => (self.avail_features >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    fn set_acked_features(&mut self, page: u32, value: u32) {
+        let features = match page {
+            0 => value as u64,
+            1 => (value as u64) << 32,
+            _ => {
+                slog::warn!(
+                    self.logger,
+                    "virtio-blk: ack of features on invalid page {}",
+                    page
+                );
+                return;
+            }
+        };
+        // The features the device doesn't offer are ignored.
+        self.acked_features |= features & self.avail_features;
+    }
+
+    fn read_config(&mut self, offset: u64, data: &mut [u8]) -> ConfigResult {
+        let start = (offset as usize).min(self.config_space.len());
+        let end = start
+            .saturating_add(data.len())
+            .min(self.config_space.len());
+        data[..end - start].copy_from_slice(&self.config_space[start..end]);
+        Ok(())
+    }
+
+    fn write_config(&mut self, offset: u64, _data: &[u8]) -> ConfigResult {
+        // The writeback mode is not offered, the configuration space is read-only.
+        slog::warn!(
+            self.logger,
+            "virtio-blk: guest writes the configuration space at offset {:#x}",
+            offset
+        );
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        mut config: VirtioDeviceConfig<GuestAddressSpaceImpl, QueueSync, GuestRegionMmap>,
+    ) -> ActivateResult {
+        if config.queues.len() != self.queue_sizes.len() {
+            return Err(ActivateError::InvalidParam);
+        }
+        self.stop_workers();
+
+        let queues = std::mem::take(&mut config.queues);
+        for (index, queue) in queues.into_iter().enumerate() {
+            let handler = QueueHandler {
+                queue,
+                vm_as: config.vm_as.clone(),
+                backend: BlockBackend {
+                    disk: self.disk.clone(),
+                    device_id: self.device_id,
+                    read_only: self.read_only,
+                    acked_features: self.acked_features,
+                },
+                rate_limiter: self.rate_limiters[index].clone(),
+                pending: None,
+                kill_evt: None,
+                stopped: Arc::new(AtomicBool::new(false)),
+                logger: self.logger.clone(),
+            };
+            match QueueWorker::spawn(index, handler) {
+                Ok(worker) => self.workers.push(worker),
+                Err(e) => {
+                    slog::error!(
+                        self.logger,
+                        "failed to start virtio-blk queue worker, {:?}",
+                        e
+                    );
+                    self.stop_workers();
+                    return Err(ActivateError::InternalError);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> ActivateResult {
+        self.stop_workers();
+        self.acked_features = 0;
+        Ok(())
+    }
+
+    fn remove(&mut self) {
+        self.stop_workers();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Drop for SyncBlock {
+    fn drop(&mut self) {
+        self.stop_workers();
+    }
+}
+
+// Thread serving the requests of a queue, with its own epoll loop.
+struct QueueWorker {
+    kill_evt: EventFd,
+    thread: JoinHandle<()>,
+}
+
+impl QueueWorker {
+    fn spawn(index: usize, mut handler: QueueHandler) -> io::Result<Self> {
+        let kill_evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        handler.kill_evt = Some(kill_evt.try_clone()?);
+        let stopped = handler.stopped.clone();
+        let logger = handler.logger.clone();
+        let epoll_mgr = EpollManager::default();
+        epoll_mgr.add_subscriber(Box::new(handler));
+
+        let thread = thread::Builder::new()
+            .name(format!("db_blk_queue{}", index))
+            .spawn(move || {
+                while !stopped.load(Ordering::Acquire) {
+                    match epoll_mgr.handle_events(-1) {
+                        Ok(_) => {}
+                        Err(EpollError::Epoll(e))
+                            if e.errno() == libc::EAGAIN || e.errno() == libc::EINTR => {}
+                        Err(e) => {
+                            slog::error!(logger, "virtio-blk queue worker failed, {:?}", e);
+                            break;
+                        }
+                    }
+                }
+            })?;
+
+        Ok(QueueWorker { kill_evt, thread })
+    }
+
+    fn stop(self) -> io::Result<()> {
+        self.kill_evt.write(1)?;
+        self.thread
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "queue worker panicked"))
+    }
+}
+
+// Guest buffer of the request data.
+#[derive(Clone, Copy)]
+struct DataDesc {
+    addr: GuestAddress,
+    len: u32,
+    write_only: bool,
+}
+
+impl From<&Descriptor> for DataDesc {
+    fn from(desc: &Descriptor) -> Self {
+        DataDesc {
+            addr: desc.addr(),
+            len: desc.len(),
+            write_only: desc.is_write_only(),
+        }
+    }
+}
+
+// A request parsed from the descriptors of the guest.
+struct Request {
+    head: u16,
+    request_type: u32,
+    sector: u64,
+    data: Vec<DataDesc>,
+    status: GuestAddress,
+}
+
+impl Request {
+    fn parse<M: GuestMemory>(
+        head: u16,
+        descs: &[Descriptor],
+        mem: &M,
+    ) -> std::result::Result<Self, String> {
+        let (header, status) = match (descs.first(), descs.last()) {
+            (Some(header), Some(status)) if descs.len() >= 2 => (header, status),
+            _ => return Err(format!("{} descriptors", descs.len())),
+        };
+        if header.is_write_only() || header.len() < REQUEST_HEADER_SIZE {
+            return Err("invalid request header".to_string());
+        }
+        if !status.is_write_only() || status.len() < 1 {
+            return Err("invalid status descriptor".to_string());
+        }
+
+        let request_type: u32 = mem
+            .read_obj(header.addr())
+            .map_err(|e| format!("failed to read request type, {:?}", e))?;
+        let sector: u64 = mem
+            .read_obj(header.addr().unchecked_add(8))
+            .map_err(|e| format!("failed to read request sector, {:?}", e))?;
+
+        Ok(Request {
+            head,
+            request_type: u32::from_le(request_type),
+            sector: u64::from_le(sector),
+            data: descs[1..descs.len() - 1]
+                .iter()
+                .map(DataDesc::from)
+                .collect(),
+            status: status.addr(),
+        })
+    }
+
+    // Bytes transferred by the request, accounted by the rate limiter.
+    fn data_len(&self) -> u64 {
+        match self.request_type {
+            VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT => {
+                self.data.iter().map(|desc| desc.len as u64).sum()
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum RequestError {
+    Unsupported(u32),
+    InvalidDescriptor,
+    InvalidRange(u64, u64),
+    ReadOnly,
+    GuestMemory(GuestMemoryError),
+    Io(io::Error),
+}
+
+impl From<GuestMemoryError> for RequestError {
+    fn from(e: GuestMemoryError) -> Self {
+        RequestError::GuestMemory(e)
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+// Disk of the device and negotiated features, serving the requests of a queue.
+struct BlockBackend {
+    disk: Arc<dyn DiskFile>,
+    device_id: [u8; VIRTIO_BLK_ID_BYTES],
+    read_only: bool,
+    acked_features: u64,
+}
+
+impl BlockBackend {
+    fn has_feature(&self, feature: u32) -> bool {
+        self.acked_features & (1u64 << feature) != 0
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> std::result::Result<(), RequestError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.disk.virtual_size() => Ok(()),
+            _ => Err(RequestError::InvalidRange(offset, len)),
+        }
+    }
+
+    fn sector_offset(&self, sector: u64) -> std::result::Result<u64, RequestError> {
+        sector
+            .checked_mul(SECTOR_SIZE)
+            .ok_or(RequestError::InvalidRange(sector, 0))
+    }
+
+    // Serve the request, returning the number of bytes written to the guest buffers.
+    fn execute<M: GuestMemory>(
+        &self,
+        mem: &M,
+        request: &Request,
+    ) -> std::result::Result<u32, RequestError> {
+        match request.request_type {
+            VIRTIO_BLK_T_IN => {
+                let mut offset = self.sector_offset(request.sector)?;
+                self.check_range(offset, request.data_len())?;
+                let mut len = 0;
+                for desc in request.data.iter() {
+                    if !desc.write_only {
+                        return Err(RequestError::InvalidDescriptor);
+                    }
+                    let mut buf = vec![0u8; desc.len as usize];
+                    self.disk.read_at(&mut buf, offset)?;
+                    mem.write_slice(&buf, desc.addr)?;
+                    offset += desc.len as u64;
+                    len += desc.len;
+                }
+                Ok(len)
+            }
+            VIRTIO_BLK_T_OUT => {
+                if self.read_only {
+                    return Err(RequestError::ReadOnly);
+                }
+                let mut offset = self.sector_offset(request.sector)?;
+                self.check_range(offset, request.data_len())?;
+                for desc in request.data.iter() {
+                    if desc.write_only {
+                        return Err(RequestError::InvalidDescriptor);
+                    }
+                    let mut buf = vec![0u8; desc.len as usize];
+                    mem.read_slice(&mut buf, desc.addr)?;
+                    self.disk.write_at(&buf, offset)?;
+                    offset += desc.len as u64;
+                }
+                Ok(0)
+            }
+            VIRTIO_BLK_T_FLUSH => {
+                self.disk.flush()?;
+                Ok(0)
+            }
+            VIRTIO_BLK_T_GET_ID => {
+                let desc = match request.data.first() {
+                    Some(desc) if desc.write_only => desc,
+                    _ => return Err(RequestError::InvalidDescriptor),
+                };
+                let len = (desc.len as usize).min(VIRTIO_BLK_ID_BYTES);
+                mem.write_slice(&self.device_id[..len], desc.addr)?;
+                Ok(len as u32)
+            }
+            VIRTIO_BLK_T_DISCARD if self.has_feature(VIRTIO_BLK_F_DISCARD) => {
+                self.execute_segments(mem, request)?;
+                Ok(0)
+            }
+            VIRTIO_BLK_T_WRITE_ZEROES if self.has_feature(VIRTIO_BLK_F_WRITE_ZEROES) => {
+                self.execute_segments(mem, request)?;
+                Ok(0)
+            }
+            request_type => Err(RequestError::Unsupported(request_type)),
+        }
+    }
+
+    // Serve the segments of a discard or write zeroes request.
+    fn execute_segments<M: GuestMemory>(
+        &self,
+        mem: &M,
+        request: &Request,
+    ) -> std::result::Result<(), RequestError> {
+        if self.read_only {
+            return Err(RequestError::ReadOnly);
+        }
+        let (max_sectors, allowed_flags) = if request.request_type == VIRTIO_BLK_T_DISCARD {
+            (MAX_DISCARD_SECTORS, 0)
+        } else {
+            (MAX_WRITE_ZEROES_SECTORS, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP)
+        };
+
+        for desc in request.data.iter() {
+            if desc.write_only || desc.len % SEGMENT_SIZE != 0 {
+                return Err(RequestError::InvalidDescriptor);
+            }
+            for index in 0..(desc.len / SEGMENT_SIZE) as u64 {
+                let segment = desc.addr.unchecked_add(index * SEGMENT_SIZE as u64);
+                let sector = u64::from_le(mem.read_obj(segment)?);
+                let num_sectors = u32::from_le(mem.read_obj(segment.unchecked_add(8))?);
+                let flags = u32::from_le(mem.read_obj(segment.unchecked_add(12))?);
+                if flags & !allowed_flags != 0 {
+                    return Err(RequestError::Unsupported(request.request_type));
+                }
+                if num_sectors > max_sectors {
+                    return Err(RequestError::InvalidRange(sector, num_sectors as u64));
+                }
+
+                let offset = self.sector_offset(sector)?;
+                let len = num_sectors as u64 * SECTOR_SIZE;
+                self.check_range(offset, len)?;
+                if request.request_type == VIRTIO_BLK_T_DISCARD {
+                    self.disk.discard(offset, len)?;
+                } else {
+                    let unmap = flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0;
+                    self.disk.write_zeroes(offset, len, unmap)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Handler of a queue, running on the worker thread of the queue.
+struct QueueHandler {
+    queue: VirtioQueueConfig<QueueSync>,
+    vm_as: GuestAddressSpaceImpl,
+    backend: BlockBackend,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    // Request held back by the rate limiter, served first once the limiter is replenished.
+    pending: Option<Request>,
+    kill_evt: Option<EventFd>,
+    stopped: Arc<AtomicBool>,
+    logger: slog::Logger,
+}
+
+impl QueueHandler {
+    fn process_queue(&mut self) {
+        if let Err(e) = self.queue.consume_event() {
+            slog::error!(
+                self.logger,
+                "failed to read virtio-blk queue event, {:?}",
+                e
+            );
+            return;
+        }
+        self.process_requests();
+    }
+
+    fn process_requests(&mut self) {
+        let mem = self.vm_as.memory();
+        let mut used = false;
+        loop {
+            let request = match self.pending.take() {
+                Some(request) => request,
+                None => match self.queue.get_next_descriptor(mem.clone()) {
+                    Ok(Some(chain)) => {
+                        let head = chain.head_index();
+                        let descs: Vec<Descriptor> = chain.collect();
+                        match Request::parse(head, &descs, &*mem) {
+                            Ok(request) => request,
+                            Err(e) => {
+                                slog::error!(self.logger, "invalid virtio-blk request, {}", e);
+                                self.queue.add_used(&*mem, head, 0);
+                                used = true;
+                                continue;
+                            }
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        slog::error!(self.logger, "invalid virtio-blk descriptor, {:?}", e);
+                        break;
+                    }
+                },
+            };
+
+            if self.rate_limited(&request) {
+                self.pending = Some(request);
+                break;
+            }
+
+            let (status, len) = match self.backend.execute(&*mem, &request) {
+                Ok(len) => (VIRTIO_BLK_S_OK, len),
+                Err(RequestError::Unsupported(request_type)) => {
+                    slog::warn!(
+                        self.logger,
+                        "unsupported virtio-blk request type {}",
+                        request_type
+                    );
+                    (VIRTIO_BLK_S_UNSUPP, 0)
+                }
+                Err(e) => {
+                    slog::error!(self.logger, "failed to serve virtio-blk request, {:?}", e);
+                    (VIRTIO_BLK_S_IOERR, 0)
+                }
+            };
+            if let Err(e) = mem.write_obj(status, request.status) {
+                slog::error!(self.logger, "failed to write virtio-blk status, {:?}", e);
+            }
+            // The status byte is written on top of the data.
+            self.queue.add_used(&*mem, request.head, len + 1);
+            used = true;
+        }
+
+        if used {
+            if let Err(e) = self.queue.notify() {
+                slog::error!(self.logger, "failed to notify virtio-blk queue, {:?}", e);
+            }
+        }
+    }
+
+    fn rate_limited(&mut self, request: &Request) -> bool {
+        let mut limiter = self.rate_limiter.lock().unwrap();
+        if !limiter.consume(1, TokenType::Ops) {
+            return true;
+        }
+        let bytes = request.data_len();
+        if bytes > 0 && !limiter.consume(bytes, TokenType::Bytes) {
+            limiter.manual_replenish(1, TokenType::Ops);
+            return true;
+        }
+        false
+    }
+
+    fn process_rate_limiter(&mut self) {
+        if let Err(e) = self.rate_limiter.lock().unwrap().event_handler() {
+            slog::error!(
+                self.logger,
+                "failed to process virtio-blk rate limiter event, {:?}",
+                e
+            );
+            return;
+        }
+        self.process_requests();
+    }
+
+    fn register(&self, ops: &mut EventOps, events: Events, name: &str) {
+        if let Err(e) = ops.add(events) {
+            slog::error!(
+                self.logger,
+                "failed to register virtio-blk {} event, {:?}",
+                name,
+                e
+            );
+        }
+    }
+}
+
+impl MutEventSubscriber for QueueHandler {
+    fn process(&mut self, events: Events, _ops: &mut EventOps) {
+        match events.data() {
+            QUEUE_EVENT => self.process_queue(),
+            RATE_LIMITER_EVENT => self.process_rate_limiter(),
+            KILL_EVENT => self.stopped.store(true, Ordering::Release),
+            slot => slog::error!(self.logger, "unknown epoll slot number {}", slot),
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        let events = Events::with_data(self.queue.eventfd.as_ref(), QUEUE_EVENT, EventSet::IN);
+        self.register(ops, events, "queue");
+        let limiter_fd = self.rate_limiter.lock().unwrap().as_raw_fd();
+        let events = Events::with_data_raw(limiter_fd, RATE_LIMITER_EVENT, EventSet::IN);
+        self.register(ops, events, "rate limiter");
+        if let Some(kill_evt) = self.kill_evt.as_ref() {
+            let events = Events::with_data(kill_evt, KILL_EVENT, EventSet::IN);
+            self.register(ops, events, "kill");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_memory::GuestMemoryMmap;
+    use vmm_sys_util::tempfile::TempFile;
+
+    use super::*;
+    use crate::device_manager::blk_backend::SparseFile;
+
+    fn create_disk(size: u64) -> (TempFile, Arc<dyn DiskFile>) {
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(size).unwrap();
+        let disk = SparseFile::new(file.as_file().try_clone().unwrap()).unwrap();
+        (file, Arc::new(disk))
+    }
+
+    fn create_backend(disk: Arc<dyn DiskFile>, acked_features: u64) -> BlockBackend {
+        BlockBackend {
+            disk,
+            device_id: [b'a'; VIRTIO_BLK_ID_BYTES],
+            read_only: false,
+            acked_features,
+        }
+    }
+
+    fn create_memory() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap()
+    }
+
+    fn desc(addr: u64, len: u32, write_only: bool) -> DataDesc {
+        DataDesc {
+            addr: GuestAddress(addr),
+            len,
+            write_only,
+        }
+    }
+
+    fn request(request_type: u32, sector: u64, data: Vec<DataDesc>) -> Request {
+        Request {
+            head: 0,
+            request_type,
+            sector,
+            data,
+            status: GuestAddress(0x100),
+        }
+    }
+
+    fn write_segment(mem: &GuestMemoryMmap, sector: u64, num_sectors: u32, flags: u32) {
+        let addr = GuestAddress(0x1000);
+        mem.write_obj(sector.to_le(), addr).unwrap();
+        mem.write_obj(num_sectors.to_le(), addr.unchecked_add(8))
+            .unwrap();
+        mem.write_obj(flags.to_le(), addr.unchecked_add(12))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sync_block_config() {
+        let (_file, disk) = create_disk(0x100000);
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let mut block =
+            SyncBlock::new(disk.clone(), false, vec![128, 128], vec![], logger.clone()).unwrap();
+        assert_eq!(block.device_type(), VIRTIO_ID_BLOCK);
+        assert_eq!(block.queue_max_sizes(), &[128, 128]);
+        assert_eq!(block.rate_limiters.len(), 2);
+        let features = block.get_avail_features(0);
+        assert_ne!(features & (1 << VIRTIO_BLK_F_DISCARD), 0);
+        assert_ne!(features & (1 << VIRTIO_BLK_F_WRITE_ZEROES), 0);
+        assert_ne!(features & (1 << VIRTIO_BLK_F_MQ), 0);
+        assert_eq!(features & (1 << VIRTIO_BLK_F_RO), 0);
+        assert_eq!(block.get_avail_features(1), 1);
+
+        // Only the offered features are acked.
+        block.set_acked_features(0, (1 << VIRTIO_BLK_F_DISCARD) | (1 << VIRTIO_BLK_F_RO));
+        assert_eq!(block.acked_features, 1 << VIRTIO_BLK_F_DISCARD);
+
+        let mut capacity = [0u8; 8];
+        block
+            .read_config(CONFIG_CAPACITY as u64, &mut capacity)
+            .unwrap();
+        assert_eq!(u64::from_le_bytes(capacity), 0x100000 >> SECTOR_SHIFT);
+        let mut seg_max = [0u8; 4];
+        block
+            .read_config(CONFIG_SEG_MAX as u64, &mut seg_max)
+            .unwrap();
+        assert_eq!(u32::from_le_bytes(seg_max), 126);
+        // Reads beyond the configuration space leave the buffer untouched.
+        let mut may_unmap = [0xffu8; 2];
+        block
+            .read_config(CONFIG_SPACE_SIZE as u64 - 4, &mut may_unmap[..1])
+            .unwrap();
+        block
+            .read_config(CONFIG_SPACE_SIZE as u64, &mut may_unmap[1..])
+            .unwrap();
+        assert_eq!(may_unmap, [1, 0xff]);
+
+        let block = SyncBlock::new(disk, true, vec![128], vec![], logger).unwrap();
+        let features = block.get_avail_features(0);
+        assert_eq!(features & (1 << VIRTIO_BLK_F_DISCARD), 0);
+        assert_eq!(features & (1 << VIRTIO_BLK_F_MQ), 0);
+        assert_ne!(features & (1 << VIRTIO_BLK_F_RO), 0);
+    }
+
+    #[test]
+    fn test_sync_block_read_write() {
+        let (_file, disk) = create_disk(0x100000);
+        let backend = create_backend(disk, 0);
+        let mem = create_memory();
+
+        let data = vec![0x5au8; 0x400];
+        mem.write_slice(&data, GuestAddress(0x1000)).unwrap();
+        let write = request(VIRTIO_BLK_T_OUT, 8, vec![desc(0x1000, 0x400, false)]);
+        assert_eq!(backend.execute(&mem, &write).unwrap(), 0);
+
+        let read = request(
+            VIRTIO_BLK_T_IN,
+            9,
+            vec![desc(0x2000, 0x100, true), desc(0x3000, 0x100, true)],
+        );
+        assert_eq!(backend.execute(&mem, &read).unwrap(), 0x200);
+        let mut buf = vec![0u8; 0x100];
+        mem.read_slice(&mut buf, GuestAddress(0x3000)).unwrap();
+        assert_eq!(buf, data[..0x100]);
+
+        // The direction of the buffers is checked.
+        let read = request(VIRTIO_BLK_T_IN, 8, vec![desc(0x2000, 0x200, false)]);
+        assert!(matches!(
+            backend.execute(&mem, &read),
+            Err(RequestError::InvalidDescriptor)
+        ));
+        // Beyond the end of the disk.
+        let read = request(
+            VIRTIO_BLK_T_IN,
+            0x100000 >> SECTOR_SHIFT,
+            vec![desc(0x2000, 0x200, true)],
+        );
+        assert!(matches!(
+            backend.execute(&mem, &read),
+            Err(RequestError::InvalidRange(_, _))
+        ));
+
+        let get_id = request(VIRTIO_BLK_T_GET_ID, 0, vec![desc(0x2000, 0x40, true)]);
+        assert_eq!(
+            backend.execute(&mem, &get_id).unwrap(),
+            VIRTIO_BLK_ID_BYTES as u32
+        );
+        let flush = request(VIRTIO_BLK_T_FLUSH, 0, vec![]);
+        assert_eq!(backend.execute(&mem, &flush).unwrap(), 0);
+        let unknown = request(0xff, 0, vec![]);
+        assert!(matches!(
+            backend.execute(&mem, &unknown),
+            Err(RequestError::Unsupported(0xff))
+        ));
+    }
+
+    #[test]
+    fn test_sync_block_discard_write_zeroes() {
+        let (_file, disk) = create_disk(0x100000);
+        disk.write_at(&vec![0x5au8; 0x100000], 0).unwrap();
+        let mem = create_memory();
+        let discard = request(
+            VIRTIO_BLK_T_DISCARD,
+            0,
+            vec![desc(0x1000, SEGMENT_SIZE, false)],
+        );
+        let write_zeroes = request(
+            VIRTIO_BLK_T_WRITE_ZEROES,
+            0,
+            vec![desc(0x1000, SEGMENT_SIZE, false)],
+        );
+
+        // The commands are only served once negotiated.
+        let backend = create_backend(disk.clone(), 0);
+        write_segment(&mem, 0, 8, 0);
+        assert!(matches!(
+            backend.execute(&mem, &discard),
+            Err(RequestError::Unsupported(VIRTIO_BLK_T_DISCARD))
+        ));
+
+        let features = 1u64 << VIRTIO_BLK_F_DISCARD | 1u64 << VIRTIO_BLK_F_WRITE_ZEROES;
+        let backend = create_backend(disk.clone(), features);
+        write_segment(&mem, 0x10, 0x10, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP);
+        assert_eq!(backend.execute(&mem, &write_zeroes).unwrap(), 0);
+        let mut buf = vec![0u8; 0x5000];
+        disk.read_at(&mut buf, 0).unwrap();
+        assert!(buf[..0x2000].iter().all(|b| *b == 0x5a));
+        assert!(buf[0x2000..0x4000].iter().all(|b| *b == 0));
+        assert!(buf[0x4000..].iter().all(|b| *b == 0x5a));
+
+        // Discard doesn't take flags, and the size of the segments is bounded.
+        write_segment(&mem, 0, 8, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP);
+        assert!(matches!(
+            backend.execute(&mem, &discard),
+            Err(RequestError::Unsupported(VIRTIO_BLK_T_DISCARD))
+        ));
+        write_segment(&mem, 0, MAX_DISCARD_SECTORS + 1, 0);
+        assert!(matches!(
+            backend.execute(&mem, &discard),
+            Err(RequestError::InvalidRange(_, _))
+        ));
+        write_segment(&mem, 0x7f8, 0x10, 0);
+        assert!(matches!(
+            backend.execute(&mem, &discard),
+            Err(RequestError::InvalidRange(_, _))
+        ));
+        write_segment(&mem, 0, 8, 0);
+        assert_eq!(backend.execute(&mem, &discard).unwrap(), 0);
+
+        let read_only = BlockBackend {
+            read_only: true,
+            ..create_backend(disk, features)
+        };
+        assert!(matches!(
+            read_only.execute(&mem, &write_zeroes),
+            Err(RequestError::ReadOnly)
+        ));
+    }
+}
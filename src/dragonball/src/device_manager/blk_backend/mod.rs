@@ -0,0 +1,51 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Backends of virtio-blk devices for the disk image formats which can't be served by the
+//! asynchronous `LocalFile` backend.
+//!
+//! The image formats implement [`DiskFile`], and are served by [`SyncBlock`], a virtio-blk
+//! device running one worker thread per queue, which processes the requests of its queue with
+//! blocking IO on the image. Unlike the virtio-blk device of `dbs-virtio-devices`, it offers the
+//! discard and write zeroes commands, which deallocate the ranges from the image.
+
+use std::fs::File;
+use std::io;
+
+mod device;
+mod qcow;
+mod sparse;
+
+pub(crate) use self::device::SyncBlock;
+pub(crate) use self::qcow::{is_qcow2, QcowFile};
+pub(crate) use self::sparse::SparseFile;
+
+/// A disk image accessed with synchronous positional IO.
+///
+/// The image is shared by the queues of the device, which may access it concurrently.
+pub(crate) trait DiskFile: Send + Sync {
+    /// Size of the disk seen by the guest, in bytes.
+    fn virtual_size(&self) -> u64;
+
+    /// Fill `buf` with the content of the disk at `offset`.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Write the whole `buf` to the disk at `offset`.
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Hint that the range is not used anymore, the ranges which can be deallocated read back as
+    /// zeroes afterwards and the others are left untouched.
+    fn discard(&self, offset: u64, len: u64) -> io::Result<()>;
+
+    /// Granularity of the ranges deallocated by `discard`, in bytes.
+    fn discard_granularity(&self) -> u64;
+
+    /// Zero the range, and deallocate it if `unmap` is set.
+    fn write_zeroes(&self, offset: u64, len: u64, unmap: bool) -> io::Result<()>;
+
+    /// Flush the written data to the storage.
+    fn flush(&self) -> io::Result<()>;
+
+    /// Host file holding the disk image.
+    fn file(&self) -> &File;
+}
@@ -0,0 +1,935 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Qcow2 disk images, with backing file chains and copy-on-write of the clusters.
+//!
+//! Supported images are version 2 and 3 images, with 16 bits refcounts and without encryption,
+//! compressed clusters, external data file or extended L2 entries. Internal snapshots are only
+//! allowed in read-only images.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::sparse::punch_hole;
+use super::{DiskFile, SparseFile};
+
+const QCOW_MAGIC: u32 = 0x5146_49fb;
+const V2_HEADER_SIZE: usize = 72;
+const V3_HEADER_SIZE: usize = 104;
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+// 16 bits refcounts, the default of qemu-img.
+const REFCOUNT_ORDER: u32 = 4;
+const MAX_BACKING_FILE_SIZE: u32 = 1023;
+const MAX_BACKING_CHAIN_DEPTH: usize = 16;
+// Number of L2 tables kept in memory, covering 16GiB of the disk with 64KiB clusters.
+const L2_CACHE_SIZE: usize = 32;
+
+// Offsets of L2 tables, data clusters and refcount blocks in the table entries.
+const L1_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const L2_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const REFCOUNT_OFFSET_MASK: u64 = 0xffff_ffff_ffff_fe00;
+// The cluster is only referenced once, so it can be written in place.
+const CLUSTER_COPIED: u64 = 1 << 63;
+const CLUSTER_COMPRESSED: u64 = 1 << 62;
+// The cluster reads as zeroes, version 3 only.
+const CLUSTER_ZERO: u64 = 1;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn unsupported(msg: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("unsupported qcow2 image: {}", msg),
+    )
+}
+
+fn be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn be64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Check whether `file` starts with the qcow2 magic.
+pub(crate) fn is_qcow2(file: &File) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    match file.read_exact_at(&mut magic, 0) {
+        Ok(()) => Ok(u32::from_be_bytes(magic) == QCOW_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+struct QcowHeader {
+    version: u32,
+    backing_file_offset: u64,
+    backing_file_size: u32,
+    cluster_bits: u32,
+    size: u64,
+    l1_size: u32,
+    l1_table_offset: u64,
+    refcount_table_offset: u64,
+    refcount_table_clusters: u32,
+    nb_snapshots: u32,
+}
+
+impl QcowHeader {
+    fn read(file: &File) -> io::Result<Self> {
+        let mut buf = [0u8; V3_HEADER_SIZE];
+        file.read_exact_at(&mut buf[..V2_HEADER_SIZE], 0)?;
+
+        if be32(&buf, 0) != QCOW_MAGIC {
+            return Err(invalid_data("invalid qcow2 magic".to_string()));
+        }
+        let version = be32(&buf, 4);
+        match version {
+            2 => {}
+            3 => {
+                file.read_exact_at(&mut buf[V2_HEADER_SIZE..], V2_HEADER_SIZE as u64)?;
+                let incompatible_features = be64(&buf, 72);
+                if incompatible_features != 0 {
+                    return Err(unsupported(format!(
+                        "incompatible features {:#x}",
+                        incompatible_features
+                    )));
+                }
+                let refcount_order = be32(&buf, 96);
+                if refcount_order != REFCOUNT_ORDER {
+                    return Err(unsupported(format!("refcount order {}", refcount_order)));
+                }
+            }
+            _ => return Err(unsupported(format!("version {}", version))),
+        }
+
+        let cluster_bits = be32(&buf, 20);
+        if !(MIN_CLUSTER_BITS..=MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            return Err(invalid_data(format!(
+                "invalid cluster bits {}",
+                cluster_bits
+            )));
+        }
+        let crypt_method = be32(&buf, 32);
+        if crypt_method != 0 {
+            return Err(unsupported(format!("encryption method {}", crypt_method)));
+        }
+
+        Ok(QcowHeader {
+            version,
+            backing_file_offset: be64(&buf, 8),
+            backing_file_size: be32(&buf, 16),
+            cluster_bits,
+            size: be64(&buf, 24),
+            l1_size: be32(&buf, 36),
+            l1_table_offset: be64(&buf, 40),
+            refcount_table_offset: be64(&buf, 48),
+            refcount_table_clusters: be32(&buf, 56),
+            nb_snapshots: be32(&buf, 60),
+        })
+    }
+
+    fn backing_file(&self, file: &File, image_path: &Path) -> io::Result<Option<PathBuf>> {
+        if self.backing_file_offset == 0 {
+            return Ok(None);
+        }
+        if self.backing_file_size == 0 || self.backing_file_size > MAX_BACKING_FILE_SIZE {
+            return Err(invalid_data(format!(
+                "invalid backing file name size {}",
+                self.backing_file_size
+            )));
+        }
+
+        let mut name = vec![0u8; self.backing_file_size as usize];
+        file.read_exact_at(&mut name, self.backing_file_offset)?;
+        let name = String::from_utf8(name)
+            .map_err(|_| invalid_data("invalid backing file name".to_string()))?;
+
+        // Relative backing file names are relative to the directory of the image.
+        let path = match image_path.parent() {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        Ok(Some(path))
+    }
+}
+
+enum ClusterState {
+    // The guest data is in the host cluster at this offset.
+    Allocated(u64),
+    // The cluster reads as zeroes.
+    Zero,
+    // The cluster reads from the backing file, or as zeroes without backing file.
+    Unallocated,
+}
+
+// Most recently used L2 tables, the updated entries are written through to the image.
+struct L2Cache {
+    // Entries and last use of the tables, by offset of the tables in the image.
+    tables: HashMap<u64, (Vec<u64>, u64)>,
+    uses: u64,
+}
+
+impl L2Cache {
+    fn new() -> Self {
+        L2Cache {
+            tables: HashMap::new(),
+            uses: 0,
+        }
+    }
+
+    fn get(&mut self, file: &File, table_offset: u64, entries: u64) -> io::Result<&mut Vec<u64>> {
+        if !self.tables.contains_key(&table_offset) {
+            let table = read_table(file, table_offset, entries)?;
+            self.insert(table_offset, table);
+        }
+        self.uses += 1;
+        let (table, last_use) = self.tables.get_mut(&table_offset).unwrap();
+        *last_use = self.uses;
+        Ok(table)
+    }
+
+    fn insert(&mut self, table_offset: u64, table: Vec<u64>) {
+        if self.tables.len() >= L2_CACHE_SIZE {
+            let lru = self
+                .tables
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(offset, _)| *offset);
+            if let Some(offset) = lru {
+                self.tables.remove(&offset);
+            }
+        }
+        self.tables.insert(table_offset, (table, self.uses));
+    }
+}
+
+// Tables of the image, updated when clusters are allocated or deallocated.
+struct QcowMetadata {
+    file: File,
+    version: u32,
+    cluster_bits: u32,
+    cluster_size: u64,
+    l2_entries: u64,
+    l1_table_offset: u64,
+    l1_table: Vec<u64>,
+    l2_cache: L2Cache,
+    refcount_table_offset: u64,
+    refcount_table: Vec<u64>,
+    refcount_block_entries: u64,
+    // New clusters are appended at the end of the image.
+    next_free_cluster: u64,
+}
+
+impl QcowMetadata {
+    fn l1_index(&self, guest_offset: u64) -> usize {
+        (guest_offset >> self.cluster_bits) as usize / self.l2_entries as usize
+    }
+
+    fn l2_index(&self, guest_offset: u64) -> usize {
+        ((guest_offset >> self.cluster_bits) % self.l2_entries) as usize
+    }
+
+    // Get the L2 entry of the cluster at `guest_offset`, 0 if there is no L2 table for it.
+    fn l2_entry(&mut self, guest_offset: u64) -> io::Result<u64> {
+        let l2_table = self.l1_table[self.l1_index(guest_offset)] & L1_OFFSET_MASK;
+        if l2_table == 0 {
+            return Ok(0);
+        }
+
+        let index = self.l2_index(guest_offset);
+        let entry = self.l2_cache.get(&self.file, l2_table, self.l2_entries)?[index];
+        if entry & CLUSTER_COMPRESSED != 0 {
+            return Err(unsupported("compressed clusters".to_string()));
+        }
+        Ok(entry)
+    }
+
+    fn cluster_state(&mut self, guest_offset: u64) -> io::Result<ClusterState> {
+        let entry = self.l2_entry(guest_offset)?;
+        if self.version >= 3 && entry & CLUSTER_ZERO != 0 {
+            return Ok(ClusterState::Zero);
+        }
+        match entry & L2_OFFSET_MASK {
+            0 => Ok(ClusterState::Unallocated),
+            offset => Ok(ClusterState::Allocated(offset)),
+        }
+    }
+
+    fn set_l2_entry(&mut self, guest_offset: u64, entry: u64) -> io::Result<()> {
+        let l2_table = self.l2_table_for_write(guest_offset)?;
+        let index = self.l2_index(guest_offset);
+        self.file
+            .write_all_at(&entry.to_be_bytes(), l2_table + index as u64 * 8)?;
+        self.l2_cache.get(&self.file, l2_table, self.l2_entries)?[index] = entry;
+        Ok(())
+    }
+
+    // Get the L2 table covering `guest_offset`, allocating it if needed.
+    fn l2_table_for_write(&mut self, guest_offset: u64) -> io::Result<u64> {
+        let l1_index = self.l1_index(guest_offset);
+        let l2_table = self.l1_table[l1_index] & L1_OFFSET_MASK;
+        if l2_table != 0 {
+            return Ok(l2_table);
+        }
+
+        let l2_table = self.allocate_cluster()?;
+        self.file
+            .write_all_at(&vec![0u8; self.cluster_size as usize], l2_table)?;
+        let entry = l2_table | CLUSTER_COPIED;
+        self.file.write_all_at(
+            &entry.to_be_bytes(),
+            self.l1_table_offset + l1_index as u64 * 8,
+        )?;
+        self.l1_table[l1_index] = entry;
+        self.l2_cache
+            .insert(l2_table, vec![0u64; self.l2_entries as usize]);
+
+        Ok(l2_table)
+    }
+
+    fn allocate_cluster(&mut self) -> io::Result<u64> {
+        let offset = self.next_free_cluster;
+        self.next_free_cluster += self.cluster_size;
+        self.set_refcount(offset, 1)?;
+        Ok(offset)
+    }
+
+    // The freed cluster is not reused, but its space is given back to the host filesystem.
+    fn free_cluster(&mut self, host_offset: u64) -> io::Result<()> {
+        self.set_refcount(host_offset, 0)?;
+        match punch_hole(&self.file, host_offset, self.cluster_size) {
+            Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn set_refcount(&mut self, host_offset: u64, refcount: u16) -> io::Result<()> {
+        let cluster_index = host_offset >> self.cluster_bits;
+        let table_index = (cluster_index / self.refcount_block_entries) as usize;
+        let block_index = cluster_index % self.refcount_block_entries;
+
+        let mut block = self
+            .refcount_table
+            .get(table_index)
+            .ok_or_else(|| unsupported("growing the refcount table".to_string()))?
+            & REFCOUNT_OFFSET_MASK;
+        if block == 0 {
+            block = self.next_free_cluster;
+            self.next_free_cluster += self.cluster_size;
+            self.file
+                .write_all_at(&vec![0u8; self.cluster_size as usize], block)?;
+            self.file.write_all_at(
+                &block.to_be_bytes(),
+                self.refcount_table_offset + table_index as u64 * 8,
+            )?;
+            self.refcount_table[table_index] = block;
+            // The new refcount block is accounted either in itself or in a following block.
+            self.set_refcount(block, 1)?;
+        }
+
+        self.file
+            .write_all_at(&refcount.to_be_bytes(), block + block_index * 2)
+    }
+}
+
+/// A qcow2 image file.
+///
+/// The queues of the device access the data of the allocated clusters concurrently, the
+/// metadata is locked to look the clusters up and to allocate or deallocate them.
+pub(crate) struct QcowFile {
+    file: File,
+    read_only: bool,
+    version: u32,
+    size: u64,
+    cluster_size: u64,
+    backing_file: Option<Box<dyn DiskFile>>,
+    metadata: Mutex<QcowMetadata>,
+}
+
+impl QcowFile {
+    /// Open the qcow2 image at `path`, and the chain of its backing files.
+    pub(crate) fn open(path: &Path, read_only: bool) -> io::Result<Self> {
+        Self::open_with_depth(path, read_only, 0)
+    }
+
+    fn open_with_depth(path: &Path, read_only: bool, depth: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        let header = QcowHeader::read(&file)?;
+        if !read_only && header.nb_snapshots != 0 {
+            return Err(unsupported(
+                "internal snapshots in writable image".to_string(),
+            ));
+        }
+
+        let cluster_size = 1u64 << header.cluster_bits;
+        let l2_entries = cluster_size / 8;
+        let required_l1_size =
+            (header.size + cluster_size * l2_entries - 1) / (cluster_size * l2_entries);
+        if (header.l1_size as u64) < required_l1_size {
+            return Err(invalid_data(format!(
+                "L1 table too small: {} entries for a size of {}",
+                header.l1_size, header.size
+            )));
+        }
+
+        let l1_table = read_table(&file, header.l1_table_offset, header.l1_size as u64)?;
+        let refcount_table = read_table(
+            &file,
+            header.refcount_table_offset,
+            header.refcount_table_clusters as u64 * cluster_size / 8,
+        )?;
+
+        let backing_file = match header.backing_file(&file, path)? {
+            Some(backing_path) => {
+                if depth >= MAX_BACKING_CHAIN_DEPTH {
+                    return Err(invalid_data(format!(
+                        "backing file chain longer than {}",
+                        MAX_BACKING_CHAIN_DEPTH
+                    )));
+                }
+                Some(open_backing_file(&backing_path, depth + 1)?)
+            }
+            None => None,
+        };
+
+        let file_size = file.metadata()?.len();
+        let next_free_cluster = (file_size + cluster_size - 1) / cluster_size * cluster_size;
+        let metadata = QcowMetadata {
+            file: file.try_clone()?,
+            version: header.version,
+            cluster_bits: header.cluster_bits,
+            cluster_size,
+            l2_entries,
+            l1_table_offset: header.l1_table_offset,
+            l1_table,
+            l2_cache: L2Cache::new(),
+            refcount_table_offset: header.refcount_table_offset,
+            refcount_table,
+            refcount_block_entries: cluster_size * 8 / (1 << REFCOUNT_ORDER),
+            next_free_cluster,
+        };
+
+        Ok(QcowFile {
+            file,
+            read_only,
+            version: header.version,
+            size: header.size,
+            cluster_size,
+            backing_file,
+            metadata: Mutex::new(metadata),
+        })
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::from_raw_os_error(libc::EROFS));
+        }
+        Ok(())
+    }
+
+    // Call `f` with the guest offset and the length of each cluster sized chunk of the range.
+    fn for_each_cluster<F>(&self, offset: u64, len: u64, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(u64, u64) -> io::Result<()>,
+    {
+        let end = offset + len;
+        let mut guest_offset = offset;
+        while guest_offset < end {
+            let offset_in_cluster = guest_offset & (self.cluster_size - 1);
+            let chunk_len = (self.cluster_size - offset_in_cluster).min(end - guest_offset);
+            f(guest_offset, chunk_len)?;
+            guest_offset += chunk_len;
+        }
+        Ok(())
+    }
+
+    fn read_backing_file(&self, buf: &mut [u8], guest_offset: u64) -> io::Result<()> {
+        let mut len = 0;
+        if let Some(backing_file) = self.backing_file.as_ref() {
+            // The backing file may be smaller than the image.
+            let backing_size = backing_file.virtual_size();
+            if guest_offset < backing_size {
+                len = buf.len().min((backing_size - guest_offset) as usize);
+                backing_file.read_at(&mut buf[..len], guest_offset)?;
+            }
+        }
+        buf[len..].fill(0);
+        Ok(())
+    }
+
+    // Write `buf`, which doesn't cross a cluster boundary, at `guest_offset`.
+    fn write_cluster(&self, buf: &[u8], guest_offset: u64) -> io::Result<()> {
+        let mut metadata = self.metadata.lock().unwrap();
+        let entry = metadata.l2_entry(guest_offset)?;
+
+        let offset_in_cluster = guest_offset & (self.cluster_size - 1);
+        let zero = self.version >= 3 && entry & CLUSTER_ZERO != 0;
+        let host_offset = entry & L2_OFFSET_MASK;
+        if host_offset != 0 && !zero {
+            drop(metadata);
+            return self.file.write_all_at(buf, host_offset + offset_in_cluster);
+        }
+
+        // Copy on write: the new cluster holds what the guest read so far, updated with `buf`.
+        // The metadata stays locked so that concurrent writes don't allocate the cluster twice.
+        let mut cluster = vec![0u8; self.cluster_size as usize];
+        if !zero && buf.len() as u64 != self.cluster_size {
+            self.read_backing_file(&mut cluster, guest_offset - offset_in_cluster)?;
+        }
+        cluster[offset_in_cluster as usize..offset_in_cluster as usize + buf.len()]
+            .copy_from_slice(buf);
+
+        // A preallocated zero cluster is reused.
+        let host_offset = match host_offset {
+            0 => metadata.allocate_cluster()?,
+            offset => offset,
+        };
+        self.file.write_all_at(&cluster, host_offset)?;
+        metadata.set_l2_entry(guest_offset, host_offset | CLUSTER_COPIED)
+    }
+
+    // Make the cluster at `guest_offset` read as zeroes and free its host cluster, `Ok(false)` if
+    // the image can't represent it.
+    fn deallocate_cluster(
+        &self,
+        metadata: &mut QcowMetadata,
+        guest_offset: u64,
+    ) -> io::Result<bool> {
+        let entry = metadata.l2_entry(guest_offset)?;
+        let host_offset = entry & L2_OFFSET_MASK;
+        let zero = self.version >= 3 && entry & CLUSTER_ZERO != 0;
+        if host_offset == 0 && (zero || self.backing_file.is_none()) {
+            return Ok(true);
+        }
+
+        // Version 2 images don't have zero clusters, the unallocated clusters read from the
+        // backing file.
+        let new_entry = if self.version >= 3 {
+            CLUSTER_ZERO
+        } else if self.backing_file.is_none() {
+            0
+        } else {
+            return Ok(false);
+        };
+        metadata.set_l2_entry(guest_offset, new_entry)?;
+        if host_offset != 0 {
+            metadata.free_cluster(host_offset)?;
+        }
+        Ok(true)
+    }
+}
+
+impl DiskFile for QcowFile {
+    fn virtual_size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.for_each_cluster(offset, buf.len() as u64, |guest_offset, len| {
+            let start = (guest_offset - offset) as usize;
+            let chunk = &mut buf[start..start + len as usize];
+            let offset_in_cluster = guest_offset & (self.cluster_size - 1);
+
+            let state = self.metadata.lock().unwrap().cluster_state(guest_offset)?;
+            match state {
+                ClusterState::Allocated(host_offset) => self
+                    .file
+                    .read_exact_at(chunk, host_offset + offset_in_cluster),
+                ClusterState::Zero => {
+                    chunk.fill(0);
+                    Ok(())
+                }
+                ClusterState::Unallocated => self.read_backing_file(chunk, guest_offset),
+            }
+        })
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.check_writable()?;
+        self.for_each_cluster(offset, buf.len() as u64, |guest_offset, len| {
+            let start = (guest_offset - offset) as usize;
+            self.write_cluster(&buf[start..start + len as usize], guest_offset)
+        })
+    }
+
+    fn discard_granularity(&self) -> u64 {
+        self.cluster_size
+    }
+
+    fn discard(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.check_writable()?;
+        // The partial clusters are left untouched.
+        self.for_each_cluster(offset, len, |guest_offset, len| {
+            if len == self.cluster_size {
+                let mut metadata = self.metadata.lock().unwrap();
+                self.deallocate_cluster(&mut metadata, guest_offset)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn write_zeroes(&self, offset: u64, len: u64, unmap: bool) -> io::Result<()> {
+        self.check_writable()?;
+        let zeroes = vec![0u8; self.cluster_size.min(len) as usize];
+        self.for_each_cluster(offset, len, |guest_offset, len| {
+            if len == self.cluster_size {
+                let mut metadata = self.metadata.lock().unwrap();
+                if unmap && self.deallocate_cluster(&mut metadata, guest_offset)? {
+                    return Ok(());
+                }
+                // The host cluster, if any, is kept as a preallocated zero cluster.
+                if self.version >= 3 {
+                    let entry = metadata.l2_entry(guest_offset)?;
+                    let entry = (entry & (L2_OFFSET_MASK | CLUSTER_COPIED)) | CLUSTER_ZERO;
+                    return metadata.set_l2_entry(guest_offset, entry);
+                }
+            }
+            self.write_cluster(&zeroes[..len as usize], guest_offset)
+        })
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.file.sync_data()
+    }
+
+    fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+fn read_table(file: &File, offset: u64, entries: u64) -> io::Result<Vec<u64>> {
+    let mut buf = vec![0u8; entries as usize * 8];
+    file.read_exact_at(&mut buf, offset)?;
+    Ok(buf
+        .chunks_exact(8)
+        .map(|entry| u64::from_be_bytes(entry.try_into().unwrap()))
+        .collect())
+}
+
+fn open_backing_file(path: &Path, depth: usize) -> io::Result<Box<dyn DiskFile>> {
+    let file = File::open(path)?;
+    if is_qcow2(&file)? {
+        Ok(Box::new(QcowFile::open_with_depth(path, true, depth)?))
+    } else {
+        Ok(Box::new(SparseFile::new(file)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use vmm_sys_util::tempfile::TempFile;
+
+    const CLUSTER_BITS: u32 = 16;
+    const CLUSTER_SIZE: u64 = 1 << CLUSTER_BITS;
+
+    // Create an empty version 3 image like `qemu-img create -f qcow2`, with the header, the
+    // refcount table, the first refcount block and the L1 table in the four first clusters.
+    fn create_image(file: &File, size: u64, backing_file: Option<&str>) {
+        let mut header = vec![0u8; CLUSTER_SIZE as usize];
+        let mut put32 = |offset: usize, value: u32| {
+            header[offset..offset + 4].copy_from_slice(&value.to_be_bytes())
+        };
+        put32(0, QCOW_MAGIC);
+        put32(4, 3);
+        put32(20, CLUSTER_BITS);
+        let l2_coverage = CLUSTER_SIZE * (CLUSTER_SIZE / 8);
+        put32(36, ((size + l2_coverage - 1) / l2_coverage) as u32);
+        put32(56, 1);
+        put32(96, REFCOUNT_ORDER);
+        put32(100, V3_HEADER_SIZE as u32);
+        if let Some(name) = backing_file {
+            put32(16, name.len() as u32);
+        }
+        let mut put64 = |offset: usize, value: u64| {
+            header[offset..offset + 8].copy_from_slice(&value.to_be_bytes())
+        };
+        put64(24, size);
+        put64(40, 3 * CLUSTER_SIZE);
+        put64(48, CLUSTER_SIZE);
+        // The header extensions end at V3_HEADER_SIZE, the backing file name follows.
+        if let Some(name) = backing_file {
+            put64(8, V3_HEADER_SIZE as u64 + 8);
+            let start = V3_HEADER_SIZE + 8;
+            header[start..start + name.len()].copy_from_slice(name.as_bytes());
+        }
+        file.write_all_at(&header, 0).unwrap();
+
+        file.write_all_at(&(2 * CLUSTER_SIZE).to_be_bytes(), CLUSTER_SIZE)
+            .unwrap();
+        for cluster in 0..4u64 {
+            file.write_all_at(&1u16.to_be_bytes(), 2 * CLUSTER_SIZE + cluster * 2)
+                .unwrap();
+        }
+        file.set_len(4 * CLUSTER_SIZE).unwrap();
+    }
+
+    fn refcount(file: &File, host_offset: u64) -> u16 {
+        let mut refcount = [0u8; 2];
+        file.read_exact_at(
+            &mut refcount,
+            2 * CLUSTER_SIZE + (host_offset / CLUSTER_SIZE) * 2,
+        )
+        .unwrap();
+        u16::from_be_bytes(refcount)
+    }
+
+    #[test]
+    fn test_is_qcow2() {
+        let image = TempFile::new().unwrap();
+        assert!(!is_qcow2(image.as_file()).unwrap());
+        create_image(image.as_file(), 0x100000, None);
+        assert!(is_qcow2(image.as_file()).unwrap());
+
+        let raw = TempFile::new().unwrap();
+        raw.as_file().write_all_at(&[0u8; 512], 0).unwrap();
+        assert!(!is_qcow2(raw.as_file()).unwrap());
+        assert!(QcowFile::open(raw.as_path(), true).is_err());
+    }
+
+    #[test]
+    fn test_qcow_read_write() {
+        let image = TempFile::new().unwrap();
+        create_image(image.as_file(), 0x400000, None);
+
+        let disk = QcowFile::open(image.as_path(), false).unwrap();
+        assert_eq!(disk.virtual_size(), 0x400000);
+        let mut buf = vec![0xffu8; 0x1000];
+        disk.read_at(&mut buf, 0x10000).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        // Cross a cluster boundary.
+        let data = vec![0x5au8; 0x1000];
+        disk.write_at(&data, CLUSTER_SIZE - 0x800).unwrap();
+        disk.read_at(&mut buf, CLUSTER_SIZE - 0x800).unwrap();
+        assert_eq!(buf, data);
+        disk.read_at(&mut buf, CLUSTER_SIZE + 0x800).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        // The new L2 table and data clusters are appended and accounted.
+        let file_size = image.as_file().metadata().unwrap().len();
+        assert_eq!(file_size, 7 * CLUSTER_SIZE);
+        for cluster in 4..7 {
+            assert_eq!(refcount(image.as_file(), cluster * CLUSTER_SIZE), 1);
+        }
+        drop(disk);
+
+        let disk = QcowFile::open(image.as_path(), true).unwrap();
+        disk.read_at(&mut buf, CLUSTER_SIZE - 0x800).unwrap();
+        assert_eq!(buf, data);
+        assert!(disk.write_at(&data, 0).is_err());
+        drop(disk);
+
+        // The clusters allocated after reopening the image don't overlap the previous ones.
+        let disk = QcowFile::open(image.as_path(), false).unwrap();
+        let other = vec![0xa5u8; 0x1000];
+        disk.write_at(&other, 0x300000).unwrap();
+        disk.read_at(&mut buf, 0x300000).unwrap();
+        assert_eq!(buf, other);
+        disk.read_at(&mut buf, CLUSTER_SIZE - 0x800).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_qcow_backing_file() {
+        let backing = TempFile::new().unwrap();
+        backing
+            .as_file()
+            .write_all_at(&vec![0x11u8; 2 * CLUSTER_SIZE as usize], 0)
+            .unwrap();
+        let name = backing.as_path().file_name().unwrap().to_str().unwrap();
+
+        let image = TempFile::new().unwrap();
+        create_image(image.as_file(), 4 * CLUSTER_SIZE, Some(name));
+        let disk = QcowFile::open(image.as_path(), false).unwrap();
+
+        // The backing file is smaller than the image.
+        let mut buf = vec![0u8; 0x1000];
+        disk.read_at(&mut buf, CLUSTER_SIZE).unwrap();
+        assert!(buf.iter().all(|b| *b == 0x11));
+        disk.read_at(&mut buf, 2 * CLUSTER_SIZE).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        // Copy on write of a partial cluster.
+        let data = vec![0x22u8; 0x1000];
+        disk.write_at(&data, CLUSTER_SIZE + 0x1000).unwrap();
+        let mut cluster = vec![0u8; CLUSTER_SIZE as usize];
+        disk.read_at(&mut cluster, CLUSTER_SIZE).unwrap();
+        assert!(cluster[..0x1000].iter().all(|b| *b == 0x11));
+        assert!(cluster[0x1000..0x2000].iter().all(|b| *b == 0x22));
+        assert!(cluster[0x2000..].iter().all(|b| *b == 0x11));
+
+        // The backing file is left untouched.
+        backing
+            .as_file()
+            .read_exact_at(&mut buf, CLUSTER_SIZE + 0x1000)
+            .unwrap();
+        assert!(buf.iter().all(|b| *b == 0x11));
+
+        // A qcow2 image as backing file.
+        let overlay = TempFile::new().unwrap();
+        let name = image.as_path().file_name().unwrap().to_str().unwrap();
+        create_image(overlay.as_file(), 4 * CLUSTER_SIZE, Some(name));
+        let disk = QcowFile::open(overlay.as_path(), true).unwrap();
+        disk.read_at(&mut cluster, CLUSTER_SIZE).unwrap();
+        assert!(cluster[0x1000..0x2000].iter().all(|b| *b == 0x22));
+        assert!(cluster[0x2000..].iter().all(|b| *b == 0x11));
+    }
+
+    fn host_offset(disk: &QcowFile, guest_offset: u64) -> Option<u64> {
+        match disk.metadata.lock().unwrap().cluster_state(guest_offset) {
+            Ok(ClusterState::Allocated(host_offset)) => Some(host_offset),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_qcow_discard_write_zeroes() {
+        let backing = TempFile::new().unwrap();
+        backing
+            .as_file()
+            .write_all_at(&vec![0x11u8; 4 * CLUSTER_SIZE as usize], 0)
+            .unwrap();
+        let name = backing.as_path().file_name().unwrap().to_str().unwrap();
+        let image = TempFile::new().unwrap();
+        create_image(image.as_file(), 4 * CLUSTER_SIZE, Some(name));
+        let disk = QcowFile::open(image.as_path(), false).unwrap();
+
+        let data = vec![0x5au8; 2 * CLUSTER_SIZE as usize];
+        disk.write_at(&data, 0).unwrap();
+        let first = host_offset(&disk, 0).unwrap();
+
+        // Only the fully covered cluster is discarded, it reads as zeroes instead of the backing
+        // file and its host cluster is freed.
+        disk.discard(0x1000, 2 * CLUSTER_SIZE - 0x1000).unwrap();
+        let mut buf = vec![0u8; 2 * CLUSTER_SIZE as usize];
+        disk.read_at(&mut buf, 0).unwrap();
+        assert!(buf[..CLUSTER_SIZE as usize].iter().all(|b| *b == 0x5a));
+        assert!(buf[CLUSTER_SIZE as usize..].iter().all(|b| *b == 0));
+        assert_eq!(host_offset(&disk, CLUSTER_SIZE), None);
+        assert_eq!(refcount(image.as_file(), first), 1);
+
+        // Zero without unmap, the host cluster is kept.
+        disk.write_zeroes(0x800, CLUSTER_SIZE - 0x800, false)
+            .unwrap();
+        disk.read_at(&mut buf[..CLUSTER_SIZE as usize], 0).unwrap();
+        assert!(buf[..0x800].iter().all(|b| *b == 0x5a));
+        assert!(buf[0x800..CLUSTER_SIZE as usize].iter().all(|b| *b == 0));
+        disk.write_zeroes(0, CLUSTER_SIZE, false).unwrap();
+        disk.read_at(&mut buf[..CLUSTER_SIZE as usize], 0).unwrap();
+        assert!(buf[..CLUSTER_SIZE as usize].iter().all(|b| *b == 0));
+        assert_eq!(refcount(image.as_file(), first), 1);
+
+        // The preallocated zero cluster is reused by the next write.
+        disk.write_at(&data[..0x1000], 0x1000).unwrap();
+        assert_eq!(host_offset(&disk, 0), Some(first));
+        disk.read_at(&mut buf[..0x2000], 0).unwrap();
+        assert!(buf[..0x1000].iter().all(|b| *b == 0));
+        assert!(buf[0x1000..0x2000].iter().all(|b| *b == 0x5a));
+
+        // Zero with unmap, the host cluster is freed.
+        disk.write_zeroes(0, CLUSTER_SIZE, true).unwrap();
+        assert_eq!(host_offset(&disk, 0), None);
+        assert_eq!(refcount(image.as_file(), first), 0);
+        disk.read_at(&mut buf[..CLUSTER_SIZE as usize], 0).unwrap();
+        assert!(buf[..CLUSTER_SIZE as usize].iter().all(|b| *b == 0));
+
+        // The unallocated clusters read from the backing file until they are zeroed.
+        disk.read_at(&mut buf[..0x1000], 3 * CLUSTER_SIZE).unwrap();
+        assert!(buf[..0x1000].iter().all(|b| *b == 0x11));
+        disk.write_zeroes(3 * CLUSTER_SIZE, CLUSTER_SIZE, true)
+            .unwrap();
+        disk.read_at(&mut buf[..0x1000], 3 * CLUSTER_SIZE).unwrap();
+        assert!(buf[..0x1000].iter().all(|b| *b == 0));
+        drop(disk);
+
+        let disk = QcowFile::open(image.as_path(), true).unwrap();
+        disk.read_at(&mut buf, 2 * CLUSTER_SIZE).unwrap();
+        assert!(buf[..CLUSTER_SIZE as usize].iter().all(|b| *b == 0x11));
+        assert!(buf[CLUSTER_SIZE as usize..].iter().all(|b| *b == 0));
+        assert!(disk.discard(0, CLUSTER_SIZE).is_err());
+        assert!(disk.write_zeroes(0, CLUSTER_SIZE, true).is_err());
+    }
+
+    #[test]
+    fn test_qcow_l2_cache() {
+        // Each L2 table covers 512MiB, write to more regions than the cache holds.
+        let l2_coverage = CLUSTER_SIZE * (CLUSTER_SIZE / 8);
+        let regions = L2_CACHE_SIZE as u64 + 2;
+        let image = TempFile::new().unwrap();
+        create_image(image.as_file(), regions * l2_coverage, None);
+        let disk = QcowFile::open(image.as_path(), false).unwrap();
+
+        for region in 0..regions {
+            disk.write_at(&[region as u8 + 1; 0x200], region * l2_coverage)
+                .unwrap();
+        }
+        assert_eq!(
+            disk.metadata.lock().unwrap().l2_cache.tables.len(),
+            L2_CACHE_SIZE
+        );
+
+        let mut buf = [0u8; 0x200];
+        for region in (0..regions).rev() {
+            disk.read_at(&mut buf, region * l2_coverage).unwrap();
+            assert!(buf.iter().all(|b| *b == region as u8 + 1));
+        }
+        drop(disk);
+
+        // The evicted tables were written through to the image.
+        let disk = QcowFile::open(image.as_path(), true).unwrap();
+        for region in 0..regions {
+            disk.read_at(&mut buf, region * l2_coverage).unwrap();
+            assert!(buf.iter().all(|b| *b == region as u8 + 1));
+        }
+    }
+
+    #[test]
+    fn test_qcow_concurrent_writes() {
+        let image = TempFile::new().unwrap();
+        create_image(image.as_file(), 0x1000000, None);
+        let disk = Arc::new(QcowFile::open(image.as_path(), false).unwrap());
+
+        // The queues write to the same clusters concurrently, each cluster is allocated once.
+        let threads: Vec<_> = (0..4u8)
+            .map(|queue| {
+                let disk = disk.clone();
+                std::thread::spawn(move || {
+                    for cluster in 0..16 {
+                        let offset = cluster * CLUSTER_SIZE + queue as u64 * 0x1000;
+                        disk.write_at(&[queue + 1; 0x1000], offset).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut buf = [0u8; 0x1000];
+        for cluster in 0..16 {
+            for queue in 0..4u8 {
+                let offset = cluster * CLUSTER_SIZE + queue as u64 * 0x1000;
+                disk.read_at(&mut buf, offset).unwrap();
+                assert!(buf.iter().all(|b| *b == queue + 1));
+            }
+        }
+        // The header, refcount, L1, L2 and data clusters.
+        let file_size = image.as_file().metadata().unwrap().len();
+        assert_eq!(file_size, (4 + 1 + 16) * CLUSTER_SIZE);
+    }
+}
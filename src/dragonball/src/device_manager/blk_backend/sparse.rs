@@ -0,0 +1,233 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sparse raw disk files, the ranges discarded or zeroed by the guest are deallocated from the
+//! host file.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::DiskFile;
+
+// Size of the zeroed buffer used to fill the ranges which can't be deallocated.
+const ZERO_BUF_SIZE: usize = 0x10000;
+// Minimal granularity of the deallocated ranges.
+const MIN_HOLE_SIZE: u64 = 512;
+
+/// Raw disk file or block device, deallocating the discarded and zeroed ranges.
+///
+/// Also used for the raw backing files of qcow2 images, which are never written.
+pub(crate) struct SparseFile {
+    file: File,
+    size: u64,
+    hole_size: u64,
+    punch_hole_supported: AtomicBool,
+    zero_range_supported: AtomicBool,
+}
+
+impl SparseFile {
+    /// Create a sparse disk from `file`, which may be a regular file or a block device.
+    pub(crate) fn new(mut file: File) -> io::Result<Self> {
+        // The metadata of block devices doesn't contain their size.
+        let size = file.seek(SeekFrom::End(0))?;
+        let hole_size = file.metadata()?.blksize().max(MIN_HOLE_SIZE);
+
+        Ok(SparseFile {
+            file,
+            size,
+            hole_size,
+            punch_hole_supported: AtomicBool::new(true),
+            zero_range_supported: AtomicBool::new(true),
+        })
+    }
+
+    // Range of the filesystem blocks fully covered by the range, which may be empty.
+    fn aligned_range(&self, offset: u64, len: u64) -> (u64, u64) {
+        let start = (offset + self.hole_size - 1) / self.hole_size * self.hole_size;
+        let end = (offset + len) / self.hole_size * self.hole_size;
+        (start, end.max(start))
+    }
+
+    // Try to punch a hole, `Ok(false)` if the file doesn't support it.
+    fn try_punch_hole(&self, offset: u64, len: u64) -> io::Result<bool> {
+        if len == 0 || !self.punch_hole_supported.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        match punch_hole(&self.file, offset, len) {
+            Ok(()) => Ok(true),
+            Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+                self.punch_hole_supported.store(false, Ordering::Relaxed);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Zero the range keeping it allocated, `Ok(false)` if the file doesn't support it.
+    fn try_zero_range(&self, offset: u64, len: u64) -> io::Result<bool> {
+        if !self.zero_range_supported.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let mode = libc::FALLOC_FL_ZERO_RANGE | libc::FALLOC_FL_KEEP_SIZE;
+        match fallocate(&self.file, mode, offset, len) {
+            Ok(()) => Ok(true),
+            Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+                self.zero_range_supported.store(false, Ordering::Relaxed);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn fill_zeroes(&self, mut offset: u64, len: u64) -> io::Result<()> {
+        let zeroes = vec![0u8; ZERO_BUF_SIZE.min(len as usize)];
+        let end = offset + len;
+        while offset < end {
+            let n = zeroes.len().min((end - offset) as usize);
+            self.file.write_all_at(&zeroes[..n], offset)?;
+            offset += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Deallocate the range of `file`, which reads back as zeroes.
+pub(super) fn punch_hole(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    fallocate(
+        file,
+        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+        offset,
+        len,
+    )
+}
+
+fn fallocate(file: &File, mode: libc::c_int, offset: u64, len: u64) -> io::Result<()> {
+    // Safe because the file descriptor is valid and the result is checked.
+    let ret = unsafe {
+        libc::fallocate64(
+            file.as_raw_fd(),
+            mode,
+            offset as libc::off64_t,
+            len as libc::off64_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl DiskFile for SparseFile {
+    fn virtual_size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.file.read_exact_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.file.write_all_at(buf, offset)
+    }
+
+    fn discard_granularity(&self) -> u64 {
+        self.hole_size
+    }
+
+    fn discard(&self, offset: u64, len: u64) -> io::Result<()> {
+        // The partial filesystem blocks are left untouched.
+        let (start, end) = self.aligned_range(offset, len);
+        self.try_punch_hole(start, end - start).map(|_| ())
+    }
+
+    fn write_zeroes(&self, offset: u64, len: u64, unmap: bool) -> io::Result<()> {
+        if unmap {
+            let (start, end) = self.aligned_range(offset, len);
+            if self.try_punch_hole(start, end - start)? {
+                self.fill_zeroes(offset, start - offset)?;
+                return self.fill_zeroes(end, offset + len - end);
+            }
+        } else if len > 0 && self.try_zero_range(offset, len)? {
+            return Ok(());
+        }
+        self.fill_zeroes(offset, len)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_sparse_file() {
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(0x100000).unwrap();
+        let disk = SparseFile::new(file.as_file().try_clone().unwrap()).unwrap();
+        assert_eq!(disk.virtual_size(), 0x100000);
+
+        let data = vec![0x5au8; 0x40000];
+        disk.write_at(&data, 0).unwrap();
+        let mut buf = vec![0u8; 0x40000];
+        disk.read_at(&mut buf, 0).unwrap();
+        assert_eq!(buf, data);
+        let allocated = file.as_file().metadata().unwrap().blocks();
+
+        // Zeroed writes are written as is.
+        let zeroes = vec![0u8; 0x10000];
+        disk.write_at(&zeroes, 0x10000).unwrap();
+        assert_eq!(file.as_file().metadata().unwrap().blocks(), allocated);
+        disk.write_at(&data[..0x10000], 0x10000).unwrap();
+
+        // Zero an unaligned range with unmap, the bytes around it are kept.
+        disk.write_zeroes(0x8001, 0x30000, true).unwrap();
+        disk.read_at(&mut buf, 0).unwrap();
+        assert!(buf[..0x8001].iter().all(|b| *b == 0x5a));
+        assert!(buf[0x8001..0x38001].iter().all(|b| *b == 0));
+        assert!(buf[0x38001..].iter().all(|b| *b == 0x5a));
+        if disk.punch_hole_supported.load(Ordering::Relaxed) {
+            assert!(file.as_file().metadata().unwrap().blocks() < allocated);
+        }
+
+        // Zero without unmap.
+        disk.write_at(&data, 0).unwrap();
+        disk.write_zeroes(0x10, 0x20000, false).unwrap();
+        disk.read_at(&mut buf, 0).unwrap();
+        assert!(buf[..0x10].iter().all(|b| *b == 0x5a));
+        assert!(buf[0x10..0x20010].iter().all(|b| *b == 0));
+        assert!(buf[0x20010..].iter().all(|b| *b == 0x5a));
+    }
+
+    #[test]
+    fn test_sparse_file_discard() {
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(0x100000).unwrap();
+        let disk = SparseFile::new(file.as_file().try_clone().unwrap()).unwrap();
+        let hole_size = disk.hole_size as usize;
+
+        let data = vec![0x5au8; 4 * hole_size];
+        disk.write_at(&data, 0).unwrap();
+
+        // Only the fully covered blocks are deallocated.
+        disk.discard(1, 2 * hole_size as u64).unwrap();
+        let mut buf = vec![0u8; 4 * hole_size];
+        disk.read_at(&mut buf, 0).unwrap();
+        assert!(buf[..hole_size].iter().all(|b| *b == 0x5a));
+        assert!(buf[2 * hole_size..].iter().all(|b| *b == 0x5a));
+        if disk.punch_hole_supported.load(Ordering::Relaxed) {
+            assert!(buf[hole_size..2 * hole_size].iter().all(|b| *b == 0));
+        }
+    }
+}
@@ -9,7 +9,7 @@
 //! Device manager for virtio-blk and vhost-user-blk devices.
 use std::collections::{vec_deque, VecDeque};
 use std::convert::TryInto;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
@@ -21,12 +21,13 @@ use serde_derive::{Deserialize, Serialize};
 
 use crate::address_space_manager::GuestAddressSpaceImpl;
 use crate::config_manager::{ConfigItem, DeviceConfigInfo, RateLimiterConfigInfo};
+use crate::device_manager::blk_backend::{is_qcow2, DiskFile, QcowFile, SparseFile, SyncBlock};
 use crate::device_manager::blk_dev_mgr::BlockDeviceError::InvalidDeviceId;
 use crate::device_manager::{DeviceManager, DeviceMgrError, DeviceOpContext};
 use crate::get_bucket_update;
 use crate::vm::KernelConfigInfo;
 
-use super::DbsMmioV2Device;
+use super::{DbsMmioV2Device, DbsVirtioDevice};
 
 // The flag of whether to use the shared irq.
 const USE_SHARED_IRQ: bool = true;
@@ -116,6 +117,11 @@ pub enum BlockDeviceType {
     Spool,
     /// Local disk/file based low level device.
     RawBlock,
+    /// Local qcow2 image, with its chain of backing files.
+    Qcow2,
+    /// Local raw disk file, the ranges discarded or zeroed by the guest are deallocated from the
+    /// host file.
+    SparseRaw,
 }
 
 impl BlockDeviceType {
@@ -128,6 +134,26 @@ impl BlockDeviceType {
             BlockDeviceType::RawBlock
         }
     }
+
+    /// Get type of the local disk image at `path` by probing its format.
+    ///
+    /// The image must be trusted: a raw image whose content starts like a qcow2 header would be
+    /// interpreted as a qcow2 image, giving access to the host files named as backing files.
+    pub fn probe(path: &Path) -> std::io::Result<BlockDeviceType> {
+        let file = File::open(path)?;
+        if is_qcow2(&file)? {
+            Ok(BlockDeviceType::Qcow2)
+        } else {
+            Ok(BlockDeviceType::RawBlock)
+        }
+    }
+
+    fn is_local(&self) -> bool {
+        matches!(
+            self,
+            BlockDeviceType::RawBlock | BlockDeviceType::Qcow2 | BlockDeviceType::SparseRaw
+        )
+    }
 }
 
 /// Configuration information for a block device.
@@ -377,7 +403,9 @@ impl BlockDeviceMgr {
                 }
 
                 match config.device_type {
-                    BlockDeviceType::RawBlock => {
+                    BlockDeviceType::RawBlock
+                    | BlockDeviceType::Qcow2
+                    | BlockDeviceType::SparseRaw => {
                         let device = Self::create_blk_device(&config, &mut ctx)
                             .map_err(BlockDeviceError::Virtio)?;
                         let dev = DeviceManager::create_mmio_virtio_device(
@@ -416,7 +444,7 @@ impl BlockDeviceMgr {
     ) -> std::result::Result<(), BlockDeviceError> {
         for info in self.info_list.iter_mut() {
             match info.config.device_type {
-                BlockDeviceType::RawBlock => {
+                BlockDeviceType::RawBlock | BlockDeviceType::Qcow2 | BlockDeviceType::SparseRaw => {
                     info!(
                         ctx.logger(),
                         "attach virtio-blk device, drive_id {}, path {}",
@@ -492,9 +520,19 @@ impl BlockDeviceMgr {
     fn create_blk_device(
         cfg: &BlockDeviceConfigInfo,
         ctx: &mut DeviceOpContext,
-    ) -> std::result::Result<Box<Block<GuestAddressSpaceImpl>>, virtio::Error> {
+    ) -> std::result::Result<DbsVirtioDevice, virtio::Error> {
         let epoll_mgr = ctx.epoll_mgr.clone().ok_or(virtio::Error::InvalidInput)?;
 
+        let mut limiters = vec![];
+        for _i in 0..cfg.num_queues {
+            if let Some(limiter) = cfg.rate_limiter.clone().map(|mut v| {
+                v.resize(cfg.num_queues as u64);
+                v.try_into().unwrap()
+            }) {
+                limiters.push(limiter);
+            }
+        }
+
         let mut block_files: Vec<Box<dyn Ufile>> = vec![];
 
         match cfg.device_type {
@@ -541,6 +579,35 @@ impl BlockDeviceMgr {
                     }
                 }
             }
+            BlockDeviceType::Qcow2 | BlockDeviceType::SparseRaw => {
+                // Served with buffered IO by a device supporting discard and write zeroes.
+                let disk: Arc<dyn DiskFile> = if cfg.device_type == BlockDeviceType::Qcow2 {
+                    info!(
+                        ctx.logger(),
+                        "Open qcow2 image \"{}\".",
+                        cfg.path_on_host().display()
+                    );
+                    Arc::new(QcowFile::open(cfg.path_on_host(), cfg.is_read_only())?)
+                } else {
+                    info!(
+                        ctx.logger(),
+                        "Open sparse disk file \"{}\".",
+                        cfg.path_on_host().display()
+                    );
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(!cfg.is_read_only())
+                        .open(cfg.path_on_host())?;
+                    Arc::new(SparseFile::new(file)?)
+                };
+                return Ok(Box::new(SyncBlock::new(
+                    disk,
+                    cfg.is_read_only(),
+                    cfg.queue_sizes(),
+                    limiters,
+                    ctx.logger().new(slog::o!("subsystem" => "block_manager")),
+                )?));
+            }
             _ => {
                 error!(
                     ctx.logger(),
@@ -550,16 +617,6 @@ impl BlockDeviceMgr {
             }
         };
 
-        let mut limiters = vec![];
-        for _i in 0..cfg.num_queues {
-            if let Some(limiter) = cfg.rate_limiter.clone().map(|mut v| {
-                v.resize(cfg.num_queues as u64);
-                v.try_into().unwrap()
-            }) {
-                limiters.push(limiter);
-            }
-        }
-
         Ok(Box::new(Block::new(
             block_files,
             cfg.is_read_only,
@@ -697,8 +754,7 @@ impl BlockDeviceMgr {
         &self,
         block_device_config: &BlockDeviceConfigInfo,
     ) -> std::result::Result<(), BlockDeviceError> {
-        if block_device_config.device_type == BlockDeviceType::RawBlock
-            && !block_device_config.path_on_host.exists()
+        if block_device_config.device_type.is_local() && !block_device_config.path_on_host.exists()
         {
             Err(BlockDeviceError::InvalidBlockDevicePath(
                 block_device_config.path_on_host.clone(),
@@ -755,6 +811,10 @@ impl BlockDeviceMgr {
                             .map(|_p| ())
                             .map_err(|_e| BlockDeviceError::BlockEpollHanderSendFail);
                     }
+                    if let Some(blk_dev) = inner_dev.as_any().downcast_ref::<SyncBlock>() {
+                        blk_dev.set_patch_rate_limiters(new_cfg.bytes(), new_cfg.ops());
+                        return Ok(());
+                    }
                 }
                 Ok(())
             }
@@ -779,6 +839,8 @@ impl Default for BlockDeviceMgr {
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::fs::FileExt;
+
     use test_utils::skip_if_not_root;
     use vmm_sys_util::tempfile::TempFile;
 
@@ -793,6 +855,24 @@ mod tests {
         assert_eq!(dev_type, BlockDeviceType::RawBlock);
     }
 
+    #[test]
+    fn test_probe_block_device_type() {
+        let image = TempFile::new().unwrap();
+        assert_eq!(
+            BlockDeviceType::probe(image.as_path()).unwrap(),
+            BlockDeviceType::RawBlock
+        );
+        image
+            .as_file()
+            .write_all_at(&[0x51, 0x46, 0x49, 0xfb, 0, 0, 0, 3], 0)
+            .unwrap();
+        assert_eq!(
+            BlockDeviceType::probe(image.as_path()).unwrap(),
+            BlockDeviceType::Qcow2
+        );
+        assert!(BlockDeviceType::probe(Path::new("/nonexistent/image")).is_err());
+    }
+
     #[test]
     fn test_create_block_devices_configs() {
         let mgr = BlockDeviceMgr::default();
@@ -9,9 +9,14 @@
 //! Virtual machine console device manager.
 //!
 //! A virtual console are composed up of two parts: frontend in virtual machine and backend in
-//! host OS. A frontend may be serial port, virtio-console etc, a backend may be stdio or Unix
-//! domain socket. The manager connects the frontend with the backend.
-use std::io::{self, Read};
+//! host OS. A frontend may be serial port, virtio-console etc, a backend may be stdio, Unix
+//! domain socket or a log file. The manager connects the frontend with the backend.
+//!
+//! The console output may also be captured into a size rotated log file and a ring buffer, in
+//! addition to the stdio or Unix domain socket backend. The ring buffer keeps the latest output
+//! of the guest, to be dumped when the virtual machine crashes.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
@@ -21,6 +26,8 @@ use dbs_legacy_devices::{ConsoleHandler, SerialDevice};
 use dbs_utils::epoll_manager::{
     EpollManager, EventOps, EventSet, Events, MutEventSubscriber, SubscriberId,
 };
+use logging::FileRotator;
+use serde_derive::{Deserialize, Serialize};
 use vmm_sys_util::terminal::Terminal;
 
 use super::{DeviceMgrError, Result};
@@ -45,11 +52,39 @@ pub enum ConsoleManagerError {
     /// Cannot set mode for terminal.
     #[error("failure while setting attribute for terminal")]
     StdinHandle(#[source] vmm_sys_util::errno::Error),
+
+    /// Cannot create the console log file.
+    #[error("cannot create console log file")]
+    CreateLogFile(#[source] std::io::Error),
+
+    /// Cannot dump the console ring buffer.
+    #[error("cannot dump console ring buffer")]
+    DumpRingBuffer(#[source] std::io::Error),
+}
+
+/// Configuration to capture the console output of the guest.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConsoleLogConfig {
+    /// Log file to write the console output, rotated by size.
+    ///
+    /// The output is written to both the Unix domain socket and the log file if a socket is
+    /// configured, otherwise it's only written to the log file.
+    pub log_file: Option<String>,
+    /// Size in bytes to rotate the log file, 0 for the default size.
+    pub rotate_size: u64,
+    /// Number of rotated log files to keep, 0 for the default number.
+    pub rotate_count: usize,
+    /// Size in bytes of the ring buffer keeping the latest console output, 0 to disable it.
+    pub ring_buffer_size: usize,
+    /// File to dump the ring buffer when the guest crashes.
+    pub ring_buffer_dump_path: Option<String>,
 }
 
 enum Backend {
     StdinHandle(std::io::Stdin),
     SockPath(String),
+    LogFile,
 }
 
 /// Console manager to manage frontend and backend console devices.
@@ -58,6 +93,8 @@ pub struct ConsoleManager {
     logger: slog::Logger,
     subscriber_id: Option<SubscriberId>,
     backend: Option<Backend>,
+    sinks: ConsoleSinks,
+    ring_buffer_dump_path: Option<String>,
 }
 
 impl ConsoleManager {
@@ -69,15 +106,66 @@ impl ConsoleManager {
             logger,
             subscriber_id: Default::default(),
             backend: None,
+            sinks: ConsoleSinks::default(),
+            ring_buffer_dump_path: None,
         }
     }
 
+    /// Set up the log file and ring buffer capturing the console output.
+    ///
+    /// Must be called before creating the console backend.
+    pub fn set_log_config(&mut self, config: &ConsoleLogConfig) -> Result<()> {
+        self.sinks.log_file = match config.log_file.as_ref() {
+            Some(path) => {
+                let mut rotator = FileRotator::new(path).map_err(|e| {
+                    DeviceMgrError::ConsoleManager(ConsoleManagerError::CreateLogFile(e))
+                })?;
+                // Losing some console output is better than failing the guest serial port.
+                rotator.ignore_errors(true);
+                if config.rotate_size > 0 {
+                    rotator.rotate_threshold(config.rotate_size);
+                }
+                if config.rotate_count > 0 {
+                    rotator.rotate_count(config.rotate_count);
+                }
+                Some(Arc::new(Mutex::new(rotator)))
+            }
+            None => None,
+        };
+        self.sinks.ring_buffer = if config.ring_buffer_size > 0 {
+            Some(Arc::new(Mutex::new(RingBuffer::new(
+                config.ring_buffer_size,
+            ))))
+        } else {
+            None
+        };
+        self.ring_buffer_dump_path = config.ring_buffer_dump_path.clone();
+
+        Ok(())
+    }
+
+    /// Check whether the console output is captured into a log file.
+    pub fn has_log_file(&self) -> bool {
+        self.sinks.log_file.is_some()
+    }
+
+    /// Create a console backend device only writing the console output to the log file.
+    pub fn create_file_console(&mut self, device: Arc<Mutex<SerialDevice>>) -> Result<()> {
+        device
+            .lock()
+            .unwrap()
+            .set_output_stream(self.sinks.output_stream(None));
+        self.backend = Some(Backend::LogFile);
+
+        Ok(())
+    }
+
     /// Create a console backend device by using stdio streams.
     pub fn create_stdio_console(&mut self, device: Arc<Mutex<SerialDevice>>) -> Result<()> {
         device
             .lock()
             .unwrap()
-            .set_output_stream(Some(Box::new(std::io::stdout())));
+            .set_output_stream(self.sinks.output_stream(Some(Box::new(std::io::stdout()))));
         let stdin_handle = std::io::stdin();
         stdin_handle
             .lock()
@@ -89,7 +177,13 @@ impl ConsoleManager {
             .map_err(ConsoleManagerError::StdinHandle)
             .map_err(DeviceMgrError::ConsoleManager)?;
 
-        let handler = ConsoleEpollHandler::new(device, Some(stdin_handle), None, &self.logger);
+        let handler = ConsoleEpollHandler::new(
+            device,
+            Some(stdin_handle),
+            None,
+            self.sinks.clone(),
+            &self.logger,
+        );
         self.subscriber_id = Some(self.epoll_mgr.add_subscriber(Box::new(handler)));
         self.backend = Some(Backend::StdinHandle(std::io::stdin()));
 
@@ -105,7 +199,17 @@ impl ConsoleManager {
         let sock_listener = Self::bind_domain_socket(&sock_path).map_err(|e| {
             DeviceMgrError::ConsoleManager(ConsoleManagerError::CreateSerialSock(e))
         })?;
-        let handler = ConsoleEpollHandler::new(device, None, Some(sock_listener), &self.logger);
+        device
+            .lock()
+            .unwrap()
+            .set_output_stream(self.sinks.output_stream(None));
+        let handler = ConsoleEpollHandler::new(
+            device,
+            None,
+            Some(sock_listener),
+            self.sinks.clone(),
+            &self.logger,
+        );
 
         self.subscriber_id = Some(self.epoll_mgr.add_subscriber(Box::new(handler)));
         self.backend = Some(Backend::SockPath(sock_path));
@@ -125,6 +229,24 @@ impl ConsoleManager {
         Ok(())
     }
 
+    /// Dump the latest console output kept in the ring buffer to the configured dump file.
+    ///
+    /// Nothing is done if the ring buffer or the dump file isn't configured.
+    pub fn dump_ring_buffer(&self) -> Result<()> {
+        if let (Some(ring_buffer), Some(path)) = (
+            self.sinks.ring_buffer.as_ref(),
+            self.ring_buffer_dump_path.as_ref(),
+        ) {
+            let contents = ring_buffer.lock().unwrap().contents();
+            std::fs::write(path, contents).map_err(|e| {
+                DeviceMgrError::ConsoleManager(ConsoleManagerError::DumpRingBuffer(e))
+            })?;
+            slog::info!(self.logger, "console ring buffer dumped to {}", path);
+        }
+
+        Ok(())
+    }
+
     fn bind_domain_socket(serial_path: &str) -> std::result::Result<UnixListener, std::io::Error> {
         let path = Path::new(serial_path);
         if path.is_file() {
@@ -135,11 +257,102 @@ impl ConsoleManager {
     }
 }
 
+/// Buffer keeping the latest `capacity` bytes written into it.
+struct RingBuffer {
+    buf: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    fn new(capacity: usize) -> Self {
+        RingBuffer {
+            buf: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        let overflow = (self.buf.len() + data.len()).saturating_sub(self.capacity);
+        self.buf.drain(..overflow);
+        self.buf.extend(data);
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        self.buf.iter().copied().collect()
+    }
+}
+
+/// Log file and ring buffer capturing the console output, shared by the console backends.
+#[derive(Clone, Default)]
+struct ConsoleSinks {
+    log_file: Option<Arc<Mutex<FileRotator>>>,
+    ring_buffer: Option<Arc<Mutex<RingBuffer>>>,
+}
+
+impl ConsoleSinks {
+    fn is_empty(&self) -> bool {
+        self.log_file.is_none() && self.ring_buffer.is_none()
+    }
+
+    /// Build the output stream of the serial device, duplicating the output of `stream` into
+    /// the sinks.
+    fn output_stream(
+        &self,
+        stream: Option<Box<dyn io::Write + Send>>,
+    ) -> Option<Box<dyn io::Write + Send>> {
+        if self.is_empty() {
+            stream
+        } else {
+            Some(Box::new(TeeWriter {
+                stream,
+                sinks: self.clone(),
+            }))
+        }
+    }
+}
+
+/// Writer duplicating the console output into the log file and the ring buffer.
+struct TeeWriter {
+    stream: Option<Box<dyn io::Write + Send>>,
+    sinks: ConsoleSinks,
+}
+
+impl io::Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(ring_buffer) = self.sinks.ring_buffer.as_ref() {
+            ring_buffer.lock().unwrap().push(buf);
+        }
+        if let Some(log_file) = self.sinks.log_file.as_ref() {
+            // Errors are ignored by the rotator.
+            let _ = log_file.lock().unwrap().write_all(buf);
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            stream.write_all(buf)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(log_file) = self.sinks.log_file.as_ref() {
+            // Log files get rotated when flushed.
+            let _ = log_file.lock().unwrap().flush();
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            stream.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
 struct ConsoleEpollHandler {
     device: Arc<Mutex<SerialDevice>>,
     stdin_handle: Option<std::io::Stdin>,
     sock_listener: Option<UnixListener>,
     sock_conn: Option<UnixStream>,
+    sinks: ConsoleSinks,
     logger: slog::Logger,
 }
 
@@ -148,6 +361,7 @@ impl ConsoleEpollHandler {
         device: Arc<Mutex<SerialDevice>>,
         stdin_handle: Option<std::io::Stdin>,
         sock_listener: Option<UnixListener>,
+        sinks: ConsoleSinks,
         logger: &slog::Logger,
     ) -> Self {
         ConsoleEpollHandler {
@@ -155,10 +369,18 @@ impl ConsoleEpollHandler {
             stdin_handle,
             sock_listener,
             sock_conn: None,
+            sinks,
             logger: logger.new(slog::o!("subsystem" => "console_manager")),
         }
     }
 
+    fn detach_output_stream(&self) {
+        self.device
+            .lock()
+            .expect("console: poisoned console lock")
+            .set_output_stream(self.sinks.output_stream(None));
+    }
+
     fn uds_listener_accept(&mut self, ops: &mut EventOps) -> std::io::Result<()> {
         if self.sock_conn.is_some() {
             slog::warn!(self.logger,
@@ -184,7 +406,7 @@ impl ConsoleEpollHandler {
             self.device
                 .lock()
                 .unwrap()
-                .set_output_stream(Some(Box::new(conn_sock_copy)));
+                .set_output_stream(self.sinks.output_stream(Some(Box::new(conn_sock_copy))));
 
             self.sock_conn = Some(conn_sock);
         }
@@ -200,10 +422,7 @@ impl ConsoleEpollHandler {
             match conn_sock.read(&mut out[..]) {
                 Ok(0) => {
                     // Zero-length read means EOF. Remove this conn sock.
-                    self.device
-                        .lock()
-                        .expect("console: poisoned console lock")
-                        .set_output_stream(None);
+                    self.detach_output_stream();
                 }
                 Ok(count) => {
                     self.device
@@ -217,10 +436,7 @@ impl ConsoleEpollHandler {
                         "error while reading serial conn sock: {:?}", e;
                         "subsystem" => "console_mgr"
                     );
-                    self.device
-                        .lock()
-                        .expect("console: poisoned console lock")
-                        .set_output_stream(None);
+                    self.detach_output_stream();
                 }
             }
         }
@@ -251,10 +467,7 @@ impl ConsoleEpollHandler {
             match stdin_lock.read_raw(&mut out[..]) {
                 Ok(0) => {
                     // Zero-length read indicates EOF. Remove from pollables.
-                    self.device
-                        .lock()
-                        .expect("console: poisoned console lock")
-                        .set_output_stream(None);
+                    self.detach_output_stream();
                 }
                 Ok(count) => {
                     self.device
@@ -268,10 +481,7 @@ impl ConsoleEpollHandler {
                         "error while reading stdin: {:?}", e;
                         "subsystem" => "console_mgr"
                     );
-                    self.device
-                        .lock()
-                        .expect("console: poisoned console lock")
-                        .set_output_stream(None);
+                    self.detach_output_stream();
                 }
             }
         }
@@ -445,5 +655,78 @@ mod tests {
         writer.flush().unwrap();
     }
 
-    // TODO: add unit tests for console manager
+    #[test]
+    fn test_ring_buffer() {
+        let mut ring_buffer = RingBuffer::new(8);
+        assert!(ring_buffer.contents().is_empty());
+
+        ring_buffer.push(b"hello");
+        assert_eq!(ring_buffer.contents(), b"hello");
+        ring_buffer.push(b" world");
+        assert_eq!(ring_buffer.contents(), b"lo world");
+        ring_buffer.push(b"0123456789");
+        assert_eq!(ring_buffer.contents(), b"23456789");
+        ring_buffer.push(b"");
+        assert_eq!(ring_buffer.contents(), b"23456789");
+    }
+
+    #[test]
+    fn test_console_log() {
+        let dir = vmm_sys_util::tempdir::TempDir::new().unwrap();
+        let log_file = dir.as_path().join("console.log");
+        let dump_file = dir.as_path().join("console.dump");
+        let epoll_mgr = EpollManager::default();
+        let mut mgr = ConsoleManager::new(epoll_mgr, &create_logger());
+
+        // Without log file nor ring buffer, the stream is used as is.
+        assert!(mgr.sinks.output_stream(None).is_none());
+        mgr.dump_ring_buffer().unwrap();
+        assert!(!dump_file.exists());
+
+        let config = ConsoleLogConfig {
+            log_file: Some(log_file.to_str().unwrap().to_string()),
+            rotate_size: 16,
+            rotate_count: 2,
+            ring_buffer_size: 4,
+            ring_buffer_dump_path: Some(dump_file.to_str().unwrap().to_string()),
+        };
+        mgr.set_log_config(&config).unwrap();
+        assert!(mgr.has_log_file());
+
+        let stream = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = mgr
+            .sinks
+            .output_stream(Some(Box::new(SharedBuffer(stream.clone()))))
+            .unwrap();
+        writer.write_all(b"console\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(stream.lock().unwrap().as_slice(), b"console\n");
+        assert_eq!(std::fs::read(&log_file).unwrap(), b"console\n");
+
+        // The log file is rotated once over the threshold.
+        writer.write_all(b"more console output\n").unwrap();
+        writer.flush().unwrap();
+        assert!(dir.as_path().join("console.log.1").exists());
+
+        mgr.dump_ring_buffer().unwrap();
+        assert_eq!(std::fs::read(&dump_file).unwrap(), b"put\n");
+
+        let config = ConsoleLogConfig {
+            log_file: Some("/proc/self".to_string()),
+            ..Default::default()
+        };
+        assert!(mgr.set_log_config(&config).is_err());
+    }
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 }
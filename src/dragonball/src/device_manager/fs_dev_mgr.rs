@@ -16,6 +16,7 @@ use crate::config_manager::{
 };
 use crate::device_manager::{
     DbsMmioV2Device, DeviceManager, DeviceMgrError, DeviceOpContext, DeviceVirtioRegionHandler,
+    HotunplugReceiver,
 };
 use crate::get_bucket_update;
 
@@ -327,6 +328,42 @@ impl FsDeviceMgr {
         Ok(())
     }
 
+    /// Remove a shared-fs device, it is hot-unplugged from the guest if the VM is running, and the
+    /// returned receiver then gets whether the guest released it.
+    ///
+    /// The shared-fs service process of a vhost-user-fs device is left to its owner.
+    pub fn remove_device(
+        device_mgr: &mut DeviceManager,
+        ctx: DeviceOpContext,
+        tag: &str,
+    ) -> std::result::Result<Option<HotunplugReceiver>, FsDeviceError> {
+        if !cfg!(feature = "hotplug") && ctx.is_hotplug {
+            return Err(FsDeviceError::UpdateNotAllowedPostBoot);
+        }
+
+        let mut mgr = device_mgr.fs_manager.lock().unwrap();
+        let index = mgr
+            .get_index_of_tag(tag)
+            .ok_or_else(|| FsDeviceError::TagNotExists(tag.to_owned()))?;
+
+        info!(
+            ctx.logger(),
+            "remove shared-fs device";
+            "subsystem" => "shared-fs",
+            "tag" => tag,
+        );
+
+        // Keep the device in the list if the guest can't be asked to release it.
+        let mut hotunplug = None;
+        if let Some(device) = mgr.info_list[index].device.clone() {
+            hotunplug = DeviceManager::hotunplug_mmio_virtio_device(device, ctx)
+                .map_err(FsDeviceError::DeviceManager)?;
+        }
+        mgr.info_list.remove(index);
+
+        Ok(hotunplug)
+    }
+
     /// Attaches all vhost-user-fs devices from the FsDevicesConfig.
     pub fn attach_devices(
         &mut self,
@@ -3,6 +3,8 @@
 
 //! Device manager to manage IO devices for a virtual machine.
 
+#[cfg(feature = "dbs-virtio-devices")]
+use std::collections::BTreeMap;
 #[cfg(target_arch = "aarch64")]
 use std::collections::HashMap;
 
@@ -10,6 +12,8 @@ use std::io;
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use arc_swap::ArcSwap;
+#[cfg(all(feature = "hotplug", feature = "dbs-upcall"))]
+use crossbeam_channel::bounded;
 use dbs_address_space::AddressSpace;
 #[cfg(target_arch = "aarch64")]
 use dbs_arch::{DeviceType, MMIODeviceInfo};
@@ -36,9 +40,11 @@ use dbs_virtio_devices::{
     VirtioDevice,
 };
 
+#[cfg(all(feature = "hotplug", feature = "dbs-upcall", feature = "host-device"))]
+use dbs_upcall::PciDevRequest;
 #[cfg(all(feature = "hotplug", feature = "dbs-upcall"))]
 use dbs_upcall::{
-    DevMgrRequest, DevMgrService, MmioDevRequest, UpcallClient, UpcallClientError,
+    DevMgrRequest, DevMgrResponse, DevMgrService, MmioDevRequest, UpcallClient, UpcallClientError,
     UpcallClientRequest, UpcallClientResponse,
 };
 #[cfg(feature = "hotplug")]
@@ -53,7 +59,7 @@ use crate::IoManagerCached;
 /// Virtual machine console device manager.
 pub mod console_manager;
 /// Console Manager for virtual machines console device.
-pub use self::console_manager::ConsoleManager;
+pub use self::console_manager::{ConsoleLogConfig, ConsoleManager};
 
 mod legacy;
 pub use self::legacy::{Error as LegacyDeviceError, LegacyDeviceManager};
@@ -67,6 +73,8 @@ pub mod vsock_dev_mgr;
 #[cfg(feature = "virtio-vsock")]
 use self::vsock_dev_mgr::VsockDeviceMgr;
 
+#[cfg(feature = "virtio-blk")]
+mod blk_backend;
 #[cfg(feature = "virtio-blk")]
 /// virtio-block device manager
 pub mod blk_dev_mgr;
@@ -89,6 +97,26 @@ mod memory_region_handler;
 #[cfg(feature = "virtio-fs")]
 pub use self::memory_region_handler::*;
 
+#[cfg(feature = "virtio-balloon")]
+/// Device manager for virtio-balloon devices.
+pub mod balloon_dev_mgr;
+#[cfg(feature = "virtio-balloon")]
+use self::balloon_dev_mgr::BalloonDeviceMgr;
+#[cfg(feature = "virtio-balloon")]
+mod balloon_stats;
+
+#[cfg(feature = "host-device")]
+/// Device manager for host devices passed through with VFIO.
+pub mod vfio_dev_mgr;
+#[cfg(feature = "host-device")]
+use self::vfio_dev_mgr::VfioDeviceMgr;
+
+#[cfg(feature = "dbs-virtio-devices")]
+/// Transport state of the Virtio MMIO devices, saved in VM snapshots.
+pub mod virtio_mmio_state;
+#[cfg(feature = "dbs-virtio-devices")]
+use self::virtio_mmio_state::{VirtioMmioDeviceState, VirtioMmioRecorder};
+
 macro_rules! info(
     ($l:expr, $($args:tt)+) => {
         slog::info!($l, $($args)+; slog::o!("subsystem" => "device_manager"))
@@ -155,6 +183,14 @@ pub type DbsVirtioDevice = Box<
 pub type DbsMmioV2Device =
     MmioV2Device<GuestAddressSpaceImpl, virtio_queue::QueueSync, vm_memory::GuestRegionMmap>;
 
+/// Receiver of the outcome of a device hot-unplug, `true` once the guest released the device and
+/// it has been torn down.
+pub type HotunplugReceiver = crossbeam_channel::Receiver<bool>;
+
+/// Recorders of the Virtio MMIO devices, by base address of their MMIO registers.
+#[cfg(feature = "dbs-virtio-devices")]
+type VirtioMmioRecorders = Arc<Mutex<BTreeMap<u64, Arc<VirtioMmioRecorder>>>>;
+
 /// Struct to support transactional operations for device management.
 pub struct DeviceManagerTx {
     io_manager: IoManager,
@@ -248,6 +284,8 @@ pub struct DeviceOpContext {
     upcall_client: Option<Arc<UpcallClient<DevMgrService>>>,
     #[cfg(feature = "dbs-virtio-devices")]
     virtio_devices: Vec<Arc<DbsMmioV2Device>>,
+    #[cfg(feature = "dbs-virtio-devices")]
+    virtio_recorders: VirtioMmioRecorders,
 }
 
 impl DeviceOpContext {
@@ -282,6 +320,8 @@ impl DeviceOpContext {
             upcall_client: None,
             #[cfg(feature = "dbs-virtio-devices")]
             virtio_devices: Vec::new(),
+            #[cfg(feature = "dbs-virtio-devices")]
+            virtio_recorders: device_mgr.virtio_recorders.clone(),
         }
     }
 
@@ -367,8 +407,27 @@ impl DeviceOpContext {
     }
 
     pub(crate) fn remove_hotplug_mmio_device(
+        self,
+        _dev: Arc<dyn DeviceIo>,
+    ) -> Result<HotunplugReceiver> {
+        Err(DeviceMgrError::InvalidOperation)
+    }
+
+    #[cfg(feature = "host-device")]
+    pub(crate) fn insert_hotplug_pci_device(
         &self,
-        _dev: &Arc<DbsMmioV2Device>,
+        _bus_id: u8,
+        _device_id: u8,
+        _callback: Option<()>,
+    ) -> Result<()> {
+        Err(DeviceMgrError::InvalidOperation)
+    }
+
+    #[cfg(feature = "host-device")]
+    pub(crate) fn remove_hotplug_pci_device(
+        &self,
+        _bus_id: u8,
+        _device_id: u8,
         _callback: Option<()>,
     ) -> Result<()> {
         Err(DeviceMgrError::InvalidOperation)
@@ -431,21 +490,108 @@ impl DeviceOpContext {
         self.call_hotplug_device(req, callback)
     }
 
+    /// Ask the guest to release the Virtio MMIO device, which is torn down once released.
+    ///
+    /// The returned receiver gets whether the guest released the device. The resources of a
+    /// device the guest failed to release are kept, since the guest may still access them.
     pub(crate) fn remove_hotplug_mmio_device(
-        &self,
-        dev: &Arc<DbsMmioV2Device>,
-        callback: Option<Box<dyn Fn(UpcallClientResponse) + Send>>,
-    ) -> Result<()> {
+        mut self,
+        dev: Arc<dyn DeviceIo>,
+    ) -> Result<HotunplugReceiver> {
         if !self.is_hotplug {
             return Err(DeviceMgrError::InvalidOperation);
         }
-        let (mmio_base, mmio_size, mmio_irq) = DeviceManager::get_virtio_device_info(dev)?;
+        let mmio_dev = dev
+            .as_any()
+            .downcast_ref::<DbsMmioV2Device>()
+            .ok_or(DeviceMgrError::InvalidOperation)?;
+        let (mmio_base, mmio_size, mmio_irq) = DeviceManager::get_virtio_device_info(mmio_dev)?;
         let req = DevMgrRequest::DelMmioDev(MmioDevRequest {
             mmio_base,
             mmio_size,
             mmio_irq,
         });
 
+        // The response is handled on the epoll thread of the VMM, which can't wait for it. The
+        // callback is kept by the upcall client until the guest answers, so the context moved
+        // into it must not refer to the client.
+        let upcall_client = self
+            .upcall_client
+            .take()
+            .ok_or(DeviceMgrError::InvalidOperation)?;
+        let (sender, receiver) = bounded(1);
+        let pending = Mutex::new(Some((dev, self)));
+        let callback = move |response: UpcallClientResponse| {
+            let (dev, mut ctx) = match pending.lock().unwrap().take() {
+                Some(pending) => pending,
+                None => return,
+            };
+            let released = match response {
+                UpcallClientResponse::DevMgr(DevMgrResponse::Other(resp)) => resp.result == 0,
+                UpcallClientResponse::DevMgr(_) => false,
+                // The guest has been reset, it doesn't use the device anymore.
+                UpcallClientResponse::UpcallReset => true,
+            };
+            let released = if !released {
+                slog::error!(
+                    ctx.logger(),
+                    "guest failed to release virtio mmio device 0x{:x}", mmio_base;
+                    "subsystem" => "device_manager",
+                );
+                false
+            } else if let Err(e) = DeviceManager::destroy_mmio_virtio_device(dev, &mut ctx) {
+                slog::error!(
+                    ctx.logger(),
+                    "failed to teardown virtio mmio device 0x{:x}: {:?}", mmio_base, e;
+                    "subsystem" => "device_manager",
+                );
+                false
+            } else {
+                true
+            };
+            // The API client may have given up waiting.
+            let _ = sender.send(released);
+        };
+        upcall_client
+            .send_request(UpcallClientRequest::DevMgr(req), Box::new(callback))
+            .map_err(DeviceMgrError::HotplugDevice)?;
+
+        Ok(receiver)
+    }
+
+    #[cfg(feature = "host-device")]
+    pub(crate) fn insert_hotplug_pci_device(
+        &self,
+        bus_id: u8,
+        device_id: u8,
+        callback: Option<Box<dyn Fn(UpcallClientResponse) + Send>>,
+    ) -> Result<()> {
+        if !self.is_hotplug {
+            return Err(DeviceMgrError::InvalidOperation);
+        }
+        let req = DevMgrRequest::AddPciDev(PciDevRequest {
+            busno: bus_id,
+            devfn: device_id << 3,
+        });
+
+        self.call_hotplug_device(req, callback)
+    }
+
+    #[cfg(feature = "host-device")]
+    pub(crate) fn remove_hotplug_pci_device(
+        &self,
+        bus_id: u8,
+        device_id: u8,
+        callback: Option<Box<dyn Fn(UpcallClientResponse) + Send>>,
+    ) -> Result<()> {
+        if !self.is_hotplug {
+            return Err(DeviceMgrError::InvalidOperation);
+        }
+        let req = DevMgrRequest::DelPciDev(PciDevRequest {
+            busno: bus_id,
+            devfn: device_id << 3,
+        });
+
         self.call_hotplug_device(req, callback)
     }
 }
@@ -481,6 +627,15 @@ pub struct DeviceManager {
 
     #[cfg(feature = "virtio-fs")]
     fs_manager: Arc<Mutex<FsDeviceMgr>>,
+
+    #[cfg(feature = "virtio-balloon")]
+    pub(crate) balloon_manager: BalloonDeviceMgr,
+
+    #[cfg(feature = "host-device")]
+    pub(crate) vfio_manager: VfioDeviceMgr,
+
+    #[cfg(feature = "dbs-virtio-devices")]
+    virtio_recorders: VirtioMmioRecorders,
 }
 
 impl DeviceManager {
@@ -511,6 +666,12 @@ impl DeviceManager {
             virtio_net_manager: VirtioNetDeviceMgr::default(),
             #[cfg(feature = "virtio-fs")]
             fs_manager: Arc::new(Mutex::new(FsDeviceMgr::default())),
+            #[cfg(feature = "virtio-balloon")]
+            balloon_manager: BalloonDeviceMgr::default(),
+            #[cfg(feature = "host-device")]
+            vfio_manager: VfioDeviceMgr::default(),
+            #[cfg(feature = "dbs-virtio-devices")]
+            virtio_recorders: Arc::new(Mutex::new(BTreeMap::new())),
         }
     }
 
@@ -584,19 +745,30 @@ impl DeviceManager {
         &mut self,
         dmesg_fifo: Option<Box<dyn io::Write + Send>>,
         com1_sock_path: Option<String>,
+        console_log: &ConsoleLogConfig,
         _ctx: &mut DeviceOpContext,
     ) -> std::result::Result<(), StartMicroVmError> {
         // Connect serial ports to the console and dmesg_fifo.
         self.set_guest_kernel_log_stream(dmesg_fifo)
             .map_err(|_| StartMicroVmError::EventFd)?;
 
-        info!(self.logger, "init console path: {:?}", com1_sock_path);
+        info!(
+            self.logger,
+            "init console path: {:?}, log file: {:?}", com1_sock_path, console_log.log_file
+        );
         if let Some(legacy_manager) = self.legacy_manager.as_ref() {
             let com1 = legacy_manager.get_com1_serial();
+            self.con_manager
+                .set_log_config(console_log)
+                .map_err(StartMicroVmError::DeviceManager)?;
             if let Some(path) = com1_sock_path {
                 self.con_manager
                     .create_socket_console(com1, path)
                     .map_err(StartMicroVmError::DeviceManager)?;
+            } else if self.con_manager.has_log_file() {
+                self.con_manager
+                    .create_file_console(com1)
+                    .map_err(StartMicroVmError::DeviceManager)?;
             } else {
                 self.con_manager
                     .create_stdio_console(com1)
@@ -630,6 +802,11 @@ impl DeviceManager {
         self.con_manager.reset_console()
     }
 
+    /// Dump the latest console output kept in the ring buffer, if configured.
+    pub fn dump_console_ring_buffer(&self) -> Result<()> {
+        self.con_manager.dump_ring_buffer()
+    }
+
     /// Create all registered devices when booting the associated virtual machine.
     pub fn create_devices(
         &mut self,
@@ -637,6 +814,7 @@ impl DeviceManager {
         epoll_mgr: EpollManager,
         kernel_config: &mut KernelConfigInfo,
         com1_sock_path: Option<String>,
+        console_log: &ConsoleLogConfig,
         dmesg_fifo: Option<Box<dyn io::Write + Send>>,
         address_space: Option<&AddressSpace>,
     ) -> std::result::Result<(), StartMicroVmError> {
@@ -649,7 +827,7 @@ impl DeviceManager {
         );
 
         self.create_legacy_devices(&mut ctx)?;
-        self.init_legacy_devices(dmesg_fifo, com1_sock_path, &mut ctx)?;
+        self.init_legacy_devices(dmesg_fifo, com1_sock_path, console_log, &mut ctx)?;
 
         #[cfg(feature = "virtio-blk")]
         self.block_manager
@@ -672,6 +850,16 @@ impl DeviceManager {
         #[cfg(feature = "virtio-vsock")]
         self.vsock_manager.attach_devices(&mut ctx)?;
 
+        #[cfg(feature = "virtio-balloon")]
+        self.balloon_manager
+            .attach_devices(&mut ctx)
+            .map_err(StartMicroVmError::BalloonDeviceError)?;
+
+        #[cfg(feature = "host-device")]
+        self.vfio_manager
+            .attach_devices(&mut ctx)
+            .map_err(StartMicroVmError::VfioDeviceError)?;
+
         #[cfg(feature = "virtio-blk")]
         self.block_manager
             .generate_kernel_boot_args(kernel_config)
@@ -692,7 +880,6 @@ impl DeviceManager {
 
     /// Start all registered devices when booting the associated virtual machine.
     pub fn start_devices(&mut self) -> std::result::Result<(), StartMicroVmError> {
-        // TODO: add vfio support here. issue #4589.
         Ok(())
     }
 
@@ -833,7 +1020,7 @@ impl DeviceManager {
 
 #[cfg(feature = "dbs-virtio-devices")]
 impl DeviceManager {
-    fn get_virtio_device_info(device: &Arc<DbsMmioV2Device>) -> Result<(u64, u64, u32)> {
+    fn get_virtio_device_info(device: &DbsMmioV2Device) -> Result<(u64, u64, u32)> {
         let resources = device.get_assigned_resources();
         let irq = resources
             .get_legacy_irq()
@@ -921,6 +1108,24 @@ impl DeviceManager {
         Ok(())
     }
 
+    /// Teardown a Virtio MMIO device being removed, once the guest released it if the VM is
+    /// running.
+    ///
+    /// For a running VM, the guest is asked to release the device through the upcall channel and
+    /// the returned receiver gets whether it did. The device is torn down when the guest answers,
+    /// so the API clients should wait on the receiver before reusing its host resources.
+    pub fn hotunplug_mmio_virtio_device(
+        device: Arc<dyn DeviceIo>,
+        mut ctx: DeviceOpContext,
+    ) -> std::result::Result<Option<HotunplugReceiver>, DeviceMgrError> {
+        #[cfg(feature = "hotplug")]
+        if ctx.is_hotplug {
+            return ctx.remove_hotplug_mmio_device(device).map(Some);
+        }
+
+        Self::destroy_mmio_virtio_device(device, &mut ctx).map(|_| None)
+    }
+
     fn destroy_mmio_device(
         device: Arc<dyn DeviceIo>,
         ctx: &mut DeviceOpContext,
@@ -948,16 +1153,22 @@ impl DeviceManager {
             "create virtio mmio device 0x{:x}@0x{:x}, irq: 0x{:x}", mmio_size, mmio_base, irq
         );
         let resources = device.get_trapped_io_resources();
+        // Guest accesses go through the recorder, to save the device state in VM snapshots.
+        let recorder = Arc::new(VirtioMmioRecorder::new(device.clone(), mmio_base));
 
         let mut tx = ctx.io_context.begin_tx();
         if let Err(e) = ctx
             .io_context
-            .register_device_io(&mut tx, device.clone(), &resources)
+            .register_device_io(&mut tx, recorder.clone(), &resources)
         {
             ctx.io_context.cancel_tx(tx);
             Err(DeviceMgrError::IoManager(e))
         } else {
             ctx.virtio_devices.push(device.clone());
+            ctx.virtio_recorders
+                .lock()
+                .unwrap()
+                .insert(mmio_base, recorder);
             ctx.io_context.commit_tx(tx);
             Ok(device)
         }
@@ -973,15 +1184,41 @@ impl DeviceManager {
             ctx.logger(),
             "unregister mmio virtio device: {:?}", resources
         );
+        let mmio_base = match device.as_any().downcast_ref::<DbsMmioV2Device>() {
+            Some(mmio_dev) => Some(Self::get_virtio_device_info(mmio_dev)?.0),
+            None => None,
+        };
         let mut tx = ctx.io_context.begin_tx();
         if let Err(e) = ctx.io_context.unregister_device_io(&mut tx, &resources) {
             ctx.io_context.cancel_tx(tx);
             Err(DeviceMgrError::IoManager(e))
         } else {
+            if let Some(mmio_base) = mmio_base {
+                ctx.virtio_recorders.lock().unwrap().remove(&mmio_base);
+            }
             ctx.io_context.commit_tx(tx);
             Ok(())
         }
     }
+
+    /// Get the transport state of all Virtio MMIO devices.
+    pub fn virtio_mmio_states(&self) -> Vec<VirtioMmioDeviceState> {
+        self.virtio_recorders
+            .lock()
+            .unwrap()
+            .values()
+            .map(|recorder| recorder.state())
+            .collect()
+    }
+
+    /// Get the recorder of the Virtio MMIO device with registers at `mmio_base`.
+    pub fn virtio_mmio_recorder(&self, mmio_base: u64) -> Option<Arc<VirtioMmioRecorder>> {
+        self.virtio_recorders
+            .lock()
+            .unwrap()
+            .get(&mmio_base)
+            .cloned()
+    }
 }
 
 #[cfg(feature = "hotplug")]
@@ -1039,6 +1276,10 @@ mod tests {
                 virtio_net_manager: VirtioNetDeviceMgr::default(),
                 #[cfg(feature = "virtio-vsock")]
                 vsock_manager: VsockDeviceMgr::default(),
+                #[cfg(feature = "virtio-balloon")]
+                balloon_manager: BalloonDeviceMgr::default(),
+                #[cfg(feature = "host-device")]
+                vfio_manager: VfioDeviceMgr::default(),
                 #[cfg(target_arch = "aarch64")]
                 mmio_device_info: HashMap::new(),
 
@@ -1074,6 +1315,9 @@ mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 16,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 1,
@@ -1108,6 +1352,7 @@ mod tests {
             event_mgr.epoll_manager(),
             &mut cmdline,
             None,
+            &ConsoleLogConfig::default(),
             None,
             address_space.as_ref(),
         )
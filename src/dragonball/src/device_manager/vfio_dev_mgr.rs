@@ -0,0 +1,625 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Device manager for host PCI devices passed through to the guest with VFIO.
+//!
+//! The devices sit on a PCI root bus emulated by dbs-pci, they all share a single VFIO container
+//! whose IOMMU domain maps the whole guest memory, so that the devices can DMA to and from it.
+
+use std::path::Path;
+use std::sync::{Arc, Weak};
+
+use dbs_device::device_manager::IoManagerContext;
+use dbs_device::resources::{DeviceResources, Resource};
+use dbs_device::DeviceIo;
+use dbs_pci::{PciBus, PciRootDevice, VfioPciDevice, VfioPciError};
+use kvm_bindings::{kvm_create_device, kvm_device_type_KVM_DEV_TYPE_VFIO};
+use serde_derive::{Deserialize, Serialize};
+use vfio_ioctls::{VfioContainer, VfioDevice, VfioError};
+use vm_memory::{Address, GuestAddressSpace, GuestMemory, GuestMemoryRegion, MemoryRegionAddress};
+
+use crate::address_space_manager::GuestRegionImpl;
+use crate::config_manager::{ConfigItem, DeviceConfigInfo, DeviceConfigInfos};
+use crate::device_manager::{DeviceManager, DeviceMgrError, DeviceOpContext};
+
+/// Id of the PCI root bus the host devices are plugged into.
+pub const PCI_ROOT_BUS_ID: u8 = 0;
+// Slot 0 of the root bus is taken by the host bridge.
+const PCI_MIN_DEVICE_ID: u8 = 1;
+const PCI_MAX_DEVICE_ID: u8 = 31;
+// PCI configuration space access mechanism #1 ports.
+#[cfg(target_arch = "x86_64")]
+const PCI_CONFIG_IO_PORT: u16 = 0xcf8;
+#[cfg(target_arch = "x86_64")]
+const PCI_CONFIG_IO_PORT_SIZE: u16 = 0x8;
+
+/// Errors associated with host device operations.
+#[derive(Debug, thiserror::Error)]
+pub enum VfioDeviceError {
+    /// The host device id is already in use.
+    #[error("the host device id {0} already exists")]
+    DeviceIdAlreadyExist(String),
+
+    /// The host device is already passed through.
+    #[error("the host device {0} is already passed through")]
+    DeviceAlreadyPassedThrough(String),
+
+    /// The guest PCI device id is already in use.
+    #[error("the guest PCI device id {0} is already in use")]
+    GuestDeviceIdInUse(u8),
+
+    /// The guest PCI device id is out of the slots of the root bus.
+    #[error("invalid guest PCI device id {0}")]
+    InvalidGuestDeviceId(u8),
+
+    /// No free slot left on the PCI root bus.
+    #[error("no PCI device id left on the root bus")]
+    NoGuestDeviceId,
+
+    /// The host device id is invalid.
+    #[error("invalid host device id '{0}'")]
+    InvalidDeviceId(String),
+
+    /// The sysfs path of the host device doesn't exist.
+    #[error("invalid host device sysfs path {0}")]
+    InvalidSysfsPath(String),
+
+    /// The update is not allowed after booting the microvm.
+    #[error("update operation is not allowed after boot")]
+    UpdateNotAllowedPostBoot,
+
+    /// Host PCI passthrough isn't supported on the architecture.
+    #[error("host PCI device passthrough isn't supported on this architecture")]
+    NotSupported,
+
+    /// Failed to create the VFIO container or the KVM VFIO device.
+    #[error("failed to create the VFIO container: {0}")]
+    CreateContainer(#[source] VfioError),
+
+    /// Failed to create the KVM VFIO device.
+    #[error("failed to create the KVM VFIO device: {0}")]
+    CreateKvmDevice(#[source] kvm_ioctls::Error),
+
+    /// Failed to open the host device.
+    #[error("failed to open VFIO device {0}: {1}")]
+    OpenVfioDevice(String, #[source] VfioError),
+
+    /// Failed to create the guest PCI device.
+    #[error("failed to create PCI device: {0}")]
+    CreatePciDevice(#[source] VfioPciError),
+
+    /// Failed to map or unmap the guest memory in the IOMMU domain.
+    #[error("failed to map guest memory for DMA: {0}")]
+    DmaMap(#[source] VfioError),
+
+    /// Failed to allocate the resources of the device.
+    #[error("failed to allocate device resources")]
+    AllocateResource,
+
+    /// Failure from device manager.
+    #[error("failure in device manager operations, {0}")]
+    DeviceManager(#[source] DeviceMgrError),
+}
+
+/// Result for host device operations.
+pub type Result<T> = std::result::Result<T, VfioDeviceError>;
+
+/// Configuration of the guest PCI device a host device is exposed as.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct VfioPciDeviceConfig {
+    /// PCI address of the host device, "domain:bus:slot.function".
+    pub bus_slot_func: String,
+    /// Slot of the device on the guest PCI root bus, the first free one if not set.
+    pub guest_dev_id: Option<u8>,
+}
+
+/// Configuration information for host devices passed through with VFIO.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct HostDeviceConfig {
+    /// Unique identifier of the host device.
+    pub hostdev_id: String,
+    /// Sysfs path of the host device, e.g. "/sys/bus/pci/devices/0000:01:00.0".
+    pub sysfs_path: String,
+    /// Guest PCI device configuration.
+    pub dev_config: VfioPciDeviceConfig,
+}
+
+impl ConfigItem for HostDeviceConfig {
+    type Err = VfioDeviceError;
+
+    fn id(&self) -> &str {
+        &self.hostdev_id
+    }
+
+    fn check_conflicts(&self, other: &Self) -> Result<()> {
+        if self.hostdev_id == other.hostdev_id {
+            Err(VfioDeviceError::DeviceIdAlreadyExist(
+                self.hostdev_id.clone(),
+            ))
+        } else if self.dev_config.bus_slot_func == other.dev_config.bus_slot_func {
+            Err(VfioDeviceError::DeviceAlreadyPassedThrough(
+                self.dev_config.bus_slot_func.clone(),
+            ))
+        } else if self.dev_config.guest_dev_id.is_some()
+            && self.dev_config.guest_dev_id == other.dev_config.guest_dev_id
+        {
+            Err(VfioDeviceError::GuestDeviceIdInUse(
+                self.dev_config.guest_dev_id.unwrap(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Host device info.
+pub type HostDeviceInfo = DeviceConfigInfo<HostDeviceConfig>;
+
+/// Device manager to manage the host devices passed through with VFIO.
+pub struct VfioDeviceMgr {
+    pub(crate) info_list: DeviceConfigInfos<HostDeviceConfig>,
+    // guest slot of each device in `info_list`, once attached
+    guest_dev_ids: Vec<Option<u8>>,
+    container: Option<Arc<VfioContainer>>,
+    pci_bus: Option<Arc<PciBus>>,
+    // whether the guest memory is mapped in the IOMMU domain of the container
+    dma_mapped: bool,
+}
+
+impl VfioDeviceMgr {
+    /// Gets the index of the device with the specified `hostdev_id` if it exists in the list.
+    pub fn get_index_of_hostdev_id(&self, hostdev_id: &str) -> Option<usize> {
+        self.info_list
+            .iter()
+            .position(|info| info.config.hostdev_id.eq(hostdev_id))
+    }
+
+    /// Insert a host device into the manager, it is hot-plugged into the guest if the VM is
+    /// running.
+    pub fn insert_device(
+        device_mgr: &mut DeviceManager,
+        #[allow(unused_mut)] mut ctx: DeviceOpContext,
+        config: HostDeviceConfig,
+    ) -> Result<()> {
+        if !cfg!(feature = "hotplug") && ctx.is_hotplug {
+            return Err(VfioDeviceError::UpdateNotAllowedPostBoot);
+        }
+        if let Some(id) = config.dev_config.guest_dev_id {
+            if !(PCI_MIN_DEVICE_ID..=PCI_MAX_DEVICE_ID).contains(&id) {
+                return Err(VfioDeviceError::InvalidGuestDeviceId(id));
+            }
+        }
+        if !Path::new(&config.sysfs_path).exists() {
+            return Err(VfioDeviceError::InvalidSysfsPath(config.sysfs_path.clone()));
+        }
+
+        let mgr = &mut device_mgr.vfio_manager;
+        slog::info!(
+            ctx.logger(),
+            "add host device configuration";
+            "subsystem" => "vfio_dev_mgr",
+            "id" => &config.hostdev_id,
+            "bdf" => &config.dev_config.bus_slot_func,
+        );
+        let device_index = mgr.info_list.insert_or_update(&config)?;
+        mgr.guest_dev_ids.resize(mgr.info_list.len(), None);
+
+        #[cfg(feature = "hotplug")]
+        if ctx.is_hotplug {
+            if let Err(e) = mgr.attach_device(&mut ctx, device_index) {
+                mgr.remove_info(device_index);
+                return Err(e);
+            }
+            let guest_dev_id = mgr.guest_dev_ids[device_index].unwrap_or_default();
+            if let Err(e) = ctx.insert_hotplug_pci_device(PCI_ROOT_BUS_ID, guest_dev_id, None) {
+                mgr.detach_device(&mut ctx, device_index).ok();
+                mgr.remove_info(device_index);
+                return Err(VfioDeviceError::DeviceManager(e));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove a host device, it is hot-unplugged from the guest if the VM is running.
+    pub fn remove_device(
+        device_mgr: &mut DeviceManager,
+        mut ctx: DeviceOpContext,
+        hostdev_id: &str,
+    ) -> Result<()> {
+        if !cfg!(feature = "hotplug") && ctx.is_hotplug {
+            return Err(VfioDeviceError::UpdateNotAllowedPostBoot);
+        }
+
+        let mgr = &mut device_mgr.vfio_manager;
+        let index = mgr
+            .get_index_of_hostdev_id(hostdev_id)
+            .ok_or_else(|| VfioDeviceError::InvalidDeviceId(hostdev_id.to_owned()))?;
+        slog::info!(
+            ctx.logger(),
+            "remove host device";
+            "subsystem" => "vfio_dev_mgr",
+            "id" => hostdev_id,
+            "bdf" => &mgr.info_list[index].config.dev_config.bus_slot_func,
+        );
+
+        // Keep the device if the guest can't be asked to release it.
+        if let Some(_guest_dev_id) = mgr.guest_dev_ids[index] {
+            #[cfg(feature = "hotplug")]
+            if ctx.is_hotplug {
+                ctx.remove_hotplug_pci_device(PCI_ROOT_BUS_ID, _guest_dev_id, None)
+                    .map_err(VfioDeviceError::DeviceManager)?;
+            }
+            mgr.detach_device(&mut ctx, index)?;
+        }
+        mgr.remove_info(index);
+        if mgr.info_list.is_empty() {
+            mgr.unmap_guest_memory(&ctx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Attach all configured host devices to the virtual machine instance.
+    pub fn attach_devices(&mut self, ctx: &mut DeviceOpContext) -> Result<()> {
+        for index in 0..self.info_list.len() {
+            self.attach_device(ctx, index)?;
+        }
+        Ok(())
+    }
+
+    /// Map a memory region hot-added to the guest in the IOMMU domain, if the guest memory is
+    /// mapped already.
+    pub fn map_guest_memory_region(&self, region: &GuestRegionImpl) -> Result<()> {
+        match self.container.as_ref() {
+            Some(container) if self.dma_mapped => Self::dma_map(container, region),
+            _ => Ok(()),
+        }
+    }
+
+    fn attach_device(&mut self, ctx: &mut DeviceOpContext, index: usize) -> Result<()> {
+        if !cfg!(target_arch = "x86_64") {
+            return Err(VfioDeviceError::NotSupported);
+        }
+        let config = self.info_list[index].config.clone();
+        slog::info!(
+            ctx.logger(),
+            "attach host device";
+            "subsystem" => "vfio_dev_mgr",
+            "id" => &config.hostdev_id,
+            "bdf" => &config.dev_config.bus_slot_func,
+        );
+
+        let container = self.get_container(ctx)?;
+        let bus = self.get_pci_bus(ctx)?;
+        let vfio_dev = VfioDevice::new(Path::new(&config.sysfs_path), container)
+            .map_err(|e| VfioDeviceError::OpenVfioDevice(config.sysfs_path.clone(), e))?;
+        // The group of the device is in the container now, so the guest memory can be mapped.
+        self.map_guest_memory(ctx)?;
+
+        let guest_dev_id = bus
+            .allocate_device_id(config.dev_config.guest_dev_id)
+            .ok_or_else(|| match config.dev_config.guest_dev_id {
+                Some(id) => VfioDeviceError::GuestDeviceIdInUse(id),
+                None => VfioDeviceError::NoGuestDeviceId,
+            })?;
+        match Self::create_pci_device(ctx, &bus, guest_dev_id, &config, vfio_dev) {
+            Ok(device) => {
+                self.guest_dev_ids[index] = Some(guest_dev_id);
+                self.info_list[index].set_device(device);
+                Ok(())
+            }
+            Err(e) => {
+                bus.free_device_id(guest_dev_id);
+                Err(e)
+            }
+        }
+    }
+
+    fn create_pci_device(
+        ctx: &mut DeviceOpContext,
+        bus: &Arc<PciBus>,
+        guest_dev_id: u8,
+        config: &HostDeviceConfig,
+        vfio_dev: VfioDevice,
+    ) -> Result<Arc<dyn DeviceIo>> {
+        let pci_dev = VfioPciDevice::create(
+            guest_dev_id,
+            config.sysfs_path.clone(),
+            Arc::downgrade(bus),
+            vfio_dev,
+            ctx.irq_manager.clone(),
+        )
+        .map_err(VfioDeviceError::CreatePciDevice)?;
+
+        // BARs and interrupts of the device
+        let mut requests = Vec::new();
+        pci_dev.get_resource_requirements(&mut requests);
+        let resources = ctx
+            .res_manager
+            .allocate_device_resources(&requests, false)
+            .map_err(|_| VfioDeviceError::AllocateResource)?;
+
+        let pci_dev = Arc::new(pci_dev);
+        let weak_dev: Weak<dyn DeviceIo> = Arc::downgrade(&(pci_dev.clone() as Arc<dyn DeviceIo>));
+        if let Err(e) = pci_dev.activate(weak_dev, resources.clone()) {
+            ctx.res_manager.free_device_resources(&resources).ok();
+            return Err(VfioDeviceError::CreatePciDevice(e));
+        }
+        if let Err(e) = bus.register_device(pci_dev.clone()) {
+            pci_dev.clear_device().ok();
+            ctx.res_manager.free_device_resources(&resources).ok();
+            return Err(VfioDeviceError::CreatePciDevice(e));
+        }
+
+        let device: Arc<dyn DeviceIo> = pci_dev;
+        if let Err(e) = Self::register_device_io(ctx, &device) {
+            bus.unregister_device(guest_dev_id).ok();
+            ctx.res_manager.free_device_resources(&resources).ok();
+            return Err(e);
+        }
+        Ok(device)
+    }
+
+    fn detach_device(&mut self, ctx: &mut DeviceOpContext, index: usize) -> Result<()> {
+        let guest_dev_id = match self.guest_dev_ids[index].take() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let device = match self.info_list[index].device.take() {
+            Some(device) => device,
+            None => return Ok(()),
+        };
+
+        let mut tx = ctx.io_context.begin_tx();
+        if let Err(e) = ctx
+            .io_context
+            .unregister_device_io(&mut tx, &device.get_trapped_io_resources())
+        {
+            ctx.io_context.cancel_tx(tx);
+            self.guest_dev_ids[index] = Some(guest_dev_id);
+            self.info_list[index].set_device(device);
+            return Err(VfioDeviceError::DeviceManager(DeviceMgrError::IoManager(e)));
+        }
+        ctx.io_context.commit_tx(tx);
+
+        if let Some(bus) = self.pci_bus.as_ref() {
+            bus.unregister_device(guest_dev_id).ok();
+            bus.free_device_id(guest_dev_id);
+        }
+        if let Some(pci_dev) = device.as_any().downcast_ref::<VfioPciDevice>() {
+            pci_dev
+                .clear_device()
+                .map_err(VfioDeviceError::CreatePciDevice)?;
+        }
+        ctx.res_manager
+            .free_device_resources(&device.get_assigned_resources())
+            .map_err(|e| VfioDeviceError::DeviceManager(DeviceMgrError::ResourceError(e)))
+    }
+
+    fn remove_info(&mut self, index: usize) {
+        self.info_list.remove(index);
+        self.guest_dev_ids.remove(index);
+    }
+
+    fn register_device_io(ctx: &mut DeviceOpContext, device: &Arc<dyn DeviceIo>) -> Result<()> {
+        let mut tx = ctx.io_context.begin_tx();
+        if let Err(e) = ctx.io_context.register_device_io(
+            &mut tx,
+            device.clone(),
+            &device.get_trapped_io_resources(),
+        ) {
+            ctx.io_context.cancel_tx(tx);
+            Err(VfioDeviceError::DeviceManager(DeviceMgrError::IoManager(e)))
+        } else {
+            ctx.io_context.commit_tx(tx);
+            Ok(())
+        }
+    }
+
+    // The container is created along with the first device, with the KVM VFIO device which
+    // lets KVM know about the non-coherent DMA of the groups added to it.
+    fn get_container(&mut self, ctx: &DeviceOpContext) -> Result<Arc<VfioContainer>> {
+        if let Some(container) = self.container.as_ref() {
+            return Ok(container.clone());
+        }
+        let mut vfio_dev = kvm_create_device {
+            type_: kvm_device_type_KVM_DEV_TYPE_VFIO,
+            fd: 0,
+            flags: 0,
+        };
+        let kvm_dev = ctx
+            .vm_fd
+            .create_device(&mut vfio_dev)
+            .map_err(VfioDeviceError::CreateKvmDevice)?;
+        let container = Arc::new(
+            VfioContainer::new(Arc::new(kvm_dev)).map_err(VfioDeviceError::CreateContainer)?,
+        );
+        self.container = Some(container.clone());
+        Ok(container)
+    }
+
+    // The root bus and its host bridge are only exposed to the guest along with the first
+    // device, so that guests without host devices don't probe PCI.
+    fn get_pci_bus(&mut self, ctx: &mut DeviceOpContext) -> Result<Arc<PciBus>> {
+        if let Some(bus) = self.pci_bus.as_ref() {
+            return Ok(bus.clone());
+        }
+
+        let bus = Arc::new(PciBus::new(
+            PCI_ROOT_BUS_ID,
+            PCI_MIN_DEVICE_ID,
+            PCI_MAX_DEVICE_ID,
+        ));
+        let mut resources = DeviceResources::new();
+        #[cfg(target_arch = "x86_64")]
+        resources.append(Resource::PioAddressRange {
+            base: PCI_CONFIG_IO_PORT,
+            size: PCI_CONFIG_IO_PORT_SIZE,
+        });
+        let root = PciRootDevice::create(PCI_ROOT_BUS_ID, &resources)
+            .map_err(VfioDeviceError::CreatePciDevice)?;
+        root.add_bus(bus.clone(), PCI_ROOT_BUS_ID)
+            .map_err(VfioDeviceError::CreatePciDevice)?;
+
+        let root: Arc<dyn DeviceIo> = Arc::new(root);
+        Self::register_device_io(ctx, &root)?;
+        self.pci_bus = Some(bus.clone());
+        Ok(bus)
+    }
+
+    fn map_guest_memory(&mut self, ctx: &DeviceOpContext) -> Result<()> {
+        if self.dma_mapped {
+            return Ok(());
+        }
+        let container = match self.container.as_ref() {
+            Some(container) => container,
+            None => return Ok(()),
+        };
+        let vm_as = ctx.get_vm_as().map_err(VfioDeviceError::DeviceManager)?;
+        let vm_memory = vm_as.memory();
+        for region in vm_memory.iter() {
+            Self::dma_map(container, region)?;
+        }
+        self.dma_mapped = true;
+        Ok(())
+    }
+
+    fn unmap_guest_memory(&mut self, ctx: &DeviceOpContext) -> Result<()> {
+        if !self.dma_mapped {
+            return Ok(());
+        }
+        let container = match self.container.as_ref() {
+            Some(container) => container,
+            None => return Ok(()),
+        };
+        let vm_as = ctx.get_vm_as().map_err(VfioDeviceError::DeviceManager)?;
+        let vm_memory = vm_as.memory();
+        for region in vm_memory.iter() {
+            container
+                .vfio_dma_unmap(region.start_addr().raw_value(), region.len())
+                .map_err(VfioDeviceError::DmaMap)?;
+        }
+        self.dma_mapped = false;
+        Ok(())
+    }
+
+    fn dma_map(container: &VfioContainer, region: &GuestRegionImpl) -> Result<()> {
+        let host_addr = region
+            .get_host_address(MemoryRegionAddress(0))
+            .map_err(|_| VfioDeviceError::AllocateResource)?;
+        container
+            .vfio_dma_map(
+                region.start_addr().raw_value(),
+                region.len(),
+                host_addr as u64,
+            )
+            .map_err(VfioDeviceError::DmaMap)
+    }
+}
+
+impl Default for VfioDeviceMgr {
+    /// Create a new host device manager.
+    fn default() -> Self {
+        VfioDeviceMgr {
+            info_list: DeviceConfigInfos::new(),
+            guest_dev_ids: Vec::new(),
+            container: None,
+            pci_bus: None,
+            dma_mapped: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::skip_if_not_root;
+
+    use super::*;
+    use crate::test_utils::tests::create_vm_for_test;
+
+    fn host_device_config(id: &str, bdf: &str, guest_dev_id: Option<u8>) -> HostDeviceConfig {
+        HostDeviceConfig {
+            hostdev_id: id.to_string(),
+            sysfs_path: format!("/sys/bus/pci/devices/{}", bdf),
+            dev_config: VfioPciDeviceConfig {
+                bus_slot_func: bdf.to_string(),
+                guest_dev_id,
+            },
+        }
+    }
+
+    #[test]
+    fn test_host_device_config_check_conflicts() {
+        let config = host_device_config("dev0", "0000:01:00.0", Some(2));
+
+        assert!(matches!(
+            config.check_conflicts(&host_device_config("dev0", "0000:02:00.0", None)),
+            Err(VfioDeviceError::DeviceIdAlreadyExist(_))
+        ));
+        assert!(matches!(
+            config.check_conflicts(&host_device_config("dev1", "0000:01:00.0", None)),
+            Err(VfioDeviceError::DeviceAlreadyPassedThrough(_))
+        ));
+        assert!(matches!(
+            config.check_conflicts(&host_device_config("dev1", "0000:02:00.0", Some(2))),
+            Err(VfioDeviceError::GuestDeviceIdInUse(2))
+        ));
+        assert!(config
+            .check_conflicts(&host_device_config("dev1", "0000:02:00.0", Some(3)))
+            .is_ok());
+        assert!(host_device_config("dev1", "0000:02:00.0", None)
+            .check_conflicts(&host_device_config("dev2", "0000:03:00.0", None))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_vfio_insert_and_remove_before_boot() {
+        skip_if_not_root!();
+
+        let mut vm = create_vm_for_test();
+        let dir = vmm_sys_util::tempdir::TempDir::new().unwrap();
+        let mut config = host_device_config("dev0", "0000:01:00.0", Some(2));
+        config.sysfs_path = dir.as_path().to_str().unwrap().to_string();
+
+        // The devices are only opened when the VM boots.
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        VfioDeviceMgr::insert_device(vm.device_manager_mut(), ctx, config.clone()).unwrap();
+        assert_eq!(vm.device_manager().vfio_manager.info_list.len(), 1);
+
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        let mut other = host_device_config("dev1", "0000:01:00.0", None);
+        other.sysfs_path = config.sysfs_path.clone();
+        assert!(matches!(
+            VfioDeviceMgr::insert_device(vm.device_manager_mut(), ctx, other),
+            Err(VfioDeviceError::DeviceAlreadyPassedThrough(_))
+        ));
+
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        let mut invalid = host_device_config("dev1", "0000:03:00.0", Some(0));
+        invalid.sysfs_path = config.sysfs_path.clone();
+        assert!(matches!(
+            VfioDeviceMgr::insert_device(vm.device_manager_mut(), ctx, invalid),
+            Err(VfioDeviceError::InvalidGuestDeviceId(0))
+        ));
+
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        assert!(matches!(
+            VfioDeviceMgr::insert_device(
+                vm.device_manager_mut(),
+                ctx,
+                host_device_config("dev1", "ffff:ff:1f.7", None)
+            ),
+            Err(VfioDeviceError::InvalidSysfsPath(_))
+        ));
+
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        assert!(matches!(
+            VfioDeviceMgr::remove_device(vm.device_manager_mut(), ctx, "dev1"),
+            Err(VfioDeviceError::InvalidDeviceId(_))
+        ));
+        let ctx = DeviceOpContext::create_boot_ctx(&vm, None);
+        VfioDeviceMgr::remove_device(vm.device_manager_mut(), ctx, "dev0").unwrap();
+        assert!(vm.device_manager().vfio_manager.info_list.is_empty());
+    }
+}
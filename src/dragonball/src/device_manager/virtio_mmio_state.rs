@@ -0,0 +1,254 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transport state of the Virtio MMIO devices, saved in VM snapshots.
+//!
+//! The virtio-mmio transport doesn't let the VMM read back what the guest driver programmed, such
+//! as the driver features and the queue addresses. So the MMIO accesses to the devices go through
+//! a recorder keeping the writes of the driver, which are replayed into the same device of the
+//! restored VM.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use dbs_device::resources::DeviceResources;
+#[cfg(target_arch = "x86_64")]
+use dbs_device::PioAddress;
+use dbs_device::{DeviceIo, IoAddress};
+use serde_derive::{Deserialize, Serialize};
+use virtio_queue::QueueT;
+use vm_memory::{Bytes, GuestAddress, GuestMemory, GuestMemoryError};
+
+use super::DbsMmioV2Device;
+
+// virtio-mmio transport registers, from the virtio 1.1 specification.
+const REG_QUEUE_SEL: u64 = 0x30;
+const REG_QUEUE_NOTIFY: u64 = 0x50;
+const REG_INTERRUPT_ACK: u64 = 0x64;
+const REG_STATUS: u64 = 0x70;
+const REG_QUEUE_DEVICE_LOW: u64 = 0xa0;
+const REG_QUEUE_DEVICE_HIGH: u64 = 0xa4;
+const REG_CONFIG: u64 = 0x100;
+const STATUS_DRIVER_OK: u32 = 0x4;
+// Offset of the index in the used ring.
+const USED_RING_IDX_OFFSET: u64 = 2;
+
+/// Transport state of a Virtio MMIO device.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct VirtioMmioDeviceState {
+    /// Base address of the MMIO registers of the device.
+    pub mmio_base: u64,
+    /// (offset, value) writes of the guest driver to the transport registers since the last
+    /// device reset, in order.
+    pub transport_writes: Vec<(u64, u32)>,
+    /// Last write of the guest driver to each offset of the device configuration space.
+    pub config_writes: BTreeMap<u64, Vec<u8>>,
+}
+
+impl VirtioMmioDeviceState {
+    fn new(mmio_base: u64) -> Self {
+        VirtioMmioDeviceState {
+            mmio_base,
+            ..Default::default()
+        }
+    }
+
+    fn record(&mut self, offset: u64, data: &[u8]) {
+        if offset >= REG_CONFIG {
+            self.config_writes.insert(offset, data.to_vec());
+            return;
+        }
+        // Notifications and interrupt acknowledgements don't change the transport state.
+        if offset == REG_QUEUE_NOTIFY || offset == REG_INTERRUPT_ACK || data.len() != 4 {
+            return;
+        }
+        let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        if offset == REG_STATUS && value == 0 {
+            // The driver resets the device, and programs it again from scratch.
+            self.transport_writes.clear();
+            self.config_writes.clear();
+            return;
+        }
+        self.transport_writes.push((offset, value));
+    }
+
+    /// Guest addresses of the used rings programmed by the driver, by queue index.
+    fn used_rings(&self) -> BTreeMap<u32, u64> {
+        let mut rings = BTreeMap::new();
+        let mut queue = 0;
+        for (offset, value) in self.transport_writes.iter() {
+            match *offset {
+                REG_QUEUE_SEL => queue = *value,
+                REG_QUEUE_DEVICE_LOW => {
+                    let addr: &mut u64 = rings.entry(queue).or_default();
+                    *addr = (*addr & !0xffff_ffff) | *value as u64;
+                }
+                REG_QUEUE_DEVICE_HIGH => {
+                    let addr: &mut u64 = rings.entry(queue).or_default();
+                    *addr = (*addr & 0xffff_ffff) | ((*value as u64) << 32);
+                }
+                _ => {}
+            }
+        }
+        rings
+    }
+}
+
+/// Virtio MMIO device recording the writes of the guest driver to its registers.
+pub struct VirtioMmioRecorder {
+    device: Arc<DbsMmioV2Device>,
+    state: Mutex<VirtioMmioDeviceState>,
+}
+
+impl VirtioMmioRecorder {
+    /// Create a recorder for the Virtio MMIO device with registers at `mmio_base`.
+    pub fn new(device: Arc<DbsMmioV2Device>, mmio_base: u64) -> Self {
+        VirtioMmioRecorder {
+            device,
+            state: Mutex::new(VirtioMmioDeviceState::new(mmio_base)),
+        }
+    }
+
+    /// Get the transport state of the device.
+    pub fn state(&self) -> VirtioMmioDeviceState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Program the device of a restored VM like the guest driver programmed it in the VM the
+    /// snapshot was taken from. The queues resume at the index of their used ring in the restored
+    /// guest memory, so requests in flight when the snapshot was taken are processed again.
+    pub fn restore<M: GuestMemory>(
+        &self,
+        state: &VirtioMmioDeviceState,
+        vm_memory: &M,
+    ) -> std::result::Result<(), GuestMemoryError> {
+        let base = IoAddress(state.mmio_base);
+        for (offset, value) in state.transport_writes.iter() {
+            // The device starts processing its queues once the driver is ready.
+            if *offset == REG_STATUS && *value & STATUS_DRIVER_OK != 0 {
+                self.set_queue_indexes(&state.used_rings(), vm_memory)?;
+            }
+            self.write(base, IoAddress(*offset), &value.to_le_bytes());
+        }
+        for (offset, data) in state.config_writes.iter() {
+            self.write(base, IoAddress(*offset), data);
+        }
+        Ok(())
+    }
+
+    fn set_queue_indexes<M: GuestMemory>(
+        &self,
+        used_rings: &BTreeMap<u32, u64>,
+        vm_memory: &M,
+    ) -> std::result::Result<(), GuestMemoryError> {
+        let mut indexes = BTreeMap::new();
+        for (queue, ring) in used_rings.iter() {
+            let index: u16 = vm_memory.read_obj(GuestAddress(ring + USED_RING_IDX_OFFSET))?;
+            indexes.insert(*queue as usize, index);
+        }
+
+        let mut state = self.device.state();
+        for (queue, config) in state.queues_mut().iter_mut().enumerate() {
+            if let Some(index) = indexes.get(&queue) {
+                config.queue.set_next_avail(*index);
+                config.queue.set_next_used(*index);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DeviceIo for VirtioMmioRecorder {
+    fn read(&self, base: IoAddress, offset: IoAddress, data: &mut [u8]) {
+        self.device.read(base, offset, data)
+    }
+
+    fn write(&self, base: IoAddress, offset: IoAddress, data: &[u8]) {
+        self.device.write(base, offset, data);
+        let mut state = self.state.lock().unwrap();
+        // The doorbell registers of the device are in another range.
+        if base.raw_value() == state.mmio_base {
+            state.record(offset.raw_value(), data);
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pio_read(&self, base: PioAddress, offset: PioAddress, data: &mut [u8]) {
+        self.device.pio_read(base, offset, data)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pio_write(&self, base: PioAddress, offset: PioAddress, data: &[u8]) {
+        self.device.pio_write(base, offset, data)
+    }
+
+    fn get_assigned_resources(&self) -> DeviceResources {
+        self.device.get_assigned_resources()
+    }
+
+    fn get_trapped_io_resources(&self) -> DeviceResources {
+        self.device.get_trapped_io_resources()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(state: &mut VirtioMmioDeviceState, offset: u64, value: u32) {
+        state.record(offset, &value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_virtio_mmio_state_record() {
+        let mut state = VirtioMmioDeviceState::new(0xd000_0000);
+        write(&mut state, REG_STATUS, 0x3);
+        write(&mut state, REG_QUEUE_SEL, 1);
+        write(&mut state, REG_QUEUE_DEVICE_LOW, 0x2000);
+        write(&mut state, REG_QUEUE_DEVICE_HIGH, 0x1);
+        write(&mut state, REG_QUEUE_NOTIFY, 1);
+        write(&mut state, REG_INTERRUPT_ACK, 1);
+        write(&mut state, REG_STATUS, 0xf);
+        state.record(REG_CONFIG + 4, &[1, 2]);
+        state.record(REG_CONFIG + 4, &[3, 4]);
+
+        assert_eq!(
+            state.transport_writes,
+            vec![
+                (REG_STATUS, 0x3),
+                (REG_QUEUE_SEL, 1),
+                (REG_QUEUE_DEVICE_LOW, 0x2000),
+                (REG_QUEUE_DEVICE_HIGH, 0x1),
+                (REG_STATUS, 0xf),
+            ]
+        );
+        assert_eq!(
+            state.config_writes.get(&(REG_CONFIG + 4)),
+            Some(&vec![3, 4])
+        );
+        assert_eq!(state.used_rings().get(&1), Some(&0x1_0000_2000));
+        assert_eq!(state.used_rings().get(&0), None);
+
+        // A device reset drops the state programmed so far.
+        write(&mut state, REG_STATUS, 0);
+        assert!(state.transport_writes.is_empty());
+        assert!(state.config_writes.is_empty());
+        assert!(state.used_rings().is_empty());
+    }
+
+    #[test]
+    fn test_virtio_mmio_state_serde() {
+        let mut state = VirtioMmioDeviceState::new(0xd000_0000);
+        write(&mut state, REG_QUEUE_SEL, 0);
+        state.record(REG_CONFIG, &[0xff]);
+
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: VirtioMmioDeviceState = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, state);
+    }
+}
@@ -20,7 +20,7 @@ use crate::address_space_manager::GuestAddressSpaceImpl;
 use crate::config_manager::{
     ConfigItem, DeviceConfigInfo, DeviceConfigInfos, RateLimiterConfigInfo,
 };
-use crate::device_manager::{DeviceManager, DeviceMgrError, DeviceOpContext};
+use crate::device_manager::{DeviceManager, DeviceMgrError, DeviceOpContext, HotunplugReceiver};
 use crate::get_bucket_update;
 
 use super::DbsMmioV2Device;
@@ -279,6 +279,41 @@ impl VirtioNetDeviceMgr {
         Ok(())
     }
 
+    /// Remove a virtio net device, it is hot-unplugged from the guest if the VM is running, and the
+    /// returned receiver then gets whether the guest released it.
+    pub fn remove_device(
+        device_mgr: &mut DeviceManager,
+        ctx: DeviceOpContext,
+        iface_id: &str,
+    ) -> std::result::Result<Option<HotunplugReceiver>, VirtioNetDeviceError> {
+        if !cfg!(feature = "hotplug") && ctx.is_hotplug {
+            return Err(VirtioNetDeviceError::UpdateNotAllowedPostBoot);
+        }
+
+        let mgr = &mut device_mgr.virtio_net_manager;
+        let index = mgr
+            .get_index_of_iface_id(iface_id)
+            .ok_or_else(|| VirtioNetDeviceError::InvalidIfaceId(iface_id.to_owned()))?;
+
+        slog::info!(
+            ctx.logger(),
+            "remove virtio-net device";
+            "subsystem" => "net_dev_mgr",
+            "id" => iface_id,
+            "host_dev_name" => &mgr.info_list[index].config.host_dev_name,
+        );
+
+        // Keep the device in the list if the guest can't be asked to release it.
+        let mut hotunplug = None;
+        if let Some(device) = mgr.info_list[index].device.clone() {
+            hotunplug = DeviceManager::hotunplug_mmio_virtio_device(device, ctx)
+                .map_err(VirtioNetDeviceError::DeviceManager)?;
+        }
+        mgr.info_list.remove(index);
+
+        Ok(hotunplug)
+    }
+
     /// Update the ratelimiter settings of a virtio net device.
     pub fn update_device_ratelimiters(
         device_mgr: &mut DeviceManager,
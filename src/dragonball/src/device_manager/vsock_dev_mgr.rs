@@ -64,6 +64,10 @@ pub enum VsockDeviceError {
     #[error("vsock id {0} already exists")]
     VsockIdAlreadyExists(String),
 
+    /// The vsock id doesn't exist.
+    #[error("vsock id {0} doesn't exist")]
+    VsockIdNotExists(String),
+
     /// Inner backend create error
     #[error("vsock inner backend create error: {0}")]
     CreateInnerBackend(#[source] std::io::Error),
@@ -195,6 +199,43 @@ impl VsockDeviceMgr {
         Ok(())
     }
 
+    /// Remove a vsock device configuration.
+    ///
+    /// The upcall channel used to hot-unplug devices goes through the vsock device, so vsock
+    /// devices can only be removed before the VM boots.
+    pub fn remove_device(
+        &mut self,
+        ctx: DeviceOpContext,
+        id: &str,
+    ) -> std::result::Result<(), VsockDeviceError> {
+        if ctx.is_hotplug {
+            slog::error!(
+                ctx.logger(),
+                "no support of virtio-vsock device hot-unplug";
+                "subsystem" => SUBSYSTEM,
+                "id" => id,
+            );
+
+            return Err(VsockDeviceError::UpdateNotAllowedPostBoot);
+        }
+
+        let index = self
+            .info_list
+            .iter()
+            .position(|info| info.config.id() == id)
+            .ok_or_else(|| VsockDeviceError::VsockIdNotExists(id.to_owned()))?;
+
+        slog::info!(
+            ctx.logger(),
+            "remove virtio-vsock device configuration";
+            "subsystem" => SUBSYSTEM,
+            "id" => id,
+        );
+        self.info_list.remove(index);
+
+        Ok(())
+    }
+
     /// Attach all configured vsock device to the virtual machine instance.
     pub fn attach_devices(
         &mut self,
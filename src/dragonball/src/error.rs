@@ -188,6 +188,16 @@ pub enum StartMicroVmError {
     /// Virtio-fs errors.
     #[error("virtio-fs errors: {0}")]
     FsDeviceError(#[source] device_manager::fs_dev_mgr::FsDeviceError),
+
+    #[cfg(feature = "virtio-balloon")]
+    /// Virtio-balloon errors.
+    #[error("virtio-balloon errors: {0}")]
+    BalloonDeviceError(#[source] device_manager::balloon_dev_mgr::BalloonDeviceError),
+
+    #[cfg(feature = "host-device")]
+    /// Host device passthrough errors.
+    #[error("host device errors: {0}")]
+    VfioDeviceError(#[source] device_manager::vfio_dev_mgr::VfioDeviceError),
 }
 
 /// Errors associated with starting the instance.
@@ -143,6 +143,14 @@ impl MutEventSubscriber for VmmEpollHandler {
                     }
                     None => warn!("event_manager: leftover exit event in epoll context!"),
                 }
+                // The guest exits by itself on crash, e.g. panic with `panic=1` or triple fault,
+                // keep its latest console output and memory for debugging.
+                if let Err(e) = vm.dump_console_ring_buffer() {
+                    error!("event_manager: failed to dump console ring buffer, {:?}", e);
+                }
+                if let Err(e) = vm.dump_guest_memory_on_crash() {
+                    error!("event_manager: failed to dump guest memory, {:?}", e);
+                }
                 vmm.event_ctx.exit_evt_triggered = true;
                 self.vmm_event_count.fetch_add(1, Ordering::AcqRel);
             }
@@ -25,6 +25,8 @@ pub mod kvm_context;
 pub mod metric;
 /// Resource manager for virtual machines.
 pub mod resource_manager;
+/// Built-in seccomp filters for the VMM and vCPU threads.
+pub mod seccomp;
 /// Signal handler for virtual machines.
 pub mod signal_handler;
 /// Virtual CPU manager for virtual machines.
@@ -46,6 +46,17 @@ pub struct SignalMetrics {
     pub sigsegv: SharedIncMetric,
 }
 
+/// Metrics related to device hotplug operations issued through the VMM API.
+#[derive(Default, Serialize)]
+pub struct DeviceMetrics {
+    /// Number of devices successfully attached.
+    pub attach: SharedIncMetric,
+    /// Number of devices successfully detached.
+    pub detach: SharedIncMetric,
+    /// Number of failed attach or detach operations.
+    pub failures: SharedIncMetric,
+}
+
 /// Structure storing all metrics while enforcing serialization support on them.
 #[derive(Default, Serialize)]
 pub struct DragonballMetrics {
@@ -55,4 +66,28 @@ pub struct DragonballMetrics {
     pub seccomp: SeccompMetrics,
     /// Metrics related to signals.
     pub signals: SignalMetrics,
+    /// Metrics related to virtio-blk devices.
+    pub block: DeviceMetrics,
+    /// Metrics related to virtio-net devices.
+    pub net: DeviceMetrics,
+    /// Metrics related to virtio-fs devices.
+    pub fs: DeviceMetrics,
+}
+
+impl DeviceMetrics {
+    /// Account the result of an attach operation.
+    pub fn record_attach<T, E>(&self, result: &std::result::Result<T, E>) {
+        match result {
+            Ok(_) => self.attach.inc(),
+            Err(_) => self.failures.inc(),
+        }
+    }
+
+    /// Account the result of a detach operation.
+    pub fn record_detach<T, E>(&self, result: &std::result::Result<T, E>) {
+        match result {
+            Ok(_) => self.detach.inc(),
+            Err(_) => self.failures.inc(),
+        }
+    }
 }
@@ -0,0 +1,651 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Built-in seccomp filters for the VMM and vCPU threads.
+//!
+//! The VMM thread installs its filter right before the boot vCPUs start running, and every vCPU
+//! thread installs its own filter before entering `KVM_RUN`. Device worker threads are spawned
+//! either by the VMM thread (device manager, hotplug) or by a vCPU thread (virtio device
+//! activation triggered by a MMIO write), and they inherit the filter of their parent. So the
+//! syscalls needed by the device backends are allowed by both filters.
+//!
+//! The `ioctl` syscall is only allowed for the requests on KVM, TUN, vhost, VFIO, block and
+//! terminal file descriptors issued by Dragonball and its device backends.
+//!
+//! A violation of the built-in filters is only logged by the kernel, until the filters are validated
+//! with all the device backends. A custom filter file chooses its own action, a violation raising
+//! `SIGSYS` is accounted in `METRICS.seccomp.num_faults` by the handler registered with
+//! [`crate::signal_handler::register_sigsys_handler`], which then exits.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::path::Path;
+
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule, TargetArch,
+};
+
+/// Name of the VMM thread filter in a custom seccomp filter file.
+pub const VMM_FILTER_NAME: &str = "vmm";
+/// Name of the vCPU thread filter in a custom seccomp filter file.
+pub const VCPU_FILTER_NAME: &str = "vcpu";
+
+/// Errors associated with the seccomp filters.
+#[derive(Debug, thiserror::Error)]
+pub enum SeccompError {
+    /// The architecture of the host is not supported by seccompiler.
+    #[error("seccomp filters are not supported on architecture {0}")]
+    UnsupportedArch(String),
+
+    /// Failed to build a built-in filter.
+    #[error("failed to build the seccomp filter: {0}")]
+    Backend(#[source] seccompiler::BackendError),
+
+    /// Failed to open the custom filter file.
+    #[error("failed to open the seccomp filter file: {0}")]
+    OpenFile(#[source] std::io::Error),
+
+    /// Failed to compile the custom filter file.
+    #[error("failed to compile the seccomp filter file: {0}")]
+    Compile(#[source] seccompiler::Error),
+
+    /// A filter is missing from the custom filter file.
+    #[error("the seccomp filter file doesn't contain the \"{0}\" filter")]
+    MissingFilter(&'static str),
+}
+
+/// Specialized std::result::Result for seccomp filter operations.
+pub type Result<T> = std::result::Result<T, SeccompError>;
+
+/// Seccomp filters of the Dragonball threads.
+///
+/// An empty filter means that the corresponding threads are not confined.
+#[derive(Clone, Debug, Default)]
+pub struct SeccompFilters {
+    /// Filter applied to the VMM thread.
+    pub vmm: BpfProgram,
+    /// Filter applied to the vCPU threads.
+    pub vcpu: BpfProgram,
+}
+
+impl SeccompFilters {
+    /// Build the built-in filters for the host architecture.
+    pub fn new_default() -> Result<Self> {
+        let vmm = [common_syscalls(), device_syscalls(), vmm_syscalls()].concat();
+        let vcpu = [common_syscalls(), device_syscalls(), vcpu_syscalls()].concat();
+
+        Ok(SeccompFilters {
+            vmm: build_filter(vmm)?,
+            vcpu: build_filter(vcpu)?,
+        })
+    }
+
+    /// Load the filters from a file in the seccompiler JSON format, which must contain the
+    /// "vmm" and "vcpu" filters.
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path).map_err(SeccompError::OpenFile)?;
+        let mut filters: HashMap<String, BpfProgram> =
+            seccompiler::compile_from_json(file, target_arch()?).map_err(SeccompError::Compile)?;
+
+        let vmm = filters
+            .remove(VMM_FILTER_NAME)
+            .ok_or(SeccompError::MissingFilter(VMM_FILTER_NAME))?;
+        let vcpu = filters
+            .remove(VCPU_FILTER_NAME)
+            .ok_or(SeccompError::MissingFilter(VCPU_FILTER_NAME))?;
+
+        Ok(SeccompFilters { vmm, vcpu })
+    }
+}
+
+fn target_arch() -> Result<TargetArch> {
+    TargetArch::try_from(std::env::consts::ARCH)
+        .map_err(|_| SeccompError::UnsupportedArch(std::env::consts::ARCH.to_string()))
+}
+
+fn build_filter(syscalls: Vec<i64>) -> Result<BpfProgram> {
+    // The syscalls are allowed regardless of their arguments, except ioctl.
+    let mut rules: BTreeMap<i64, Vec<SeccompRule>> =
+        syscalls.into_iter().map(|nr| (nr, vec![])).collect();
+    rules.insert(libc::SYS_ioctl, ioctl_rules()?);
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Log,
+        SeccompAction::Allow,
+        target_arch()?,
+    )
+    .map_err(SeccompError::Backend)?;
+
+    filter.try_into().map_err(SeccompError::Backend)
+}
+
+// Allow ioctl for the known requests only, the request is the second argument.
+#[allow(clippy::unnecessary_cast)]
+fn ioctl_rules() -> Result<Vec<SeccompRule>> {
+    ioctls::requests()
+        .into_iter()
+        .map(|request| {
+            let condition =
+                SeccompCondition::new(1, SeccompCmpArgLen::Dword, SeccompCmpOp::Eq, request as u64)
+                    .map_err(SeccompError::Backend)?;
+            SeccompRule::new(vec![condition]).map_err(SeccompError::Backend)
+        })
+        .collect()
+}
+
+// Memory management, signals, synchronization and thread lifecycle.
+fn common_syscalls() -> Vec<i64> {
+    let mut syscalls = vec![
+        libc::SYS_brk,
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_close,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        libc::SYS_futex,
+        libc::SYS_getpid,
+        libc::SYS_getrandom,
+        libc::SYS_gettid,
+        libc::SYS_madvise,
+        libc::SYS_mmap,
+        libc::SYS_mprotect,
+        libc::SYS_mremap,
+        libc::SYS_munmap,
+        libc::SYS_nanosleep,
+        libc::SYS_prctl,
+        libc::SYS_restart_syscall,
+        libc::SYS_rseq,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_sched_yield,
+        libc::SYS_set_robust_list,
+        libc::SYS_sigaltstack,
+        libc::SYS_tgkill,
+    ];
+    #[cfg(target_arch = "x86_64")]
+    syscalls.push(libc::SYS_arch_prctl);
+
+    syscalls
+}
+
+// IO of the device backends, including the inline virtio-fs server.
+fn device_syscalls() -> Vec<i64> {
+    let mut syscalls = vec![
+        libc::SYS_accept4,
+        libc::SYS_dup,
+        libc::SYS_dup3,
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_pwait,
+        libc::SYS_eventfd2,
+        libc::SYS_fadvise64,
+        libc::SYS_fallocate,
+        libc::SYS_fchmod,
+        libc::SYS_fchmodat,
+        libc::SYS_fchown,
+        libc::SYS_fchownat,
+        libc::SYS_fcntl,
+        libc::SYS_fdatasync,
+        libc::SYS_fgetxattr,
+        libc::SYS_flistxattr,
+        libc::SYS_flock,
+        libc::SYS_fremovexattr,
+        libc::SYS_fsetxattr,
+        libc::SYS_fstat,
+        libc::SYS_fstatfs,
+        libc::SYS_fsync,
+        libc::SYS_ftruncate,
+        libc::SYS_getdents64,
+        libc::SYS_getegid,
+        libc::SYS_geteuid,
+        libc::SYS_lgetxattr,
+        libc::SYS_linkat,
+        libc::SYS_llistxattr,
+        libc::SYS_lremovexattr,
+        libc::SYS_lseek,
+        libc::SYS_lsetxattr,
+        libc::SYS_mkdirat,
+        libc::SYS_mknodat,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_newfstatat,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_openat,
+        libc::SYS_pipe2,
+        libc::SYS_ppoll,
+        libc::SYS_pread64,
+        libc::SYS_preadv,
+        libc::SYS_pwrite64,
+        libc::SYS_pwritev,
+        libc::SYS_read,
+        libc::SYS_readlinkat,
+        libc::SYS_readv,
+        libc::SYS_recvfrom,
+        libc::SYS_recvmsg,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_sendmsg,
+        libc::SYS_sendto,
+        libc::SYS_setresgid,
+        libc::SYS_setresuid,
+        libc::SYS_shutdown,
+        libc::SYS_statx,
+        libc::SYS_symlinkat,
+        libc::SYS_sync_file_range,
+        libc::SYS_timerfd_create,
+        libc::SYS_timerfd_settime,
+        libc::SYS_umask,
+        libc::SYS_unlinkat,
+        libc::SYS_utimensat,
+        libc::SYS_write,
+        libc::SYS_writev,
+    ];
+    #[cfg(target_arch = "x86_64")]
+    syscalls.extend_from_slice(&[
+        libc::SYS_epoll_wait,
+        libc::SYS_lstat,
+        libc::SYS_mkdir,
+        libc::SYS_poll,
+        libc::SYS_rename,
+        libc::SYS_stat,
+        libc::SYS_unlink,
+    ]);
+
+    syscalls
+}
+
+// API handling, device management and guest memory management on the VMM thread.
+fn vmm_syscalls() -> Vec<i64> {
+    vec![
+        libc::SYS_bind,
+        libc::SYS_connect,
+        libc::SYS_getsockname,
+        libc::SYS_getsockopt,
+        libc::SYS_kill,
+        libc::SYS_listen,
+        libc::SYS_memfd_create,
+        libc::SYS_mount,
+        libc::SYS_setsockopt,
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_statfs,
+        libc::SYS_umount2,
+        libc::SYS_wait4,
+    ]
+}
+
+// The vCPU threads mostly loop on `KVM_RUN`, which is allowed along with the device IO.
+fn vcpu_syscalls() -> Vec<i64> {
+    vec![libc::SYS_kill]
+}
+
+// ioctl requests issued by Dragonball and its device backends.
+mod ioctls {
+    use std::os::raw::{c_int, c_uint, c_ulong};
+
+    use kvm_bindings::*;
+    use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr};
+
+    // KVM system, VM and vCPU file descriptors.
+    ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+    ioctl_io_nr!(KVM_GET_VCPU_MMAP_SIZE, KVMIO, 0x04);
+    ioctl_io_nr!(KVM_CREATE_VCPU, KVMIO, 0x41);
+    ioctl_iow_nr!(
+        KVM_SET_USER_MEMORY_REGION,
+        KVMIO,
+        0x46,
+        kvm_userspace_memory_region
+    );
+    ioctl_iow_nr!(KVM_IRQ_LINE, KVMIO, 0x61, kvm_irq_level);
+    ioctl_iow_nr!(KVM_SET_GSI_ROUTING, KVMIO, 0x6a, kvm_irq_routing);
+    ioctl_iow_nr!(KVM_IRQFD, KVMIO, 0x76, kvm_irqfd);
+    ioctl_iow_nr!(KVM_IOEVENTFD, KVMIO, 0x79, kvm_ioeventfd);
+    ioctl_io_nr!(KVM_RUN, KVMIO, 0x80);
+    ioctl_ior_nr!(KVM_GET_MP_STATE, KVMIO, 0x98, kvm_mp_state);
+    ioctl_iow_nr!(KVM_SET_MP_STATE, KVMIO, 0x99, kvm_mp_state);
+    ioctl_iow_nr!(KVM_SIGNAL_MSI, KVMIO, 0xa5, kvm_msi);
+    ioctl_iowr_nr!(KVM_CREATE_DEVICE, KVMIO, 0xe0, kvm_create_device);
+    ioctl_iow_nr!(KVM_SET_DEVICE_ATTR, KVMIO, 0xe1, kvm_device_attr);
+    ioctl_iow_nr!(KVM_GET_DEVICE_ATTR, KVMIO, 0xe2, kvm_device_attr);
+    ioctl_iow_nr!(KVM_HAS_DEVICE_ATTR, KVMIO, 0xe3, kvm_device_attr);
+
+    // x86_64 vCPU setup on hotplug, and vCPU and VM state of the snapshots.
+    #[cfg(target_arch = "x86_64")]
+    mod x86_64 {
+        use std::os::raw::c_ulong;
+
+        use kvm_bindings::*;
+        use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr};
+
+        ioctl_iowr_nr!(KVM_GET_MSR_INDEX_LIST, KVMIO, 0x02, kvm_msr_list);
+        ioctl_iowr_nr!(KVM_GET_SUPPORTED_CPUID, KVMIO, 0x05, kvm_cpuid2);
+        ioctl_iowr_nr!(KVM_GET_IRQCHIP, KVMIO, 0x62, kvm_irqchip);
+        ioctl_ior_nr!(KVM_SET_IRQCHIP, KVMIO, 0x63, kvm_irqchip);
+        ioctl_iow_nr!(KVM_SET_CLOCK, KVMIO, 0x7b, kvm_clock_data);
+        ioctl_ior_nr!(KVM_GET_CLOCK, KVMIO, 0x7c, kvm_clock_data);
+        ioctl_ior_nr!(KVM_GET_REGS, KVMIO, 0x81, kvm_regs);
+        ioctl_iow_nr!(KVM_SET_REGS, KVMIO, 0x82, kvm_regs);
+        ioctl_ior_nr!(KVM_GET_SREGS, KVMIO, 0x83, kvm_sregs);
+        ioctl_iow_nr!(KVM_SET_SREGS, KVMIO, 0x84, kvm_sregs);
+        ioctl_iowr_nr!(KVM_GET_MSRS, KVMIO, 0x88, kvm_msrs);
+        ioctl_iow_nr!(KVM_SET_MSRS, KVMIO, 0x89, kvm_msrs);
+        ioctl_ior_nr!(KVM_GET_FPU, KVMIO, 0x8c, kvm_fpu);
+        ioctl_iow_nr!(KVM_SET_FPU, KVMIO, 0x8d, kvm_fpu);
+        ioctl_ior_nr!(KVM_GET_LAPIC, KVMIO, 0x8e, kvm_lapic_state);
+        ioctl_iow_nr!(KVM_SET_LAPIC, KVMIO, 0x8f, kvm_lapic_state);
+        ioctl_iow_nr!(KVM_SET_CPUID2, KVMIO, 0x90, kvm_cpuid2);
+        ioctl_iowr_nr!(KVM_GET_CPUID2, KVMIO, 0x91, kvm_cpuid2);
+        ioctl_io_nr!(KVM_NMI, KVMIO, 0x9a);
+        ioctl_ior_nr!(KVM_GET_PIT2, KVMIO, 0x9f, kvm_pit_state2);
+        ioctl_iow_nr!(KVM_SET_PIT2, KVMIO, 0xa0, kvm_pit_state2);
+        ioctl_ior_nr!(KVM_GET_VCPU_EVENTS, KVMIO, 0x9f, kvm_vcpu_events);
+        ioctl_iow_nr!(KVM_SET_VCPU_EVENTS, KVMIO, 0xa0, kvm_vcpu_events);
+        ioctl_ior_nr!(KVM_GET_DEBUGREGS, KVMIO, 0xa1, kvm_debugregs);
+        ioctl_iow_nr!(KVM_SET_DEBUGREGS, KVMIO, 0xa2, kvm_debugregs);
+        ioctl_io_nr!(KVM_SET_TSC_KHZ, KVMIO, 0xa2);
+        ioctl_io_nr!(KVM_GET_TSC_KHZ, KVMIO, 0xa3);
+        ioctl_ior_nr!(KVM_GET_XSAVE, KVMIO, 0xa4, kvm_xsave);
+        ioctl_iow_nr!(KVM_SET_XSAVE, KVMIO, 0xa5, kvm_xsave);
+        ioctl_ior_nr!(KVM_GET_XCRS, KVMIO, 0xa6, kvm_xcrs);
+        ioctl_iow_nr!(KVM_SET_XCRS, KVMIO, 0xa7, kvm_xcrs);
+        ioctl_io_nr!(KVM_KVMCLOCK_CTRL, KVMIO, 0xad);
+
+        pub(super) fn requests() -> Vec<c_ulong> {
+            vec![
+                KVM_GET_MSR_INDEX_LIST(),
+                KVM_GET_SUPPORTED_CPUID(),
+                KVM_GET_IRQCHIP(),
+                KVM_SET_IRQCHIP(),
+                KVM_SET_CLOCK(),
+                KVM_GET_CLOCK(),
+                KVM_GET_REGS(),
+                KVM_SET_REGS(),
+                KVM_GET_SREGS(),
+                KVM_SET_SREGS(),
+                KVM_GET_MSRS(),
+                KVM_SET_MSRS(),
+                KVM_GET_FPU(),
+                KVM_SET_FPU(),
+                KVM_GET_LAPIC(),
+                KVM_SET_LAPIC(),
+                KVM_SET_CPUID2(),
+                KVM_GET_CPUID2(),
+                KVM_NMI(),
+                KVM_GET_PIT2(),
+                KVM_SET_PIT2(),
+                KVM_GET_VCPU_EVENTS(),
+                KVM_SET_VCPU_EVENTS(),
+                KVM_GET_DEBUGREGS(),
+                KVM_SET_DEBUGREGS(),
+                KVM_SET_TSC_KHZ(),
+                KVM_GET_TSC_KHZ(),
+                KVM_GET_XSAVE(),
+                KVM_SET_XSAVE(),
+                KVM_GET_XCRS(),
+                KVM_SET_XCRS(),
+                KVM_KVMCLOCK_CTRL(),
+            ]
+        }
+    }
+
+    // aarch64 vCPU setup on hotplug.
+    #[cfg(target_arch = "aarch64")]
+    mod aarch64 {
+        use std::os::raw::c_ulong;
+
+        use kvm_bindings::*;
+        use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr};
+
+        ioctl_iow_nr!(KVM_GET_ONE_REG, KVMIO, 0xab, kvm_one_reg);
+        ioctl_iow_nr!(KVM_SET_ONE_REG, KVMIO, 0xac, kvm_one_reg);
+        ioctl_iow_nr!(KVM_ARM_VCPU_INIT, KVMIO, 0xae, kvm_vcpu_init);
+        ioctl_ior_nr!(KVM_ARM_PREFERRED_TARGET, KVMIO, 0xaf, kvm_vcpu_init);
+        ioctl_iowr_nr!(KVM_GET_REG_LIST, KVMIO, 0xb0, kvm_reg_list);
+
+        pub(super) fn requests() -> Vec<c_ulong> {
+            vec![
+                KVM_GET_ONE_REG(),
+                KVM_SET_ONE_REG(),
+                KVM_ARM_VCPU_INIT(),
+                KVM_ARM_PREFERRED_TARGET(),
+                KVM_GET_REG_LIST(),
+            ]
+        }
+    }
+
+    // TUN devices of the virtio-net backend.
+    const TUNTAP: c_uint = 0x54;
+    ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, c_int);
+    ioctl_iow_nr!(TUNSETOFFLOAD, TUNTAP, 208, c_uint);
+    ioctl_ior_nr!(TUNGETIFF, TUNTAP, 210, c_uint);
+    ioctl_iow_nr!(TUNSETVNETHDRSZ, TUNTAP, 216, c_int);
+    ioctl_iow_nr!(TUNSETQUEUE, TUNTAP, 217, c_int);
+
+    // vhost devices, the types have the size of the kernel structures.
+    const VHOST_VIRTIO: c_uint = 0xaf;
+    ioctl_ior_nr!(VHOST_GET_FEATURES, VHOST_VIRTIO, 0x00, u64);
+    ioctl_iow_nr!(VHOST_SET_FEATURES, VHOST_VIRTIO, 0x00, u64);
+    ioctl_io_nr!(VHOST_SET_OWNER, VHOST_VIRTIO, 0x01);
+    ioctl_io_nr!(VHOST_RESET_OWNER, VHOST_VIRTIO, 0x02);
+    // struct vhost_memory
+    ioctl_iow_nr!(VHOST_SET_MEM_TABLE, VHOST_VIRTIO, 0x03, u64);
+    // struct vhost_vring_state
+    ioctl_iow_nr!(VHOST_SET_VRING_NUM, VHOST_VIRTIO, 0x10, u64);
+    // struct vhost_vring_addr
+    ioctl_iow_nr!(VHOST_SET_VRING_ADDR, VHOST_VIRTIO, 0x11, [u64; 5]);
+    ioctl_iow_nr!(VHOST_SET_VRING_BASE, VHOST_VIRTIO, 0x12, u64);
+    ioctl_iowr_nr!(VHOST_GET_VRING_BASE, VHOST_VIRTIO, 0x12, u64);
+    // struct vhost_vring_file
+    ioctl_iow_nr!(VHOST_SET_VRING_KICK, VHOST_VIRTIO, 0x20, u64);
+    ioctl_iow_nr!(VHOST_SET_VRING_CALL, VHOST_VIRTIO, 0x21, u64);
+    ioctl_iow_nr!(VHOST_SET_VRING_ERR, VHOST_VIRTIO, 0x22, u64);
+    ioctl_iow_nr!(VHOST_NET_SET_BACKEND, VHOST_VIRTIO, 0x30, u64);
+
+    // Block devices backing the virtio-blk devices.
+    const BLOCK: c_uint = 0x12;
+    ioctl_io_nr!(BLKSSZGET, BLOCK, 104);
+    ioctl_ior_nr!(BLKGETSIZE64, BLOCK, 114, u64);
+
+    // VFIO container, group and device of the host devices.
+    #[cfg(feature = "host-device")]
+    mod vfio {
+        use std::os::raw::{c_uint, c_ulong};
+
+        use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr};
+
+        const VFIO_TYPE: c_uint = 0x3b;
+        const VFIO_BASE: c_uint = 100;
+        ioctl_io_nr!(VFIO_GET_API_VERSION, VFIO_TYPE, VFIO_BASE);
+        ioctl_io_nr!(VFIO_CHECK_EXTENSION, VFIO_TYPE, VFIO_BASE + 1);
+        ioctl_io_nr!(VFIO_SET_IOMMU, VFIO_TYPE, VFIO_BASE + 2);
+        ioctl_io_nr!(VFIO_GROUP_GET_STATUS, VFIO_TYPE, VFIO_BASE + 3);
+        ioctl_io_nr!(VFIO_GROUP_SET_CONTAINER, VFIO_TYPE, VFIO_BASE + 4);
+        ioctl_io_nr!(VFIO_GROUP_UNSET_CONTAINER, VFIO_TYPE, VFIO_BASE + 5);
+        ioctl_io_nr!(VFIO_GROUP_GET_DEVICE_FD, VFIO_TYPE, VFIO_BASE + 6);
+        ioctl_io_nr!(VFIO_DEVICE_GET_INFO, VFIO_TYPE, VFIO_BASE + 7);
+        ioctl_io_nr!(VFIO_DEVICE_GET_REGION_INFO, VFIO_TYPE, VFIO_BASE + 8);
+        ioctl_io_nr!(VFIO_DEVICE_GET_IRQ_INFO, VFIO_TYPE, VFIO_BASE + 9);
+        ioctl_io_nr!(VFIO_DEVICE_SET_IRQS, VFIO_TYPE, VFIO_BASE + 10);
+        ioctl_io_nr!(VFIO_DEVICE_RESET, VFIO_TYPE, VFIO_BASE + 11);
+        ioctl_io_nr!(VFIO_IOMMU_GET_INFO, VFIO_TYPE, VFIO_BASE + 12);
+        ioctl_io_nr!(VFIO_IOMMU_MAP_DMA, VFIO_TYPE, VFIO_BASE + 13);
+        ioctl_io_nr!(VFIO_IOMMU_UNMAP_DMA, VFIO_TYPE, VFIO_BASE + 14);
+
+        pub(super) fn requests() -> Vec<c_ulong> {
+            vec![
+                VFIO_GET_API_VERSION(),
+                VFIO_CHECK_EXTENSION(),
+                VFIO_SET_IOMMU(),
+                VFIO_GROUP_GET_STATUS(),
+                VFIO_GROUP_SET_CONTAINER(),
+                VFIO_GROUP_UNSET_CONTAINER(),
+                VFIO_GROUP_GET_DEVICE_FD(),
+                VFIO_DEVICE_GET_INFO(),
+                VFIO_DEVICE_GET_REGION_INFO(),
+                VFIO_DEVICE_GET_IRQ_INFO(),
+                VFIO_DEVICE_SET_IRQS(),
+                VFIO_DEVICE_RESET(),
+                VFIO_IOMMU_GET_INFO(),
+                VFIO_IOMMU_MAP_DMA(),
+                VFIO_IOMMU_UNMAP_DMA(),
+            ]
+        }
+    }
+
+    // The request type of libc depends on the C library.
+    #[allow(clippy::unnecessary_cast)]
+    pub(super) fn requests() -> Vec<c_ulong> {
+        let mut requests = vec![
+            KVM_CHECK_EXTENSION(),
+            KVM_GET_VCPU_MMAP_SIZE(),
+            KVM_CREATE_VCPU(),
+            KVM_SET_USER_MEMORY_REGION(),
+            KVM_IRQ_LINE(),
+            KVM_SET_GSI_ROUTING(),
+            KVM_IRQFD(),
+            KVM_IOEVENTFD(),
+            KVM_RUN(),
+            KVM_GET_MP_STATE(),
+            KVM_SET_MP_STATE(),
+            KVM_SIGNAL_MSI(),
+            KVM_CREATE_DEVICE(),
+            KVM_SET_DEVICE_ATTR(),
+            KVM_GET_DEVICE_ATTR(),
+            KVM_HAS_DEVICE_ATTR(),
+            TUNSETIFF(),
+            TUNSETOFFLOAD(),
+            TUNGETIFF(),
+            TUNSETVNETHDRSZ(),
+            TUNSETQUEUE(),
+            VHOST_GET_FEATURES(),
+            VHOST_SET_FEATURES(),
+            VHOST_SET_OWNER(),
+            VHOST_RESET_OWNER(),
+            VHOST_SET_MEM_TABLE(),
+            VHOST_SET_VRING_NUM(),
+            VHOST_SET_VRING_ADDR(),
+            VHOST_SET_VRING_BASE(),
+            VHOST_GET_VRING_BASE(),
+            VHOST_SET_VRING_KICK(),
+            VHOST_SET_VRING_CALL(),
+            VHOST_SET_VRING_ERR(),
+            VHOST_NET_SET_BACKEND(),
+            BLKSSZGET(),
+            BLKGETSIZE64(),
+            // Serial console backends and non blocking sockets.
+            libc::TCGETS as c_ulong,
+            libc::TCSETS as c_ulong,
+            libc::TIOCGWINSZ as c_ulong,
+            libc::FIONBIO as c_ulong,
+            libc::FIOCLEX as c_ulong,
+        ];
+        #[cfg(target_arch = "x86_64")]
+        requests.extend(x86_64::requests());
+        #[cfg(target_arch = "aarch64")]
+        requests.extend(aarch64::requests());
+        #[cfg(feature = "host-device")]
+        requests.extend(vfio::requests());
+
+        requests
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_default_filters() {
+        let filters = SeccompFilters::new_default().unwrap();
+        assert!(!filters.vmm.is_empty());
+        assert!(!filters.vcpu.is_empty());
+
+        let filters = SeccompFilters::default();
+        assert!(filters.vmm.is_empty());
+        assert!(filters.vcpu.is_empty());
+    }
+
+    #[test]
+    fn test_ioctl_requests() {
+        let requests = ioctls::requests();
+        // KVM_RUN, TUNSETIFF and VHOST_SET_OWNER
+        assert!(requests.contains(&0xae80));
+        assert!(requests.contains(&0x4004_54ca));
+        assert!(requests.contains(&0xaf01));
+        assert_eq!(ioctl_rules().unwrap().len(), requests.len());
+    }
+
+    #[test]
+    fn test_console_log_rotation_syscalls() {
+        // The console log file is rotated on the vCPU threads, writing to the serial port, and
+        // on the VMM thread.
+        let syscalls = device_syscalls();
+        for nr in [libc::SYS_openat, libc::SYS_renameat, libc::SYS_unlinkat] {
+            assert!(syscalls.contains(&nr));
+        }
+        #[cfg(target_arch = "x86_64")]
+        for nr in [libc::SYS_rename, libc::SYS_unlink] {
+            assert!(syscalls.contains(&nr));
+        }
+    }
+
+    #[test]
+    fn test_guest_memory_dump_syscalls() {
+        // The guest memory of a crashed guest is dumped on the VMM thread.
+        let syscalls = [device_syscalls(), vmm_syscalls()].concat();
+        for nr in [
+            libc::SYS_mkdirat,
+            libc::SYS_openat,
+            libc::SYS_fstatfs,
+            libc::SYS_unlinkat,
+        ] {
+            assert!(syscalls.contains(&nr));
+        }
+        #[cfg(target_arch = "x86_64")]
+        assert!(syscalls.contains(&libc::SYS_mkdir));
+    }
+
+    #[test]
+    fn test_filters_from_json_file() {
+        let filter = r#"{"mismatch_action": "trap", "match_action": "allow", "filter": [{"syscall": "read"}]}"#;
+
+        let file = TempFile::new().unwrap();
+        write!(
+            file.as_file(),
+            r#"{{"vmm": {}, "vcpu": {}}}"#,
+            filter,
+            filter
+        )
+        .unwrap();
+        let filters = SeccompFilters::from_json_file(file.as_path()).unwrap();
+        assert!(!filters.vmm.is_empty());
+        assert!(!filters.vcpu.is_empty());
+
+        let file = TempFile::new().unwrap();
+        write!(file.as_file(), r#"{{"vmm": {}}}"#, filter).unwrap();
+        assert!(matches!(
+            SeccompFilters::from_json_file(file.as_path()),
+            Err(SeccompError::MissingFilter(VCPU_FILTER_NAME))
+        ));
+
+        let file = TempFile::new().unwrap();
+        write!(file.as_file(), "not a filter").unwrap();
+        assert!(matches!(
+            SeccompFilters::from_json_file(file.as_path()),
+            Err(SeccompError::Compile(_))
+        ));
+
+        assert!(matches!(
+            SeccompFilters::from_json_file("/nonexistent/seccomp.json"),
+            Err(SeccompError::OpenFile(_))
+        ));
+    }
+}
@@ -119,6 +119,15 @@ pub fn register_signal_handlers() -> vmm_sys_util::errno::Result<()> {
     Ok(())
 }
 
+/// Registers the `SIGSYS` handler only.
+///
+/// Used when Dragonball is embedded into another process, which keeps its own handlers for
+/// `SIGBUS` and `SIGSEGV`.
+pub fn register_sigsys_handler() -> vmm_sys_util::errno::Result<()> {
+    // Safe for the same reasons as in register_signal_handlers().
+    register_signal_handler(SIGSYS, sigsys_handler)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -32,6 +32,9 @@ pub mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 1,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 1,
@@ -23,6 +23,7 @@ use dbs_utils::time::TimestampUs;
 use kvm_ioctls::{Cap, VcpuFd, VmFd};
 use log::{debug, error, info};
 use seccompiler::{apply_filter, BpfProgram, Error as SecError};
+use serde_derive::{Deserialize, Serialize};
 use vm_memory::GuestAddress;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -178,7 +179,7 @@ enum VcpuAction {
 }
 
 /// VcpuResizeInfo describes the information for vcpu hotplug / hot-unplug
-#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct VcpuResizeInfo {
     /// The desired vcpu count to resize.
     pub vcpu_count: Option<u8>,
@@ -474,6 +475,16 @@ impl VcpuManager {
             .collect()
     }
 
+    /// return the kvm fds of all vcpus which are either created or running
+    pub(crate) fn vcpu_fds(&self) -> Vec<(u8, Arc<VcpuFd>)> {
+        self.vcpu_infos
+            .iter()
+            .enumerate()
+            .filter(|(_i, info)| info.handle.is_some() || info.vcpu.is_some())
+            .filter_map(|(i, info)| info.vcpu_fd.clone().map(|fd| (i as u8, fd)))
+            .collect()
+    }
+
     /// Get available vcpus to create with target vcpu_count
     /// Argument:
     /// * vcpu_count: target vcpu_count online in VcpuManager.
@@ -1114,6 +1125,9 @@ mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 100,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 3,
@@ -1162,6 +1176,9 @@ mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 1,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 2,
@@ -0,0 +1,282 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Guest memory dump of virtual machine instances.
+//!
+//! The guest memory is dumped into an ELF core file with one `PT_LOAD` segment per guest memory
+//! region, whose physical address is the guest physical address of the region. The segments
+//! are page aligned in the file.
+//!
+//! The core file has no `PT_NOTE` segment: neither the vCPU registers nor the `VMCOREINFO` of
+//! the guest kernel are saved, so it can't be opened by `crash`. The guest memory can be read by
+//! physical address, e.g. to extract the kernel log buffer with the addresses of `System.map`.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use slog::info;
+use vm_memory::{
+    ByteValued, Bytes, GuestAddressSpace, GuestMemory, GuestMemoryError, GuestMemoryRegion,
+};
+
+use crate::api::v1::{GuestMemoryDumpConfigInfo, GuestMemoryDumpError};
+use crate::vm::Vm;
+
+type Result<T> = std::result::Result<T, GuestMemoryDumpError>;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LSB: u8 = 1;
+const ELF_VERSION_CURRENT: u8 = 1;
+const ELF_TYPE_CORE: u16 = 4;
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u16 = 183;
+const PT_LOAD: u32 = 1;
+const PF_RWX: u32 = 0x7;
+const SEGMENT_ALIGN: u64 = 0x1000;
+
+/// ELF64 file header.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Elf64Header {
+    ident: [u8; 16],
+    elf_type: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+// Safe because Elf64Header only contains plain data without padding.
+unsafe impl ByteValued for Elf64Header {}
+
+/// ELF64 program header.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Elf64ProgramHeader {
+    seg_type: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+// Safe because Elf64ProgramHeader only contains plain data without padding.
+unsafe impl ByteValued for Elf64ProgramHeader {}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) / align * align
+}
+
+/// Program headers of the ELF core file of `memory`, and the size of the file.
+fn core_layout<M: GuestMemory>(memory: &M) -> (Vec<Elf64ProgramHeader>, u64) {
+    let header_size = std::mem::size_of::<Elf64Header>() as u64;
+    let phdr_size = std::mem::size_of::<Elf64ProgramHeader>() as u64;
+    let phnum = memory.num_regions() as u64;
+
+    let mut offset = align_up(header_size + phdr_size * phnum, SEGMENT_ALIGN);
+    let mut size = offset;
+    let mut phdrs = Vec::with_capacity(phnum as usize);
+    for region in memory.iter() {
+        phdrs.push(Elf64ProgramHeader {
+            seg_type: PT_LOAD,
+            flags: PF_RWX,
+            offset,
+            paddr: region.start_addr().0,
+            filesz: region.len(),
+            memsz: region.len(),
+            align: SEGMENT_ALIGN,
+            ..Default::default()
+        });
+        size = offset + region.len();
+        offset = align_up(size, SEGMENT_ALIGN);
+    }
+
+    (phdrs, size)
+}
+
+/// Space available to unprivileged users in the filesystem holding `file`, in bytes.
+fn available_space(file: &File) -> io::Result<u64> {
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // Safe because the file descriptor is valid and the result is checked.
+    let ret = unsafe { libc::fstatvfs(file.as_raw_fd(), stat.as_mut_ptr()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because fstatvfs() succeeded and initialized the structure.
+    let stat = unsafe { stat.assume_init() };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Write the ELF core file of `memory` into `writer`.
+fn write_core<M: GuestMemory, W: Write>(
+    memory: &M,
+    writer: &mut W,
+) -> std::result::Result<(), GuestMemoryError> {
+    let header_size = std::mem::size_of::<Elf64Header>() as u64;
+    let phdr_size = std::mem::size_of::<Elf64ProgramHeader>() as u64;
+    let (phdrs, _) = core_layout(memory);
+    let phnum = phdrs.len() as u64;
+
+    let mut ident = [0u8; 16];
+    ident[..4].copy_from_slice(&ELF_MAGIC);
+    ident[4] = ELF_CLASS_64;
+    ident[5] = ELF_DATA_LSB;
+    ident[6] = ELF_VERSION_CURRENT;
+    let header = Elf64Header {
+        ident,
+        elf_type: ELF_TYPE_CORE,
+        machine: ELF_MACHINE,
+        version: ELF_VERSION_CURRENT as u32,
+        phoff: header_size,
+        ehsize: header_size as u16,
+        phentsize: phdr_size as u16,
+        phnum: phnum as u16,
+        ..Default::default()
+    };
+
+    writer
+        .write_all(header.as_slice())
+        .map_err(GuestMemoryError::IOError)?;
+    let mut written = header_size;
+    for phdr in phdrs.iter() {
+        writer
+            .write_all(phdr.as_slice())
+            .map_err(GuestMemoryError::IOError)?;
+        written += phdr_size;
+    }
+    for (region, phdr) in memory.iter().zip(phdrs.iter()) {
+        let padding = vec![0u8; (phdr.offset - written) as usize];
+        writer
+            .write_all(&padding)
+            .map_err(GuestMemoryError::IOError)?;
+        memory.write_all_to(region.start_addr(), writer, region.len() as usize)?;
+        written = phdr.offset + region.len();
+    }
+
+    Ok(())
+}
+
+impl Vm {
+    /// Dump the guest memory into the ELF core file given by `config`.
+    ///
+    /// The vCPUs should be paused, or stopped, for the dump to be consistent.
+    pub fn dump_guest_memory(&self, config: &GuestMemoryDumpConfigInfo) -> Result<()> {
+        let vm_as = self.vm_as().ok_or(GuestMemoryDumpError::NoGuestMemory)?;
+        let path = config.dump_path.as_path();
+        info!(self.logger, "VM: dump guest memory to {}", path.display());
+
+        let file = File::create(path).map_err(|e| GuestMemoryDumpError::Io(path.into(), e))?;
+        let vm_memory = vm_as.memory();
+        // Don't fill up the filesystem, which may be shared with the host.
+        let (_, size) = core_layout(vm_memory.deref());
+        let available =
+            available_space(&file).map_err(|e| GuestMemoryDumpError::Io(path.into(), e))?;
+        if available < size {
+            drop(file);
+            let _ = fs::remove_file(path);
+            return Err(GuestMemoryDumpError::NoSpace(path.into(), size, available));
+        }
+
+        let mut writer = BufWriter::new(file);
+        write_core(vm_memory.deref(), &mut writer).map_err(GuestMemoryDumpError::GuestMemory)?;
+        writer
+            .flush()
+            .map_err(|e| GuestMemoryDumpError::Io(path.into(), e))?;
+
+        info!(self.logger, "VM: guest memory dumped");
+        Ok(())
+    }
+
+    /// Dump the guest memory of a crashed guest into the directory given by the
+    /// `guest_memory_dump_path` configuration, if any.
+    pub fn dump_guest_memory_on_crash(&self) -> Result<()> {
+        let dir = match self.vm_config.guest_memory_dump_path.as_ref() {
+            Some(dir) => Path::new(dir),
+            None => return Ok(()),
+        };
+        fs::create_dir_all(dir).map_err(|e| GuestMemoryDumpError::Io(dir.into(), e))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        self.dump_guest_memory(&GuestMemoryDumpConfigInfo {
+            dump_path: dir.join(format!("vmcore-{}.elf", timestamp)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vm_memory::{GuestAddress, GuestMemoryMmap};
+
+    fn read_obj<T: ByteValued>(buf: &[u8], offset: usize) -> T {
+        T::from_slice(&buf[offset..offset + std::mem::size_of::<T>()])
+            .copied()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_write_core() {
+        assert_eq!(std::mem::size_of::<Elf64Header>(), 64);
+        assert_eq!(std::mem::size_of::<Elf64ProgramHeader>(), 56);
+
+        let memory: GuestMemoryMmap = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x2000),
+            (GuestAddress(0x100000), 0x1000),
+        ])
+        .unwrap();
+        memory.write_obj(0x5au8, GuestAddress(0x10)).unwrap();
+        memory.write_obj(0xa5u8, GuestAddress(0x100fff)).unwrap();
+
+        let mut core = Vec::new();
+        write_core(&memory, &mut core).unwrap();
+        assert_eq!(core.len(), 0x4000);
+        assert_eq!(core_layout(&memory).1, 0x4000);
+
+        let header: Elf64Header = read_obj(&core, 0);
+        assert_eq!(header.ident[..4], ELF_MAGIC);
+        assert_eq!(header.elf_type, ELF_TYPE_CORE);
+        assert_eq!(header.machine, ELF_MACHINE);
+        assert_eq!(header.phnum, 2);
+
+        let phdr: Elf64ProgramHeader = read_obj(&core, header.phoff as usize);
+        assert_eq!(phdr.seg_type, PT_LOAD);
+        assert_eq!(phdr.offset, 0x1000);
+        assert_eq!(phdr.paddr, 0);
+        assert_eq!(phdr.filesz, 0x2000);
+        assert_eq!(core[phdr.offset as usize + 0x10], 0x5a);
+
+        let phdr: Elf64ProgramHeader = read_obj(&core, header.phoff as usize + 56);
+        assert_eq!(phdr.offset, 0x3000);
+        assert_eq!(phdr.paddr, 0x100000);
+        assert_eq!(phdr.memsz, 0x1000);
+        assert_eq!(core[phdr.offset as usize + 0xfff], 0xa5);
+    }
+
+    #[test]
+    fn test_available_space() {
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        assert!(available_space(file.as_file()).unwrap() > 0);
+    }
+}
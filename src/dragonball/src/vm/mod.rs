@@ -20,7 +20,9 @@ use linux_loader::loader::{KernelLoader, KernelLoaderResult};
 use seccompiler::BpfProgram;
 use serde_derive::{Deserialize, Serialize};
 use slog::{error, info};
-use vm_memory::{Bytes, GuestAddress, GuestAddressSpace};
+#[cfg(feature = "host-device")]
+use vm_memory::GuestMemory;
+use vm_memory::{Address, Bytes, GuestAddress, GuestAddressSpace};
 use vmm_sys_util::eventfd::EventFd;
 
 #[cfg(all(feature = "hotplug", feature = "dbs-upcall"))]
@@ -33,7 +35,11 @@ use crate::address_space_manager::{
     GuestMemoryImpl,
 };
 use crate::api::v1::{InstanceInfo, InstanceState};
-use crate::device_manager::console_manager::DmesgWriter;
+#[cfg(feature = "hotplug")]
+use crate::api::v1::{
+    MemoryRegionConfigInfo, MemoryRegionError, MemoryRegionInfo, MEMORY_BLOCK_SIZE_MIB,
+};
+use crate::device_manager::console_manager::{ConsoleLogConfig, DmesgWriter};
 use crate::device_manager::{DeviceManager, DeviceMgrError, DeviceOpContext};
 use crate::error::{LoadInitrdError, Result, StartMicroVmError, StopMicrovmError};
 use crate::event_manager::EventManager;
@@ -56,6 +62,15 @@ mod aarch64;
 #[path = "x86_64.rs"]
 mod x86_64;
 
+mod memory_dump;
+
+#[cfg(target_arch = "x86_64")]
+mod snapshot;
+#[cfg(target_arch = "x86_64")]
+pub use self::snapshot::{
+    MemoryRegionSnapshotInfo, SnapshotInfo, VcpuSnapshotInfo, SNAPSHOT_META_FILE, SNAPSHOT_VERSION,
+};
+
 /// Errors associated with virtual machine instance related operations.
 #[derive(Debug, thiserror::Error)]
 pub enum VmError {
@@ -116,7 +131,8 @@ impl Default for CpuTopology {
 }
 
 /// Configuration information for virtual machine instance.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
 pub struct VmConfigInfo {
     /// Number of vcpu to start.
     pub vcpu_count: u8,
@@ -138,6 +154,13 @@ pub struct VmConfigInfo {
 
     /// sock path
     pub serial_path: Option<String>,
+    /// Capture of the guest console output into a log file and a ring buffer.
+    pub console_log: ConsoleLogConfig,
+    /// Directory to dump the guest memory into when the guest crashes.
+    pub guest_memory_dump_path: Option<String>,
+    /// NUMA regions splitting the guest memory and vCPUs, whose memory may be bound to host NUMA
+    /// nodes. A single region holding all the memory and vCPUs is used if empty.
+    pub numa_regions: Vec<NumaRegionInfo>,
 }
 
 impl Default for VmConfigInfo {
@@ -157,6 +180,9 @@ impl Default for VmConfigInfo {
             mem_file_path: String::from(""),
             mem_size_mib: 128,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
         }
     }
 }
@@ -454,6 +480,7 @@ impl Vm {
         info!(self.logger, "VM: initializing devices ...");
 
         let com1_sock_path = self.vm_config.serial_path.clone();
+        let console_log = self.vm_config.console_log.clone();
         let kernel_config = self
             .kernel_config
             .as_mut()
@@ -476,6 +503,7 @@ impl Vm {
             epoll_manager,
             kernel_config,
             com1_sock_path,
+            &console_log,
             self.dmesg_fifo.take(),
             self.address_space.address_space(),
         )?;
@@ -516,6 +544,11 @@ impl Vm {
         self.device_manager.reset_console()
     }
 
+    /// Dump the latest console output of the guest, if the console ring buffer is configured.
+    pub fn dump_console_ring_buffer(&self) -> std::result::Result<(), DeviceMgrError> {
+        self.device_manager.dump_console_ring_buffer()
+    }
+
     pub(crate) fn init_dmesg_logger(&mut self) {
         let writer = self.dmesg_logger();
         self.dmesg_fifo = Some(writer);
@@ -537,29 +570,24 @@ impl Vm {
         let mem_size = (self.vm_config.mem_size_mib as u64) << 20;
 
         let mem_type = self.vm_config.mem_type.clone();
-        let mut mem_file_path = String::from("");
-        if mem_type == "hugetlbfs" {
-            mem_file_path = self.vm_config.mem_file_path.clone();
-            let shared_info = self.shared_info.read()
-                    .expect("Failed to determine if instance is initialized because shared info couldn't be read due to poisoned lock");
-            mem_file_path.push_str("/dragonball/");
-            mem_file_path.push_str(shared_info.id.as_str());
-        }
+        let mem_file_path = self.mem_file_path();
 
-        let mut vcpu_ids: Vec<u32> = Vec::new();
-        for i in 0..self.vm_config().max_vcpu_count {
-            vcpu_ids.push(i as u32);
-        }
+        let mut numa_regions = self.vm_config.numa_regions.clone();
+        if numa_regions.is_empty() {
+            let mut vcpu_ids: Vec<u32> = Vec::new();
+            for i in 0..self.vm_config().max_vcpu_count {
+                vcpu_ids.push(i as u32);
+            }
 
-        // init default regions.
-        let mut numa_regions = Vec::with_capacity(1);
-        let numa_node = NumaRegionInfo {
-            size: self.vm_config.mem_size_mib as u64,
-            host_numa_node_id: None,
-            guest_numa_node_id: Some(0),
-            vcpu_ids,
-        };
-        numa_regions.push(numa_node);
+            // init default regions.
+            let numa_node = NumaRegionInfo {
+                size: self.vm_config.mem_size_mib as u64,
+                host_numa_node_id: None,
+                guest_numa_node_id: Some(0),
+                vcpu_ids,
+            };
+            numa_regions.push(numa_node);
+        }
 
         info!(
             self.logger,
@@ -581,6 +609,18 @@ impl Vm {
         Ok(())
     }
 
+    fn mem_file_path(&self) -> String {
+        let mut mem_file_path = String::from("");
+        if self.vm_config.mem_type == "hugetlbfs" {
+            mem_file_path = self.vm_config.mem_file_path.clone();
+            let shared_info = self.shared_info.read()
+                    .expect("Failed to determine if instance is initialized because shared info couldn't be read due to poisoned lock");
+            mem_file_path.push_str("/dragonball/");
+            mem_file_path.push_str(shared_info.id.as_str());
+        }
+        mem_file_path
+    }
+
     fn init_configure_system(
         &mut self,
         vm_as: &GuestAddressSpaceImpl,
@@ -834,6 +874,65 @@ impl Vm {
         }
     }
 
+    /// Hot-add guest memory to the MicroVM. The guest has to probe and online it.
+    pub fn insert_memory_region(
+        &mut self,
+        config: MemoryRegionConfigInfo,
+    ) -> std::result::Result<MemoryRegionInfo, MemoryRegionError> {
+        if config.size_mib == 0 || config.size_mib % MEMORY_BLOCK_SIZE_MIB != 0 {
+            return Err(MemoryRegionError::InvalidSize(config.size_mib));
+        }
+        if !self.is_vm_initialized() {
+            return Err(MemoryRegionError::UpdateNotAllowedPreBoot);
+        }
+
+        let mem_type = self.vm_config.mem_type.clone();
+        let mem_file_path = self.mem_file_path();
+        let mut param = AddressSpaceMgrBuilder::new(&mem_type, &mem_file_path)
+            .map_err(MemoryRegionError::AddressManager)?;
+        param.set_kvm_vm_fd(self.vm_fd.clone());
+        let numa_region = NumaRegionInfo {
+            size: config.size_mib,
+            host_numa_node_id: None,
+            guest_numa_node_id: Some(0),
+            vcpu_ids: Vec::new(),
+        };
+        let region = self
+            .address_space
+            .insert_memory_region(
+                &self.resource_manager,
+                config.size_mib << 20,
+                MEMORY_BLOCK_SIZE_MIB << 20,
+                &numa_region,
+                param,
+            )
+            .map_err(MemoryRegionError::AddressManager)?;
+        self.vm_config.mem_size_mib += config.size_mib as usize;
+
+        // Host devices DMA to the whole guest memory.
+        #[cfg(feature = "host-device")]
+        if let Some(vm_as) = self.vm_as() {
+            let vm_memory = vm_as.memory();
+            if let Some(guest_region) = vm_memory.find_region(region.start_addr()) {
+                self.device_manager
+                    .vfio_manager
+                    .map_guest_memory_region(guest_region)
+                    .map_err(MemoryRegionError::HostDevice)?;
+            }
+        }
+
+        info!(
+            self.logger,
+            "VM: hot-added {} MiB of guest memory at 0x{:x}",
+            config.size_mib,
+            region.start_addr().raw_value()
+        );
+        Ok(MemoryRegionInfo {
+            guest_addr: region.start_addr().raw_value(),
+            size_mib: config.size_mib,
+        })
+    }
+
     // We will support hotplug without upcall in future stages.
     #[cfg(not(feature = "dbs-upcall"))]
     fn create_device_hotplug_context(
@@ -907,6 +1006,9 @@ pub mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 16,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 1,
@@ -933,6 +1035,9 @@ pub mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 16,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 1,
@@ -972,6 +1077,9 @@ pub mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 16,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 1,
@@ -1042,6 +1150,9 @@ pub mod tests {
             mem_file_path: "".to_string(),
             mem_size_mib: 10,
             serial_path: None,
+            console_log: Default::default(),
+            guest_memory_dump_path: None,
+            numa_regions: Vec::new(),
             cpu_topology: CpuTopology {
                 threads_per_core: 1,
                 cores_per_die: 1,
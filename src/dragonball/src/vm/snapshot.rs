@@ -0,0 +1,602 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Snapshot and restore of x86_64 virtual machine instances.
+//!
+//! A snapshot is a directory holding:
+//! - `vm.state`: the in-kernel device state, that's the kvm clock, PIC, IOAPIC and PIT;
+//! - `vcpu-<id>.state`: the register, LAPIC and event state of each vCPU;
+//! - `memory-<index>`: the content of each guest memory region;
+//! - `snapshot.json`: the metadata, including vCPU MSRs, the guest memory layout and the
+//!   transport state of the Virtio MMIO devices.
+//!
+//! The metadata file is written last, so a snapshot without it is incomplete. The virtio queues
+//! live in guest memory, and the devices of the restored VM resume them at the index of their
+//! used ring, so requests in flight when the snapshot was taken are processed again.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use dbs_utils::epoll_manager::EpollManager;
+use dbs_utils::time::TimestampUs;
+use kvm_bindings::{
+    kvm_clock_data, kvm_debugregs, kvm_irqchip, kvm_lapic_state, kvm_mp_state, kvm_msr_entry,
+    kvm_pit_state2, kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs, kvm_xsave, Msrs,
+    KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
+};
+use kvm_ioctls::VcpuFd;
+use seccompiler::BpfProgram;
+use serde_derive::{Deserialize, Serialize};
+use slog::info;
+use vm_memory::{
+    ByteValued, Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryMmap,
+    GuestMemoryRegion,
+};
+
+use crate::api::v1::{InstanceState, SnapshotConfigInfo, SnapshotError};
+#[cfg(feature = "dbs-virtio-devices")]
+use crate::device_manager::virtio_mmio_state::VirtioMmioDeviceState;
+use crate::event_manager::EventManager;
+use crate::vm::Vm;
+
+type Result<T> = std::result::Result<T, SnapshotError>;
+
+/// Version of the snapshot layout.
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// Name of the snapshot metadata file.
+pub const SNAPSHOT_META_FILE: &str = "snapshot.json";
+const VM_STATE_FILE: &str = "vm.state";
+
+/// In-kernel device state of the virtual machine.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VmKvmState {
+    pit: kvm_pit_state2,
+    pic_master: kvm_irqchip,
+    pic_slave: kvm_irqchip,
+    ioapic: kvm_irqchip,
+    clock: kvm_clock_data,
+}
+
+// Safe because VmKvmState only contains plain KVM data structures.
+unsafe impl ByteValued for VmKvmState {}
+
+/// KVM state of a vCPU, except for MSRs which are stored in the metadata.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VcpuKvmState {
+    regs: kvm_regs,
+    sregs: kvm_sregs,
+    xsave: kvm_xsave,
+    xcrs: kvm_xcrs,
+    debugregs: kvm_debugregs,
+    lapic: kvm_lapic_state,
+    vcpu_events: kvm_vcpu_events,
+    mp_state: kvm_mp_state,
+}
+
+// Safe because VcpuKvmState only contains plain KVM data structures.
+unsafe impl ByteValued for VcpuKvmState {}
+
+/// MSRs of a vCPU.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct VcpuSnapshotInfo {
+    /// vCPU index.
+    pub id: u8,
+    /// (index, value) pairs of the saved MSRs.
+    pub msrs: Vec<(u32, u64)>,
+}
+
+/// Layout of a guest memory region.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MemoryRegionSnapshotInfo {
+    /// Guest physical address of the region.
+    pub guest_addr: u64,
+    /// Size of the region in bytes.
+    pub size: u64,
+}
+
+/// Metadata of a snapshot, stored as `snapshot.json`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct SnapshotInfo {
+    /// Version of the snapshot layout.
+    pub version: u32,
+    /// Saved vCPUs.
+    pub vcpus: Vec<VcpuSnapshotInfo>,
+    /// Saved guest memory regions, in the order of the `memory-<index>` files.
+    pub memory: Vec<MemoryRegionSnapshotInfo>,
+    /// Transport state of the Virtio MMIO devices.
+    #[cfg(feature = "dbs-virtio-devices")]
+    #[serde(default)]
+    pub virtio_devices: Vec<VirtioMmioDeviceState>,
+}
+
+impl SnapshotInfo {
+    /// Load the snapshot metadata from a snapshot directory.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(SNAPSHOT_META_FILE);
+        let file = File::open(&path).map_err(|e| SnapshotError::Io(path, e))?;
+        let info: SnapshotInfo = serde_json::from_reader(file).map_err(SnapshotError::Metadata)?;
+        if info.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::ConfigMismatch(format!(
+                "unsupported snapshot version {}",
+                info.version
+            )));
+        }
+        Ok(info)
+    }
+
+    fn store(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(SNAPSHOT_META_FILE);
+        let file = File::create(&path).map_err(|e| SnapshotError::Io(path, e))?;
+        serde_json::to_writer(file, self).map_err(SnapshotError::Metadata)
+    }
+}
+
+fn vcpu_state_path(dir: &Path, id: u8) -> PathBuf {
+    dir.join(format!("vcpu-{}.state", id))
+}
+
+fn memory_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("memory-{}", index))
+}
+
+fn store_state<T: ByteValued>(path: PathBuf, state: &T) -> Result<()> {
+    File::create(&path)
+        .and_then(|mut f| f.write_all(state.as_slice()))
+        .map_err(|e| SnapshotError::Io(path, e))
+}
+
+fn load_state<T: ByteValued>(path: PathBuf) -> Result<T> {
+    let mut state = T::default();
+    File::open(&path)
+        .and_then(|mut f| f.read_exact(state.as_mut_slice()))
+        .map_err(|e| SnapshotError::Io(path, e))?;
+    Ok(state)
+}
+
+fn memory_layout(vm_memory: &GuestMemoryMmap) -> Vec<MemoryRegionSnapshotInfo> {
+    vm_memory
+        .iter()
+        .map(|region| MemoryRegionSnapshotInfo {
+            guest_addr: region.start_addr().0,
+            size: region.len(),
+        })
+        .collect()
+}
+
+fn save_memory(dir: &Path, vm_memory: &GuestMemoryMmap) -> Result<Vec<MemoryRegionSnapshotInfo>> {
+    let memory = memory_layout(vm_memory);
+    for (index, region) in memory.iter().enumerate() {
+        let path = memory_path(dir, index);
+        let mut file = File::create(&path).map_err(|e| SnapshotError::Io(path, e))?;
+        vm_memory
+            .write_all_to(
+                GuestAddress(region.guest_addr),
+                &mut file,
+                region.size as usize,
+            )
+            .map_err(SnapshotError::GuestMemory)?;
+    }
+    Ok(memory)
+}
+
+fn restore_memory(
+    dir: &Path,
+    vm_memory: &GuestMemoryMmap,
+    memory: &[MemoryRegionSnapshotInfo],
+) -> Result<()> {
+    if memory_layout(vm_memory) != memory {
+        return Err(SnapshotError::ConfigMismatch(
+            "guest memory layout differs".to_string(),
+        ));
+    }
+    for (index, region) in memory.iter().enumerate() {
+        let path = memory_path(dir, index);
+        let mut file = File::open(&path).map_err(|e| SnapshotError::Io(path, e))?;
+        vm_memory
+            .read_exact_from(
+                GuestAddress(region.guest_addr),
+                &mut file,
+                region.size as usize,
+            )
+            .map_err(SnapshotError::GuestMemory)?;
+    }
+    Ok(())
+}
+
+fn save_vcpu(fd: &VcpuFd, msr_indexes: &[u32]) -> Result<(VcpuKvmState, Vec<(u32, u64)>)> {
+    let state = VcpuKvmState {
+        regs: fd.get_regs().map_err(SnapshotError::Kvm)?,
+        sregs: fd.get_sregs().map_err(SnapshotError::Kvm)?,
+        xsave: fd.get_xsave().map_err(SnapshotError::Kvm)?,
+        xcrs: fd.get_xcrs().map_err(SnapshotError::Kvm)?,
+        debugregs: fd.get_debug_regs().map_err(SnapshotError::Kvm)?,
+        lapic: fd.get_lapic().map_err(SnapshotError::Kvm)?,
+        vcpu_events: fd.get_vcpu_events().map_err(SnapshotError::Kvm)?,
+        mp_state: fd.get_mp_state().map_err(SnapshotError::Kvm)?,
+    };
+
+    let entries: Vec<kvm_msr_entry> = msr_indexes
+        .iter()
+        .map(|index| kvm_msr_entry {
+            index: *index,
+            ..Default::default()
+        })
+        .collect();
+    let mut msrs = Msrs::from_entries(&entries)
+        .map_err(|_| SnapshotError::ConfigMismatch(format!("too many MSRs: {}", entries.len())))?;
+    let count = fd.get_msrs(&mut msrs).map_err(SnapshotError::Kvm)?;
+    let msrs = msrs.as_slice()[..count]
+        .iter()
+        .map(|entry| (entry.index, entry.data))
+        .collect();
+
+    Ok((state, msrs))
+}
+
+fn restore_vcpu(fd: &VcpuFd, state: &VcpuKvmState, msrs: &[(u32, u64)]) -> Result<()> {
+    // The ordering follows the KVM requirements: sregs before LAPIC since the LAPIC base is part
+    // of sregs, MSRs before the vCPU events, and the MP state last.
+    fd.set_regs(&state.regs).map_err(SnapshotError::Kvm)?;
+    fd.set_sregs(&state.sregs).map_err(SnapshotError::Kvm)?;
+    fd.set_xsave(&state.xsave).map_err(SnapshotError::Kvm)?;
+    fd.set_xcrs(&state.xcrs).map_err(SnapshotError::Kvm)?;
+    fd.set_debug_regs(&state.debugregs)
+        .map_err(SnapshotError::Kvm)?;
+    fd.set_lapic(&state.lapic).map_err(SnapshotError::Kvm)?;
+
+    let entries: Vec<kvm_msr_entry> = msrs
+        .iter()
+        .map(|(index, data)| kvm_msr_entry {
+            index: *index,
+            data: *data,
+            ..Default::default()
+        })
+        .collect();
+    let msrs = Msrs::from_entries(&entries)
+        .map_err(|_| SnapshotError::ConfigMismatch(format!("too many MSRs: {}", entries.len())))?;
+    let count = fd.set_msrs(&msrs).map_err(SnapshotError::Kvm)?;
+    if count != entries.len() {
+        return Err(SnapshotError::ConfigMismatch(format!(
+            "only {} of {} MSRs restored",
+            count,
+            entries.len()
+        )));
+    }
+
+    fd.set_vcpu_events(&state.vcpu_events)
+        .map_err(SnapshotError::Kvm)?;
+    fd.set_mp_state(state.mp_state).map_err(SnapshotError::Kvm)
+}
+
+impl Vm {
+    /// Save the vCPU state, the in-kernel device state and the guest memory of a paused VM into
+    /// the directory given by `config`.
+    pub fn create_snapshot(&self, config: &SnapshotConfigInfo) -> Result<()> {
+        if self.shared_info.read().unwrap().state != InstanceState::Paused {
+            return Err(SnapshotError::MicroVMNotPaused);
+        }
+        let dir = config.snapshot_path.as_path();
+        info!(self.logger, "VM: create snapshot at {}", dir.display());
+        fs::create_dir_all(dir).map_err(|e| SnapshotError::Io(dir.to_path_buf(), e))?;
+        // Invalidate a previous snapshot before overwriting it.
+        let meta_path = dir.join(SNAPSHOT_META_FILE);
+        if meta_path.exists() {
+            fs::remove_file(&meta_path).map_err(|e| SnapshotError::Io(meta_path, e))?;
+        }
+
+        let vm_state = VmKvmState {
+            pit: self.vm_fd.get_pit2().map_err(SnapshotError::Kvm)?,
+            pic_master: self.get_irqchip(KVM_IRQCHIP_PIC_MASTER)?,
+            pic_slave: self.get_irqchip(KVM_IRQCHIP_PIC_SLAVE)?,
+            ioapic: self.get_irqchip(KVM_IRQCHIP_IOAPIC)?,
+            clock: self.vm_fd.get_clock().map_err(SnapshotError::Kvm)?,
+        };
+        store_state(dir.join(VM_STATE_FILE), &vm_state)?;
+
+        let msr_list = self.kvm.supported_msrs(0).map_err(SnapshotError::Kvm)?;
+        let msr_indexes = msr_list.as_slice();
+
+        let mut vcpus = Vec::new();
+        let vcpu_fds = self.vcpu_manager().map_err(SnapshotError::Vcpu)?.vcpu_fds();
+        for (id, fd) in vcpu_fds {
+            let (state, msrs) = save_vcpu(&fd, msr_indexes)?;
+            store_state(vcpu_state_path(dir, id), &state)?;
+            vcpus.push(VcpuSnapshotInfo { id, msrs });
+        }
+
+        let vm_as = self
+            .vm_as()
+            .ok_or_else(|| SnapshotError::ConfigMismatch("no guest memory".to_string()))?;
+        let memory = save_memory(dir, vm_as.memory().deref())?;
+
+        SnapshotInfo {
+            version: SNAPSHOT_VERSION,
+            vcpus,
+            memory,
+            #[cfg(feature = "dbs-virtio-devices")]
+            virtio_devices: self.device_manager.virtio_mmio_states(),
+        }
+        .store(dir)?;
+
+        info!(self.logger, "VM: snapshot created");
+        Ok(())
+    }
+
+    /// Restore the VM from the snapshot in the directory given by `config`, and start the vCPUs.
+    ///
+    /// The VM must have been configured the same way as the one the snapshot was created from:
+    /// same boot source, machine configuration and devices.
+    pub fn restore_microvm(
+        &mut self,
+        config: &SnapshotConfigInfo,
+        event_mgr: &mut EventManager,
+        vmm_seccomp_filter: BpfProgram,
+        vcpu_seccomp_filter: BpfProgram,
+    ) -> Result<()> {
+        info!(self.logger, "VM: received instance restore command");
+        if self.is_vm_initialized() {
+            return Err(SnapshotError::LoadNotAllowedPostBoot);
+        }
+        let dir = config.snapshot_path.as_path();
+        let snapshot = SnapshotInfo::load(dir)?;
+        if snapshot.vcpus.len() != self.vm_config.vcpu_count as usize {
+            return Err(SnapshotError::ConfigMismatch(format!(
+                "snapshot has {} vcpus while {} are configured",
+                snapshot.vcpus.len(),
+                self.vm_config.vcpu_count
+            )));
+        }
+
+        self.init_restored_microvm(event_mgr.epoll_manager(), vcpu_seccomp_filter)
+            .map_err(SnapshotError::StartMicroVm)?;
+
+        let vm_as = self
+            .vm_as()
+            .cloned()
+            .ok_or_else(|| SnapshotError::ConfigMismatch("no guest memory".to_string()))?;
+        let vm_memory = vm_as.memory();
+        restore_memory(dir, vm_memory.deref(), &snapshot.memory)?;
+
+        // The devices are programmed before the vCPUs run, like the guest drivers left them.
+        #[cfg(feature = "dbs-virtio-devices")]
+        for state in snapshot.virtio_devices.iter() {
+            let recorder = self
+                .device_manager
+                .virtio_mmio_recorder(state.mmio_base)
+                .ok_or_else(|| {
+                    SnapshotError::ConfigMismatch(format!(
+                        "no virtio device at 0x{:x}",
+                        state.mmio_base
+                    ))
+                })?;
+            recorder
+                .restore(state, vm_memory.deref().deref())
+                .map_err(SnapshotError::GuestMemory)?;
+        }
+
+        let vm_state: VmKvmState = load_state(dir.join(VM_STATE_FILE))?;
+        self.vm_fd
+            .set_pit2(&vm_state.pit)
+            .map_err(SnapshotError::Kvm)?;
+        for irqchip in [vm_state.pic_master, vm_state.pic_slave, vm_state.ioapic].iter() {
+            self.vm_fd
+                .set_irqchip(irqchip)
+                .map_err(SnapshotError::Kvm)?;
+        }
+        // The clock flags returned by KVM_GET_CLOCK aren't accepted by KVM_SET_CLOCK.
+        let clock = kvm_clock_data {
+            clock: vm_state.clock.clock,
+            ..Default::default()
+        };
+        self.vm_fd.set_clock(&clock).map_err(SnapshotError::Kvm)?;
+
+        let vcpu_fds = self.vcpu_manager().map_err(SnapshotError::Vcpu)?.vcpu_fds();
+        for vcpu in snapshot.vcpus.iter() {
+            let fd = vcpu_fds
+                .iter()
+                .find(|(id, _)| *id == vcpu.id)
+                .map(|(_, fd)| fd)
+                .ok_or_else(|| {
+                    SnapshotError::ConfigMismatch(format!("vcpu {} doesn't exist", vcpu.id))
+                })?;
+            let state: VcpuKvmState = load_state(vcpu_state_path(dir, vcpu.id))?;
+            restore_vcpu(fd, &state, &vcpu.msrs)?;
+        }
+
+        #[cfg(feature = "dbs-upcall")]
+        self.init_upcall().map_err(SnapshotError::StartMicroVm)?;
+
+        info!(self.logger, "VM: register events");
+        self.register_events(event_mgr)
+            .map_err(SnapshotError::StartMicroVm)?;
+
+        info!(self.logger, "VM: start vcpus");
+        self.vcpu_manager()
+            .map_err(SnapshotError::Vcpu)?
+            .start_boot_vcpus(vmm_seccomp_filter)
+            .map_err(SnapshotError::Vcpu)?;
+
+        // Use expect() to crash if the other thread poisoned this lock.
+        self.shared_info
+            .write()
+            .expect("Failed to restore microVM because shared info couldn't be written due to poisoned lock")
+            .state = InstanceState::Running;
+
+        info!(self.logger, "VM restored");
+        Ok(())
+    }
+
+    /// Create the guest memory, devices and vCPUs the snapshot is loaded into.
+    fn init_restored_microvm(
+        &mut self,
+        epoll_mgr: EpollManager,
+        vcpu_seccomp_filter: BpfProgram,
+    ) -> std::result::Result<(), crate::error::StartMicroVmError> {
+        use crate::address_space_manager::AddressManagerError;
+        use crate::error::StartMicroVmError;
+
+        let request_ts = TimestampUs::default();
+        self.start_instance_request_ts = request_ts.time_us;
+        self.start_instance_request_cpu_ts = request_ts.cputime_us;
+
+        self.init_dmesg_logger();
+        self.check_health()?;
+
+        // Use expect() to crash if the other thread poisoned this lock.
+        self.shared_info
+            .write()
+            .expect("Failed to restore microVM because shared info couldn't be written due to poisoned lock")
+            .state = InstanceState::Starting;
+
+        self.init_guest_memory()?;
+        let vm_as = self
+            .vm_as()
+            .cloned()
+            .ok_or(StartMicroVmError::AddressManagerError(
+                AddressManagerError::GuestMemoryNotInitialized,
+            ))?;
+
+        self.init_vcpu_manager(vm_as.clone(), vcpu_seccomp_filter)
+            .map_err(StartMicroVmError::Vcpu)?;
+        // The kernel loaded here is overwritten by the guest memory of the snapshot.
+        self.init_microvm(epoll_mgr, vm_as, request_ts)
+    }
+
+    fn get_irqchip(&self, chip_id: u32) -> Result<kvm_irqchip> {
+        let mut irqchip = kvm_irqchip {
+            chip_id,
+            ..Default::default()
+        };
+        self.vm_fd
+            .get_irqchip(&mut irqchip)
+            .map_err(SnapshotError::Kvm)?;
+        Ok(irqchip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvm_ioctls::Kvm;
+    use test_utils::skip_if_not_root;
+    use vmm_sys_util::tempdir::TempDir;
+
+    use super::*;
+    use crate::vm::tests::create_vm_instance;
+
+    #[test]
+    fn test_snapshot_info_store_load() {
+        let dir = TempDir::new().unwrap();
+        let info = SnapshotInfo {
+            version: SNAPSHOT_VERSION,
+            vcpus: vec![VcpuSnapshotInfo {
+                id: 0,
+                msrs: vec![(0x174, 0x10)],
+            }],
+            memory: vec![MemoryRegionSnapshotInfo {
+                guest_addr: 0,
+                size: 0x1000_0000,
+            }],
+            #[cfg(feature = "dbs-virtio-devices")]
+            virtio_devices: Vec::new(),
+        };
+        info.store(dir.as_path()).unwrap();
+        assert_eq!(SnapshotInfo::load(dir.as_path()).unwrap(), info);
+
+        let info = SnapshotInfo {
+            version: SNAPSHOT_VERSION + 1,
+            ..info
+        };
+        info.store(dir.as_path()).unwrap();
+        assert!(matches!(
+            SnapshotInfo::load(dir.as_path()),
+            Err(SnapshotError::ConfigMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_state_store_load() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join(VM_STATE_FILE);
+        let mut state = VmKvmState::default();
+        state.clock.clock = 0x1234;
+        store_state(path.clone(), &state).unwrap();
+        let loaded: VmKvmState = load_state(path.clone()).unwrap();
+        assert_eq!(loaded.clock, state.clock);
+
+        // A truncated state file is rejected.
+        File::create(&path).unwrap();
+        assert!(load_state::<VmKvmState>(path).is_err());
+    }
+
+    #[test]
+    fn test_memory_save_restore() {
+        let dir = TempDir::new().unwrap();
+        let ranges = [
+            (GuestAddress(0), 0x10000),
+            (GuestAddress(0x100000), 0x10000),
+        ];
+        let src = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        src.write_obj(0xdead_beef_u32, GuestAddress(0x1000))
+            .unwrap();
+        src.write_obj(0x1234_u16, GuestAddress(0x10fffe)).unwrap();
+        let memory = save_memory(dir.as_path(), &src).unwrap();
+
+        let dst = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        restore_memory(dir.as_path(), &dst, &memory).unwrap();
+        assert_eq!(
+            dst.read_obj::<u32>(GuestAddress(0x1000)).unwrap(),
+            0xdead_beef
+        );
+        assert_eq!(dst.read_obj::<u16>(GuestAddress(0x10fffe)).unwrap(), 0x1234);
+
+        // The memory of the restored VM must have the same layout.
+        let dst = GuestMemoryMmap::from_ranges(&ranges[..1]).unwrap();
+        assert!(matches!(
+            restore_memory(dir.as_path(), &dst, &memory),
+            Err(SnapshotError::ConfigMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_vcpu_save_restore() {
+        skip_if_not_root!();
+        let dir = TempDir::new().unwrap();
+        let kvm = Kvm::new().unwrap();
+        let msr_list = dbs_arch::msr::supported_guest_msrs(&kvm).unwrap();
+
+        let src_vm = kvm.create_vm().unwrap();
+        src_vm.create_irq_chip().unwrap();
+        let src = src_vm.create_vcpu(0).unwrap();
+        let mut regs = src.get_regs().unwrap();
+        regs.rip = 0x1000;
+        regs.rax = 0x1234;
+        src.set_regs(&regs).unwrap();
+        let (state, msrs) = save_vcpu(&src, msr_list.as_slice()).unwrap();
+        store_state(vcpu_state_path(dir.as_path(), 0), &state).unwrap();
+
+        let dst_vm = kvm.create_vm().unwrap();
+        dst_vm.create_irq_chip().unwrap();
+        let dst = dst_vm.create_vcpu(0).unwrap();
+        let state: VcpuKvmState = load_state(vcpu_state_path(dir.as_path(), 0)).unwrap();
+        restore_vcpu(&dst, &state, &msrs).unwrap();
+        assert_eq!(dst.get_regs().unwrap(), regs);
+        assert_eq!(dst.get_sregs().unwrap(), src.get_sregs().unwrap());
+    }
+
+    #[test]
+    fn test_create_snapshot_not_paused() {
+        skip_if_not_root!();
+        let vm = create_vm_instance();
+        let config = SnapshotConfigInfo {
+            snapshot_path: TempDir::new().unwrap().as_path().to_path_buf(),
+        };
+        assert!(matches!(
+            vm.create_snapshot(&config),
+            Err(SnapshotError::MicroVMNotPaused)
+        ));
+    }
+}
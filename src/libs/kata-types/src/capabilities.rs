@@ -17,6 +17,12 @@ pub enum CapabilityBits {
     MultiQueueSupport,
     /// hypervisor supports filesystem share
     FsSharingSupport,
+    /// hypervisor supports network device rate limiters
+    NetRateLimiterSupport,
+    /// hypervisor supports network devices backed by queue fds opened by the runtime
+    NetQueueFdsSupport,
+    /// hypervisor supports vhost-user network devices
+    VhostUserNetSupport,
 }
 
 /// Capabilities describe a virtcontainers hypervisor capabilities through a bit mask.
@@ -64,6 +70,22 @@ impl Capabilities {
     pub fn is_fs_sharing_supported(&self) -> bool {
         self.flags.and(CapabilityBits::FsSharingSupport) != 0
     }
+
+    /// is_net_rate_limiter_supported tells if an hypervisor supports rate limiting network devices.
+    pub fn is_net_rate_limiter_supported(&self) -> bool {
+        self.flags.and(CapabilityBits::NetRateLimiterSupport) != 0
+    }
+
+    /// is_net_queue_fds_supported tells if an hypervisor supports network devices backed by
+    /// queue fds opened by the runtime.
+    pub fn is_net_queue_fds_supported(&self) -> bool {
+        self.flags.and(CapabilityBits::NetQueueFdsSupport) != 0
+    }
+
+    /// is_vhost_user_net_supported tells if an hypervisor supports vhost-user network devices.
+    pub fn is_vhost_user_net_supported(&self) -> bool {
+        self.flags.and(CapabilityBits::VhostUserNetSupport) != 0
+    }
 }
 
 #[cfg(test)]
@@ -102,6 +124,21 @@ mod tests {
                 | CapabilityBits::MultiQueueSupport
                 | CapabilityBits::FsSharingSupport,
         );
-        assert!(cap.is_fs_sharing_supported())
+        assert!(cap.is_fs_sharing_supported());
+        assert!(!cap.is_net_rate_limiter_supported());
+
+        // test set network rate limiter support
+        cap.set(CapabilityBits::FsSharingSupport | CapabilityBits::NetRateLimiterSupport);
+        assert!(cap.is_net_rate_limiter_supported());
+        assert!(!cap.is_net_queue_fds_supported());
+
+        // test set network queue fds support
+        cap.set(CapabilityBits::FsSharingSupport | CapabilityBits::NetQueueFdsSupport);
+        assert!(cap.is_net_queue_fds_supported());
+        assert!(!cap.is_vhost_user_net_supported());
+
+        // test set vhost-user network device support
+        cap.set(CapabilityBits::FsSharingSupport | CapabilityBits::VhostUserNetSupport);
+        assert!(cap.is_vhost_user_net_supported());
     }
 }
@@ -85,3 +85,6 @@ pub const DEFAULT_CH_PCI_BRIDGES: u32 = 2;
 pub const MAX_CH_PCI_BRIDGES: u32 = 5;
 pub const MAX_CH_VCPUS: u32 = 256;
 pub const MIN_CH_MEMORY_SIZE_MB: u32 = 64;
+
+// Default configuration for the VM factory
+pub const DEFAULT_TEMPLATE_PATH: &str = "/run/vc/vm/template";
@@ -0,0 +1,201 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::io::Result;
+use std::path::Path;
+
+use super::default;
+use super::hypervisor::{HYPERVISOR_NAME_QEMU, VIRTIO_FS, VIRTIO_FS_INLINE};
+use crate::config::{ConfigOps, TomlConfig};
+use crate::eother;
+
+/// Name of the guest memory file of a VM template.
+pub const TEMPLATE_MEMORY_FILE: &str = "memory";
+/// Name of the device state file of a VM template.
+pub const TEMPLATE_STATE_FILE: &str = "state";
+
+/// Hypervisors supporting VM templating.
+///
+/// Dragonball isn't one of them: its snapshots are restored by copying the guest memory, which
+/// then isn't shared with the template.
+pub const TEMPLATE_HYPERVISORS: &[&str] = &[HYPERVISOR_NAME_QEMU];
+
+/// VM factory configuration information.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Factory {
+    /// Enable VM templating support.
+    ///
+    /// Once enabled, new VMs are cloned from a template VM, sharing the initial kernel, initramfs
+    /// and agent memory copy-on-write. This speeds up sandbox creation and saves memory when many
+    /// sandboxes run on the same host. The template must be created by `kata-ctl factory init`
+    /// beforehand, otherwise VMs are created from scratch.
+    ///
+    /// Requires `initrd` to be set (`image` is not supported) and can't be used with virtio-fs,
+    /// which needs the guest memory to be shared with the host.
+    #[serde(default)]
+    pub enable_template: bool,
+
+    /// Path of the directory holding the VM template.
+    #[serde(default)]
+    pub template_path: String,
+
+    /// Number of VMs to keep in the VM cache, 0 to disable it.
+    ///
+    /// The VM cache of the Go runtime isn't implemented by runtime-rs, a non-zero value is
+    /// rejected.
+    #[serde(default)]
+    pub vm_cache_number: u32,
+}
+
+impl Factory {
+    /// Path of the guest memory file of the VM template.
+    pub fn template_memory_path(&self) -> String {
+        Path::new(&self.template_path)
+            .join(TEMPLATE_MEMORY_FILE)
+            .display()
+            .to_string()
+    }
+
+    /// Path of the device state file of the VM template.
+    pub fn template_state_path(&self) -> String {
+        Path::new(&self.template_path)
+            .join(TEMPLATE_STATE_FILE)
+            .display()
+            .to_string()
+    }
+}
+
+impl ConfigOps for Factory {
+    fn adjust_config(conf: &mut TomlConfig) -> Result<()> {
+        let factory = &mut conf.factory;
+        if factory.template_path.is_empty() {
+            factory.template_path = default::DEFAULT_TEMPLATE_PATH.to_string();
+        }
+
+        Ok(())
+    }
+
+    fn validate(conf: &TomlConfig) -> Result<()> {
+        if conf.factory.vm_cache_number > 0 {
+            return Err(eother!(
+                "VM cache is not supported, vm_cache_number must be 0"
+            ));
+        }
+        if !conf.factory.enable_template {
+            return Ok(());
+        }
+
+        let hypervisor_name = &conf.runtime.hypervisor_name;
+        if !TEMPLATE_HYPERVISORS.contains(&hypervisor_name.as_str()) {
+            return Err(eother!(
+                "VM templating is not supported by hypervisor {}",
+                hypervisor_name
+            ));
+        }
+        if let Some(hv) = conf.hypervisor.get(hypervisor_name) {
+            if hv.boot_info.initrd.is_empty() {
+                return Err(eother!("VM templating requires an initrd image"));
+            }
+            if let Some(fs @ (VIRTIO_FS | VIRTIO_FS_INLINE)) = hv.shared_fs.shared_fs.as_deref() {
+                return Err(eother!("VM templating can't be used with {}", fs));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// VM templating information of a VM, filled in by the runtime rather than loaded from the
+/// configuration file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct VmTemplateInfo {
+    /// Boot the VM to be saved as the VM template.
+    pub boot_to_be_template: bool,
+
+    /// Boot the VM by cloning the VM template.
+    pub boot_from_template: bool,
+
+    /// Path of the guest memory file of the VM template.
+    pub memory_path: String,
+
+    /// Path of the device state file of the VM template.
+    pub devices_state_path: String,
+}
+
+impl VmTemplateInfo {
+    /// Templating information to create the template VM of `factory`.
+    pub fn to_be_template(factory: &Factory) -> Self {
+        VmTemplateInfo {
+            boot_to_be_template: true,
+            boot_from_template: false,
+            memory_path: factory.template_memory_path(),
+            devices_state_path: factory.template_state_path(),
+        }
+    }
+
+    /// Templating information to clone a VM from the template VM of `factory`.
+    pub fn from_template(factory: &Factory) -> Self {
+        VmTemplateInfo {
+            boot_to_be_template: false,
+            boot_from_template: true,
+            memory_path: factory.template_memory_path(),
+            devices_state_path: factory.template_state_path(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factory_config() {
+        let content = r#"
+[runtime]
+hypervisor_name = "qemu"
+"#;
+        let config = TomlConfig::load(content).unwrap();
+        assert!(!config.factory.enable_template);
+        assert_eq!(config.factory.template_path, default::DEFAULT_TEMPLATE_PATH);
+        assert_eq!(
+            config.factory.template_memory_path(),
+            "/run/vc/vm/template/memory"
+        );
+        assert_eq!(
+            config.factory.template_state_path(),
+            "/run/vc/vm/template/state"
+        );
+        config.validate().unwrap();
+
+        let content = r#"
+[runtime]
+hypervisor_name = "qemu"
+
+[factory]
+enable_template = true
+template_path = "/run/test/template"
+vm_cache_number = 2
+"#;
+        let mut config = TomlConfig::load(content).unwrap();
+        assert_eq!(config.factory.template_path, "/run/test/template");
+        assert_eq!(config.factory.vm_cache_number, 2);
+        // The VM cache isn't supported.
+        config.validate().unwrap_err();
+        config.factory.vm_cache_number = 0;
+        config.validate().unwrap();
+
+        // Templating isn't supported by all hypervisors.
+        config.runtime.hypervisor_name = "cloud-hypervisor".to_string();
+        config.validate().unwrap_err();
+        config.runtime.hypervisor_name = "dragonball".to_string();
+        config.validate().unwrap_err();
+
+        let info = VmTemplateInfo::from_template(&config.factory);
+        assert!(info.boot_from_template);
+        assert!(!info.boot_to_be_template);
+        assert_eq!(info.memory_path, "/run/test/template/memory");
+        assert_eq!(info.devices_state_path, "/run/test/template/state");
+    }
+}
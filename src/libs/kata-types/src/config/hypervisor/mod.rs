@@ -30,7 +30,7 @@ use std::sync::{Arc, Mutex};
 use lazy_static::lazy_static;
 use regex::RegexSet;
 
-use super::{default, ConfigOps, ConfigPlugin, TomlConfig};
+use super::{default, ConfigOps, ConfigPlugin, TomlConfig, VmTemplateInfo};
 use crate::annotations::KATA_ANNO_CFG_HYPERVISOR_PREFIX;
 use crate::{eother, resolve_path, sl, validate_path};
 
@@ -49,8 +49,8 @@ const VIRTIO_BLK_CCW: &str = "virtio-blk-ccw";
 const VIRTIO_SCSI: &str = "virtio-scsi";
 const VIRTIO_PMEM: &str = "nvdimm";
 const VIRTIO_9P: &str = "virtio-9p";
-const VIRTIO_FS: &str = "virtio-fs";
-const VIRTIO_FS_INLINE: &str = "inline-virtio-fs";
+pub(crate) const VIRTIO_FS: &str = "virtio-fs";
+pub(crate) const VIRTIO_FS_INLINE: &str = "inline-virtio-fs";
 const MAX_BRIDGE_SIZE: u32 = 5;
 
 const KERNEL_PARAM_DELIMITER: &str = " ";
@@ -300,6 +300,15 @@ pub struct CpuInfo {
     /// NOTICE: on arm platform with gicv2 interrupt controller, set it to 8.
     #[serde(default)]
     pub default_maxvcpus: u32,
+
+    /// Mirror the host NUMA nodes of the sandbox CPU set into the guest.
+    ///
+    /// The vCPUs and the guest memory are split across guest NUMA nodes matching the host NUMA
+    /// nodes of the CPUs the sandbox is restricted to. The memory of each guest node is bound to
+    /// its host node and the vCPU threads are pinned to the host CPUs of their node. Nothing is
+    /// done if the sandbox isn't restricted to a CPU set.
+    #[serde(default)]
+    pub enable_numa: bool,
 }
 
 impl CpuInfo {
@@ -365,6 +374,29 @@ pub struct DebugInfo {
     /// much disk space.
     #[serde(default)]
     pub guest_memory_dump_path: String,
+
+    /// Set where to save the guest console output.
+    ///
+    /// If set, the console output of the guest is written to `console.log` under
+    /// console_log_path/<sandbox id>, besides the console socket. The log file is rotated by
+    /// size. Only supported by Dragonball.
+    #[serde(default)]
+    pub console_log_path: String,
+
+    /// Size in bytes to rotate the console log file, 0 for the default size.
+    #[serde(default)]
+    pub console_log_rotate_size: u64,
+
+    /// Number of rotated console log files to keep, 0 for the default number.
+    #[serde(default)]
+    pub console_log_rotate_count: usize,
+
+    /// Size in bytes of the ring buffer keeping the latest console output, 0 to disable it.
+    ///
+    /// When the guest crashes, the ring buffer is dumped to `console-crash.log` next to the
+    /// console log file, so console_log_path must be set.
+    #[serde(default)]
+    pub console_ring_buffer_size: usize,
 }
 
 impl DebugInfo {
@@ -375,6 +407,11 @@ impl DebugInfo {
 
     /// Validate the configuration information.
     pub fn validate(&self) -> Result<()> {
+        if self.console_ring_buffer_size > 0 && self.console_log_path.is_empty() {
+            return Err(eother!(
+                "The console ring buffer needs console_log_path to be dumped"
+            ));
+        }
         Ok(())
     }
 }
@@ -584,6 +621,13 @@ pub struct MemoryInfo {
     /// If swap_in_bytes and memory_limit_in_bytes is not set, the size should be default_memory.
     #[serde(default)]
     pub enable_guest_swap: bool,
+
+    /// Reclaim the memory freed by the guest, default false.
+    ///
+    /// When enabled, a virtio-balloon device with free page reporting is added to the VM, so that
+    /// the pages the guest frees are returned to the host.
+    #[serde(default)]
+    pub reclaim_guest_freed_memory: bool,
 }
 
 impl MemoryInfo {
@@ -674,6 +718,14 @@ pub struct SecurityInfo {
     #[serde(default)]
     pub disable_seccomp: bool,
 
+    /// Path to a JSON file with custom seccomp filters for the hypervisor threads.
+    ///
+    /// The file uses the seccompiler JSON format, with one filter per thread category. If empty,
+    /// the hypervisor applies its built-in filters unless `disable_seccomp` is set. Only supported
+    /// by Dragonball, which expects the "vmm" and "vcpu" filters.
+    #[serde(default)]
+    pub seccomp_filter_path: String,
+
     /// Enable confidential guest support.
     ///
     /// Toggling that setting may trigger different hardware features, ranging from memory
@@ -723,6 +775,10 @@ impl SecurityInfo {
 
     /// Validate the configuration information.
     pub fn validate(&self) -> Result<()> {
+        validate_path!(
+            self.seccomp_filter_path,
+            "seccomp filter file {} is invalid: {}"
+        )?;
         Ok(())
     }
 
@@ -986,6 +1042,11 @@ pub struct Hypervisor {
     #[serde(default)]
     pub prefetch_list_path: String,
 
+    /// VM templating information, set up by the runtime according to the `[factory]`
+    /// configuration.
+    #[serde(skip)]
+    pub vm_template: VmTemplateInfo,
+
     /// Vendor customized runtime configuration.
     #[serde(default, flatten)]
     pub vendor: HypervisorVendor,
@@ -1159,11 +1220,13 @@ mod tests {
                     cpu_features: "".to_string(),
                     default_vcpus: 0,
                     default_maxvcpus: 0,
+                    enable_numa: false,
                 },
                 output: CpuInfo {
                     cpu_features: "".to_string(),
                     default_vcpus,
                     default_maxvcpus: node_cpus,
+                    enable_numa: false,
                 },
             },
             TestData {
@@ -1172,11 +1235,13 @@ mod tests {
                     cpu_features: "a,b,c".to_string(),
                     default_vcpus: 9999999,
                     default_maxvcpus: 9999999,
+                    enable_numa: false,
                 },
                 output: CpuInfo {
                     cpu_features: "a,b,c".to_string(),
                     default_vcpus: node_cpus as i32,
                     default_maxvcpus: node_cpus,
+                    enable_numa: false,
                 },
             },
             TestData {
@@ -1185,11 +1250,13 @@ mod tests {
                     cpu_features: "a, b ,c".to_string(),
                     default_vcpus: -1,
                     default_maxvcpus: 1,
+                    enable_numa: false,
                 },
                 output: CpuInfo {
                     cpu_features: "a,b,c".to_string(),
                     default_vcpus: 1,
                     default_maxvcpus: 1,
+                    enable_numa: false,
                 },
             },
         ];
@@ -1215,4 +1282,16 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_debug_info_validate() {
+        let mut debug_info = DebugInfo {
+            console_ring_buffer_size: 0x10000,
+            ..Default::default()
+        };
+        assert!(debug_info.validate().is_err());
+
+        debug_info.console_log_path = String::from("/var/log/kata");
+        assert!(debug_info.validate().is_ok());
+    }
 }
@@ -20,10 +20,14 @@ pub mod default;
 
 mod agent;
 mod drop_in;
+mod factory;
 pub mod hypervisor;
 
 pub use self::agent::Agent;
 use self::default::DEFAULT_AGENT_DBG_CONSOLE_PORT;
+pub use self::factory::{
+    Factory, VmTemplateInfo, TEMPLATE_HYPERVISORS, TEMPLATE_MEMORY_FILE, TEMPLATE_STATE_FILE,
+};
 pub use self::hypervisor::{
     BootInfo, CloudHypervisorConfig, DragonballConfig, Hypervisor, QemuConfig,
     HYPERVISOR_NAME_DRAGONBALL, HYPERVISOR_NAME_QEMU,
@@ -108,6 +112,9 @@ pub struct TomlConfig {
     /// Kata runtime configuration information.
     #[serde(default)]
     pub runtime: Runtime,
+    /// VM factory configuration information.
+    #[serde(default)]
+    pub factory: Factory,
 }
 
 impl TomlConfig {
@@ -121,6 +128,7 @@ impl TomlConfig {
             Hypervisor::adjust_config(config)?;
             Runtime::adjust_config(config)?;
             Agent::adjust_config(config)?;
+            Factory::adjust_config(config)?;
             info!(sl!(), "get kata config: {:?}", config);
         }
 
@@ -165,6 +173,7 @@ impl TomlConfig {
         Hypervisor::adjust_config(&mut config)?;
         Runtime::adjust_config(&mut config)?;
         Agent::adjust_config(&mut config)?;
+        Factory::adjust_config(&mut config)?;
         info!(sl!(), "get kata config: {:?}", config);
         Ok(config)
     }
@@ -174,6 +183,7 @@ impl TomlConfig {
         Hypervisor::validate(self)?;
         Runtime::validate(self)?;
         Agent::validate(self)?;
+        Factory::validate(self)?;
 
         Ok(())
     }
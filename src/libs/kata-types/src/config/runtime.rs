@@ -63,6 +63,12 @@ pub struct Runtime {
     #[serde(default)]
     pub disable_new_netns: bool,
 
+    /// If enabled, the runtime watches the links of the sandbox network namespace, and hotplugs
+    /// or unplugs the network endpoints of the links added or removed once the sandbox is
+    /// running, e.g. by CNI chaining or Multus.
+    #[serde(default)]
+    pub enable_network_watcher: bool,
+
     /// If specified, sandbox_bind_mounts identifies host paths to be mounted into the sandboxes
     /// shared path.
     ///
@@ -117,5 +117,9 @@ impl_agent!(
     get_ip_tables | crate::GetIPTablesRequest | crate::GetIPTablesResponse | None,
     set_ip_tables | crate::SetIPTablesRequest | crate::SetIPTablesResponse | None,
     get_volume_stats | crate::VolumeStatsRequest | crate::VolumeStatsResponse | None,
-    resize_volume | crate::ResizeVolumeRequest | crate::Empty | None
+    resize_volume | crate::ResizeVolumeRequest | crate::Empty | None,
+    get_metrics | crate::GetMetricsRequest | crate::MetricsResponse | None,
+    online_cpu_mem | crate::OnlineCPUMemRequest | crate::Empty | None,
+    mem_hotplug_by_probe | crate::MemHotplugByProbeRequest | crate::Empty | None,
+    get_guest_details | crate::GetGuestDetailsRequest | crate::GuestDetailsResponse | None
 );
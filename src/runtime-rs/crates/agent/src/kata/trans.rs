@@ -16,16 +16,16 @@ use crate::{
         ARPNeighbor, ARPNeighbors, AddArpNeighborRequest, AgentDetails, BlkioStats,
         BlkioStatsEntry, CgroupStats, CheckRequest, CloseStdinRequest, ContainerID,
         CopyFileRequest, CpuStats, CpuUsage, CreateContainerRequest, CreateSandboxRequest, Device,
-        Empty, ExecProcessRequest, FSGroup, FSGroupChangePolicy, GetIPTablesRequest,
-        GetIPTablesResponse, GuestDetailsResponse, HealthCheckResponse, HugetlbStats, IPAddress,
-        IPFamily, Interface, Interfaces, KernelModule, MemHotplugByProbeRequest, MemoryData,
-        MemoryStats, NetworkStats, OnlineCPUMemRequest, PidsStats, ReadStreamRequest,
-        ReadStreamResponse, RemoveContainerRequest, ReseedRandomDevRequest, ResizeVolumeRequest,
-        Route, Routes, SetGuestDateTimeRequest, SetIPTablesRequest, SetIPTablesResponse,
-        SignalProcessRequest, StatsContainerResponse, Storage, StringUser, ThrottlingData,
-        TtyWinResizeRequest, UpdateContainerRequest, UpdateInterfaceRequest, UpdateRoutesRequest,
-        VersionCheckResponse, VolumeStatsRequest, VolumeStatsResponse, WaitProcessRequest,
-        WriteStreamRequest,
+        Empty, ExecProcessRequest, FSGroup, FSGroupChangePolicy, GetGuestDetailsRequest,
+        GetIPTablesRequest, GetIPTablesResponse, GetMetricsRequest, GuestDetailsResponse,
+        HealthCheckResponse, HugetlbStats, IPAddress, IPFamily, Interface, Interfaces,
+        KernelModule, MemHotplugByProbeRequest, MemoryData, MemoryStats, MetricsResponse,
+        NetworkStats, OnlineCPUMemRequest, PidsStats, ReadStreamRequest, ReadStreamResponse,
+        RemoveContainerRequest, ReseedRandomDevRequest, ResizeVolumeRequest, Route, Routes,
+        SetGuestDateTimeRequest, SetIPTablesRequest, SetIPTablesResponse, SignalProcessRequest,
+        StatsContainerResponse, Storage, StringUser, ThrottlingData, TtyWinResizeRequest,
+        UpdateContainerRequest, UpdateInterfaceRequest, UpdateRoutesRequest, VersionCheckResponse,
+        VolumeStatsRequest, VolumeStatsResponse, WaitProcessRequest, WriteStreamRequest,
     },
     OomEventResponse, WaitProcessResponse, WriteStreamResponse,
 };
@@ -721,6 +721,16 @@ impl From<agent::AgentDetails> for AgentDetails {
     }
 }
 
+impl From<GetGuestDetailsRequest> for agent::GuestDetailsRequest {
+    fn from(from: GetGuestDetailsRequest) -> Self {
+        Self {
+            mem_block_size: from.mem_block_size,
+            mem_hotplug_probe: from.mem_hotplug_probe,
+            ..Default::default()
+        }
+    }
+}
+
 impl From<agent::GuestDetailsResponse> for GuestDetailsResponse {
     fn from(src: agent::GuestDetailsResponse) -> Self {
         Self {
@@ -817,6 +827,22 @@ impl From<csi::VolumeStatsResponse> for VolumeStatsResponse {
     }
 }
 
+impl From<GetMetricsRequest> for agent::GetMetricsRequest {
+    fn from(_: GetMetricsRequest) -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+}
+
+impl From<agent::Metrics> for MetricsResponse {
+    fn from(from: agent::Metrics) -> Self {
+        Self {
+            metrics: from.metrics,
+        }
+    }
+}
+
 impl From<ResizeVolumeRequest> for agent::ResizeVolumeRequest {
     fn from(from: ResizeVolumeRequest) -> Self {
         Self {
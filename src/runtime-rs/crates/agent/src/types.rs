@@ -579,6 +579,15 @@ pub struct VolumeStatsResponse {
     pub data: String,
 }
 
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct GetMetricsRequest {}
+
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct MetricsResponse {
+    /// Guest metrics, in the Prometheus text format.
+    pub metrics: String,
+}
+
 #[cfg(test)]
 mod test {
     use std::convert::TryFrom;
@@ -2,7 +2,10 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{DeviceConfig, FsConfig, VmConfig};
+use crate::{
+    DeviceConfig, DiskConfig, FsConfig, NetConfig, RestoreConfig, VmConfig, VmRemoveDeviceData,
+    VmResizeData, VmSnapshotConfig,
+};
 use anyhow::{anyhow, Result};
 use api_client::simple_api_full_command_and_response;
 
@@ -104,3 +107,280 @@ pub async fn cloud_hypervisor_vm_fs_add(
     })
     .await?
 }
+
+pub async fn cloud_hypervisor_vm_netdev_add(
+    mut socket: UnixStream,
+    net_config: NetConfig,
+) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(
+            &mut socket,
+            "PUT",
+            "vm.add-net",
+            Some(&serde_json::to_string(&net_config)?),
+        )
+        .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+pub async fn cloud_hypervisor_vm_blockdev_add(
+    mut socket: UnixStream,
+    disk_config: DiskConfig,
+) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(
+            &mut socket,
+            "PUT",
+            "vm.add-disk",
+            Some(&serde_json::to_string(&disk_config)?),
+        )
+        .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+pub async fn cloud_hypervisor_vm_remove_device(
+    mut socket: UnixStream,
+    device_data: VmRemoveDeviceData,
+) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(
+            &mut socket,
+            "PUT",
+            "vm.remove-device",
+            Some(&serde_json::to_string(&device_data)?),
+        )
+        .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+pub async fn cloud_hypervisor_vm_resize(
+    mut socket: UnixStream,
+    resize_data: VmResizeData,
+) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(
+            &mut socket,
+            "PUT",
+            "vm.resize",
+            Some(&serde_json::to_string(&resize_data)?),
+        )
+        .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+pub async fn cloud_hypervisor_vm_pause(mut socket: UnixStream) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(&mut socket, "PUT", "vm.pause", None)
+            .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+pub async fn cloud_hypervisor_vm_resume(mut socket: UnixStream) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(&mut socket, "PUT", "vm.resume", None)
+            .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+pub async fn cloud_hypervisor_vm_snapshot(
+    mut socket: UnixStream,
+    snapshot_config: VmSnapshotConfig,
+) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(
+            &mut socket,
+            "PUT",
+            "vm.snapshot",
+            Some(&serde_json::to_string(&snapshot_config)?),
+        )
+        .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+pub async fn cloud_hypervisor_vm_restore(
+    mut socket: UnixStream,
+    restore_config: RestoreConfig,
+) -> Result<Option<String>> {
+    task::spawn_blocking(move || -> Result<Option<String>> {
+        let response = simple_api_full_command_and_response(
+            &mut socket,
+            "PUT",
+            "vm.restore",
+            Some(&serde_json::to_string(&restore_config)?),
+        )
+        .map_err(|e| anyhow!(e))?;
+
+        Ok(response)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use std::thread::{self, JoinHandle};
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Request {
+        method: String,
+        path: String,
+        body: String,
+    }
+
+    // Serve a single HTTP request on the returned socket, replying with the
+    // specified response. The request received is returned by the handle.
+    fn mock_api_server(response: &'static str) -> (UnixStream, JoinHandle<Request>) {
+        let (client, mut server) = UnixStream::pair().unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut data = Vec::new();
+            let mut buf = [0u8; 256];
+
+            let header_end = loop {
+                let count = server.read(&mut buf).unwrap();
+                assert!(count > 0, "connection closed before end of headers");
+                data.extend_from_slice(&buf[..count]);
+
+                if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+            };
+
+            let headers = String::from_utf8(data[..header_end].to_vec()).unwrap();
+
+            let content_length = headers
+                .lines()
+                .find_map(|l| l.strip_prefix("Content-Length: "))
+                .map(|l| l.trim().parse::<usize>().unwrap())
+                .unwrap_or(0);
+
+            while data.len() < header_end + content_length {
+                let count = server.read(&mut buf).unwrap();
+                assert!(count > 0, "connection closed before end of body");
+                data.extend_from_slice(&buf[..count]);
+            }
+
+            server.write_all(response.as_bytes()).unwrap();
+
+            let mut request_line = headers.lines().next().unwrap().split_whitespace();
+
+            Request {
+                method: request_line.next().unwrap().to_string(),
+                path: request_line.next().unwrap().to_string(),
+                body: String::from_utf8(data[header_end..].to_vec()).unwrap(),
+            }
+        });
+
+        (client, handle)
+    }
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    const NO_CONTENT: &str = "HTTP/1.1 204 No Content\r\n\r\n";
+    const SERVER_ERROR: &str =
+        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 6\r\n\r\nfailed";
+
+    #[test]
+    fn test_vm_pause_resume() {
+        let (socket, server) = mock_api_server(NO_CONTENT);
+        let result = block_on(cloud_hypervisor_vm_pause(socket));
+        assert!(matches!(result, Ok(None)), "{:?}", result);
+        assert_eq!(
+            server.join().unwrap(),
+            Request {
+                method: "PUT".into(),
+                path: "/api/v1/vm.pause".into(),
+                ..Default::default()
+            }
+        );
+
+        let (socket, server) = mock_api_server(NO_CONTENT);
+        let result = block_on(cloud_hypervisor_vm_resume(socket));
+        assert!(matches!(result, Ok(None)), "{:?}", result);
+        assert_eq!(
+            server.join().unwrap(),
+            Request {
+                method: "PUT".into(),
+                path: "/api/v1/vm.resume".into(),
+                ..Default::default()
+            }
+        );
+
+        let (socket, server) = mock_api_server(SERVER_ERROR);
+        let result = block_on(cloud_hypervisor_vm_pause(socket));
+        assert!(result.is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_vm_snapshot_restore() {
+        let snapshot_config = VmSnapshotConfig {
+            destination_url: "file:///run/vc/vm/foo/snapshot".into(),
+        };
+
+        let (socket, server) = mock_api_server(NO_CONTENT);
+        let result = block_on(cloud_hypervisor_vm_snapshot(
+            socket,
+            snapshot_config.clone(),
+        ));
+        assert!(matches!(result, Ok(None)), "{:?}", result);
+
+        let request = server.join().unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/api/v1/vm.snapshot");
+        assert_eq!(
+            serde_json::from_str::<VmSnapshotConfig>(&request.body).unwrap(),
+            snapshot_config
+        );
+
+        let restore_config = RestoreConfig {
+            source_url: PathBuf::from("file:///run/vc/vm/foo/snapshot"),
+            prefault: false,
+        };
+
+        let (socket, server) = mock_api_server(NO_CONTENT);
+        let result = block_on(cloud_hypervisor_vm_restore(socket, restore_config.clone()));
+        assert!(matches!(result, Ok(None)), "{:?}", result);
+
+        let request = server.join().unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/api/v1/vm.restore");
+        assert_eq!(
+            serde_json::from_str::<RestoreConfig>(&request.body).unwrap(),
+            restore_config
+        );
+
+        let (socket, server) = mock_api_server(SERVER_ERROR);
+        let result = block_on(cloud_hypervisor_vm_restore(socket, restore_config));
+        assert!(result.is_err());
+        server.join().unwrap();
+    }
+}
@@ -6,8 +6,9 @@ use crate::net_util::MAC_ADDR_LEN;
 use crate::NamedHypervisorConfig;
 use crate::VmConfig;
 use crate::{
-    ConsoleConfig, ConsoleOutputMode, CpuFeatures, CpuTopology, CpusConfig, DiskConfig, MacAddr,
-    MemoryConfig, PayloadConfig, PlatformConfig, PmemConfig, RngConfig, VsockConfig,
+    ConsoleConfig, ConsoleOutputMode, CpuFeatures, CpuTopology, CpusConfig, DiskConfig,
+    HotplugMethod, MacAddr, MemoryConfig, PayloadConfig, PlatformConfig, PmemConfig, RngConfig,
+    VsockConfig,
 };
 use anyhow::{anyhow, Context, Result};
 use kata_types::config::default::DEFAULT_CH_ENTROPY_SOURCE;
@@ -58,6 +59,7 @@ impl TryFrom<NamedHypervisorConfig> for VmConfig {
         };
 
         let fs = n.shared_fs_devices;
+        let net = n.network_devices;
 
         let cpus = CpusConfig::try_from(cfg.cpu_info).map_err(VmConfigError::CPUError)?;
 
@@ -108,6 +110,16 @@ impl TryFrom<NamedHypervisorConfig> for VmConfig {
             None
         };
 
+        // Block devices (container rootfs and volumes) follow the guest
+        // image disk, if any.
+        let disks = match (disks, n.disk_devices) {
+            (Some(mut disks), Some(devices)) => {
+                disks.extend(devices);
+                Some(disks)
+            }
+            (disks, devices) => disks.or(devices),
+        };
+
         let serial = get_serial_cfg(debug, confidential_guest);
         let console = get_console_cfg(debug, confidential_guest);
 
@@ -131,6 +143,7 @@ impl TryFrom<NamedHypervisorConfig> for VmConfig {
             fs,
             pmem,
             disks,
+            net,
             vsock: Some(vsock),
             rng,
             platform,
@@ -208,12 +221,21 @@ impl TryFrom<(MemoryInfo, bool)> for MemoryConfig {
             Some(aligned_hotplug_size_bytes)
         };
 
+        // virtio-mem lets the guest memory be resized at a finer granularity
+        // than ACPI DIMMs.
+        let hotplug_method = if mem.enable_virtio_mem {
+            HotplugMethod::VirtioMem
+        } else {
+            HotplugMethod::Acpi
+        };
+
         let cfg = MemoryConfig {
             size: mem_bytes,
 
             // Required
             shared: true,
 
+            hotplug_method,
             hotplug_size,
 
             ..Default::default()
@@ -484,6 +506,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::NetConfig;
     use kata_types::config::hypervisor::{Hypervisor as HypervisorConfig, SecurityInfo};
 
     // Generate a valid generic memory info object and a valid CH specific
@@ -1373,6 +1396,26 @@ mod tests {
                     ..Default::default()
                 }),
             },
+            TestData {
+                mem_info: MemoryInfo {
+                    default_memory: 1024,
+                    enable_virtio_mem: true,
+
+                    ..Default::default()
+                },
+                confidential_guest: false,
+                result: Ok(MemoryConfig {
+                    size: 1024_u64 * MIB,
+                    shared: true,
+                    hotplug_method: HotplugMethod::VirtioMem,
+                    hotplug_size: checked_next_multiple_of(
+                        usable_max_mem_bytes - (1024 * MIB),
+                        PMEM_ALIGN_BYTES,
+                    ),
+
+                    ..Default::default()
+                }),
+            },
             TestData {
                 mem_info: mem_info_std,
                 confidential_guest: false,
@@ -1660,6 +1703,30 @@ mod tests {
             ..Default::default()
         };
 
+        let net_device = NetConfig {
+            tap: Some("tap0_kata".into()),
+            mac: parse_mac("02:00:ca:fe:00:01").unwrap(),
+            id: Some("net0".into()),
+
+            ..Default::default()
+        };
+
+        let disk_device = DiskConfig {
+            path: Some(PathBuf::from("/dev/dm-3")),
+            id: Some("blk0".into()),
+            num_queues: 1,
+            queue_size: 1024,
+
+            ..Default::default()
+        };
+
+        let vmconfig_with_image_and_devices = VmConfig {
+            net: Some(vec![net_device.clone()]),
+            disks: Some(vec![disk_device.clone()]),
+
+            ..vmconfig_with_image_and_kernel.clone()
+        };
+
         let vmconfig_with_initrd = VmConfig {
             cpus: cpus_config.clone(),
             memory: mem_config_std,
@@ -1690,6 +1757,15 @@ mod tests {
             ..Default::default()
         };
 
+        let vmconfig_confidential_guest_image_and_devices = VmConfig {
+            disks: Some(vec![
+                disk_config_confidential_guest_image.clone(),
+                disk_device.clone(),
+            ]),
+
+            ..vmconfig_confidential_guest_image.clone()
+        };
+
         let vmconfig_confidential_guest_initrd = VmConfig {
             cpus: cpus_config.clone(),
             memory: mem_config_confidential_guest.clone(),
@@ -1739,8 +1815,20 @@ mod tests {
             sandbox_path: sandbox_path.into(),
             vsock_socket_path: vsock_socket_path.into(),
 
+            cfg: hypervisor_cfg_with_image_and_kernel.clone(),
+
+            ..Default::default()
+        };
+
+        let named_hypervisor_cfg_with_image_and_devices = NamedHypervisorConfig {
+            sandbox_path: sandbox_path.into(),
+            vsock_socket_path: vsock_socket_path.into(),
+
             cfg: hypervisor_cfg_with_image_and_kernel,
 
+            network_devices: Some(vec![net_device]),
+            disk_devices: Some(vec![disk_device.clone()]),
+
             ..Default::default()
         };
 
@@ -1757,8 +1845,19 @@ mod tests {
             sandbox_path: sandbox_path.into(),
             vsock_socket_path: vsock_socket_path.into(),
 
+            cfg: hypervisor_cfg_confidential_guest_image.clone(),
+
+            ..Default::default()
+        };
+
+        let named_hypervisor_cfg_confidential_guest_image_and_devices = NamedHypervisorConfig {
+            sandbox_path: sandbox_path.into(),
+            vsock_socket_path: vsock_socket_path.into(),
+
             cfg: hypervisor_cfg_confidential_guest_image,
 
+            disk_devices: Some(vec![disk_device]),
+
             ..Default::default()
         };
 
@@ -1849,6 +1948,10 @@ mod tests {
                 cfg: named_hypervisor_cfg_with_image_and_kernel,
                 result: Ok(vmconfig_with_image_and_kernel),
             },
+            TestData {
+                cfg: named_hypervisor_cfg_with_image_and_devices,
+                result: Ok(vmconfig_with_image_and_devices),
+            },
             TestData {
                 cfg: named_hypervisor_cfg_with_initrd,
                 result: Ok(vmconfig_with_initrd),
@@ -1857,6 +1960,10 @@ mod tests {
                 cfg: named_hypervisor_cfg_confidential_guest_image,
                 result: Ok(vmconfig_confidential_guest_image),
             },
+            TestData {
+                cfg: named_hypervisor_cfg_confidential_guest_image_and_devices,
+                result: Ok(vmconfig_confidential_guest_image_and_devices),
+            },
             TestData {
                 cfg: named_hypervisor_cfg_confidential_guest_initrd,
                 result: Ok(vmconfig_confidential_guest_initrd),
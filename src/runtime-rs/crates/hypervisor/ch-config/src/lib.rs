@@ -11,7 +11,7 @@ pub mod convert;
 pub mod net_util;
 mod virtio_devices;
 
-use crate::virtio_devices::RateLimiterConfig;
+pub use crate::virtio_devices::{RateLimiterConfig, TokenBucketConfig};
 use kata_types::config::hypervisor::Hypervisor as HypervisorConfig;
 pub use net_util::MacAddr;
 
@@ -481,6 +481,34 @@ fn u16_is_zero(v: &u16) -> bool {
     *v == 0
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct VmRemoveDeviceData {
+    pub id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct VmResizeData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_vcpus: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_ram: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_balloon: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct VmSnapshotConfig {
+    /// The snapshot destination URL
+    pub destination_url: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct RestoreConfig {
+    pub source_url: PathBuf,
+    #[serde(default)]
+    pub prefault: bool,
+}
+
 // Type used to simplify conversion from a generic Hypervisor config
 // to a CH specific VmConfig.
 #[derive(Debug, Clone, Default)]
@@ -492,4 +520,6 @@ pub struct NamedHypervisorConfig {
     pub tdx_enabled: bool,
 
     pub shared_fs_devices: Option<Vec<FsConfig>>,
+    pub network_devices: Option<Vec<NetConfig>>,
+    pub disk_devices: Option<Vec<DiskConfig>>,
 }
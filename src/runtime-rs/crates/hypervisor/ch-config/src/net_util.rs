@@ -4,6 +4,7 @@
 
 use serde::{Deserialize, Serialize, Serializer};
 use std::fmt;
+use std::str::FromStr;
 
 pub const MAC_ADDR_LEN: usize = 6;
 
@@ -30,3 +31,36 @@ impl Serialize for MacAddr {
         self.to_string().serialize(serializer)
     }
 }
+
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != MAC_ADDR_LEN {
+            return Err(format!("invalid MAC address {}", s));
+        }
+
+        let mut bytes = [0u8; MAC_ADDR_LEN];
+        for (byte, part) in bytes.iter_mut().zip(parts) {
+            *byte =
+                u8::from_str_radix(part, 16).map_err(|_| format!("invalid MAC address {}", s))?;
+        }
+        Ok(MacAddr { bytes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mac_addr_from_str() {
+        let mac = MacAddr::from_str("02:42:ac:11:00:0a").unwrap();
+        assert_eq!(mac.bytes, [0x02, 0x42, 0xac, 0x11, 0x00, 0x0a]);
+        assert_eq!(mac.to_string(), "02:42:ac:11:00:0a");
+
+        assert!(MacAddr::from_str("02:42:ac:11:00").is_err());
+        assert!(MacAddr::from_str("02:42:ac:11:00:zz").is_err());
+    }
+}
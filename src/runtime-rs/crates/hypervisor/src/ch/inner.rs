@@ -4,15 +4,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::HypervisorState;
+use crate::ch::utils::{get_api_socket_path, get_snapshot_path};
 use crate::device::DeviceType;
 use crate::VmmState;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use kata_types::capabilities::{Capabilities, CapabilityBits};
 use kata_types::config::hypervisor::Hypervisor as HypervisorConfig;
 use kata_types::config::hypervisor::HYPERVISOR_NAME_CH;
 use persist::sandbox_persist::Persist;
 use std::os::unix::net::UnixStream;
+use std::path::Path;
 use tokio::process::Child;
 use tokio::sync::watch::{channel, Receiver, Sender};
 use tokio::task::JoinHandle;
@@ -51,6 +53,12 @@ pub struct CloudHypervisorInner {
     pub(crate) shutdown_tx: Option<Sender<bool>>,
     pub(crate) shutdown_rx: Option<Receiver<bool>>,
     pub(crate) tasks: Option<Vec<JoinHandle<Result<()>>>>,
+
+    // Guest memory hot-added since boot, in MiB
+    pub(crate) hotplugged_memory_mb: u32,
+
+    // vCPU count once resized, the boot vCPU count until then
+    pub(crate) resized_vcpus: Option<u32>,
 }
 
 const CH_DEFAULT_TIMEOUT_SECS: u32 = 10;
@@ -61,7 +69,9 @@ impl CloudHypervisorInner {
         capabilities.set(
             CapabilityBits::BlockDeviceSupport
                 | CapabilityBits::BlockDeviceHotplugSupport
-                | CapabilityBits::FsSharingSupport,
+                | CapabilityBits::FsSharingSupport
+                | CapabilityBits::NetRateLimiterSupport
+                | CapabilityBits::VhostUserNetSupport,
         );
 
         let (tx, rx) = channel(true);
@@ -86,6 +96,8 @@ impl CloudHypervisorInner {
             shutdown_tx: Some(tx),
             shutdown_rx: Some(rx),
             tasks: None,
+            hotplugged_memory_mb: 0,
+            resized_vcpus: None,
         }
     }
 
@@ -111,35 +123,61 @@ impl Persist for CloudHypervisorInner {
 
     // Return a state object that will be saved by the caller.
     async fn save(&self) -> Result<Self::State> {
+        let api_socket = if self.api_socket.is_some() {
+            get_api_socket_path(&self.id)?
+        } else {
+            String::default()
+        };
+
         Ok(HypervisorState {
             hypervisor_type: HYPERVISOR_NAME_CH.to_string(),
+            pid: self.pid.map(|pid| pid as i32),
+            api_socket,
             id: self.id.clone(),
             vm_path: self.vm_path.clone(),
             jailed: false,
-            jailer_root: String::default(),
-            netns: None,
+            jailer_root: self.jailer_root.clone(),
+            netns: self.netns.clone(),
             config: self.hypervisor_config(),
             run_dir: self.run_dir.clone(),
             cached_block_devices: Default::default(),
+            hotplugged_memory_mb: self.hotplugged_memory_mb,
+            resized_vcpus: self.resized_vcpus,
             ..Default::default()
         })
     }
 
-    // Set the hypervisor state to the specified state
+    // Set the hypervisor state to the specified state. If the CH instance
+    // which was saved is still around, reconnect to it, otherwise restore
+    // the VM from its snapshot, if there is one.
     async fn restore(
         _hypervisor_args: Self::ConstructorArgs,
         hypervisor_state: Self::State,
     ) -> Result<Self> {
-        let ch = Self {
+        let mut ch = Self {
             config: Some(hypervisor_state.config),
             state: VmmState::NotReady,
             id: hypervisor_state.id,
+            pid: hypervisor_state.pid.map(|pid| pid as u32),
             vm_path: hypervisor_state.vm_path,
             run_dir: hypervisor_state.run_dir,
+            jailer_root: hypervisor_state.jailer_root,
+            netns: hypervisor_state.netns,
+            hotplugged_memory_mb: hypervisor_state.hotplugged_memory_mb,
+            resized_vcpus: hypervisor_state.resized_vcpus,
 
             ..Default::default()
         };
 
+        if !hypervisor_state.api_socket.is_empty() && ch.reconnect().is_ok() {
+            return Ok(ch);
+        }
+
+        if Path::new(&get_snapshot_path(&ch.id)?).exists() {
+            ch.pid = None;
+            ch.restore_vm().await.context("restore vm")?;
+        }
+
         Ok(ch)
     }
 }
@@ -6,17 +6,29 @@
 
 use super::inner::CloudHypervisorInner;
 use crate::device::DeviceType;
+use crate::utils::{get_net_rate_limiter_bucket_size, NET_RATE_LIMITER_REFILL_TIME_MS};
+use crate::BlockDevice;
 use crate::HybridVsockConfig;
+use crate::NetworkDevice;
 use crate::ShareFsDeviceConfig;
 use crate::VmmState;
+use crate::{VhostUserConfig, VHOST_USER_BLK, VHOST_USER_FS, VHOST_USER_NET};
 use anyhow::{anyhow, Context, Result};
-use ch_config::ch_api::cloud_hypervisor_vm_fs_add;
-use ch_config::FsConfig;
+use ch_config::ch_api::{
+    cloud_hypervisor_vm_blockdev_add, cloud_hypervisor_vm_fs_add, cloud_hypervisor_vm_netdev_add,
+    cloud_hypervisor_vm_remove_device,
+};
+use ch_config::{
+    DiskConfig, FsConfig, MacAddr, NetConfig, RateLimiterConfig, TokenBucketConfig,
+    VmRemoveDeviceData,
+};
 use safe_path::scoped_join;
 use std::convert::TryFrom;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 const VIRTIO_FS: &str = "virtio-fs";
+const DEFAULT_QUEUE_SIZE: u16 = 1024;
 
 impl CloudHypervisorInner {
     pub(crate) async fn add_device(&mut self, device: DeviceType) -> Result<()> {
@@ -43,13 +55,17 @@ impl CloudHypervisorInner {
         match device {
             DeviceType::ShareFs(sharefs) => self.handle_share_fs_device(sharefs.config).await,
             DeviceType::HybridVsock(hvsock) => self.handle_hvsock_device(&hvsock.config).await,
+            DeviceType::VhostUser(vhost_user) => {
+                self.handle_vhost_user_device(vhost_user.config).await
+            }
+            DeviceType::Network(network) => self.handle_network_device(network).await,
+            DeviceType::Block(block) => self.handle_block_device(block).await,
             _ => Err(anyhow!("unhandled device: {:?}", device)),
         }
     }
 
     /// Add the device that were requested to be added before the VMM was
     /// started.
-    #[allow(dead_code)]
     pub(crate) async fn handle_pending_devices_after_boot(&mut self) -> Result<()> {
         if self.state != VmmState::VmRunning {
             return Err(anyhow!(
@@ -67,10 +83,162 @@ impl CloudHypervisorInner {
         Ok(())
     }
 
-    pub(crate) async fn remove_device(&mut self, _device: DeviceType) -> Result<()> {
+    pub(crate) async fn remove_device(&mut self, device: DeviceType) -> Result<()> {
+        match device {
+            DeviceType::VhostUser(vhost_user) => {
+                self.remove_ch_device(&vhost_user.config.dev_id).await
+            }
+            DeviceType::Network(network) => self.remove_ch_device(&network.id).await,
+            DeviceType::Block(block) => self.remove_ch_device(&block.device_id).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn remove_ch_device(&mut self, id: &str) -> Result<()> {
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let response = cloud_hypervisor_vm_remove_device(
+            socket.try_clone().context("failed to clone socket")?,
+            VmRemoveDeviceData { id: id.to_string() },
+        )
+        .await?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "device remove response: {:?}", detail);
+        }
+
+        Ok(())
+    }
+
+    async fn handle_vhost_user_device(&mut self, cfg: VhostUserConfig) -> Result<()> {
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?
+            .try_clone()
+            .context("failed to clone socket")?;
+
+        let response = match cfg.device_type.as_str() {
+            VHOST_USER_NET => {
+                let mut net_config = NetConfig {
+                    vhost_user: true,
+                    vhost_socket: Some(cfg.socket_path),
+                    id: Some(cfg.dev_id),
+                    ..Default::default()
+                };
+                if !cfg.mac_address.is_empty() {
+                    net_config.mac = MacAddr::from_str(&cfg.mac_address).map_err(|e| anyhow!(e))?;
+                }
+                cloud_hypervisor_vm_netdev_add(socket, net_config).await?
+            }
+            VHOST_USER_BLK => {
+                let disk_config = DiskConfig {
+                    vhost_user: true,
+                    vhost_socket: Some(cfg.socket_path),
+                    id: Some(cfg.dev_id),
+                    num_queues: 1,
+                    queue_size: DEFAULT_QUEUE_SIZE,
+                    ..Default::default()
+                };
+                cloud_hypervisor_vm_blockdev_add(socket, disk_config).await?
+            }
+            VHOST_USER_FS => {
+                let fs_config = FsConfig {
+                    tag: cfg.tag,
+                    socket: PathBuf::from(cfg.socket_path),
+                    num_queues: 1,
+                    queue_size: if cfg.queue_size > 0 {
+                        u16::try_from(cfg.queue_size)?
+                    } else {
+                        DEFAULT_QUEUE_SIZE
+                    },
+                    id: Some(cfg.dev_id),
+                    ..Default::default()
+                };
+                cloud_hypervisor_vm_fs_add(socket, fs_config).await?
+            }
+            device_type => {
+                return Err(anyhow!("cannot handle vhost-user type: {:?}", device_type));
+            }
+        };
+
+        if let Some(detail) = response {
+            debug!(sl!(), "vhost-user device add response: {:?}", detail);
+        }
+
+        Ok(())
+    }
+
+    async fn handle_network_device(&mut self, device: NetworkDevice) -> Result<()> {
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let net_config = self.get_net_config(device)?;
+
+        let response = cloud_hypervisor_vm_netdev_add(
+            socket.try_clone().context("failed to clone socket")?,
+            net_config,
+        )
+        .await?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "net device add response: {:?}", detail);
+        }
+
+        Ok(())
+    }
+
+    async fn handle_block_device(&mut self, device: BlockDevice) -> Result<()> {
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let disk_config = DiskConfig::try_from(device)?;
+
+        let response = cloud_hypervisor_vm_blockdev_add(
+            socket.try_clone().context("failed to clone socket")?,
+            disk_config,
+        )
+        .await?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "block device add response: {:?}", detail);
+        }
+
         Ok(())
     }
 
+    // CH counts the rx and tx queues separately, so each configured network
+    // queue maps to a pair of virtqueues. A value of zero lets CH pick its
+    // own default.
+    fn get_net_config(&self, device: NetworkDevice) -> Result<NetConfig> {
+        let network_info = self
+            .config
+            .as_ref()
+            .map(|c| c.network_info.clone())
+            .unwrap_or_default();
+
+        let mut net_config = NetConfig::try_from(device)?;
+
+        net_config.num_queues = (network_info.network_queues as usize) * 2;
+        net_config.rate_limiter_config = get_net_rate_limiter_config(
+            network_info.rx_rate_limiter_max_rate,
+            network_info.tx_rate_limiter_max_rate,
+        )?;
+
+        Ok(net_config)
+    }
+
     async fn handle_share_fs_device(&mut self, cfg: ShareFsDeviceConfig) -> Result<()> {
         if cfg.fs_type != VIRTIO_FS {
             return Err(anyhow!("cannot handle share fs type: {:?}", cfg.fs_type));
@@ -125,32 +293,68 @@ impl CloudHypervisorInner {
         Ok(())
     }
 
-    pub(crate) async fn get_shared_fs_devices(&mut self) -> Result<Option<Vec<FsConfig>>> {
-        let pending_root_devices = self.pending_devices.take();
-
-        let mut root_devices = Vec::<FsConfig>::new();
+    /// Split the devices requested before the VMM was started into those
+    /// that are passed in the boot configuration and those that must be
+    /// hotplugged once the VM is running.
+    pub(crate) async fn get_boot_devices(&mut self) -> Result<BootDevices> {
+        let mut boot_devices = BootDevices::default();
 
-        if let Some(devices) = pending_root_devices {
-            for dev in devices {
-                match dev {
-                    DeviceType::ShareFs(dev) => {
-                        let settings = ShareFsSettings::new(dev.config, self.vm_path.clone());
-
-                        let fs_cfg = FsConfig::try_from(settings)?;
-
-                        root_devices.push(fs_cfg);
-                    }
-                    _ => continue,
-                };
-            }
+        let pending_devices = match self.pending_devices.take() {
+            Some(devices) => devices,
+            None => return Ok(boot_devices),
+        };
 
-            Ok(Some(root_devices))
-        } else {
-            Ok(None)
+        // Pending devices are stored in reverse order of addition.
+        for dev in pending_devices.into_iter().rev() {
+            match dev {
+                DeviceType::ShareFs(dev) => {
+                    let settings = ShareFsSettings::new(dev.config, self.vm_path.clone());
+
+                    let fs_cfg = FsConfig::try_from(settings)?;
+
+                    boot_devices
+                        .shared_fs_devices
+                        .get_or_insert_with(Vec::new)
+                        .push(fs_cfg);
+                }
+                DeviceType::Network(dev) => {
+                    let net_cfg = self.get_net_config(dev)?;
+
+                    boot_devices
+                        .network_devices
+                        .get_or_insert_with(Vec::new)
+                        .push(net_cfg);
+                }
+                DeviceType::Block(dev) => {
+                    let disk_cfg = DiskConfig::try_from(dev)?;
+
+                    boot_devices
+                        .disk_devices
+                        .get_or_insert_with(Vec::new)
+                        .push(disk_cfg);
+                }
+                // vhost-user devices are hotplugged once the VM is
+                // booted, see handle_pending_devices_after_boot().
+                DeviceType::VhostUser(_) => self
+                    .pending_devices
+                    .get_or_insert_with(Vec::new)
+                    .insert(0, dev),
+                _ => continue,
+            };
         }
+
+        Ok(boot_devices)
     }
 }
 
+/// Devices which are part of the initial CH VM configuration.
+#[derive(Debug, Default)]
+pub struct BootDevices {
+    pub shared_fs_devices: Option<Vec<FsConfig>>,
+    pub network_devices: Option<Vec<NetConfig>>,
+    pub disk_devices: Option<Vec<DiskConfig>>,
+}
+
 #[derive(Debug)]
 pub struct ShareFsSettings {
     cfg: ShareFsDeviceConfig,
@@ -199,3 +403,211 @@ impl TryFrom<ShareFsSettings> for FsConfig {
         Ok(fs_cfg)
     }
 }
+
+// CH applies the rate limiter of a network device to both its rx and tx
+// queues, so the rx and tx rates can't be configured separately: they must be
+// equal, or both unlimited.
+fn get_net_rate_limiter_config(rx_rate: u64, tx_rate: u64) -> Result<Option<RateLimiterConfig>> {
+    if rx_rate != tx_rate {
+        return Err(anyhow!(
+            "cloud hypervisor can't limit the network rx rate ({}) and tx rate ({}) separately",
+            rx_rate,
+            tx_rate
+        ));
+    }
+
+    Ok(
+        get_net_rate_limiter_bucket_size(rx_rate).map(|size| RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size,
+                one_time_burst: None,
+                refill_time: NET_RATE_LIMITER_REFILL_TIME_MS,
+            }),
+            ops: None,
+        }),
+    )
+}
+
+impl TryFrom<NetworkDevice> for NetConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(device: NetworkDevice) -> Result<Self, Self::Error> {
+        let cfg = device.config;
+
+        if cfg.host_dev_name.is_empty() {
+            return Err(anyhow!("missing host device name for network device"));
+        }
+        if !cfg.queue_fds.is_empty() {
+            return Err(anyhow!(
+                "cloud hypervisor doesn't support network queue fds for {}",
+                cfg.host_dev_name
+            ));
+        }
+
+        let mut net_cfg = NetConfig {
+            tap: Some(cfg.host_dev_name),
+            id: Some(device.id),
+            ..Default::default()
+        };
+
+        if let Some(mac) = cfg.guest_mac {
+            net_cfg.mac = MacAddr { bytes: mac.0 };
+        }
+
+        Ok(net_cfg)
+    }
+}
+
+impl TryFrom<BlockDevice> for DiskConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(device: BlockDevice) -> Result<Self, Self::Error> {
+        let cfg = device.config;
+
+        if cfg.path_on_host.is_empty() {
+            return Err(anyhow!("missing host path for block device"));
+        }
+
+        let disk_cfg = DiskConfig {
+            path: Some(PathBuf::from(cfg.path_on_host)),
+            readonly: cfg.is_readonly,
+            id: Some(device.device_id),
+            num_queues: 1,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            ..Default::default()
+        };
+
+        Ok(disk_cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Address, BlockConfig, NetworkConfig};
+    use std::os::unix::io::RawFd;
+
+    fn make_network_device(host_dev_name: &str, queue_fds: Vec<RawFd>) -> NetworkDevice {
+        NetworkDevice {
+            id: "net0".to_string(),
+            config: NetworkConfig {
+                host_dev_name: host_dev_name.to_string(),
+                guest_mac: Some(Address([0x02, 0, 0, 0, 0, 0x01])),
+                queue_fds,
+            },
+        }
+    }
+
+    #[test]
+    fn test_network_device_to_netconfig() {
+        #[derive(Debug)]
+        struct TestData {
+            device: NetworkDevice,
+            result: Option<NetConfig>,
+        }
+
+        let tests = &[
+            TestData {
+                device: make_network_device("", vec![]),
+                result: None,
+            },
+            TestData {
+                device: make_network_device("tap0", vec![10]),
+                result: None,
+            },
+            TestData {
+                device: make_network_device("tap0", vec![]),
+                result: Some(NetConfig {
+                    tap: Some("tap0".to_string()),
+                    id: Some("net0".to_string()),
+                    mac: MacAddr {
+                        bytes: [0x02, 0, 0, 0, 0, 0x01],
+                    },
+                    ..Default::default()
+                }),
+            },
+        ];
+
+        for (i, d) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, d);
+
+            let result = NetConfig::try_from(d.device.clone());
+
+            let msg = format!("{}: actual result: {:?}", msg, result);
+
+            if std::env::var("DEBUG").is_ok() {
+                eprintln!("DEBUG: {}", msg);
+            }
+
+            assert_eq!(result.ok(), d.result, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_block_device_to_diskconfig() {
+        #[derive(Debug)]
+        struct TestData {
+            device: BlockDevice,
+            result: Option<DiskConfig>,
+        }
+
+        let tests = &[
+            TestData {
+                device: BlockDevice::new("blk0".to_string(), BlockConfig::default()),
+                result: None,
+            },
+            TestData {
+                device: BlockDevice::new(
+                    "blk0".to_string(),
+                    BlockConfig {
+                        path_on_host: "/dev/loop0".to_string(),
+                        is_readonly: true,
+                        ..Default::default()
+                    },
+                ),
+                result: Some(DiskConfig {
+                    path: Some(PathBuf::from("/dev/loop0")),
+                    readonly: true,
+                    id: Some("blk0".to_string()),
+                    num_queues: 1,
+                    queue_size: DEFAULT_QUEUE_SIZE,
+                    ..Default::default()
+                }),
+            },
+        ];
+
+        for (i, d) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, d);
+
+            let result = DiskConfig::try_from(d.device.clone());
+
+            let msg = format!("{}: actual result: {:?}", msg, result);
+
+            if std::env::var("DEBUG").is_ok() {
+                eprintln!("DEBUG: {}", msg);
+            }
+
+            assert_eq!(result.ok(), d.result, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_get_net_rate_limiter_config() {
+        assert_eq!(get_net_rate_limiter_config(0, 0).unwrap(), None);
+
+        let config = get_net_rate_limiter_config(8000, 8000).unwrap().unwrap();
+        assert_eq!(
+            config.bandwidth,
+            Some(TokenBucketConfig {
+                size: 1000,
+                one_time_burst: None,
+                refill_time: NET_RATE_LIMITER_REFILL_TIME_MS,
+            })
+        );
+        assert_eq!(config.ops, None);
+
+        assert!(get_net_rate_limiter_config(8000, 0).is_err());
+        assert!(get_net_rate_limiter_config(0, 8000).is_err());
+        assert!(get_net_rate_limiter_config(8000, 16000).is_err());
+    }
+}
@@ -5,18 +5,20 @@
 
 use super::inner::CloudHypervisorInner;
 use crate::ch::utils::get_api_socket_path;
-use crate::ch::utils::{get_jailer_root, get_sandbox_path, get_vsock_path};
+use crate::ch::utils::{get_jailer_root, get_sandbox_path, get_snapshot_path, get_vsock_path};
 use crate::device::DeviceType;
 use crate::kernel_param::KernelParams;
+use crate::utils::{get_host_memory_mb, get_memory_hotplug_size_mb, get_resized_vcpus, MIB_TO_B};
 use crate::VsockDevice;
 use crate::VM_ROOTFS_DRIVER_PMEM;
-use crate::{VcpuThreadIds, VmmState};
+use crate::{MemoryHotplugInfo, VcpuThreadIds, VmmState};
 use anyhow::{anyhow, Context, Result};
 use ch_config::ch_api::{
-    cloud_hypervisor_vm_create, cloud_hypervisor_vm_start, cloud_hypervisor_vmm_ping,
-    cloud_hypervisor_vmm_shutdown,
+    cloud_hypervisor_vm_create, cloud_hypervisor_vm_pause, cloud_hypervisor_vm_resize,
+    cloud_hypervisor_vm_restore, cloud_hypervisor_vm_resume, cloud_hypervisor_vm_snapshot,
+    cloud_hypervisor_vm_start, cloud_hypervisor_vmm_ping, cloud_hypervisor_vmm_shutdown,
 };
-use ch_config::{NamedHypervisorConfig, VmConfig};
+use ch_config::{NamedHypervisorConfig, RestoreConfig, VmConfig, VmResizeData, VmSnapshotConfig};
 use core::future::poll_fn;
 use futures::executor::block_on;
 use futures::future::join_all;
@@ -108,7 +110,7 @@ impl CloudHypervisorInner {
     }
 
     async fn boot_vm(&mut self) -> Result<()> {
-        let shared_fs_devices = self.get_shared_fs_devices().await?;
+        let boot_devices = self.get_boot_devices().await?;
 
         let socket = self
             .api_socket
@@ -147,7 +149,9 @@ impl CloudHypervisorInner {
             vsock_socket_path,
             cfg: hypervisor_config.clone(),
             tdx_enabled,
-            shared_fs_devices,
+            shared_fs_devices: boot_devices.shared_fs_devices,
+            network_devices: boot_devices.network_devices,
+            disk_devices: boot_devices.disk_devices,
         };
 
         let cfg = VmConfig::try_from(named_cfg)?;
@@ -172,6 +176,10 @@ impl CloudHypervisorInner {
 
         self.state = VmmState::VmRunning;
 
+        self.handle_pending_devices_after_boot()
+            .await
+            .context("add pending devices")?;
+
         Ok(())
     }
 
@@ -460,18 +468,232 @@ impl CloudHypervisorInner {
         Ok(())
     }
 
-    pub(crate) fn pause_vm(&self) -> Result<()> {
+    pub(crate) async fn pause_vm(&self) -> Result<()> {
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let response =
+            cloud_hypervisor_vm_pause(socket.try_clone().context("failed to clone socket")?)
+                .await?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "vm pause response: {:?}", detail);
+        }
+
         Ok(())
     }
 
-    pub(crate) fn resume_vm(&self) -> Result<()> {
+    pub(crate) async fn resume_vm(&self) -> Result<()> {
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let response =
+            cloud_hypervisor_vm_resume(socket.try_clone().context("failed to clone socket")?)
+                .await?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "vm resume response: {:?}", detail);
+        }
+
         Ok(())
     }
 
+    // save_vm pauses the VM, which CH requires to take a snapshot, and saves
+    // its state into the snapshot directory of the sandbox. The VM is left
+    // paused.
     pub(crate) async fn save_vm(&self) -> Result<()> {
+        let snapshot_path = get_snapshot_path(&self.id)?;
+
+        info!(sl!(), "do save vm to {}", &snapshot_path);
+
+        create_dir_all(&snapshot_path)
+            .with_context(|| anyhow!("failed to create snapshot directory {}", snapshot_path))?;
+
+        self.pause_vm().await.context("pause vm")?;
+
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let snapshot_config = VmSnapshotConfig {
+            destination_url: format!("file://{}", snapshot_path),
+        };
+
+        let response = cloud_hypervisor_vm_snapshot(
+            socket.try_clone().context("failed to clone socket")?,
+            snapshot_config,
+        )
+        .await?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "vm snapshot response: {:?}", detail);
+        }
+
         Ok(())
     }
 
+    // restore_vm launches a new CH instance and restores the VM saved by
+    // save_vm() into it, before resuming it.
+    pub(crate) async fn restore_vm(&mut self) -> Result<()> {
+        let snapshot_path = get_snapshot_path(&self.id)?;
+
+        info!(sl!(), "do restore vm from {}", &snapshot_path);
+
+        self.start_hypervisor(self.timeout_secs).await?;
+
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let restore_config = RestoreConfig {
+            source_url: format!("file://{}", snapshot_path).into(),
+            prefault: false,
+        };
+
+        let response = cloud_hypervisor_vm_restore(
+            socket.try_clone().context("failed to clone socket")?,
+            restore_config,
+        )
+        .await?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "vm restore response: {:?}", detail);
+        }
+
+        self.resume_vm().await.context("resume vm")?;
+
+        self.state = VmmState::VmRunning;
+
+        Ok(())
+    }
+
+    // reconnect re-establishes the API connection to a CH instance launched
+    // before the runtime was restarted.
+    pub(crate) fn reconnect(&mut self) -> Result<()> {
+        let api_socket_path = get_api_socket_path(&self.id)?;
+
+        let api_socket = UnixStream::connect(&api_socket_path)
+            .with_context(|| anyhow!("failed to connect to {}", api_socket_path))?;
+
+        self.api_socket = Some(api_socket);
+        self.state = VmmState::VmRunning;
+
+        Ok(())
+    }
+
+    // resize_memory grows the guest memory through ACPI or virtio-mem,
+    // depending on the hotplug method the VM was created with. Either way
+    // the guest is notified of the new memory.
+    pub(crate) async fn resize_memory(
+        &mut self,
+        new_mem_mb: u32,
+    ) -> Result<(u32, MemoryHotplugInfo)> {
+        let default_memory = self
+            .config
+            .as_ref()
+            .ok_or("no hypervisor config for CH")
+            .map_err(|e| anyhow!(e))?
+            .memory_info
+            .default_memory;
+        let current_mem_mb = default_memory + self.hotplugged_memory_mb;
+
+        let max_mem_mb = get_host_memory_mb()
+            .context("get host memory")?
+            .min(u32::MAX as u64) as u32;
+        let size_mb = get_memory_hotplug_size_mb(current_mem_mb, new_mem_mb, max_mem_mb);
+        if size_mb == 0 {
+            return Ok((current_mem_mb, MemoryHotplugInfo::default()));
+        }
+
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        let desired_mem_mb = current_mem_mb + size_mb;
+        info!(
+            sl!(),
+            "resize memory from {} MiB to {} MiB", current_mem_mb, desired_mem_mb
+        );
+        let response = cloud_hypervisor_vm_resize(
+            socket.try_clone().context("failed to clone socket")?,
+            VmResizeData {
+                desired_ram: Some(desired_mem_mb as u64 * MIB_TO_B),
+                ..Default::default()
+            },
+        )
+        .await
+        .context("resize vm memory")?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "vm resize response: {:?}", detail);
+        }
+
+        self.hotplugged_memory_mb += size_mb;
+
+        Ok((
+            desired_mem_mb,
+            MemoryHotplugInfo {
+                size_mb,
+                ..Default::default()
+            },
+        ))
+    }
+
+    pub(crate) async fn resize_vcpu(&mut self, new_vcpus: u32) -> Result<(u32, u32)> {
+        let cpu_info = &self
+            .config
+            .as_ref()
+            .ok_or("no hypervisor config for CH")
+            .map_err(|e| anyhow!(e))?
+            .cpu_info;
+        let old_vcpus = self
+            .resized_vcpus
+            .unwrap_or(cpu_info.default_vcpus.max(1) as u32);
+        let new_vcpus = get_resized_vcpus(new_vcpus, cpu_info);
+        if new_vcpus == old_vcpus {
+            return Ok((old_vcpus, new_vcpus));
+        }
+
+        let socket = self
+            .api_socket
+            .as_ref()
+            .ok_or("missing socket")
+            .map_err(|e| anyhow!(e))?;
+
+        info!(sl!(), "resize vcpu from {} to {}", old_vcpus, new_vcpus);
+        let desired_vcpus =
+            u8::try_from(new_vcpus).with_context(|| format!("invalid vcpu count {}", new_vcpus))?;
+        let response = cloud_hypervisor_vm_resize(
+            socket.try_clone().context("failed to clone socket")?,
+            VmResizeData {
+                desired_vcpus: Some(desired_vcpus),
+                ..Default::default()
+            },
+        )
+        .await
+        .context("resize vm vcpus")?;
+
+        if let Some(detail) = response {
+            debug!(sl!(), "vm resize response: {:?}", detail);
+        }
+
+        self.resized_vcpus = Some(new_vcpus);
+
+        Ok((old_vcpus, new_vcpus))
+    }
+
     pub(crate) async fn get_agent_socket(&self) -> Result<String> {
         const HYBRID_VSOCK_SCHEME: &str = "hvsock";
 
@@ -529,7 +751,11 @@ impl CloudHypervisorInner {
 
     pub(crate) async fn capabilities(&self) -> Result<Capabilities> {
         let mut caps = Capabilities::default();
-        caps.set(CapabilityBits::FsSharingSupport);
+        caps.set(
+            CapabilityBits::FsSharingSupport
+                | CapabilityBits::NetRateLimiterSupport
+                | CapabilityBits::VhostUserNetSupport,
+        );
         Ok(caps)
     }
 }
@@ -5,8 +5,8 @@
 
 use super::HypervisorState;
 use crate::device::DeviceType;
-use crate::{Hypervisor, VcpuThreadIds};
-use anyhow::{Context, Result};
+use crate::{BalloonStats, Hypervisor, MemoryHotplugInfo, VcpuThreadIds};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use kata_types::capabilities::Capabilities;
 use kata_types::config::hypervisor::Hypervisor as HypervisorConfig;
@@ -66,12 +66,12 @@ impl Hypervisor for CloudHypervisor {
 
     async fn pause_vm(&self) -> Result<()> {
         let inner = self.inner.write().await;
-        inner.pause_vm()
+        inner.pause_vm().await
     }
 
     async fn resume_vm(&self) -> Result<()> {
         let inner = self.inner.write().await;
-        inner.resume_vm()
+        inner.resume_vm().await
     }
 
     async fn save_vm(&self) -> Result<()> {
@@ -89,6 +89,38 @@ impl Hypervisor for CloudHypervisor {
         inner.remove_device(device).await
     }
 
+    async fn resize_memory(&self, new_mem_mb: u32) -> Result<(u32, MemoryHotplugInfo)> {
+        let mut inner = self.inner.write().await;
+        inner.resize_memory(new_mem_mb).await
+    }
+
+    async fn resize_vcpu(&self, new_vcpus: u32) -> Result<(u32, u32)> {
+        let mut inner = self.inner.write().await;
+        inner.resize_vcpu(new_vcpus).await
+    }
+
+    async fn set_numa_topology(&self, _cpus: &str) -> Result<()> {
+        let inner = self.inner.read().await;
+        match &inner.config {
+            Some(config) if config.cpu_info.enable_numa => Err(anyhow!(
+                "NUMA topology is not supported by cloud hypervisor"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_balloon_stats(&self) -> Result<BalloonStats> {
+        Err(anyhow!(
+            "balloon statistics are not supported by cloud hypervisor"
+        ))
+    }
+
+    async fn get_hypervisor_metrics(&self) -> Result<String> {
+        Err(anyhow!(
+            "hypervisor metrics are not supported by cloud hypervisor"
+        ))
+    }
+
     async fn get_agent_socket(&self) -> Result<String> {
         let inner = self.inner.write().await;
         inner.get_agent_socket().await
@@ -147,6 +179,12 @@ impl Hypervisor for CloudHypervisor {
         let inner = self.inner.read().await;
         inner.capabilities().await
     }
+
+    async fn dump_guest_memory(&self) -> Result<()> {
+        Err(anyhow!(
+            "guest memory dump is not supported by cloud hypervisor"
+        ))
+    }
 }
 
 #[async_trait]
@@ -14,6 +14,9 @@ const CH_VM_SOCKET_NAME: &str = "ch-vm.sock";
 
 const CH_JAILER_DIR: &str = "root";
 
+// The directory the VM state is saved into by save_vm().
+const CH_SNAPSHOT_DIR: &str = "snapshot";
+
 // Return the path for a _hypothetical_ sandbox: the path does *not* exist
 // yet, and for this reason safe-path cannot be used.
 pub fn get_sandbox_path(id: &str) -> Result<String> {
@@ -44,6 +47,14 @@ pub fn get_vsock_path(id: &str) -> Result<String> {
     Ok(path)
 }
 
+pub fn get_snapshot_path(id: &str) -> Result<String> {
+    let sandbox_path = get_sandbox_path(id)?;
+
+    let path = [&sandbox_path, CH_SNAPSHOT_DIR].join("/");
+
+    Ok(path)
+}
+
 pub fn get_jailer_root(id: &str) -> Result<String> {
     let sandbox_path = get_sandbox_path(id)?;
 
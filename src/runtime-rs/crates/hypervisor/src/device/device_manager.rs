@@ -4,15 +4,16 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use anyhow::{anyhow, Context, Result};
 use kata_sys_util::rand::RandomBytes;
 use tokio::sync::Mutex;
 
 use crate::{
-    BlockConfig, BlockDevice, Hypervisor, KATA_BLK_DEV_TYPE, KATA_MMIO_BLK_DEV_TYPE,
-    VIRTIO_BLOCK_MMIO, VIRTIO_BLOCK_PCI,
+    BlockConfig, BlockDevice, HostDevice, Hypervisor, PciSysfs, VfioConfig, VfioDevice,
+    VhostUserConfig, VhostUserDevice, KATA_BLK_DEV_TYPE, KATA_MMIO_BLK_DEV_TYPE, VHOST_USER_BLK,
+    VHOST_USER_FS, VHOST_USER_NET, VIRTIO_BLOCK_MMIO, VIRTIO_BLOCK_PCI,
 };
 
 use super::{
@@ -21,15 +22,23 @@ use super::{
 };
 pub type ArcMutexDevice = Arc<Mutex<dyn Device>>;
 
+// Slot 0 of the guest root bus is taken by the host bridge.
+const FIRST_PCI_SLOT: u64 = 1;
+const MAX_PCI_SLOT: u64 = 31;
+
 /// block_index and released_block_index are used to search an available block index
 /// in Sandbox.
 ///
 /// @block_index generally default is 1 for <vdb>;
 /// @released_block_index for blk devices removed and indexes will released at the same time.
+/// @pci_slot and @released_pci_slot do the same for the guest root bus slots VFIO
+/// devices are hotplugged to.
 #[derive(Clone, Debug, Default)]
 struct SharedInfo {
     block_index: u64,
     released_block_index: Vec<u64>,
+    pci_slot: u64,
+    released_pci_slot: Vec<u64>,
 }
 
 impl SharedInfo {
@@ -37,6 +46,33 @@ impl SharedInfo {
         SharedInfo {
             block_index: 1,
             released_block_index: vec![],
+            pci_slot: FIRST_PCI_SLOT,
+            released_pci_slot: vec![],
+        }
+    }
+
+    // declare an available guest PCI slot
+    fn declare_pci_slot(&mut self) -> Result<u64> {
+        if let Some(slot) = self.released_pci_slot.pop() {
+            return Ok(slot);
+        }
+        if self.pci_slot > MAX_PCI_SLOT {
+            return Err(anyhow!("no guest PCI slot left"));
+        }
+        self.pci_slot += 1;
+        Ok(self.pci_slot - 1)
+    }
+
+    fn release_pci_slot(&mut self, slot: u64) {
+        self.released_pci_slot.push(slot);
+        self.released_pci_slot.sort_by(|a, b| b.cmp(a));
+    }
+
+    fn release_pci_slots(&mut self, config: &VfioConfig) {
+        for dev in &config.devices {
+            if let Ok(slot) = u64::from_str_radix(&dev.guest_pci_path, 16) {
+                self.release_pci_slot(slot);
+            }
         }
     }
 
@@ -56,6 +92,18 @@ impl SharedInfo {
         self.released_block_index.push(index);
         self.released_block_index.sort_by(|a, b| b.cmp(a));
     }
+
+    // release the block index or guest PCI slots declared for a device
+    fn release_device(&mut self, config: &DeviceConfig) {
+        match config {
+            DeviceConfig::BlockCfg(config) => self.release_device_index(config.index),
+            DeviceConfig::VfioCfg(config) => self.release_pci_slots(config),
+            DeviceConfig::VhostUserCfg(config) if config.device_type == VHOST_USER_BLK => {
+                self.release_device_index(config.index)
+            }
+            _ => {}
+        }
+    }
 }
 
 // Device manager will manage the lifecycle of sandbox device
@@ -63,6 +111,7 @@ pub struct DeviceManager {
     devices: HashMap<String, ArcMutexDevice>,
     hypervisor: Arc<dyn Hypervisor>,
     shared_info: SharedInfo,
+    pci_sysfs: PciSysfs,
 }
 
 impl DeviceManager {
@@ -72,6 +121,7 @@ impl DeviceManager {
             devices,
             hypervisor,
             shared_info: SharedInfo::new(),
+            pci_sysfs: PciSysfs::default(),
         })
     }
 
@@ -97,9 +147,8 @@ impl DeviceManager {
         let result = device_guard.attach(self.hypervisor.as_ref()).await;
         // handle attach error
         if let Err(e) = result {
-            if let DeviceConfig::BlockCfg(config) = device_guard.get_device_info().await {
-                self.shared_info.release_device_index(config.index);
-            };
+            self.shared_info
+                .release_device(&device_guard.get_device_info().await);
             drop(device_guard);
             self.devices.remove(device_id);
             return Err(e);
@@ -110,22 +159,17 @@ impl DeviceManager {
     pub async fn try_remove_device(&mut self, device_id: &str) -> Result<()> {
         if let Some(dev) = self.devices.get(device_id) {
             let mut device_guard = dev.lock().await;
-            let result = match device_guard.detach(self.hypervisor.as_ref()).await {
-                Ok(index) => {
-                    if let Some(i) = index {
-                        // release the declared block device index
-                        self.shared_info.release_device_index(i);
-                    }
-                    Ok(())
-                }
-                Err(e) => Err(e),
-            };
-            if result.is_ok() {
+            let result = device_guard.detach(self.hypervisor.as_ref()).await;
+            if let Ok(true) = result {
+                // release the declared resources only once the device is
+                // really unplugged
+                self.shared_info
+                    .release_device(&device_guard.get_device_info().await);
                 drop(device_guard);
                 // if detach success, remove it from device manager
                 self.devices.remove(device_id);
             }
-            return result;
+            return result.map(|_| ());
         }
         Err(anyhow!(
             "device with specified ID hasn't been created. {}",
@@ -156,6 +200,26 @@ impl DeviceManager {
                         continue;
                     }
                 },
+                DeviceConfig::VfioCfg(config) => match device_config {
+                    DeviceConfig::VfioCfg(ref config_new) => {
+                        if config_new.host_path == config.host_path {
+                            return Some(device_id.to_string());
+                        }
+                    }
+                    _ => {
+                        continue;
+                    }
+                },
+                DeviceConfig::VhostUserCfg(config) => match device_config {
+                    DeviceConfig::VhostUserCfg(ref config_new) => {
+                        if config_new.socket_path == config.socket_path {
+                            return Some(device_id.to_string());
+                        }
+                    }
+                    _ => {
+                        continue;
+                    }
+                },
                 _ => {
                     // TODO: support find other device type
                     continue;
@@ -174,6 +238,12 @@ impl DeviceManager {
                 .create_block_device(config, device_id.clone())
                 .await
                 .context("failed to create device")?,
+            DeviceConfig::VfioCfg(config) => self
+                .create_vfio_device(config, device_id.clone())
+                .context("failed to create vfio device")?,
+            DeviceConfig::VhostUserCfg(config) => self
+                .create_vhost_user_device(config, device_id.clone())
+                .context("failed to create vhost-user device")?,
             _ => {
                 return Err(anyhow!("invliad device type"));
             }
@@ -221,6 +291,77 @@ impl DeviceManager {
         ))))
     }
 
+    fn create_vfio_device(
+        &mut self,
+        config: &VfioConfig,
+        device_id: String,
+    ) -> Result<ArcMutexDevice> {
+        let mut vfio_config = config.clone();
+        // find the IOMMU group from the VFIO group device, or from the PCI
+        // device when passing a single device
+        vfio_config.iommu_group = if !config.host_path.is_empty() {
+            Path::new(&config.host_path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.to_owned())
+                .ok_or_else(|| anyhow!("invalid vfio group path {}", config.host_path))?
+        } else {
+            self.pci_sysfs.iommu_group(&config.bus_slot_func)?
+        };
+
+        // all the devices of the IOMMU group must be passed through
+        vfio_config.devices = vec![];
+        for bdf in self
+            .pci_sysfs
+            .iommu_group_devices(&vfio_config.iommu_group)?
+        {
+            let slot = match self.shared_info.declare_pci_slot() {
+                Ok(slot) => slot,
+                Err(e) => {
+                    self.shared_info.release_pci_slots(&vfio_config);
+                    return Err(e);
+                }
+            };
+            vfio_config.devices.push(HostDevice {
+                host_driver: self.pci_sysfs.driver(&bdf).unwrap_or_default(),
+                bus_slot_func: bdf,
+                guest_pci_path: format!("{:02x}", slot),
+            });
+        }
+
+        Ok(Arc::new(Mutex::new(VfioDevice::new(
+            device_id,
+            vfio_config,
+            self.pci_sysfs.clone(),
+        ))))
+    }
+
+    fn create_vhost_user_device(
+        &mut self,
+        config: &VhostUserConfig,
+        device_id: String,
+    ) -> Result<ArcMutexDevice> {
+        let mut vhost_user_config = config.clone();
+        match config.device_type.as_str() {
+            VHOST_USER_BLK => {
+                vhost_user_config.index = self.shared_info.declare_device_index()?;
+            }
+            VHOST_USER_NET | VHOST_USER_FS => {}
+            device_type => {
+                return Err(anyhow!(
+                    "unsupported vhost-user device type {}",
+                    device_type
+                ));
+            }
+        }
+        vhost_user_config.dev_id = device_id.clone();
+
+        Ok(Arc::new(Mutex::new(VhostUserDevice::new(
+            device_id,
+            vhost_user_config,
+        ))))
+    }
+
     // device ID must be generated by device manager instead of device itself
     // in case of ID collision
     fn new_device_id(&self) -> Result<String> {
@@ -5,6 +5,9 @@
 //
 
 mod vhost_user;
+pub use vhost_user::{
+    VhostUserConfig, VhostUserDevice, VHOST_USER_BLK, VHOST_USER_FS, VHOST_USER_NET,
+};
 mod virtio_blk;
 pub use virtio_blk::{
     BlockConfig, BlockDevice, KATA_BLK_DEV_TYPE, KATA_MMIO_BLK_DEV_TYPE, VIRTIO_BLOCK_MMIO,
@@ -13,7 +16,10 @@ pub use virtio_blk::{
 mod virtio_net;
 pub use virtio_net::{Address, NetworkConfig, NetworkDevice};
 mod vfio;
-pub use vfio::{bind_device_to_host, bind_device_to_vfio, VfioBusMode, VfioConfig, VfioDevice};
+pub use vfio::{
+    bind_device_to_host, bind_device_to_vfio, HostDevice, PciSysfs, VfioBusMode, VfioConfig,
+    VfioDevice, KATA_VFIO_PCI_DEV_TYPE, KATA_VFIO_PCI_GK_DEV_TYPE,
+};
 mod virtio_fs;
 pub use virtio_fs::{
     ShareFsDevice, ShareFsDeviceConfig, ShareFsMountConfig, ShareFsMountDevice, ShareFsMountType,
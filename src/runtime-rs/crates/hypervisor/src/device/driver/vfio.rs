@@ -4,33 +4,40 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::{fs, path::Path, process::Command};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use crate::device::Device;
 use crate::device::DeviceConfig;
+use crate::device::DeviceType;
 use crate::Hypervisor as hypervisor;
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-use anyhow::anyhow;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 
-fn override_driver(bdf: &str, driver: &str) -> Result<()> {
-    let driver_override = format!("/sys/bus/pci/devices/{}/driver_override", bdf);
-    fs::write(&driver_override, driver)
-        .with_context(|| format!("echo {} > {}", driver, &driver_override))?;
-    info!(sl!(), "echo {} > {}", driver, driver_override);
-    Ok(())
-}
-
-const SYS_PCI_DEVICES_PATH: &str = "/sys/bus/pci/devices";
-const PCI_DRIVER_PROBE: &str = "/sys/bus/pci/drivers_probe";
+const SYS_ROOT: &str = "/sys";
+const SYS_PCI_DEVICES_PATH: &str = "bus/pci/devices";
+const PCI_DRIVER_PROBE: &str = "bus/pci/drivers_probe";
+const SYS_PCI_DRIVERS_PATH: &str = "bus/pci/drivers";
+const SYS_IOMMU_GROUPS_PATH: &str = "kernel/iommu_groups";
 const VFIO_NEW_ID_PATH: &str = "/sys/bus/pci/drivers/vfio-pci/new_id";
-const VFIO_UNBIND_PATH: &str = "/sys/bus/pci/drivers/vfio-pci/unbind";
+
+// PCI class code of PCI-to-PCI bridges, which can't be passed through.
+const PCI_CLASS_BRIDGE_PCI: &str = "0x0604";
 
 pub const VFIO_PCI: &str = "vfio-pci";
 
-#[derive(Debug, Clone)]
+/// Agent device type of a VFIO device exposed as /dev/vfio/<group> in the
+/// container.
+pub const KATA_VFIO_PCI_DEV_TYPE: &str = "vfio-pci";
+/// Agent device type of a VFIO device claimed by a driver of the guest kernel.
+pub const KATA_VFIO_PCI_GK_DEV_TYPE: &str = "vfio-pci-gk";
+
+#[derive(Debug, Clone, Default)]
 pub enum VfioBusMode {
+    #[default]
     PCI,
     MMIO,
 }
@@ -44,7 +51,19 @@ impl VfioBusMode {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
+pub struct HostDevice {
+    /// PCI device information: "domain:bus:slot.function"
+    pub bus_slot_func: String,
+
+    /// Driver bound to the device on the host, restored on detach
+    pub host_driver: String,
+
+    /// PCI path of the device in the guest, e.g. "02"
+    pub guest_pci_path: String,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct VfioConfig {
     /// Sysfs path for mdev bus type device
     pub sysfs_path: String,
@@ -54,15 +73,223 @@ pub struct VfioConfig {
 
     /// Bus Mode, PCI or MMIO
     pub mode: VfioBusMode,
+
+    /// VFIO group device on the host, i.e. "/dev/vfio/<group>"
+    pub host_path: String,
+
+    /// Device type passed to the agent, vfio-pci or vfio-pci-gk
+    pub dev_type: String,
+
+    /// IOMMU group the devices belong to
+    pub iommu_group: String,
+
+    /// PCI devices of the IOMMU group, all passed through together
+    pub devices: Vec<HostDevice>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct VfioDevice {
     /// Unique identifier of the device
     pub id: String,
 
+    /// device attach count
+    pub attach_count: u64,
+
     /// Config info for Vfio Device
     pub config: VfioConfig,
+
+    /// sysfs the PCI devices are managed through
+    pub sysfs: PciSysfs,
+}
+
+impl VfioDevice {
+    // new creates a new VfioDevice
+    pub fn new(id: String, config: VfioConfig, sysfs: PciSysfs) -> Self {
+        VfioDevice {
+            id,
+            attach_count: 0,
+            config,
+            sysfs,
+        }
+    }
+
+    fn bind_to_vfio(&self) -> Result<()> {
+        for (i, dev) in self.config.devices.iter().enumerate() {
+            if let Err(e) = self.sysfs.bind_to_vfio(&dev.bus_slot_func) {
+                // give back the devices bound so far
+                for dev in &self.config.devices[..i] {
+                    self.sysfs
+                        .bind_to_host(&dev.bus_slot_func, &dev.host_driver)
+                        .ok();
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn bind_to_host(&self) -> Result<()> {
+        for dev in &self.config.devices {
+            self.sysfs
+                .bind_to_host(&dev.bus_slot_func, &dev.host_driver)
+                .with_context(|| format!("bind {} to host", dev.bus_slot_func))?;
+        }
+        Ok(())
+    }
+}
+
+/// PciSysfs manages host PCI devices through sysfs. The root is only
+/// configurable so that tests can use a mocked tree.
+#[derive(Debug, Clone)]
+pub struct PciSysfs {
+    root: PathBuf,
+}
+
+impl Default for PciSysfs {
+    fn default() -> Self {
+        PciSysfs::new(SYS_ROOT)
+    }
+}
+
+impl PciSysfs {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        PciSysfs {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Return the sysfs directory of device `bdf`.
+    pub fn device_path(&self, bdf: &str) -> PathBuf {
+        self.root.join(SYS_PCI_DEVICES_PATH).join(bdf)
+    }
+
+    fn link_name(path: &Path) -> Result<String> {
+        let target = fs::read_link(path).with_context(|| format!("read link {:?}", path))?;
+        target
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.to_owned())
+            .ok_or_else(|| anyhow!("invalid link {:?} -> {:?}", path, target))
+    }
+
+    /// Return the driver bound to device `bdf`, if any.
+    pub fn driver(&self, bdf: &str) -> Option<String> {
+        Self::link_name(&self.device_path(bdf).join("driver")).ok()
+    }
+
+    /// Return the IOMMU group of device `bdf`.
+    pub fn iommu_group(&self, bdf: &str) -> Result<String> {
+        Self::link_name(&self.device_path(bdf).join("iommu_group"))
+            .with_context(|| format!("get iommu group of {}", bdf))
+    }
+
+    /// Return the devices of IOMMU group `group` that can be passed through,
+    /// i.e. all but PCI bridges.
+    pub fn iommu_group_devices(&self, group: &str) -> Result<Vec<String>> {
+        let devices_path = self
+            .root
+            .join(SYS_IOMMU_GROUPS_PATH)
+            .join(group)
+            .join("devices");
+        let mut devices = vec![];
+        for entry in
+            fs::read_dir(&devices_path).with_context(|| format!("read dir {:?}", devices_path))?
+        {
+            let bdf = entry?.file_name().to_string_lossy().to_string();
+            let class = fs::read_to_string(self.device_path(&bdf).join("class"))
+                .with_context(|| format!("read class of {}", bdf))?;
+            if class.trim().starts_with(PCI_CLASS_BRIDGE_PCI) {
+                info!(sl!(), "skip PCI bridge {} of iommu group {}", bdf, group);
+                continue;
+            }
+            devices.push(bdf);
+        }
+        if devices.is_empty() {
+            return Err(anyhow!(
+                "no device to pass through in iommu group {}",
+                group
+            ));
+        }
+        devices.sort();
+        Ok(devices)
+    }
+
+    fn override_driver(&self, bdf: &str, driver: &str) -> Result<()> {
+        let driver_override = self.device_path(bdf).join("driver_override");
+        fs::write(&driver_override, driver)
+            .with_context(|| format!("echo {} > {:?}", driver, &driver_override))?;
+        info!(sl!(), "echo {} > {:?}", driver, driver_override);
+        Ok(())
+    }
+
+    fn probe(&self, bdf: &str) -> Result<()> {
+        // echo bdf > /sys/bus/pci/drivers_probe
+        let probe = self.root.join(PCI_DRIVER_PROBE);
+        fs::write(&probe, bdf).with_context(|| format!("echo {} > {:?}", bdf, &probe))?;
+        info!(sl!(), "echo {} > {:?}", bdf, probe);
+        Ok(())
+    }
+
+    /// Bind device `bdf` to vfio-pci after unbinding it from its host driver.
+    pub fn bind_to_vfio(&self, bdf: &str) -> Result<()> {
+        let host_driver = self.driver(bdf);
+        // if it's already bound to vfio
+        if host_driver.as_deref() == Some(VFIO_PCI) {
+            info!(sl!(), "bdf : {} was already bound to vfio-pci", bdf);
+            return Ok(());
+        }
+
+        info!(sl!(), "host driver : {:?}", host_driver);
+        self.override_driver(bdf, VFIO_PCI)
+            .context("override driver")?;
+
+        if let Some(host_driver) = host_driver {
+            // echo bdf > /sys/bus/pci/devices/bdf/driver/unbind
+            let unbind_path = self.device_path(bdf).join("driver/unbind");
+            fs::write(&unbind_path, bdf)
+                .with_context(|| format!("Failed to echo {} > {:?}", bdf, &unbind_path))?;
+            info!(sl!(), "{} is unbound from {}", bdf, host_driver);
+        }
+
+        self.probe(bdf)
+    }
+
+    /// Bind device `bdf` back to `host_driver` after unbinding it from
+    /// vfio-pci. An empty `host_driver` stands for a device that had no
+    /// driver, it's only given back to the kernel to probe.
+    pub fn bind_to_host(&self, bdf: &str, host_driver: &str) -> Result<()> {
+        info!(sl!(), "bind {} to {:?}", bdf, host_driver);
+
+        if host_driver.is_empty() {
+            // echo > driver_override clears the override
+            self.override_driver(bdf, "\n")
+                .context("clear driver override")?;
+        } else {
+            // if it's already bound to host_driver
+            if self.driver(bdf).as_deref() == Some(host_driver) {
+                info!(
+                    sl!(),
+                    "bdf {} was already unbound to host driver {}", bdf, host_driver
+                );
+                return Ok(());
+            }
+
+            self.override_driver(bdf, host_driver)
+                .context("override driver")?;
+        }
+
+        // echo bdf > /sys/bus/pci/drivers/vfio-pci/unbind
+        let unbind_path = self
+            .root
+            .join(SYS_PCI_DRIVERS_PATH)
+            .join(VFIO_PCI)
+            .join("unbind");
+        fs::write(&unbind_path, bdf)
+            .with_context(|| format!("echo {} > {:?}", bdf, &unbind_path))?;
+        info!(sl!(), "echo {} > {:?}", bdf, unbind_path);
+
+        self.probe(bdf)
+    }
 }
 
 /// binds the device to vfio driver after unbinding from host.
@@ -86,95 +313,233 @@ pub fn bind_device_to_vfio(bdf: &str, host_driver: &str, _vendor_device_id: &str
         }
     }
 
-    // if it's already bound to vfio
-    if is_equal_driver(bdf, VFIO_PCI) {
-        info!(sl!(), "bdf : {} was already bound to vfio-pci", bdf);
-        return Ok(());
-    }
-
-    info!(sl!(), "host driver : {}", host_driver);
-    override_driver(bdf, VFIO_PCI).context("override driver")?;
-
-    let unbind_path = format!("/sys/bus/pci/devices/{}/driver/unbind", bdf);
+    info!(sl!(), "bind {} from {} to vfio", bdf, host_driver);
+    PciSysfs::default().bind_to_vfio(bdf)
+}
 
-    // echo bdf > /sys/bus/pci/drivers/virtio-pci/unbind"
-    fs::write(&unbind_path, bdf)
-        .with_context(|| format!("Failed to echo {} > {}", bdf, &unbind_path))?;
+/// bind_device_to_host binds the device to the host driver after unbinding from vfio-pci.
+pub fn bind_device_to_host(bdf: &str, host_driver: &str, _vendor_device_id: &str) -> Result<()> {
+    PciSysfs::default().bind_to_host(bdf, host_driver)
+}
 
-    info!(sl!(), "{} is unbound from {}", bdf, host_driver);
+#[async_trait]
+impl Device for VfioDevice {
+    async fn attach(&mut self, h: &dyn hypervisor) -> Result<()> {
+        // increase attach count, skip attach the device if the device is already attached
+        if self
+            .increase_attach_count()
+            .await
+            .context("failed to increase attach count")?
+        {
+            return Ok(());
+        }
 
-    // echo bdf > /sys/bus/pci/drivers_probe
-    fs::write(PCI_DRIVER_PROBE, bdf)
-        .with_context(|| format!("Failed to echo {} > {}", bdf, PCI_DRIVER_PROBE))?;
+        if let Err(e) = self.bind_to_vfio() {
+            self.decrease_attach_count().await?;
+            return Err(e).context("bind devices to vfio");
+        }
+        if let Err(e) = h.add_device(DeviceType::Vfio(self.clone())).await {
+            if let Err(err) = self.bind_to_host() {
+                error!(sl!(), "failed to restore host drivers: {:?}", err);
+            }
+            self.decrease_attach_count().await?;
+            return Err(e);
+        }
+        Ok(())
+    }
 
-    info!(sl!(), "echo {} > /sys/bus/pci/drivers_probe", bdf);
-    Ok(())
-}
+    async fn detach(&mut self, h: &dyn hypervisor) -> Result<bool> {
+        // get the count of device detached, skip detach once it reaches the 0
+        if self
+            .decrease_attach_count()
+            .await
+            .context("failed to decrease attach count")?
+        {
+            return Ok(false);
+        }
+        if let Err(e) = h.remove_device(DeviceType::Vfio(self.clone())).await {
+            self.increase_attach_count().await?;
+            return Err(e);
+        }
+        self.bind_to_host().context("restore host drivers")?;
+        Ok(true)
+    }
 
-pub fn is_equal_driver(bdf: &str, host_driver: &str) -> bool {
-    let sys_pci_devices_path = Path::new(SYS_PCI_DEVICES_PATH);
-    let driver_file = sys_pci_devices_path.join(bdf).join("driver");
+    async fn get_device_info(&self) -> DeviceConfig {
+        DeviceConfig::VfioCfg(self.config.clone())
+    }
 
-    if driver_file.exists() {
-        let driver_path = fs::read_link(driver_file).unwrap_or_default();
-        let driver_name = driver_path
-            .file_name()
-            .map_or(String::new(), |v| v.to_str().unwrap().to_owned());
-        return driver_name.eq(host_driver);
+    async fn increase_attach_count(&mut self) -> Result<bool> {
+        match self.attach_count {
+            0 => {
+                // do real attach
+                self.attach_count += 1;
+                Ok(false)
+            }
+            std::u64::MAX => Err(anyhow!("device was attached too many times")),
+            _ => {
+                self.attach_count += 1;
+                Ok(true)
+            }
+        }
     }
 
-    false
+    async fn decrease_attach_count(&mut self) -> Result<bool> {
+        match self.attach_count {
+            0 => Err(anyhow!("detaching a device that wasn't attached")),
+            1 => {
+                // do real work
+                self.attach_count -= 1;
+                Ok(false)
+            }
+            _ => {
+                self.attach_count -= 1;
+                Ok(true)
+            }
+        }
+    }
 }
 
-/// bind_device_to_host binds the device to the host driver after unbinding from vfio-pci.
-pub fn bind_device_to_host(bdf: &str, host_driver: &str, _vendor_device_id: &str) -> Result<()> {
-    // Unbind from vfio-pci driver to the original host driver
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
 
-    info!(sl!(), "bind {} to {}", bdf, host_driver);
+    // Build a sysfs tree with IOMMU group 12 made of a NIC bound to ixgbe, an
+    // unbound function and a PCI bridge.
+    fn mock_sysfs() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path();
 
-    // if it's already bound to host_driver
-    if is_equal_driver(bdf, host_driver) {
-        info!(
-            sl!(),
-            "bdf {} was already unbound to host driver {}", bdf, host_driver
-        );
-        return Ok(());
+        for driver in ["ixgbe", VFIO_PCI] {
+            fs::create_dir_all(path.join(SYS_PCI_DRIVERS_PATH).join(driver)).unwrap();
+        }
+        let group = path.join(SYS_IOMMU_GROUPS_PATH).join("12");
+        fs::create_dir_all(group.join("devices")).unwrap();
+
+        for (bdf, class, driver) in [
+            ("0000:01:00.0", "0x020000", Some("ixgbe")),
+            ("0000:01:00.1", "0x020000", None),
+            ("0000:00:01.0", "0x060400", Some("pcieport")),
+        ] {
+            let dev = path.join(SYS_PCI_DEVICES_PATH).join(bdf);
+            fs::create_dir_all(&dev).unwrap();
+            fs::write(dev.join("class"), format!("{}\n", class)).unwrap();
+            symlink(&group, dev.join("iommu_group")).unwrap();
+            symlink(&dev, group.join("devices").join(bdf)).unwrap();
+            if let Some(driver) = driver {
+                symlink(
+                    path.join(SYS_PCI_DRIVERS_PATH).join(driver),
+                    dev.join("driver"),
+                )
+                .unwrap();
+            }
+        }
+        root
     }
 
-    override_driver(bdf, host_driver).context("override driver")?;
-
-    // echo bdf > /sys/bus/pci/drivers/vfio-pci/unbind"
-    std::fs::write(VFIO_UNBIND_PATH, bdf)
-        .with_context(|| format!("echo {}> {}", bdf, VFIO_UNBIND_PATH))?;
-    info!(sl!(), "echo {} > {}", bdf, VFIO_UNBIND_PATH);
+    #[test]
+    fn test_iommu_group_devices() {
+        let root = mock_sysfs();
+        let sysfs = PciSysfs::new(root.path());
 
-    // echo bdf > /sys/bus/pci/drivers_probe
-    std::fs::write(PCI_DRIVER_PROBE, bdf)
-        .with_context(|| format!("echo {} > {}", bdf, PCI_DRIVER_PROBE))?;
-    info!(sl!(), "echo {} > {}", bdf, PCI_DRIVER_PROBE);
-
-    Ok(())
-}
+        assert_eq!(sysfs.iommu_group("0000:01:00.1").unwrap(), "12");
+        assert!(sysfs.iommu_group("0000:02:00.0").is_err());
+        assert_eq!(
+            sysfs.iommu_group_devices("12").unwrap(),
+            vec!["0000:01:00.0", "0000:01:00.1"]
+        );
+        assert!(sysfs.iommu_group_devices("13").is_err());
 
-#[async_trait]
-impl Device for VfioConfig {
-    async fn attach(&mut self, _h: &dyn hypervisor) -> Result<()> {
-        todo!()
+        assert_eq!(sysfs.driver("0000:01:00.0"), Some("ixgbe".to_string()));
+        assert_eq!(sysfs.driver("0000:01:00.1"), None);
     }
 
-    async fn detach(&mut self, _h: &dyn hypervisor) -> Result<Option<u64>> {
-        todo!()
-    }
+    #[test]
+    fn test_bind_to_vfio_and_back() {
+        let root = mock_sysfs();
+        let path = root.path();
+        let sysfs = PciSysfs::new(path);
+        let bdf = "0000:01:00.0";
+        let dev = path.join(SYS_PCI_DEVICES_PATH).join(bdf);
+
+        sysfs.bind_to_vfio(bdf).unwrap();
+        assert_eq!(
+            fs::read_to_string(dev.join("driver_override")).unwrap(),
+            VFIO_PCI
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(SYS_PCI_DRIVERS_PATH).join("ixgbe/unbind")).unwrap(),
+            bdf
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(PCI_DRIVER_PROBE)).unwrap(),
+            bdf
+        );
 
-    async fn get_device_info(&self) -> DeviceConfig {
-        todo!()
+        // what the kernel does on probe
+        fs::remove_file(dev.join("driver")).unwrap();
+        symlink(
+            path.join(SYS_PCI_DRIVERS_PATH).join(VFIO_PCI),
+            dev.join("driver"),
+        )
+        .unwrap();
+        fs::remove_file(path.join(PCI_DRIVER_PROBE)).unwrap();
+
+        // nothing to do once bound to vfio-pci
+        sysfs.bind_to_vfio(bdf).unwrap();
+        assert!(!path.join(PCI_DRIVER_PROBE).exists());
+
+        sysfs.bind_to_host(bdf, "ixgbe").unwrap();
+        assert_eq!(
+            fs::read_to_string(dev.join("driver_override")).unwrap(),
+            "ixgbe"
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(SYS_PCI_DRIVERS_PATH).join("vfio-pci/unbind")).unwrap(),
+            bdf
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(PCI_DRIVER_PROBE)).unwrap(),
+            bdf
+        );
     }
 
-    async fn increase_attach_count(&mut self) -> Result<bool> {
-        todo!()
+    #[test]
+    fn test_bind_unbound_device_to_host() {
+        let root = mock_sysfs();
+        let path = root.path();
+        let sysfs = PciSysfs::new(path);
+        let bdf = "0000:01:00.1";
+        let dev = path.join(SYS_PCI_DEVICES_PATH).join(bdf);
+        fs::write(dev.join("driver_override"), VFIO_PCI).unwrap();
+
+        // no host driver to override with, only the vfio-pci one is cleared
+        sysfs.bind_to_host(bdf, "").unwrap();
+        assert_eq!(
+            fs::read_to_string(dev.join("driver_override")).unwrap(),
+            "\n"
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(SYS_PCI_DRIVERS_PATH).join("vfio-pci/unbind")).unwrap(),
+            bdf
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(PCI_DRIVER_PROBE)).unwrap(),
+            bdf
+        );
     }
 
-    async fn decrease_attach_count(&mut self) -> Result<bool> {
-        todo!()
+    #[test]
+    fn test_bind_unbound_device_to_vfio() {
+        let root = mock_sysfs();
+        let sysfs = PciSysfs::new(root.path());
+
+        // no driver to unbind from
+        sysfs.bind_to_vfio("0000:01:00.1").unwrap();
+        assert_eq!(
+            fs::read_to_string(root.path().join(PCI_DRIVER_PROBE)).unwrap(),
+            "0000:01:00.1"
+        );
     }
 }
@@ -4,12 +4,22 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use std::os::unix::fs::FileTypeExt;
+
 use crate::device::Device;
 use crate::device::DeviceConfig;
+use crate::device::DeviceType;
 use crate::Hypervisor as hypervisor;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 
+/// Device type of a vhost-user-net device
+pub const VHOST_USER_NET: &str = "vhost-user-net";
+/// Device type of a vhost-user-blk device
+pub const VHOST_USER_BLK: &str = "vhost-user-blk";
+/// Device type of a vhost-user-fs device
+pub const VHOST_USER_FS: &str = "vhost-user-fs";
+
 #[derive(Debug, Clone, Default)]
 /// VhostUserConfig represents data shared by most vhost-user devices
 pub struct VhostUserConfig {
@@ -22,40 +32,148 @@ pub struct VhostUserConfig {
     /// These are only meaningful for vhost user fs devices
     pub tag: String,
     pub cache: String,
+    /// One of VHOST_USER_NET, VHOST_USER_BLK or VHOST_USER_FS
     pub device_type: String,
     /// Pci_addr is the PCI address used to identify the slot at which the drive is attached.
     pub pci_addr: Option<String>,
     /// Block index of the device if assigned
-    pub index: u8,
+    pub index: u64,
     pub cache_size: u32,
-    pub queue_siez: u32,
+    pub queue_size: u32,
+}
+
+impl VhostUserConfig {
+    /// Check that the backend socket exists, so that a missing or exited
+    /// backend is reported before the VMM tries to connect to it.
+    pub fn check_socket(&self) -> Result<()> {
+        let metadata = std::fs::metadata(&self.socket_path)
+            .with_context(|| format!("vhost-user socket {}", self.socket_path))?;
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow!("{} isn't a socket", self.socket_path));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct VhostUserDevice {
     pub device_id: String,
+    pub attach_count: u64,
     pub config: VhostUserConfig,
 }
 
+impl VhostUserDevice {
+    // new creates a new VhostUserDevice
+    pub fn new(device_id: String, config: VhostUserConfig) -> Self {
+        VhostUserDevice {
+            device_id,
+            attach_count: 0,
+            config,
+        }
+    }
+}
+
 #[async_trait]
-impl Device for VhostUserConfig {
-    async fn attach(&mut self, _h: &dyn hypervisor) -> Result<()> {
-        todo!()
+impl Device for VhostUserDevice {
+    async fn attach(&mut self, h: &dyn hypervisor) -> Result<()> {
+        // increase attach count, skip attach the device if the device is already attached
+        if self
+            .increase_attach_count()
+            .await
+            .context("failed to increase attach count")?
+        {
+            return Ok(());
+        }
+        if let Err(e) = self.config.check_socket() {
+            self.decrease_attach_count().await?;
+            return Err(e);
+        }
+        if let Err(e) = h.add_device(DeviceType::VhostUser(self.clone())).await {
+            self.decrease_attach_count().await?;
+            return Err(e);
+        }
+        Ok(())
     }
 
-    async fn detach(&mut self, _h: &dyn hypervisor) -> Result<Option<u64>> {
-        todo!()
+    async fn detach(&mut self, h: &dyn hypervisor) -> Result<bool> {
+        // get the count of device detached, skip detach once it reaches the 0
+        if self
+            .decrease_attach_count()
+            .await
+            .context("failed to decrease attach count")?
+        {
+            return Ok(false);
+        }
+        if let Err(e) = h.remove_device(DeviceType::VhostUser(self.clone())).await {
+            self.increase_attach_count().await?;
+            return Err(e);
+        }
+        Ok(true)
     }
 
     async fn get_device_info(&self) -> DeviceConfig {
-        todo!()
+        DeviceConfig::VhostUserCfg(self.config.clone())
     }
 
     async fn increase_attach_count(&mut self) -> Result<bool> {
-        todo!()
+        match self.attach_count {
+            0 => {
+                // do real attach
+                self.attach_count += 1;
+                Ok(false)
+            }
+            std::u64::MAX => Err(anyhow!("device was attached too many times")),
+            _ => {
+                self.attach_count += 1;
+                Ok(true)
+            }
+        }
     }
 
     async fn decrease_attach_count(&mut self) -> Result<bool> {
-        todo!()
+        match self.attach_count {
+            0 => Err(anyhow!("detaching a device that wasn't attached")),
+            1 => {
+                // do real work
+                self.attach_count -= 1;
+                Ok(false)
+            }
+            _ => {
+                self.attach_count -= 1;
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = VhostUserConfig {
+            socket_path: dir.path().join("vhost-user.sock").display().to_string(),
+            ..Default::default()
+        };
+        assert!(config.check_socket().is_err());
+
+        let _listener = std::os::unix::net::UnixListener::bind(&config.socket_path).unwrap();
+        config.check_socket().unwrap();
+
+        config.socket_path = dir.path().display().to_string();
+        assert!(config.check_socket().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_attach_count() {
+        let mut device = VhostUserDevice::new("vu0".to_string(), VhostUserConfig::default());
+
+        assert!(device.decrease_attach_count().await.is_err());
+        assert!(!device.increase_attach_count().await.unwrap());
+        assert!(device.increase_attach_count().await.unwrap());
+        assert!(device.decrease_attach_count().await.unwrap());
+        assert!(!device.decrease_attach_count().await.unwrap());
     }
 }
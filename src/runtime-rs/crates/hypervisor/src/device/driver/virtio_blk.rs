@@ -82,20 +82,20 @@ impl Device for BlockDevice {
         return Ok(());
     }
 
-    async fn detach(&mut self, h: &dyn hypervisor) -> Result<Option<u64>> {
+    async fn detach(&mut self, h: &dyn hypervisor) -> Result<bool> {
         // get the count of device detached, skip detach once it reaches the 0
         if self
             .decrease_attach_count()
             .await
             .context("failed to decrease attach count")?
         {
-            return Ok(None);
+            return Ok(false);
         }
         if let Err(e) = h.remove_device(DeviceType::Block(self.clone())).await {
             self.increase_attach_count().await?;
             return Err(e);
         }
-        Ok(Some(self.config.index))
+        Ok(true)
     }
 
     async fn get_device_info(&self) -> DeviceConfig {
@@ -5,6 +5,7 @@
 //
 
 use std::fmt;
+use std::os::unix::io::RawFd;
 
 #[derive(Clone)]
 pub struct Address(pub [u8; 6]);
@@ -27,6 +28,10 @@ pub struct NetworkConfig {
 
     /// Guest MAC address.
     pub guest_mac: Option<Address>,
+
+    /// Queues of the host device opened by the runtime, used instead of
+    /// opening `host_dev_name`. They must stay open until the device is added.
+    pub queue_fds: Vec<RawFd>,
 }
 
 #[derive(Debug, Clone)]
@@ -9,7 +9,8 @@ use std::fmt;
 use crate::{
     BlockConfig, BlockDevice, HybridVsockConfig, HybridVsockDevice, Hypervisor as hypervisor,
     NetworkConfig, NetworkDevice, ShareFsDevice, ShareFsDeviceConfig, ShareFsMountConfig,
-    ShareFsMountDevice, VfioConfig, VfioDevice, VsockConfig, VsockDevice,
+    ShareFsMountDevice, VfioConfig, VfioDevice, VhostUserConfig, VhostUserDevice, VsockConfig,
+    VsockDevice,
 };
 use anyhow::Result;
 use async_trait::async_trait;
@@ -27,6 +28,7 @@ pub enum DeviceConfig {
     ShareFsMountCfg(ShareFsMountConfig),
     VsockCfg(VsockConfig),
     HybridVsockCfg(HybridVsockConfig),
+    VhostUserCfg(VhostUserConfig),
 }
 
 #[derive(Debug)]
@@ -38,6 +40,7 @@ pub enum DeviceType {
     ShareFsMount(ShareFsMountDevice),
     HybridVsock(HybridVsockDevice),
     Vsock(VsockDevice),
+    VhostUser(VhostUserDevice),
 }
 
 impl fmt::Display for DeviceType {
@@ -51,7 +54,11 @@ pub trait Device: Send + Sync {
     // attach is to plug device into VM
     async fn attach(&mut self, h: &dyn hypervisor) -> Result<()>;
     // detach is to unplug device from VM
-    async fn detach(&mut self, h: &dyn hypervisor) -> Result<Option<u64>>;
+    // return values:
+    // * true: the device was unplugged, its resources can be released.
+    // * false: the device is still attached for other users, skip following actions.
+    // * err error: error while do detach
+    async fn detach(&mut self, h: &dyn hypervisor) -> Result<bool>;
     // get_device_info returns device config
     async fn get_device_info(&self) -> DeviceConfig;
     // increase_attach_count is used to increase the attach count for a device
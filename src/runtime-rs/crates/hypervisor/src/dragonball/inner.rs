@@ -6,15 +6,17 @@
 
 use super::vmm_instance::VmmInstance;
 use crate::{
-    device::DeviceType, hypervisor_persist::HypervisorState, kernel_param::KernelParams, VmmState,
-    DEV_HUGEPAGES, HUGETLBFS, HYPERVISOR_DRAGONBALL, SHMEM, VM_ROOTFS_DRIVER_BLK,
-    VM_ROOTFS_DRIVER_MMIO,
+    device::DeviceType, hypervisor_persist::HypervisorState, kernel_param::KernelParams,
+    numa::NumaTopology, utils, VmmState, DEV_HUGEPAGES, HUGETLBFS, HYPERVISOR_DRAGONBALL, SHMEM,
+    VM_ROOTFS_DRIVER_BLK, VM_ROOTFS_DRIVER_MMIO,
 };
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use dragonball::{
-    api::v1::{BlockDeviceConfigInfo, BootSourceConfig},
-    vm::VmConfigInfo,
+    api::v1::{BalloonDeviceConfigInfo, BlockDeviceConfigInfo, BlockDeviceType, BootSourceConfig},
+    device_manager::ConsoleLogConfig,
+    seccomp::SeccompFilters,
+    vm::{NumaRegionInfo, VmConfigInfo},
 };
 use kata_sys_util::mount;
 use kata_types::{
@@ -27,6 +29,11 @@ use std::{collections::HashSet, fs::create_dir_all, path::PathBuf};
 
 const DRAGONBALL_KERNEL: &str = "vmlinux";
 const DRAGONBALL_ROOT_FS: &str = "rootfs";
+const DRAGONBALL_CONSOLE_LOG: &str = "console.log";
+const DRAGONBALL_CONSOLE_CRASH_LOG: &str = "console-crash.log";
+pub(crate) const DRAGONBALL_BALLOON_ID: &str = "balloon0";
+// Interval in seconds to refresh the memory statistics of the guest.
+const DRAGONBALL_BALLOON_STATS_INTERVAL: u32 = 5;
 
 pub struct DragonballInner {
     /// sandbox id
@@ -64,6 +71,18 @@ pub struct DragonballInner {
 
     /// dragonball capabilities
     pub(crate) capabilities: Capabilities,
+
+    /// guest memory hot-added since boot, in MiB
+    pub(crate) hotplugged_memory_mb: u32,
+
+    /// memory slots used by the hot-added guest memory
+    pub(crate) hotplugged_memory_slots: u32,
+
+    /// vCPU count once resized, the boot vCPU count until then
+    pub(crate) resized_vcpus: Option<u32>,
+
+    /// guest NUMA topology mirroring the host NUMA nodes of the sandbox
+    pub(crate) numa_topology: Option<NumaTopology>,
 }
 
 impl DragonballInner {
@@ -72,7 +91,8 @@ impl DragonballInner {
         capabilities.set(
             CapabilityBits::BlockDeviceSupport
                 | CapabilityBits::BlockDeviceHotplugSupport
-                | CapabilityBits::FsSharingSupport,
+                | CapabilityBits::FsSharingSupport
+                | CapabilityBits::NetRateLimiterSupport,
         );
         DragonballInner {
             id: "".to_string(),
@@ -87,6 +107,10 @@ impl DragonballInner {
             run_dir: "".to_string(),
             cached_block_devices: Default::default(),
             capabilities,
+            hotplugged_memory_mb: 0,
+            hotplugged_memory_slots: 0,
+            resized_vcpus: None,
+            numa_topology: None,
         }
     }
 
@@ -140,6 +164,10 @@ impl DragonballInner {
             self.add_device(dev).await.context("add_device")?;
         }
 
+        if self.config.memory_info.reclaim_guest_freed_memory {
+            self.set_balloon().context("set balloon")?;
+        }
+
         // start vmm and wait ready
         self.start_vmm_instance().context("start vmm instance")?;
         self.wait_vmm_ready(timeout).context("wait vmm")?;
@@ -161,6 +189,19 @@ impl DragonballInner {
         create_dir_all(self.run_dir.as_str())
             .with_context(|| format!("failed to create dir {}", self.run_dir.as_str()))?;
 
+        // load the seccomp filters of the vmm and vcpu threads
+        let security_info = &self.config.security_info;
+        let seccomp_filters = if security_info.disable_seccomp {
+            SeccompFilters::default()
+        } else if !security_info.seccomp_filter_path.is_empty() {
+            SeccompFilters::from_json_file(&security_info.seccomp_filter_path).with_context(
+                || format!("load seccomp filters {}", security_info.seccomp_filter_path),
+            )?
+        } else {
+            SeccompFilters::new_default().context("build default seccomp filters")?
+        };
+        self.vmm_instance.set_seccomp_filters(seccomp_filters);
+
         // run vmm server
         self.vmm_instance
             .run_vmm_server(&self.id, self.netns.clone())
@@ -189,13 +230,41 @@ impl DragonballInner {
 
     fn set_vm_base_config(&mut self) -> Result<()> {
         let serial_path = [&self.run_dir, "console.sock"].join("/");
+        // Dragonball dumps the guest memory by itself when the guest crashes,
+        // since the VM is gone right after. The directory is created beforehand,
+        // the VMM thread is confined by the seccomp filters by then.
+        let dump_path = &self.config.debug_info.guest_memory_dump_path;
+        let guest_memory_dump_path = if dump_path.is_empty() {
+            None
+        } else {
+            let dump_dir = utils::get_guest_memory_dump_dir(dump_path, &self.id);
+            create_dir_all(&dump_dir)
+                .with_context(|| format!("failed to create guest memory dump dir {}", dump_dir))?;
+            Some(dump_dir)
+        };
         let (mem_type, mem_file_path) = if self.config.memory_info.enable_hugepages {
             (String::from(HUGETLBFS), String::from(DEV_HUGEPAGES))
         } else {
             (String::from(SHMEM), String::from(""))
         };
+        // Each guest NUMA node gets a memory region bound to its host node.
+        let numa_regions = self
+            .numa_topology
+            .iter()
+            .flat_map(|topology| topology.nodes.iter())
+            .map(|node| NumaRegionInfo {
+                size: node.memory_mb as u64,
+                host_numa_node_id: Some(node.host_node_id),
+                guest_numa_node_id: Some(node.guest_node_id),
+                vcpu_ids: node.vcpus.clone(),
+            })
+            .collect();
+        let console_log = self.console_log_config()?;
         let vm_config = VmConfigInfo {
             serial_path: Some(serial_path),
+            console_log,
+            guest_memory_dump_path,
+            numa_regions,
             mem_size_mib: self.config.memory_info.default_memory as usize,
             vcpu_count: self.config.cpu_info.default_vcpus as u8,
             max_vcpu_count: self.config.cpu_info.default_maxvcpus as u8,
@@ -210,6 +279,32 @@ impl DragonballInner {
             .context("set vm configuration")
     }
 
+    // The guest console output is written to the console socket and, if configured, captured
+    // into a log file under the console log directory of the sandbox.
+    fn console_log_config(&self) -> Result<ConsoleLogConfig> {
+        let debug_info = &self.config.debug_info;
+        if debug_info.console_log_path.is_empty() {
+            return Ok(ConsoleLogConfig::default());
+        }
+
+        let log_dir = [debug_info.console_log_path.as_str(), self.id.as_str()].join("/");
+        create_dir_all(&log_dir)
+            .with_context(|| format!("failed to create console log dir {}", log_dir))?;
+        let ring_buffer_dump_path = if debug_info.console_ring_buffer_size > 0 {
+            Some([log_dir.as_str(), DRAGONBALL_CONSOLE_CRASH_LOG].join("/"))
+        } else {
+            None
+        };
+
+        Ok(ConsoleLogConfig {
+            log_file: Some([log_dir.as_str(), DRAGONBALL_CONSOLE_LOG].join("/")),
+            rotate_size: debug_info.console_log_rotate_size,
+            rotate_count: debug_info.console_log_rotate_count,
+            ring_buffer_size: debug_info.console_ring_buffer_size,
+            ring_buffer_dump_path,
+        })
+    }
+
     pub(crate) fn umount_jail_resource(&self, jailed_path: &str) -> Result<()> {
         let path = [self.jailer_root.as_str(), jailed_path].join("/");
         nix::mount::umount2(path.as_str(), nix::mount::MntFlags::MNT_DETACH)
@@ -267,8 +362,13 @@ impl DragonballInner {
             .context("get resource")?;
 
         if driver == VM_ROOTFS_DRIVER_BLK || driver == VM_ROOTFS_DRIVER_MMIO {
+            // The guest image is trusted, it may be a qcow2 image layered on a base image.
+            let path_on_host = PathBuf::from(jail_drive);
+            let device_type = BlockDeviceType::probe(&path_on_host)
+                .with_context(|| format!("probe format of {:?}", path_on_host))?;
             let blk_cfg = BlockDeviceConfigInfo {
-                path_on_host: PathBuf::from(jail_drive),
+                path_on_host,
+                device_type,
                 drive_id: DRAGONBALL_ROOT_FS.to_string(),
                 is_root_device: false,
                 // Add it as a regular block device
@@ -291,6 +391,24 @@ impl DragonballInner {
         }
     }
 
+    // set_balloon adds an empty balloon reporting the pages freed by the guest,
+    // so that they are given back to the host.
+    fn set_balloon(&mut self) -> Result<()> {
+        let balloon_cfg = BalloonDeviceConfigInfo {
+            balloon_id: DRAGONBALL_BALLOON_ID.to_string(),
+            size_mib: 0,
+            f_deflate_on_oom: true,
+            f_reporting: true,
+            stats_polling_interval_s: DRAGONBALL_BALLOON_STATS_INTERVAL,
+            ..Default::default()
+        };
+        info!(sl!(), "set balloon {:?}", balloon_cfg);
+
+        self.vmm_instance
+            .insert_balloon_device(balloon_cfg)
+            .context("insert balloon device")
+    }
+
     fn start_vmm_instance(&mut self) -> Result<()> {
         info!(sl!(), "Starting VM");
         self.vmm_instance
@@ -353,6 +471,9 @@ impl Persist for DragonballInner {
             config: self.hypervisor_config(),
             run_dir: self.run_dir.clone(),
             cached_block_devices: self.cached_block_devices.clone(),
+            hotplugged_memory_mb: self.hotplugged_memory_mb,
+            hotplugged_memory_slots: self.hotplugged_memory_slots,
+            resized_vcpus: self.resized_vcpus,
             ..Default::default()
         })
     }
@@ -375,6 +496,10 @@ impl Persist for DragonballInner {
             pending_devices: vec![],
             cached_block_devices: hypervisor_state.cached_block_devices,
             capabilities: Capabilities::new(),
+            hotplugged_memory_mb: hypervisor_state.hotplugged_memory_mb,
+            hotplugged_memory_slots: hypervisor_state.hotplugged_memory_slots,
+            resized_vcpus: hypervisor_state.resized_vcpus,
+            numa_topology: None,
         })
     }
 }
@@ -8,15 +8,20 @@ use std::path::PathBuf;
 
 use anyhow::{anyhow, Context, Result};
 use dbs_utils::net::MacAddr;
-use dragonball::api::v1::{
-    BlockDeviceConfigInfo, FsDeviceConfigInfo, FsMountConfigInfo, VirtioNetDeviceConfigInfo,
-    VsockDeviceConfigInfo,
+use dragonball::{
+    api::v1::{
+        BlockDeviceConfigInfo, FsDeviceConfigInfo, FsMountConfigInfo, HostDeviceConfig,
+        VfioPciDeviceConfig, VirtioNetDeviceConfigInfo, VsockDeviceConfigInfo,
+    },
+    config_manager::{RateLimiterConfigInfo, TokenBucketConfigInfo},
 };
 
 use super::DragonballInner;
 use crate::{
-    device::DeviceType, HybridVsockConfig, NetworkConfig, ShareFsDeviceConfig, ShareFsMountConfig,
-    ShareFsMountType, ShareFsOperation, VmmState,
+    device::{driver::VfioDevice, DeviceType},
+    utils::{get_net_rate_limiter_bucket_size, NET_RATE_LIMITER_REFILL_TIME_MS},
+    HybridVsockConfig, NetworkConfig, ShareFsDeviceConfig, ShareFsMountConfig, ShareFsMountType,
+    ShareFsOperation, VhostUserConfig, VmmState, VHOST_USER_FS,
 };
 
 const MB_TO_B: u32 = 1024 * 1024;
@@ -30,6 +35,10 @@ pub(crate) fn drive_index_to_id(index: u64) -> String {
     format!("drive_{}", index)
 }
 
+fn host_device_id(vfio_id: &str, index: usize) -> String {
+    format!("{}_{}", vfio_id, index)
+}
+
 impl DragonballInner {
     pub(crate) async fn add_device(&mut self, device: DeviceType) -> Result<()> {
         if self.state == VmmState::NotReady {
@@ -47,9 +56,7 @@ impl DragonballInner {
             DeviceType::Network(network) => self
                 .add_net_device(&network.config, network.id)
                 .context("add net device"),
-            DeviceType::Vfio(_) => {
-                todo!()
-            }
+            DeviceType::Vfio(vfio) => self.add_vfio_device(&vfio).context("add vfio device"),
             DeviceType::Block(block) => self
                 .add_block_device(
                     block.config.path_on_host.as_str(),
@@ -68,6 +75,9 @@ impl DragonballInner {
             DeviceType::Vsock(_) => {
                 todo!()
             }
+            DeviceType::VhostUser(vhost_user) => self
+                .add_vhost_user_device(&vhost_user.config)
+                .context("add vhost-user device"),
         }
     }
 
@@ -80,13 +90,71 @@ impl DragonballInner {
                 self.remove_block_drive(drive_id.as_str())
                     .context("remove block drive")
             }
-            DeviceType::Vfio(_config) => {
-                todo!()
+            DeviceType::Network(network) => self
+                .vmm_instance
+                .remove_network_device(network.id.as_str())
+                .context("remove net device"),
+            DeviceType::ShareFs(sharefs) => self
+                .vmm_instance
+                .remove_fs(sharefs.config.mount_tag.as_str())
+                .context("remove share fs device"),
+            DeviceType::VhostUser(vhost_user) if vhost_user.config.device_type == VHOST_USER_FS => {
+                self.vmm_instance
+                    .remove_fs(vhost_user.config.tag.as_str())
+                    .context("remove vhost-user fs device")
             }
+            DeviceType::VhostUser(vhost_user) => Err(anyhow!(
+                "dragonball doesn't support {} devices, can't remove device {}",
+                vhost_user.config.device_type,
+                vhost_user.device_id
+            )),
+            DeviceType::Vfio(vfio) => self.remove_vfio_device(&vfio).context("remove vfio device"),
+            // The upcall channel used to hot-unplug devices goes through the vsock device.
+            DeviceType::HybridVsock(_) | DeviceType::Vsock(_) => Err(anyhow!(
+                "dragonball can't hot-unplug vsock device {}",
+                device
+            )),
             _ => Err(anyhow!("unsupported device {:?}", device)),
         }
     }
 
+    fn add_vfio_device(&mut self, device: &VfioDevice) -> Result<()> {
+        for (index, dev) in device.config.devices.iter().enumerate() {
+            let guest_dev_id = u8::from_str_radix(&dev.guest_pci_path, 16)
+                .with_context(|| format!("invalid guest PCI path {}", dev.guest_pci_path))?;
+            let hostdev_cfg = HostDeviceConfig {
+                hostdev_id: host_device_id(&device.id, index),
+                sysfs_path: device
+                    .sysfs
+                    .device_path(&dev.bus_slot_func)
+                    .display()
+                    .to_string(),
+                dev_config: VfioPciDeviceConfig {
+                    bus_slot_func: dev.bus_slot_func.clone(),
+                    guest_dev_id: Some(guest_dev_id),
+                },
+            };
+            if let Err(e) = self.vmm_instance.insert_host_device(hostdev_cfg) {
+                // remove the devices of the IOMMU group added so far
+                for index in 0..index {
+                    self.vmm_instance
+                        .remove_host_device(&host_device_id(&device.id, index))
+                        .ok();
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_vfio_device(&mut self, device: &VfioDevice) -> Result<()> {
+        for index in 0..device.config.devices.len() {
+            self.vmm_instance
+                .remove_host_device(&host_device_id(&device.id, index))?;
+        }
+        Ok(())
+    }
+
     fn add_block_device(
         &mut self,
         path: &str,
@@ -124,6 +192,12 @@ impl DragonballInner {
     }
 
     fn add_net_device(&mut self, config: &NetworkConfig, device_id: String) -> Result<()> {
+        if !config.queue_fds.is_empty() {
+            return Err(anyhow!(
+                "dragonball doesn't support network queue fds for {}",
+                config.host_dev_name
+            ));
+        }
         let iface_cfg = VirtioNetDeviceConfigInfo {
             iface_id: device_id,
             host_dev_name: config.host_dev_name.clone(),
@@ -131,6 +205,12 @@ impl DragonballInner {
                 Some(mac) => MacAddr::from_bytes(&mac.0).ok(),
                 None => None,
             },
+            rx_rate_limiter: get_net_rate_limiter(
+                self.config.network_info.rx_rate_limiter_max_rate,
+            ),
+            tx_rate_limiter: get_net_rate_limiter(
+                self.config.network_info.tx_rate_limiter_max_rate,
+            ),
             ..Default::default()
         };
 
@@ -237,6 +317,32 @@ impl DragonballInner {
         self.do_add_fs_device(&config.fs_type, &mut fs_cfg)
     }
 
+    // Only vhost-user-fs is supported, dragonball has no vhost-user net or
+    // block backend.
+    fn add_vhost_user_device(&self, config: &VhostUserConfig) -> Result<()> {
+        if config.device_type != VHOST_USER_FS {
+            return Err(anyhow!(
+                "dragonball doesn't support {} devices",
+                config.device_type
+            ));
+        }
+
+        let mut fs_cfg = FsDeviceConfigInfo {
+            sock_path: config.socket_path.clone(),
+            tag: config.tag.clone(),
+            num_queues: DEFAULT_VIRTIO_FS_NUM_QUEUES as usize,
+            queue_size: if config.queue_size > 0 {
+                config.queue_size as u16
+            } else {
+                DEFAULT_VIRTIO_FS_QUEUE_SIZE as u16
+            },
+            cache_size: (config.cache_size as u64).saturating_mul(MB_TO_B as u64),
+            xattr: true,
+            ..Default::default()
+        };
+        self.do_add_fs_device(VIRTIO_FS, &mut fs_cfg)
+    }
+
     fn do_add_fs_device(&self, fs_type: &str, fs_cfg: &mut FsDeviceConfigInfo) -> Result<()> {
         match fs_type {
             VIRTIO_FS => {
@@ -291,6 +397,18 @@ impl DragonballInner {
     }
 }
 
+// Rate limiter of a network device limiting its bandwidth to `rate` bits/sec.
+fn get_net_rate_limiter(rate: u64) -> Option<RateLimiterConfigInfo> {
+    get_net_rate_limiter_bucket_size(rate).map(|size| RateLimiterConfigInfo {
+        bandwidth: TokenBucketConfigInfo {
+            size,
+            one_time_burst: 0,
+            refill_time: NET_RATE_LIMITER_REFILL_TIME_MS,
+        },
+        ..Default::default()
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use dragonball::api::v1::FsDeviceConfigInfo;
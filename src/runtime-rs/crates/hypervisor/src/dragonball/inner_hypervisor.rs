@@ -6,18 +6,25 @@
 
 use std::{
     collections::{HashMap, HashSet},
+    convert::TryFrom,
     iter::FromIterator,
 };
 
-use anyhow::{Context, Ok, Result};
+use anyhow::{anyhow, Context, Ok, Result};
+use dragonball::api::v1::{
+    GuestMemoryDumpConfigInfo, MemoryRegionConfigInfo, SnapshotConfigInfo, VcpuResizeInfo,
+};
 use kata_types::capabilities::Capabilities;
+use persist::sandbox_persist::Persist;
 
-use super::inner::DragonballInner;
+use super::inner::{DragonballInner, DRAGONBALL_BALLOON_ID};
 use crate::{
-    device::DeviceType, utils, HybridVsockConfig, HybridVsockDevice, VcpuThreadIds, VmmState,
+    device::DeviceType, numa, utils, BalloonStats, HybridVsockConfig, HybridVsockDevice,
+    MemoryHotplugInfo, VcpuThreadIds, VmmState,
 };
 use shim_interface::KATA_PATH;
 const DEFAULT_HYBRID_VSOCK_NAME: &str = "kata.hvsock";
+const SNAPSHOT_DIR: &str = "snapshot";
 
 fn get_vsock_path(root: &str) -> String {
     [root, DEFAULT_HYBRID_VSOCK_NAME].join("/")
@@ -57,7 +64,26 @@ impl DragonballInner {
             }
             error
         })?;
+        self.pin_vcpus().await;
+
+        Ok(())
+    }
+
+    // pin_vcpus pins the vCPU threads to the host CPUs of their NUMA node,
+    // if the guest mirrors the host NUMA nodes. Failing to do so only hurts
+    // performance.
+    pub(crate) async fn pin_vcpus(&self) {
+        if let Some(topology) = self.numa_topology.as_ref() {
+            let pinned = self.get_thread_ids().await;
+            if let Err(e) = pinned.and_then(|tids| topology.pin_vcpus(&tids)) {
+                warn!(sl!(), "failed to pin vcpus to host NUMA nodes: {:?}", e);
+            }
+        }
+    }
 
+    pub(crate) fn set_numa_topology(&mut self, cpus: &str) -> Result<()> {
+        self.numa_topology =
+            numa::get_numa_topology(&self.config, cpus).context("get numa topology")?;
         Ok(())
     }
 
@@ -79,8 +105,145 @@ impl DragonballInner {
         Ok(())
     }
 
+    // save_vm pauses the VM, if it's still running, and saves its state into the
+    // snapshot directory of the sandbox. The VM is left paused.
     pub(crate) async fn save_vm(&self) -> Result<()> {
-        todo!()
+        let snapshot_path = [self.vm_path.as_str(), SNAPSHOT_DIR].join("/");
+        info!(sl!(), "do save vm to {}", &snapshot_path);
+        self.vmm_instance.pause().context("pause vm")?;
+        self.vmm_instance
+            .create_snapshot(SnapshotConfigInfo {
+                snapshot_path: snapshot_path.into(),
+            })
+            .context("create snapshot")?;
+        Ok(())
+    }
+
+    // dump_guest_memory saves the hypervisor config and state, then pauses
+    // the VM and dumps its memory into the guest memory dump directory of the
+    // sandbox. The memory of a VM which exited on a guest crash has been
+    // dumped by dragonball already.
+    pub(crate) async fn dump_guest_memory(&mut self) -> Result<()> {
+        let dump_path = &self.config.debug_info.guest_memory_dump_path;
+        if dump_path.is_empty() {
+            return Ok(());
+        }
+        let dump_dir = utils::get_guest_memory_dump_dir(dump_path, &self.id);
+        info!(sl!(), "dump guest memory to {}", &dump_dir);
+
+        let state = self.save().await.context("save state")?;
+        utils::save_guest_memory_dump_info(&dump_dir, &state)
+            .context("save guest memory dump info")?;
+        if self.vmm_instance.is_exited() {
+            info!(sl!(), "vm exited, guest memory dumped on exit");
+            return Ok(());
+        }
+
+        let memory_mb = self.config.memory_info.default_memory + self.hotplugged_memory_mb;
+        utils::check_guest_memory_dump_space(&dump_dir, memory_mb as u64)?;
+        self.vmm_instance.pause().context("pause vm")?;
+        self.vmm_instance
+            .dump_guest_memory(GuestMemoryDumpConfigInfo {
+                dump_path: utils::get_guest_memory_dump_file(&dump_dir).into(),
+            })
+            .context("dump guest memory")?;
+        Ok(())
+    }
+
+    // resize_memory hot-adds guest memory in a single region above the
+    // existing guest memory. Dragonball has no ACPI, the guest has to probe
+    // the new memory blocks.
+    pub(crate) fn resize_memory(&mut self, new_mem_mb: u32) -> Result<(u32, MemoryHotplugInfo)> {
+        let memory_info = &self.config.memory_info;
+        let current_mem_mb = memory_info.default_memory + self.hotplugged_memory_mb;
+        if memory_info.enable_virtio_mem {
+            warn!(
+                sl!(),
+                "virtio-mem is not supported by dragonball, hot-adding memory regions"
+            );
+        }
+
+        let max_mem_mb = utils::get_host_memory_mb()
+            .context("get host memory")?
+            .min(u32::MAX as u64) as u32;
+        let size_mb = utils::get_memory_hotplug_size_mb(current_mem_mb, new_mem_mb, max_mem_mb);
+        if size_mb == 0 {
+            return Ok((current_mem_mb, MemoryHotplugInfo::default()));
+        }
+        if self.hotplugged_memory_slots >= memory_info.memory_slots {
+            return Err(anyhow!(
+                "no memory slot left to hot-add memory, {} used",
+                self.hotplugged_memory_slots
+            ));
+        }
+
+        info!(
+            sl!(),
+            "resize memory from {} MiB to {} MiB",
+            current_mem_mb,
+            current_mem_mb + size_mb
+        );
+        let region = self
+            .vmm_instance
+            .insert_memory_region(MemoryRegionConfigInfo {
+                size_mib: size_mb as u64,
+            })
+            .context("insert memory region")?;
+        self.hotplugged_memory_mb += size_mb;
+        self.hotplugged_memory_slots += 1;
+
+        Ok((
+            current_mem_mb + size_mb,
+            MemoryHotplugInfo {
+                addr: region.guest_addr,
+                size_mb,
+                probe: true,
+            },
+        ))
+    }
+
+    pub(crate) fn resize_vcpu(&mut self, new_vcpus: u32) -> Result<(u32, u32)> {
+        let cpu_info = &self.config.cpu_info;
+        let old_vcpus = self
+            .resized_vcpus
+            .unwrap_or(cpu_info.default_vcpus.max(1) as u32);
+        let new_vcpus = utils::get_resized_vcpus(new_vcpus, cpu_info);
+        if new_vcpus == old_vcpus {
+            return Ok((old_vcpus, new_vcpus));
+        }
+
+        info!(sl!(), "resize vcpu from {} to {}", old_vcpus, new_vcpus);
+        let vcpu_count =
+            u8::try_from(new_vcpus).with_context(|| format!("invalid vcpu count {}", new_vcpus))?;
+        self.vmm_instance
+            .resize_vcpu(VcpuResizeInfo {
+                vcpu_count: Some(vcpu_count),
+            })
+            .context("resize vcpu")?;
+        self.resized_vcpus = Some(new_vcpus);
+
+        Ok((old_vcpus, new_vcpus))
+    }
+
+    pub(crate) fn get_balloon_stats(&self) -> Result<BalloonStats> {
+        if !self.config.memory_info.reclaim_guest_freed_memory {
+            return Err(anyhow!(
+                "no balloon device, reclaim_guest_freed_memory is disabled"
+            ));
+        }
+
+        let stats = self
+            .vmm_instance
+            .get_balloon_stats(DRAGONBALL_BALLOON_ID)
+            .context("get balloon stats")?;
+        Ok(BalloonStats {
+            target_size_mb: stats.target_size_mib,
+            actual_size_mb: stats.actual_size_mib,
+            free_page_reporting: stats.free_page_reporting,
+            free_memory: stats.guest_stats.free_memory,
+            available_memory: stats.guest_stats.available_memory,
+            total_memory: stats.guest_stats.total_memory,
+        })
     }
 
     pub(crate) async fn get_agent_socket(&self) -> Result<String> {
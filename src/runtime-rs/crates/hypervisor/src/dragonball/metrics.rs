@@ -0,0 +1,128 @@
+// Copyright (c) 2023 Alibaba Cloud
+// Copyright (c) 2023 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use anyhow::{Context, Result};
+use dragonball::metric::{DeviceMetrics, IncMetric, METRICS};
+use prometheus::{Encoder, IntCounter, Registry, TextEncoder};
+
+const NAMESPACE_KATA_HYPERVISOR: &str = "kata_hypervisor";
+
+// Dragonball runs inside the shim process, so its global METRICS are read directly and
+// exported as a snapshot on every scrape.
+pub(crate) fn get_hypervisor_metrics() -> Result<String> {
+    let registry = Registry::new();
+
+    let vcpu = &METRICS.vcpu;
+    register(
+        &registry,
+        "vcpu_exit_io_in",
+        "Number of KVM exits for handling input IO",
+        vcpu.exit_io_in.count(),
+    )?;
+    register(
+        &registry,
+        "vcpu_exit_io_out",
+        "Number of KVM exits for handling output IO",
+        vcpu.exit_io_out.count(),
+    )?;
+    register(
+        &registry,
+        "vcpu_exit_mmio_read",
+        "Number of KVM exits for handling MMIO reads",
+        vcpu.exit_mmio_read.count(),
+    )?;
+    register(
+        &registry,
+        "vcpu_exit_mmio_write",
+        "Number of KVM exits for handling MMIO writes",
+        vcpu.exit_mmio_write.count(),
+    )?;
+    register(
+        &registry,
+        "vcpu_failures",
+        "Number of errors during vCPU runs",
+        vcpu.failures.count(),
+    )?;
+    register(
+        &registry,
+        "vcpu_filter_cpuid",
+        "Failures in configuring the CPUID",
+        vcpu.filter_cpuid.count(),
+    )?;
+    register(
+        &registry,
+        "seccomp_num_faults",
+        "Number of errors inside the seccomp filtering",
+        METRICS.seccomp.num_faults.count(),
+    )?;
+    register(
+        &registry,
+        "signals_sigbus",
+        "Number of times that SIGBUS was handled",
+        METRICS.signals.sigbus.count(),
+    )?;
+    register(
+        &registry,
+        "signals_sigsegv",
+        "Number of times that SIGSEGV was handled",
+        METRICS.signals.sigsegv.count(),
+    )?;
+
+    register_device(&registry, "block", &METRICS.block)?;
+    register_device(&registry, "net", &METRICS.net)?;
+    register_device(&registry, "fs", &METRICS.fs)?;
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut buffer)
+        .context("encode hypervisor metrics")?;
+    String::from_utf8(buffer).context("hypervisor metrics to string")
+}
+
+fn register(registry: &Registry, name: &str, help: &str, value: usize) -> Result<()> {
+    let counter = IntCounter::new(format!("{}_{}", NAMESPACE_KATA_HYPERVISOR, name), help)
+        .with_context(|| format!("new counter {}", name))?;
+    counter.inc_by(value as u64);
+    registry
+        .register(Box::new(counter))
+        .with_context(|| format!("register counter {}", name))
+}
+
+fn register_device(registry: &Registry, device: &str, metrics: &DeviceMetrics) -> Result<()> {
+    register(
+        registry,
+        &format!("{}_attach", device),
+        &format!("Number of {} devices attached", device),
+        metrics.attach.count(),
+    )?;
+    register(
+        registry,
+        &format!("{}_detach", device),
+        &format!("Number of {} devices detached", device),
+        metrics.detach.count(),
+    )?;
+    register(
+        registry,
+        &format!("{}_failures", device),
+        &format!("Number of failed {} device operations", device),
+        metrics.failures.count(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_hypervisor_metrics() {
+        METRICS.block.attach.inc();
+        let metrics = get_hypervisor_metrics().unwrap();
+        assert!(metrics.contains("kata_hypervisor_vcpu_exit_io_in"));
+        assert!(metrics.contains("kata_hypervisor_seccomp_num_faults"));
+        assert!(metrics.contains("kata_hypervisor_net_failures"));
+        assert!(!metrics.contains("kata_hypervisor_block_attach 0"));
+    }
+}
@@ -7,6 +7,7 @@
 mod inner;
 mod inner_device;
 mod inner_hypervisor;
+mod metrics;
 use super::HypervisorState;
 use inner::DragonballInner;
 use persist::sandbox_persist::Persist;
@@ -20,7 +21,7 @@ use kata_types::capabilities::Capabilities;
 use kata_types::config::hypervisor::Hypervisor as HypervisorConfig;
 use tokio::sync::RwLock;
 
-use crate::{DeviceType, Hypervisor, VcpuThreadIds};
+use crate::{BalloonStats, DeviceType, Hypervisor, MemoryHotplugInfo, VcpuThreadIds};
 
 pub struct Dragonball {
     inner: Arc<RwLock<DragonballInner>>,
@@ -87,6 +88,33 @@ impl Hypervisor for Dragonball {
         inner.remove_device(device).await
     }
 
+    async fn resize_memory(&self, new_mem_mb: u32) -> Result<(u32, MemoryHotplugInfo)> {
+        let mut inner = self.inner.write().await;
+        inner.resize_memory(new_mem_mb)
+    }
+
+    async fn resize_vcpu(&self, new_vcpus: u32) -> Result<(u32, u32)> {
+        let mut inner = self.inner.write().await;
+        let vcpus = inner.resize_vcpu(new_vcpus)?;
+        // hot-added vCPUs are pinned like the boot ones
+        inner.pin_vcpus().await;
+        Ok(vcpus)
+    }
+
+    async fn set_numa_topology(&self, cpus: &str) -> Result<()> {
+        let mut inner = self.inner.write().await;
+        inner.set_numa_topology(cpus)
+    }
+
+    async fn get_balloon_stats(&self) -> Result<BalloonStats> {
+        let inner = self.inner.read().await;
+        inner.get_balloon_stats()
+    }
+
+    async fn get_hypervisor_metrics(&self) -> Result<String> {
+        metrics::get_hypervisor_metrics()
+    }
+
     async fn get_agent_socket(&self) -> Result<String> {
         let inner = self.inner.read().await;
         inner.get_agent_socket().await
@@ -145,6 +173,11 @@ impl Hypervisor for Dragonball {
         let inner = self.inner.read().await;
         inner.capabilities().await
     }
+
+    async fn dump_guest_memory(&self) -> Result<()> {
+        let mut inner = self.inner.write().await;
+        inner.dump_guest_memory().await
+    }
 }
 
 #[async_trait]
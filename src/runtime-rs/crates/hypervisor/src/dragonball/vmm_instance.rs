@@ -15,15 +15,18 @@ use anyhow::{anyhow, Context, Result};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use dragonball::{
     api::v1::{
-        BlockDeviceConfigInfo, BootSourceConfig, FsDeviceConfigInfo, FsMountConfigInfo,
-        InstanceInfo, InstanceState, VirtioNetDeviceConfigInfo, VmmAction, VmmActionError, VmmData,
-        VmmRequest, VmmResponse, VmmService, VsockDeviceConfigInfo,
+        BalloonDeviceConfigInfo, BalloonStatsInfo, BlockDeviceConfigInfo, BootSourceConfig,
+        FsDeviceConfigInfo, FsMountConfigInfo, GuestMemoryDumpConfigInfo, HostDeviceConfig,
+        InstanceInfo, InstanceState, MemoryRegionConfigInfo, MemoryRegionInfo, SnapshotConfigInfo,
+        VcpuResizeInfo, VirtioNetDeviceConfigInfo, VmmAction, VmmActionError, VmmData, VmmRequest,
+        VmmResponse, VmmService, VsockDeviceConfigInfo, HOTUNPLUG_TIMEOUT,
     },
+    seccomp::SeccompFilters,
+    signal_handler::register_sigsys_handler,
     vm::VmConfigInfo,
     Vmm,
 };
 use nix::sched::{setns, CloneFlags};
-use seccompiler::BpfProgram;
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::ShareFsOperation;
@@ -42,7 +45,7 @@ pub struct VmmInstance {
     to_vmm: Option<Sender<VmmRequest>>,
     from_vmm: Option<Receiver<VmmResponse>>,
     to_vmm_fd: EventFd,
-    seccomp: BpfProgram,
+    seccomp_filters: SeccompFilters,
     vmm_thread: Option<thread::JoinHandle<Result<i32>>>,
 }
 
@@ -61,7 +64,7 @@ impl VmmInstance {
             to_vmm: None,
             from_vmm: None,
             to_vmm_fd,
-            seccomp: vec![],
+            seccomp_filters: SeccompFilters::default(),
             vmm_thread: None,
         }
     }
@@ -94,9 +97,18 @@ impl VmmInstance {
         result
     }
 
+    pub fn set_seccomp_filters(&mut self, filters: SeccompFilters) {
+        self.seccomp_filters = filters;
+    }
+
     pub fn run_vmm_server(&mut self, id: &str, netns: Option<String>) -> Result<()> {
         let kvm = OpenOptions::new().read(true).write(true).open(KVM_DEVICE)?;
 
+        // account and log the seccomp violations of the vmm and vcpu threads
+        if !self.seccomp_filters.vmm.is_empty() || !self.seccomp_filters.vcpu.is_empty() {
+            register_sigsys_handler().context("register sigsys handler")?;
+        }
+
         let (to_vmm, from_runtime) = unbounded();
         let (to_runtime, from_vmm) = unbounded();
 
@@ -111,8 +123,8 @@ impl VmmInstance {
         let vmm = Vmm::new(
             self.vmm_shared_info.clone(),
             api_event_fd2,
-            self.seccomp.clone(),
-            self.seccomp.clone(),
+            self.seccomp_filters.vmm.clone(),
+            self.seccomp_filters.vcpu.clone(),
             Some(kvm.into_raw_fd()),
         )
         .expect("Failed to start vmm");
@@ -183,6 +195,14 @@ impl VmmInstance {
         Err(anyhow!("vmm is not running"))
     }
 
+    pub fn is_exited(&self) -> bool {
+        let share_info = self
+            .vmm_shared_info
+            .read()
+            .expect("Failed to read share_info due to poisoned lock");
+        matches!(share_info.state, InstanceState::Exited(_))
+    }
+
     pub fn get_machine_info(&self) -> Result<Box<VmConfigInfo>> {
         if let Ok(VmmData::MachineConfiguration(vm_config)) =
             self.handle_request(Request::Sync(VmmAction::GetVmConfiguration))
@@ -223,6 +243,19 @@ impl VmmInstance {
         Ok(())
     }
 
+    pub fn remove_network_device(&self, id: &str) -> Result<()> {
+        info!(sl!(), "remove network device {}", id);
+        self.handle_request(Request::Sync(VmmAction::RemoveNetworkDevice(
+            id.to_string(),
+        )))
+        .and_then(|data| {
+            data.wait_hotunplug(HOTUNPLUG_TIMEOUT)
+                .map_err(|e| anyhow!("vmm action error: {:?}", e))
+        })
+        .with_context(|| format!("Failed to remove network device {:?}", id))?;
+        Ok(())
+    }
+
     pub fn insert_vsock(&self, vsock_cfg: VsockDeviceConfigInfo) -> Result<()> {
         self.handle_request(Request::Sync(VmmAction::InsertVsockDevice(
             vsock_cfg.clone(),
@@ -237,6 +270,17 @@ impl VmmInstance {
         Ok(())
     }
 
+    pub fn remove_fs(&self, tag: &str) -> Result<()> {
+        info!(sl!(), "remove fs device {}", tag);
+        self.handle_request(Request::Sync(VmmAction::RemoveFsDevice(tag.to_string())))
+            .and_then(|data| {
+                data.wait_hotunplug(HOTUNPLUG_TIMEOUT)
+                    .map_err(|e| anyhow!("vmm action error: {:?}", e))
+            })
+            .with_context(|| format!("Failed to remove fs device {:?}", tag))?;
+        Ok(())
+    }
+
     pub fn patch_fs(&self, cfg: &FsMountConfigInfo, op: ShareFsOperation) -> Result<()> {
         self.handle_request(Request::Sync(VmmAction::ManipulateFsBackendFs(cfg.clone())))
             .with_context(|| {
@@ -248,12 +292,91 @@ impl VmmInstance {
         Ok(())
     }
 
+    pub fn insert_memory_region(
+        &self,
+        region_cfg: MemoryRegionConfigInfo,
+    ) -> Result<MemoryRegionInfo> {
+        match self
+            .handle_request(Request::Sync(VmmAction::InsertMemoryRegion(
+                region_cfg.clone(),
+            )))
+            .with_context(|| format!("Failed to insert memory region {:?}", region_cfg))?
+        {
+            VmmData::MemoryRegion(region) => Ok(region),
+            data => Err(anyhow!("unexpected response {:?}", data)),
+        }
+    }
+
+    pub fn insert_balloon_device(&self, balloon_cfg: BalloonDeviceConfigInfo) -> Result<()> {
+        self.handle_request(Request::Sync(VmmAction::InsertBalloonDevice(
+            balloon_cfg.clone(),
+        )))
+        .with_context(|| format!("Failed to insert balloon device {:?}", balloon_cfg))?;
+        Ok(())
+    }
+
+    pub fn get_balloon_stats(&self, balloon_id: &str) -> Result<BalloonStatsInfo> {
+        match self
+            .handle_request(Request::Sync(VmmAction::GetBalloonStats(
+                balloon_id.to_string(),
+            )))
+            .with_context(|| format!("Failed to get balloon {} stats", balloon_id))?
+        {
+            VmmData::BalloonStats(stats) => Ok(stats),
+            data => Err(anyhow!("unexpected response {:?}", data)),
+        }
+    }
+
+    pub fn insert_host_device(&self, device_cfg: HostDeviceConfig) -> Result<()> {
+        self.handle_request(Request::Sync(VmmAction::InsertHostDevice(
+            device_cfg.clone(),
+        )))
+        .with_context(|| format!("Failed to insert host device {:?}", device_cfg))?;
+        Ok(())
+    }
+
+    pub fn remove_host_device(&self, id: &str) -> Result<()> {
+        self.handle_request(Request::Sync(VmmAction::RemoveHostDevice(id.to_string())))
+            .with_context(|| format!("Failed to remove host device {:?}", id))?;
+        Ok(())
+    }
+
+    pub fn resize_vcpu(&self, resize_info: VcpuResizeInfo) -> Result<()> {
+        self.handle_request(Request::Sync(VmmAction::ResizeVcpu(resize_info.clone())))
+            .with_context(|| format!("Failed to resize vcpu {:?}", resize_info))?;
+        Ok(())
+    }
+
     pub fn pause(&self) -> Result<()> {
-        todo!()
+        self.handle_request(Request::Sync(VmmAction::PauseVm))
+            .context("Failed to pause MicroVm")?;
+        Ok(())
     }
 
     pub fn resume(&self) -> Result<()> {
-        todo!()
+        self.handle_request(Request::Sync(VmmAction::ResumeVm))
+            .context("Failed to resume MicroVm")?;
+        Ok(())
+    }
+
+    pub fn create_snapshot(&self, snapshot_cfg: SnapshotConfigInfo) -> Result<()> {
+        self.handle_request(Request::Sync(VmmAction::CreateSnapshot(
+            snapshot_cfg.clone(),
+        )))
+        .with_context(|| format!("Failed to create snapshot {:?}", snapshot_cfg))?;
+        Ok(())
+    }
+
+    pub fn load_snapshot(&self, snapshot_cfg: SnapshotConfigInfo) -> Result<()> {
+        self.handle_request(Request::Sync(VmmAction::LoadSnapshot(snapshot_cfg.clone())))
+            .with_context(|| format!("Failed to load snapshot {:?}", snapshot_cfg))?;
+        Ok(())
+    }
+
+    pub fn dump_guest_memory(&self, dump_cfg: GuestMemoryDumpConfigInfo) -> Result<()> {
+        self.handle_request(Request::Sync(VmmAction::DumpGuestMemory(dump_cfg.clone())))
+            .with_context(|| format!("Failed to dump guest memory {:?}", dump_cfg))?;
+        Ok(())
     }
 
     pub fn pid(&self) -> u32 {
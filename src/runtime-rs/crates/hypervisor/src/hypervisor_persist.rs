@@ -33,4 +33,18 @@ pub struct HypervisorState {
     /// cached block device
     pub cached_block_devices: HashSet<String>,
     pub virtiofs_daemon_pid: i32,
+    /// guest vsock CID, for VMMs without hybrid vsock
+    pub vsock_cid: Option<u32>,
+    /// guest memory hot-added since boot, in MiB
+    #[serde(default)]
+    pub hotplugged_memory_mb: u32,
+    /// memory slots used by the hot-added guest memory
+    #[serde(default)]
+    pub hotplugged_memory_slots: u32,
+    /// vCPU count once resized
+    #[serde(default)]
+    pub resized_vcpus: Option<u32>,
+    /// ids of the hot-plugged vCPU devices, for VMMs plugging vCPUs as devices
+    #[serde(default)]
+    pub hotplugged_vcpus: Vec<String>,
 }
@@ -15,10 +15,12 @@ pub use device::driver::*;
 use device::DeviceType;
 pub mod dragonball;
 mod kernel_param;
+mod numa;
 pub mod qemu;
 pub use kernel_param::Param;
 mod utils;
 use std::collections::HashMap;
+pub use utils::MIB_TO_B;
 
 #[cfg(feature = "cloud-hypervisor")]
 pub mod ch;
@@ -69,6 +71,34 @@ pub struct VcpuThreadIds {
     pub vcpus: HashMap<u32, u32>,
 }
 
+/// Guest memory hot-added by `Hypervisor::resize_memory()`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemoryHotplugInfo {
+    /// Guest physical address of the memory.
+    pub addr: u64,
+    /// Size of the memory in MiB, 0 if no memory was hot-added.
+    pub size_mb: u32,
+    /// Whether the guest has to probe the memory, it's not notified of it otherwise.
+    pub probe: bool,
+}
+
+/// Statistics of the guest memory balloon.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BalloonStats {
+    /// Target size of the balloon in MiB.
+    pub target_size_mb: u64,
+    /// Memory the guest has given to the balloon so far, in MiB.
+    pub actual_size_mb: u64,
+    /// Whether the guest reports its free pages to the host.
+    pub free_page_reporting: bool,
+    /// Memory not used at all by the guest, in bytes, if reported by the guest.
+    pub free_memory: Option<u64>,
+    /// Memory available to the guest applications, in bytes, if reported by the guest.
+    pub available_memory: Option<u64>,
+    /// Total memory of the guest, in bytes, if reported by the guest.
+    pub total_memory: Option<u64>,
+}
+
 #[async_trait]
 pub trait Hypervisor: Send + Sync {
     // vm manager
@@ -83,6 +113,22 @@ pub trait Hypervisor: Send + Sync {
     async fn add_device(&self, device: DeviceType) -> Result<()>;
     async fn remove_device(&self, device: DeviceType) -> Result<()>;
 
+    // resource manager
+    // Grow the guest memory to at least `new_mem_mb` MiB, returning the guest memory size in
+    // MiB once resized and the memory hot-added. The guest memory doesn't shrink.
+    async fn resize_memory(&self, new_mem_mb: u32) -> Result<(u32, MemoryHotplugInfo)>;
+    // Hot-plug or unplug vCPUs so that the guest has `new_vcpus` vCPUs, within the boot and
+    // maximum vCPUs, returning the vCPU counts before and after the resize.
+    async fn resize_vcpu(&self, new_vcpus: u32) -> Result<(u32, u32)>;
+    // Mirror the host NUMA nodes of the `cpus` CPU list, the CPU set of the sandbox, into the
+    // guest if enable_numa is set. Must be called before the VM is started.
+    async fn set_numa_topology(&self, cpus: &str) -> Result<()>;
+    // Get the statistics of the guest memory balloon, which only exists if
+    // reclaim_guest_freed_memory is enabled.
+    async fn get_balloon_stats(&self) -> Result<BalloonStats>;
+    // Get the hypervisor metrics in the Prometheus text format.
+    async fn get_hypervisor_metrics(&self) -> Result<String>;
+
     // utils
     async fn get_agent_socket(&self) -> Result<String>;
     async fn disconnect(&self);
@@ -96,4 +142,8 @@ pub trait Hypervisor: Send + Sync {
     async fn get_jailer_root(&self) -> Result<String>;
     async fn save_state(&self) -> Result<HypervisorState>;
     async fn capabilities(&self) -> Result<Capabilities>;
+    // Dump the guest memory as an ELF core file, along with the hypervisor
+    // config and state, into the guest_memory_dump_path of the hypervisor
+    // config. Nothing is done if guest_memory_dump_path isn't set.
+    async fn dump_guest_memory(&self) -> Result<()>;
 }
@@ -0,0 +1,202 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Guest NUMA topology mirroring the host NUMA nodes of the sandbox CPU set.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use kata_sys_util::numa;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+
+use crate::{HypervisorConfig, VcpuThreadIds};
+
+// Guest memory of each NUMA node is aligned to 2MiB, so that it can be backed
+// by huge pages.
+const NUMA_MEMORY_ALIGN_MB: u32 = 2;
+
+/// A guest NUMA node backed by a host NUMA node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NumaNode {
+    pub(crate) guest_node_id: u32,
+    pub(crate) host_node_id: u32,
+    /// Host CPUs the vCPU threads of the node are pinned to.
+    pub(crate) host_cpus: Vec<u32>,
+    /// Contiguous range of guest vCPUs of the node.
+    pub(crate) vcpus: Vec<u32>,
+    pub(crate) memory_mb: u32,
+}
+
+/// Split of the guest vCPUs and memory across guest NUMA nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct NumaTopology {
+    pub(crate) nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Build the guest topology of `max_vcpus` vCPUs and `memory_mb` MiB of memory mirroring the
+    /// host NUMA nodes of the `cpus` CPU list.
+    pub(crate) fn new(cpus: &str, max_vcpus: u32, memory_mb: u32) -> Result<Self> {
+        let node_map =
+            numa::get_node_map(cpus).with_context(|| format!("get NUMA nodes of CPUs {}", cpus))?;
+        Self::from_node_map(node_map, max_vcpus, memory_mb)
+    }
+
+    // The vCPUs are split in proportion to the host CPUs of each node, with
+    // at least one vCPU per node, and the memory in proportion to the vCPUs.
+    fn from_node_map(
+        node_map: HashMap<u32, Vec<u32>>,
+        max_vcpus: u32,
+        memory_mb: u32,
+    ) -> Result<Self> {
+        let mut host_nodes: Vec<(u32, Vec<u32>)> = node_map.into_iter().collect();
+        host_nodes.sort_by_key(|(id, _)| *id);
+        let num_nodes = host_nodes.len() as u32;
+        if num_nodes == 0 {
+            return Err(anyhow!("no host NUMA node"));
+        }
+        if max_vcpus < num_nodes {
+            return Err(anyhow!(
+                "{} vCPUs can't be split across {} NUMA nodes",
+                max_vcpus,
+                num_nodes
+            ));
+        }
+        if memory_mb < num_nodes * NUMA_MEMORY_ALIGN_MB {
+            return Err(anyhow!(
+                "{}MiB of memory can't be split across {} NUMA nodes",
+                memory_mb,
+                num_nodes
+            ));
+        }
+
+        let total_cpus: u64 = host_nodes.iter().map(|(_, cpus)| cpus.len() as u64).sum();
+        let mut nodes = Vec::with_capacity(host_nodes.len());
+        let mut host_cpus_sum = 0;
+        let mut vcpu_start = 0;
+        let mut memory_start = 0;
+        for (index, (host_node_id, host_cpus)) in host_nodes.into_iter().enumerate() {
+            let index = index as u32;
+            let nodes_left = num_nodes - index - 1;
+
+            host_cpus_sum += host_cpus.len() as u64;
+            let vcpu_end = ((max_vcpus as u64 * host_cpus_sum / total_cpus) as u32)
+                .max(vcpu_start + 1)
+                .min(max_vcpus - nodes_left);
+            let memory_end = if nodes_left == 0 {
+                memory_mb
+            } else {
+                let end = (memory_mb as u64 * vcpu_end as u64 / max_vcpus as u64) as u32;
+                (end - end % NUMA_MEMORY_ALIGN_MB)
+                    .max(memory_start + NUMA_MEMORY_ALIGN_MB)
+                    .min(memory_mb - nodes_left * NUMA_MEMORY_ALIGN_MB)
+            };
+
+            nodes.push(NumaNode {
+                guest_node_id: index,
+                host_node_id,
+                host_cpus,
+                vcpus: (vcpu_start..vcpu_end).collect(),
+                memory_mb: memory_end - memory_start,
+            });
+            vcpu_start = vcpu_end;
+            memory_start = memory_end;
+        }
+
+        Ok(NumaTopology { nodes })
+    }
+
+    /// Get the NUMA node of vCPU `vcpu`.
+    pub(crate) fn node_of_vcpu(&self, vcpu: u32) -> Option<&NumaNode> {
+        self.nodes.iter().find(|node| node.vcpus.contains(&vcpu))
+    }
+
+    /// Pin the vCPU threads to the host CPUs of their NUMA node.
+    pub(crate) fn pin_vcpus(&self, tids: &VcpuThreadIds) -> Result<()> {
+        for (vcpu, tid) in tids.vcpus.iter() {
+            let node = match self.node_of_vcpu(*vcpu) {
+                Some(node) => node,
+                None => continue,
+            };
+            let mut cpu_set = CpuSet::new();
+            for cpu in node.host_cpus.iter() {
+                cpu_set
+                    .set(*cpu as usize)
+                    .with_context(|| format!("invalid host CPU {}", cpu))?;
+            }
+            sched_setaffinity(Pid::from_raw(*tid as i32), &cpu_set).with_context(|| {
+                format!(
+                    "pin vCPU {} thread {} to host NUMA node {}",
+                    vcpu, tid, node.host_node_id
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Build the guest NUMA topology of a VM restricted to the `cpus` CPU list, nothing to build if
+/// `enable_numa` isn't set or the VM isn't restricted to a CPU set.
+pub(crate) fn get_numa_topology(
+    config: &HypervisorConfig,
+    cpus: &str,
+) -> Result<Option<NumaTopology>> {
+    if !config.cpu_info.enable_numa || cpus.is_empty() {
+        return Ok(None);
+    }
+    let topology = NumaTopology::new(
+        cpus,
+        config.cpu_info.default_maxvcpus,
+        config.memory_info.default_memory,
+    )?;
+    info!(sl!(), "guest NUMA topology {:?}", topology);
+    Ok(Some(topology))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numa_topology() {
+        let node_map = HashMap::from([(1, vec![4, 5, 6, 7]), (0, vec![0, 1])]);
+        let topology = NumaTopology::from_node_map(node_map, 6, 2048).unwrap();
+        assert_eq!(
+            topology.nodes,
+            vec![
+                NumaNode {
+                    guest_node_id: 0,
+                    host_node_id: 0,
+                    host_cpus: vec![0, 1],
+                    vcpus: vec![0, 1],
+                    memory_mb: 682,
+                },
+                NumaNode {
+                    guest_node_id: 1,
+                    host_node_id: 1,
+                    host_cpus: vec![4, 5, 6, 7],
+                    vcpus: vec![2, 3, 4, 5],
+                    memory_mb: 1366,
+                },
+            ]
+        );
+        assert_eq!(topology.node_of_vcpu(3).unwrap().host_node_id, 1);
+        assert!(topology.node_of_vcpu(6).is_none());
+
+        // Each node gets a vCPU even with few host CPUs.
+        let node_map = HashMap::from([(0, vec![0]), (1, (1..16).collect())]);
+        let topology = NumaTopology::from_node_map(node_map, 4, 1024).unwrap();
+        assert_eq!(topology.nodes[0].vcpus, vec![0]);
+        assert_eq!(topology.nodes[1].vcpus, vec![1, 2, 3]);
+        assert_eq!(topology.nodes[0].memory_mb, 256);
+        assert_eq!(topology.nodes[1].memory_mb, 768);
+
+        let node_map = HashMap::from([(0, vec![0]), (1, vec![1])]);
+        assert!(NumaTopology::from_node_map(node_map.clone(), 1, 1024).is_err());
+        assert!(NumaTopology::from_node_map(node_map, 2, 2).is_err());
+        assert!(NumaTopology::from_node_map(HashMap::new(), 2, 1024).is_err());
+    }
+}
@@ -0,0 +1,1232 @@
+// Copyright (c) 2023 Red Hat
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::os::unix::io::RawFd;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::kernel_param::KernelParams;
+use crate::numa::{NumaNode, NumaTopology};
+use crate::utils::{get_host_memory_mb, MIB_TO_B};
+use crate::{
+    HypervisorConfig, NetworkConfig, DEV_HUGEPAGES, VM_ROOTFS_DRIVER_BLK, VM_ROOTFS_DRIVER_PMEM,
+};
+
+// Memory backend used for shared memory when no file backend is configured.
+const DEV_SHM: &str = "/dev/shm";
+
+const MACHINE_TYPE_Q35: &str = "q35";
+const MACHINE_TYPE_PC: &str = "pc";
+
+const VIRTIO_FS: &str = "virtio-fs";
+
+const MEMORY_BACKEND_ID: &str = "dimm1";
+const ROOTFS_ID: &str = "image";
+const CONSOLE_ID: &str = "charconsole0";
+const RNG_ID: &str = "rng0";
+
+// Sandbox profile of QEMU's built-in seccomp filter, see `-sandbox` in
+// qemu(1).
+const QEMU_SECCOMP_SANDBOX: &str =
+    "on,obsolete=deny,elevateprivileges=deny,spawn=deny,resourcecontrol=deny";
+
+/// Backend of the guest memory configured in `config`, None if the default
+/// anonymous memory fits. Also used for hot-added memory, which must be
+/// backed the same way as the boot memory.
+pub(crate) fn memory_backend_kind(config: &HypervisorConfig) -> Option<MemoryBackendKind> {
+    let memory_info = &config.memory_info;
+    let shared = config.shared_fs.shared_fs.as_deref() == Some(VIRTIO_FS);
+
+    if memory_info.enable_hugepages {
+        Some(MemoryBackendKind::File {
+            path: DEV_HUGEPAGES.to_string(),
+            share: shared,
+        })
+    } else if !memory_info.file_mem_backend.is_empty() {
+        Some(MemoryBackendKind::File {
+            path: memory_info.file_mem_backend.clone(),
+            share: true,
+        })
+    } else if shared {
+        Some(MemoryBackendKind::File {
+            path: DEV_SHM.to_string(),
+            share: true,
+        })
+    } else if memory_info.enable_mem_prealloc {
+        Some(MemoryBackendKind::Ram)
+    } else {
+        None
+    }
+}
+
+/// A component of the QEMU command line.
+pub trait ToQemuParams: Send + Sync {
+    /// Return the QEMU arguments needed to set up this component.
+    fn qemu_params(&self) -> Result<Vec<String>>;
+}
+
+// QMP identifiers must start with a letter, so prefix the device manager
+// generated ids.
+pub(crate) fn qemu_node_name(id: &str) -> String {
+    format!("drive-{}", id)
+}
+
+pub(crate) fn qemu_netdev_id(id: &str) -> String {
+    format!("netdev-{}", id)
+}
+
+pub(crate) fn qemu_device_id(id: &str) -> String {
+    format!("virtio-{}", id)
+}
+
+// QEMU option values are comma separated, so commas in user supplied values
+// (e.g. paths) must be doubled.
+fn escape(value: &str) -> String {
+    value.replace(',', ",,")
+}
+
+#[derive(Debug)]
+struct Machine {
+    r#type: String,
+    accelerators: String,
+    nvdimm: bool,
+}
+
+impl Machine {
+    fn new(config: &HypervisorConfig) -> Self {
+        Machine {
+            r#type: config.machine_info.machine_type.clone(),
+            accelerators: config.machine_info.machine_accelerators.clone(),
+            nvdimm: false,
+        }
+    }
+}
+
+impl ToQemuParams for Machine {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut options = vec![
+            self.r#type.clone(),
+            "accel=kvm".to_string(),
+            "kernel_irqchip=on".to_string(),
+        ];
+        if self.nvdimm {
+            options.push("nvdimm=on".to_string());
+        }
+        if !self.accelerators.is_empty() {
+            options.push(self.accelerators.clone());
+        }
+        Ok(vec!["-machine".to_string(), options.join(",")])
+    }
+}
+
+#[derive(Debug)]
+struct Cpu {
+    model: String,
+    features: String,
+}
+
+impl ToQemuParams for Cpu {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut cpu = self.model.clone();
+        if !self.features.is_empty() {
+            cpu.push(',');
+            cpu.push_str(&self.features);
+        }
+        Ok(vec!["-cpu".to_string(), cpu])
+    }
+}
+
+#[derive(Debug)]
+struct Smp {
+    num_vcpus: u32,
+    max_vcpus: u32,
+}
+
+impl ToQemuParams for Smp {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        // Each possible vCPU gets a socket of its own so that single vCPUs
+        // can be hotplugged.
+        Ok(vec![
+            "-smp".to_string(),
+            format!(
+                "{},cores=1,threads=1,sockets={},maxcpus={}",
+                self.num_vcpus, self.max_vcpus, self.max_vcpus
+            ),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct Memory {
+    size_mb: u32,
+    num_slots: u32,
+    max_size_mb: u64,
+}
+
+impl ToQemuParams for Memory {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut memory = format!("{}M", self.size_mb);
+        if self.num_slots > 0 {
+            memory.push_str(&format!(
+                ",slots={},maxmem={}M",
+                self.num_slots, self.max_size_mb
+            ));
+        }
+        Ok(vec!["-m".to_string(), memory])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MemoryBackendKind {
+    File { path: String, share: bool },
+    Ram,
+}
+
+// Guest RAM backend, only needed when the default anonymous memory doesn't
+// fit: shared memory for vhost-user, hugepages or preallocation, or when the
+// guest has NUMA nodes, each one getting a backend bound to its host node.
+#[derive(Debug)]
+struct MemoryBackend {
+    kind: MemoryBackendKind,
+    size_mb: u32,
+    prealloc: bool,
+    numa_nodes: Vec<NumaNode>,
+}
+
+impl MemoryBackend {
+    fn object(&self, id: &str, size_mb: u32) -> String {
+        let mut object = match &self.kind {
+            MemoryBackendKind::File { path, share } => {
+                let mut object = format!(
+                    "memory-backend-file,id={},size={}M,mem-path={}",
+                    id,
+                    size_mb,
+                    escape(path)
+                );
+                if *share {
+                    object.push_str(",share=on");
+                }
+                object
+            }
+            MemoryBackendKind::Ram => {
+                format!("memory-backend-ram,id={},size={}M", id, size_mb)
+            }
+        };
+        if self.prealloc {
+            object.push_str(",prealloc=on");
+        }
+        object
+    }
+}
+
+impl ToQemuParams for MemoryBackend {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        if self.numa_nodes.is_empty() {
+            return Ok(vec![
+                "-object".to_string(),
+                self.object(MEMORY_BACKEND_ID, self.size_mb),
+                "-numa".to_string(),
+                format!("node,memdev={}", MEMORY_BACKEND_ID),
+            ]);
+        }
+
+        let mut params = vec![];
+        for node in &self.numa_nodes {
+            let (first_vcpu, last_vcpu) = match (node.vcpus.first(), node.vcpus.last()) {
+                (Some(first), Some(last)) => (first, last),
+                _ => return Err(anyhow!("no vCPU in NUMA node {}", node.guest_node_id)),
+            };
+            let id = format!("{}-node{}", MEMORY_BACKEND_ID, node.guest_node_id);
+            let mut object = self.object(&id, node.memory_mb);
+            object.push_str(&format!(",host-nodes={},policy=bind", node.host_node_id));
+            params.push("-object".to_string());
+            params.push(object);
+            params.push("-numa".to_string());
+            params.push(format!(
+                "node,nodeid={},cpus={}-{},memdev={}",
+                node.guest_node_id, first_vcpu, last_vcpu, id
+            ));
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Debug)]
+struct Kernel {
+    path: String,
+    initrd: String,
+    params: String,
+}
+
+impl ToQemuParams for Kernel {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut params = vec!["-kernel".to_string(), self.path.clone()];
+        if !self.initrd.is_empty() {
+            params.push("-initrd".to_string());
+            params.push(self.initrd.clone());
+        }
+        params.push("-append".to_string());
+        params.push(self.params.clone());
+        Ok(params)
+    }
+}
+
+#[derive(Debug)]
+struct Firmware {
+    bios: String,
+    pflashes: Vec<String>,
+}
+
+impl ToQemuParams for Firmware {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut params = vec![];
+        if !self.bios.is_empty() {
+            params.push("-bios".to_string());
+            params.push(self.bios.clone());
+        }
+        for pflash in &self.pflashes {
+            params.push("-drive".to_string());
+            params.push(format!(
+                "if=pflash,format=raw,readonly=on,file={}",
+                escape(pflash)
+            ));
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Debug)]
+struct Knobs {
+    seccomp: bool,
+}
+
+impl ToQemuParams for Knobs {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut params: Vec<String> = [
+            "-no-user-config",
+            "-nodefaults",
+            "-nographic",
+            "-vga",
+            "none",
+            "-rtc",
+            "base=utc,driftfix=slew,clock=host",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        if self.seccomp {
+            params.push("-sandbox".to_string());
+            params.push(QEMU_SECCOMP_SANDBOX.to_string());
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Debug)]
+struct Qmp {
+    path: String,
+}
+
+impl ToQemuParams for Qmp {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-qmp".to_string(),
+            format!("unix:{},server=on,wait=off", escape(&self.path)),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct Console {
+    path: String,
+}
+
+impl ToQemuParams for Console {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-device".to_string(),
+            "virtio-serial-pci,id=serial0".to_string(),
+            "-device".to_string(),
+            format!("virtconsole,chardev={},id=console0", CONSOLE_ID),
+            "-chardev".to_string(),
+            format!(
+                "socket,id={},path={},server=on,wait=off",
+                CONSOLE_ID,
+                escape(&self.path)
+            ),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct RngDevice {
+    filename: String,
+}
+
+impl ToQemuParams for RngDevice {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-object".to_string(),
+            format!(
+                "rng-random,id={},filename={}",
+                RNG_ID,
+                escape(&self.filename)
+            ),
+            "-device".to_string(),
+            format!("virtio-rng-pci,rng={}", RNG_ID),
+        ])
+    }
+}
+
+// Device reporting guest panics. With -no-shutdown, QEMU pauses the guest
+// once it panicked instead of letting it reboot, for its memory to be dumped.
+#[derive(Debug)]
+struct PvPanic;
+
+impl ToQemuParams for PvPanic {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-device".to_string(),
+            "pvpanic".to_string(),
+            "-no-shutdown".to_string(),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct PcieRootPort {
+    index: u32,
+}
+
+impl ToQemuParams for PcieRootPort {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-device".to_string(),
+            format!(
+                "pcie-root-port,id=rp{0},bus=pcie.0,chassis=0,slot={0},multifunction=off",
+                self.index
+            ),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct PciBridge {
+    index: u32,
+    bus: String,
+}
+
+impl ToQemuParams for PciBridge {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-device".to_string(),
+            format!(
+                "pci-bridge,bus={},id=pci-bridge-{},chassis_nr={},shpc=off",
+                self.bus,
+                self.index,
+                self.index + 1
+            ),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct Nvdimm {
+    id: String,
+    path: String,
+    size: u64,
+}
+
+impl ToQemuParams for Nvdimm {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-object".to_string(),
+            format!(
+                "memory-backend-file,id=mem-{},mem-path={},size={},readonly=on",
+                self.id,
+                escape(&self.path),
+                self.size
+            ),
+            "-device".to_string(),
+            format!("nvdimm,id=nv-{0},memdev=mem-{0},unarmed=on", self.id),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct BlockDevice {
+    id: String,
+    path: String,
+    read_only: bool,
+    direct: bool,
+    no_flush: bool,
+}
+
+impl ToQemuParams for BlockDevice {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let on_off = |b: bool| if b { "on" } else { "off" };
+        let file_driver = match std::fs::metadata(&self.path) {
+            Ok(m) if std::os::unix::fs::FileTypeExt::is_block_device(&m.file_type()) => {
+                "host_device"
+            }
+            _ => "file",
+        };
+
+        Ok(vec![
+            "-blockdev".to_string(),
+            format!(
+                "driver=raw,node-name={},read-only={},cache.direct={},cache.no-flush={},file.driver={},file.filename={}",
+                qemu_node_name(&self.id),
+                on_off(self.read_only),
+                on_off(self.direct),
+                on_off(self.no_flush),
+                file_driver,
+                escape(&self.path)
+            ),
+            "-device".to_string(),
+            format!(
+                "virtio-blk-pci,id={},drive={}",
+                qemu_device_id(&self.id),
+                qemu_node_name(&self.id)
+            ),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct NetDevice {
+    id: String,
+    ifname: String,
+    // queues opened by the runtime, inherited by QEMU
+    fds: Vec<RawFd>,
+    mac: Option<String>,
+    queues: u32,
+    vhost: bool,
+}
+
+impl ToQemuParams for NetDevice {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut netdev = if self.fds.is_empty() {
+            format!(
+                "tap,id={},ifname={},script=no,downscript=no",
+                qemu_netdev_id(&self.id),
+                self.ifname
+            )
+        } else {
+            // QEMU gets the number of queues from the fds
+            let fds: Vec<String> = self.fds.iter().map(|fd| fd.to_string()).collect();
+            format!("tap,id={},fds={}", qemu_netdev_id(&self.id), fds.join(":"))
+        };
+        if self.vhost {
+            netdev.push_str(",vhost=on");
+        }
+        let mut device = format!(
+            "virtio-net-pci,id={},netdev={}",
+            qemu_device_id(&self.id),
+            qemu_netdev_id(&self.id)
+        );
+        if let Some(mac) = &self.mac {
+            device.push_str(&format!(",mac={}", mac));
+        }
+        if self.queues > 1 {
+            if self.fds.is_empty() {
+                netdev.push_str(&format!(",queues={}", self.queues));
+            }
+            device.push_str(&format!(",mq=on,vectors={}", 2 * self.queues + 2));
+        }
+
+        Ok(vec![
+            "-netdev".to_string(),
+            netdev,
+            "-device".to_string(),
+            device,
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct VhostVsock {
+    guest_cid: u32,
+    vhost_fd: RawFd,
+}
+
+impl ToQemuParams for VhostVsock {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "-device".to_string(),
+            format!(
+                "vhost-vsock-pci,id=vsock-{0},guest-cid={0},vhostfd={1}",
+                self.guest_cid, self.vhost_fd
+            ),
+        ])
+    }
+}
+
+#[derive(Debug)]
+struct VhostUserFs {
+    tag: String,
+    sock_path: String,
+    queue_size: u64,
+}
+
+impl ToQemuParams for VhostUserFs {
+    fn qemu_params(&self) -> Result<Vec<String>> {
+        let mut device = format!("vhost-user-fs-pci,chardev=char-{0},tag={0}", self.tag);
+        if self.queue_size > 0 {
+            device.push_str(&format!(",queue-size={}", self.queue_size));
+        }
+        Ok(vec![
+            "-chardev".to_string(),
+            format!(
+                "socket,id=char-{},path={}",
+                self.tag,
+                escape(&self.sock_path)
+            ),
+            "-device".to_string(),
+            device,
+        ])
+    }
+}
+
+/// Typed representation of a QEMU command line built from the generic
+/// hypervisor configuration. Devices known before the VM is started are
+/// cold-plugged through the `add_*()` methods.
+pub struct QemuCmdLine<'a> {
+    id: String,
+    config: &'a HypervisorConfig,
+
+    machine: Machine,
+    cpu: Cpu,
+    smp: Smp,
+    memory: Memory,
+    memory_backend: Option<MemoryBackend>,
+    kernel: Kernel,
+    firmware: Firmware,
+    knobs: Knobs,
+    qmp: Qmp,
+    console: Console,
+
+    devices: Vec<Box<dyn ToQemuParams + 'a>>,
+}
+
+impl<'a> QemuCmdLine<'a> {
+    /// Create the command line of sandbox `id` whose runtime files (QMP and
+    /// console sockets) live in `vm_path`.
+    pub fn new(id: &str, vm_path: &str, config: &'a HypervisorConfig) -> Result<Self> {
+        let mut cmdline = QemuCmdLine {
+            id: id.to_string(),
+            config,
+            machine: Machine::new(config),
+            cpu: Cpu {
+                model: "host".to_string(),
+                features: config.cpu_info.cpu_features.clone(),
+            },
+            smp: Smp {
+                num_vcpus: config.cpu_info.default_vcpus as u32,
+                max_vcpus: config.cpu_info.default_maxvcpus,
+            },
+            memory: Memory {
+                size_mb: config.memory_info.default_memory,
+                num_slots: config.memory_info.memory_slots,
+                max_size_mb: get_host_memory_mb()?.max(config.memory_info.default_memory as u64),
+            },
+            memory_backend: None,
+            kernel: Kernel {
+                path: config.boot_info.kernel.clone(),
+                initrd: config.boot_info.initrd.clone(),
+                params: String::new(),
+            },
+            firmware: Firmware {
+                bios: config.boot_info.firmware.clone(),
+                pflashes: config.machine_info.pflashes.clone(),
+            },
+            knobs: Knobs {
+                seccomp: !config.security_info.disable_seccomp,
+            },
+            qmp: Qmp {
+                path: [vm_path, super::inner::QMP_SOCKET_NAME].join("/"),
+            },
+            console: Console {
+                path: [vm_path, "console.sock"].join("/"),
+            },
+            devices: vec![],
+        };
+
+        cmdline.add_memory_backend();
+        cmdline.add_bridges();
+        cmdline.add_rootfs()?;
+        cmdline.kernel.params = cmdline.kernel_params()?;
+
+        if !config.machine_info.entropy_source.is_empty() {
+            cmdline.devices.push(Box::new(RngDevice {
+                filename: config.machine_info.entropy_source.clone(),
+            }));
+        }
+        if !config.debug_info.guest_memory_dump_path.is_empty() {
+            cmdline.devices.push(Box::new(PvPanic));
+        }
+
+        Ok(cmdline)
+    }
+
+    fn rootfs_driver(&self) -> &'static str {
+        if self.config.blockdev_info.disable_image_nvdimm {
+            VM_ROOTFS_DRIVER_BLK
+        } else {
+            VM_ROOTFS_DRIVER_PMEM
+        }
+    }
+
+    fn kernel_params(&self) -> Result<String> {
+        let mut params = KernelParams::new(self.config.debug_info.enable_debug);
+
+        if self.config.boot_info.initrd.is_empty() {
+            params.append(&mut KernelParams::new_rootfs_kernel_params(
+                self.rootfs_driver(),
+                &self.config.boot_info.rootfs_type,
+            )?);
+        }
+        if self.config.debug_info.enable_debug {
+            params.append(&mut KernelParams::from_string("console=hvc0"));
+        }
+        params.append(&mut KernelParams::from_string(
+            &self.config.boot_info.kernel_params,
+        ));
+
+        params.to_string()
+    }
+
+    fn add_memory_backend(&mut self) {
+        let memory_info = &self.config.memory_info;
+        let template = &self.config.vm_template;
+
+        let kind = if template.boot_to_be_template || template.boot_from_template {
+            // The template VM writes its memory to the template file, VMs
+            // cloned from it map the file privately, i.e. copy-on-write.
+            MemoryBackendKind::File {
+                path: template.memory_path.clone(),
+                share: template.boot_to_be_template,
+            }
+        } else if let Some(kind) = memory_backend_kind(self.config) {
+            kind
+        } else {
+            return;
+        };
+
+        self.memory_backend = Some(MemoryBackend {
+            kind,
+            size_mb: memory_info.default_memory,
+            prealloc: memory_info.enable_mem_prealloc,
+            numa_nodes: vec![],
+        });
+    }
+
+    /// Split the guest memory and vCPUs across the nodes of `topology`,
+    /// the memory of each node being bound to its host NUMA node.
+    pub fn set_numa_topology(&mut self, topology: &NumaTopology) -> Result<()> {
+        let template = &self.config.vm_template;
+        if template.boot_to_be_template || template.boot_from_template {
+            return Err(anyhow!("NUMA topology is not supported with VM templates"));
+        }
+
+        let memory_info = &self.config.memory_info;
+        let memory_backend = self.memory_backend.get_or_insert_with(|| MemoryBackend {
+            kind: MemoryBackendKind::Ram,
+            size_mb: memory_info.default_memory,
+            prealloc: memory_info.enable_mem_prealloc,
+            numa_nodes: vec![],
+        });
+        memory_backend.numa_nodes = topology.nodes.clone();
+        Ok(())
+    }
+
+    // Only the x86 machine types get bridges. The other ones, such as virt on
+    // aarch64, s390-ccw-virtio or pseries, plug the devices into their root bus.
+    fn add_bridges(&mut self) {
+        match self.config.machine_info.machine_type.as_str() {
+            MACHINE_TYPE_Q35 => {
+                for index in 0..self.config.device_info.pcie_root_port {
+                    self.devices.push(Box::new(PcieRootPort { index }));
+                }
+                for index in 0..self.config.device_info.default_bridges {
+                    self.devices.push(Box::new(PciBridge {
+                        index,
+                        bus: "pcie.0".to_string(),
+                    }));
+                }
+            }
+            MACHINE_TYPE_PC => {
+                for index in 0..self.config.device_info.default_bridges {
+                    self.devices.push(Box::new(PciBridge {
+                        index,
+                        bus: "pci.0".to_string(),
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_rootfs(&mut self) -> Result<()> {
+        let image = &self.config.boot_info.image;
+        if !self.config.boot_info.initrd.is_empty() || image.is_empty() {
+            return Ok(());
+        }
+
+        match self.rootfs_driver() {
+            VM_ROOTFS_DRIVER_PMEM => {
+                let size = std::fs::metadata(image)
+                    .with_context(|| format!("stat rootfs image {}", image))?
+                    .len();
+                self.machine.nvdimm = true;
+                // NVDIMMs need a memory slot of their own.
+                if self.memory.num_slots == 0 {
+                    self.memory.num_slots = 1;
+                }
+                self.memory.max_size_mb = self
+                    .memory
+                    .max_size_mb
+                    .max(self.memory.size_mb as u64 + (size + MIB_TO_B - 1) / MIB_TO_B);
+                self.devices.push(Box::new(Nvdimm {
+                    id: ROOTFS_ID.to_string(),
+                    path: image.clone(),
+                    size,
+                }));
+            }
+            _ => self.devices.push(Box::new(BlockDevice {
+                id: ROOTFS_ID.to_string(),
+                path: image.clone(),
+                read_only: true,
+                direct: false,
+                no_flush: false,
+            })),
+        }
+        Ok(())
+    }
+
+    pub fn add_block_device(&mut self, id: &str, path: &str, read_only: bool) -> Result<()> {
+        let driver = self.config.blockdev_info.block_device_driver.as_str();
+        if driver != VM_ROOTFS_DRIVER_BLK {
+            return Err(anyhow!("unsupported block device driver {}", driver));
+        }
+
+        self.devices.push(Box::new(BlockDevice {
+            id: id.to_string(),
+            path: path.to_string(),
+            read_only,
+            direct: self.config.blockdev_info.block_device_cache_direct,
+            no_flush: self.config.blockdev_info.block_device_cache_noflush,
+        }));
+        Ok(())
+    }
+
+    /// Add a network device. The queue fds of `config`, if any, must be
+    /// inherited by QEMU.
+    pub fn add_network_device(&mut self, id: &str, config: &NetworkConfig) -> Result<()> {
+        let queues = if config.queue_fds.is_empty() {
+            self.config.network_info.network_queues
+        } else {
+            config.queue_fds.len() as u32
+        };
+        self.devices.push(Box::new(NetDevice {
+            id: id.to_string(),
+            ifname: config.host_dev_name.clone(),
+            fds: config.queue_fds.clone(),
+            mac: config.guest_mac.as_ref().map(|mac| format!("{:?}", mac)),
+            queues,
+            vhost: !self.config.network_info.disable_vhost_net,
+        }));
+        Ok(())
+    }
+
+    /// Add the agent vsock device. `vhost_fd` must be inherited by QEMU and
+    /// already have `guest_cid` set, so that the CID can't be stolen.
+    pub fn add_vsock(&mut self, guest_cid: u32, vhost_fd: RawFd) -> Result<()> {
+        self.devices.push(Box::new(VhostVsock {
+            guest_cid,
+            vhost_fd,
+        }));
+        Ok(())
+    }
+
+    pub fn add_virtiofs_share(
+        &mut self,
+        sock_path: &str,
+        tag: &str,
+        queue_size: u64,
+    ) -> Result<()> {
+        match &self.memory_backend {
+            Some(MemoryBackend {
+                kind: MemoryBackendKind::File { share: true, .. },
+                ..
+            }) => {}
+            _ => return Err(anyhow!("virtio-fs requires shared guest memory")),
+        }
+
+        self.devices.push(Box::new(VhostUserFs {
+            tag: tag.to_string(),
+            sock_path: sock_path.to_string(),
+            queue_size,
+        }));
+        Ok(())
+    }
+
+    /// Render the complete argument list, excluding the QEMU binary itself.
+    pub fn build(&self) -> Result<Vec<String>> {
+        let mut params = vec!["-name".to_string(), format!("sandbox-{}", self.id)];
+
+        let components: [&dyn ToQemuParams; 9] = [
+            &self.machine,
+            &self.cpu,
+            &self.qmp,
+            &self.smp,
+            &self.memory,
+            &self.kernel,
+            &self.firmware,
+            &self.knobs,
+            &self.console,
+        ];
+        for component in components {
+            params.append(&mut component.qemu_params()?);
+        }
+        if let Some(memory_backend) = &self.memory_backend {
+            params.append(&mut memory_backend.qemu_params()?);
+        }
+        for device in &self.devices {
+            params.append(&mut device.qemu_params()?);
+        }
+        if self.config.vm_template.boot_from_template {
+            // The template device state is loaded through QMP once QEMU is
+            // up.
+            params.push("-incoming".to_string());
+            params.push("defer".to_string());
+        }
+
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Address;
+    use std::io::Write;
+
+    fn base_config() -> HypervisorConfig {
+        let mut config = HypervisorConfig::default();
+        config.boot_info.kernel = "/usr/share/kata-containers/vmlinuz".to_string();
+        config.boot_info.rootfs_type = "ext4".to_string();
+        config.machine_info.machine_type = "q35".to_string();
+        config.cpu_info.default_vcpus = 1;
+        config.cpu_info.default_maxvcpus = 4;
+        config.memory_info.default_memory = 2048;
+        config.blockdev_info.block_device_driver = "virtio-blk-pci".to_string();
+        config.security_info.disable_seccomp = true;
+        config
+    }
+
+    fn assert_args(actual: Vec<String>, expected: &[&str]) {
+        let actual: Vec<&str> = actual.iter().map(|s| s.as_str()).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_cmdline_initrd() {
+        let mut config = base_config();
+        config.boot_info.initrd = "/usr/share/kata-containers/kata-initrd.img".to_string();
+        config.boot_info.kernel_params = "agent.log=debug".to_string();
+
+        let mut cmdline = QemuCmdLine::new("sb1", "/run/kata/sb1", &config).unwrap();
+        cmdline.add_vsock(3, 10).unwrap();
+
+        assert_args(
+            cmdline.build().unwrap(),
+            &[
+                "-name", "sandbox-sb1",
+                "-machine", "q35,accel=kvm,kernel_irqchip=on",
+                "-cpu", "host",
+                "-qmp", "unix:/run/kata/sb1/qmp.sock,server=on,wait=off",
+                "-smp", "1,cores=1,threads=1,sockets=4,maxcpus=4",
+                "-m", "2048M",
+                "-kernel", "/usr/share/kata-containers/vmlinuz",
+                "-initrd", "/usr/share/kata-containers/kata-initrd.img",
+                "-append", "reboot=k earlyprintk=ttyS0 initcall_debug panic=1 systemd.unit=kata-containers.target systemd.mask=systemd-networkd.service agent.log=debug",
+                "-no-user-config", "-nodefaults", "-nographic", "-vga", "none",
+                "-rtc", "base=utc,driftfix=slew,clock=host",
+                "-device", "virtio-serial-pci,id=serial0",
+                "-device", "virtconsole,chardev=charconsole0,id=console0",
+                "-chardev", "socket,id=charconsole0,path=/run/kata/sb1/console.sock,server=on,wait=off",
+                "-device", "vhost-vsock-pci,id=vsock-3,guest-cid=3,vhostfd=10",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_cmdline_image_virtio_fs() {
+        let mut config = base_config();
+        config.boot_info.image = "/usr/share/kata-containers/kata,containers.img".to_string();
+        config.blockdev_info.disable_image_nvdimm = true;
+        config.blockdev_info.block_device_cache_direct = true;
+        config.cpu_info.cpu_features = "pmu=off".to_string();
+        config.machine_info.machine_accelerators = "nosmm".to_string();
+        config.machine_info.entropy_source = "/dev/urandom".to_string();
+        config.memory_info.memory_slots = 10;
+        config.memory_info.enable_hugepages = true;
+        config.memory_info.enable_mem_prealloc = true;
+        config.device_info.pcie_root_port = 2;
+        config.network_info.network_queues = 2;
+        config.security_info.disable_seccomp = false;
+        config.shared_fs.shared_fs = Some("virtio-fs".to_string());
+
+        let mut cmdline = QemuCmdLine::new("sb2", "/run/kata/sb2", &config).unwrap();
+        cmdline.memory.max_size_mb = 8192;
+        cmdline
+            .add_virtiofs_share("/run/kata/sb2/vhost-fs.sock", "kataShared", 1024)
+            .unwrap();
+        cmdline
+            .add_block_device("d1", "/tmp/not-a-device.img", false)
+            .unwrap();
+        cmdline
+            .add_network_device(
+                "n1",
+                &NetworkConfig {
+                    host_dev_name: "tap0_kata".to_string(),
+                    guest_mac: Some(Address([0x02, 0x42, 0xac, 0x11, 0x00, 0x02])),
+                    queue_fds: vec![],
+                },
+            )
+            .unwrap();
+        cmdline
+            .add_network_device(
+                "n2",
+                &NetworkConfig {
+                    host_dev_name: "tap1_kata".to_string(),
+                    guest_mac: None,
+                    queue_fds: vec![10, 11, 12],
+                },
+            )
+            .unwrap();
+
+        assert_args(
+            cmdline.build().unwrap(),
+            &[
+                "-name", "sandbox-sb2",
+                "-machine", "q35,accel=kvm,kernel_irqchip=on,nosmm",
+                "-cpu", "host,pmu=off",
+                "-qmp", "unix:/run/kata/sb2/qmp.sock,server=on,wait=off",
+                "-smp", "1,cores=1,threads=1,sockets=4,maxcpus=4",
+                "-m", "2048M,slots=10,maxmem=8192M",
+                "-kernel", "/usr/share/kata-containers/vmlinuz",
+                "-append", "reboot=k earlyprintk=ttyS0 initcall_debug panic=1 systemd.unit=kata-containers.target systemd.mask=systemd-networkd.service root=/dev/vda1 rootflags=data=ordered,errors=remount-ro ro rootfstype=ext4",
+                "-no-user-config", "-nodefaults", "-nographic", "-vga", "none",
+                "-rtc", "base=utc,driftfix=slew,clock=host",
+                "-sandbox", "on,obsolete=deny,elevateprivileges=deny,spawn=deny,resourcecontrol=deny",
+                "-device", "virtio-serial-pci,id=serial0",
+                "-device", "virtconsole,chardev=charconsole0,id=console0",
+                "-chardev", "socket,id=charconsole0,path=/run/kata/sb2/console.sock,server=on,wait=off",
+                "-object", "memory-backend-file,id=dimm1,size=2048M,mem-path=/dev/hugepages,share=on,prealloc=on",
+                "-numa", "node,memdev=dimm1",
+                "-device", "pcie-root-port,id=rp0,bus=pcie.0,chassis=0,slot=0,multifunction=off",
+                "-device", "pcie-root-port,id=rp1,bus=pcie.0,chassis=0,slot=1,multifunction=off",
+                "-blockdev", "driver=raw,node-name=drive-image,read-only=on,cache.direct=off,cache.no-flush=off,file.driver=file,file.filename=/usr/share/kata-containers/kata,,containers.img",
+                "-device", "virtio-blk-pci,id=virtio-image,drive=drive-image",
+                "-object", "rng-random,id=rng0,filename=/dev/urandom",
+                "-device", "virtio-rng-pci,rng=rng0",
+                "-chardev", "socket,id=char-kataShared,path=/run/kata/sb2/vhost-fs.sock",
+                "-device", "vhost-user-fs-pci,chardev=char-kataShared,tag=kataShared,queue-size=1024",
+                "-blockdev", "driver=raw,node-name=drive-d1,read-only=off,cache.direct=on,cache.no-flush=off,file.driver=file,file.filename=/tmp/not-a-device.img",
+                "-device", "virtio-blk-pci,id=virtio-d1,drive=drive-d1",
+                "-netdev", "tap,id=netdev-n1,ifname=tap0_kata,script=no,downscript=no,vhost=on,queues=2",
+                "-device", "virtio-net-pci,id=virtio-n1,netdev=netdev-n1,mac=02:42:ac:11:00:02,mq=on,vectors=6",
+                "-netdev", "tap,id=netdev-n2,fds=10:11:12,vhost=on",
+                "-device", "virtio-net-pci,id=virtio-n2,netdev=netdev-n2,mq=on,vectors=8",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_cmdline_image_nvdimm() {
+        let mut image = tempfile::NamedTempFile::new().unwrap();
+        image.write_all(&[0u8; 4096]).unwrap();
+        let image_path = image.path().to_str().unwrap().to_string();
+
+        let mut config = base_config();
+        config.boot_info.image = image_path.clone();
+        config.machine_info.machine_type = "pc".to_string();
+        config.device_info.default_bridges = 1;
+        config.debug_info.enable_debug = true;
+
+        let cmdline = QemuCmdLine::new("sb3", "/run/kata/sb3", &config).unwrap();
+        let args = cmdline.build().unwrap();
+
+        let machine = args.iter().position(|a| a == "-machine").unwrap();
+        assert_eq!(
+            args[machine + 1],
+            "pc,accel=kvm,kernel_irqchip=on,nvdimm=on"
+        );
+        let memory = args.iter().position(|a| a == "-m").unwrap();
+        assert!(args[memory + 1].starts_with("2048M,slots=1,maxmem="));
+        let append = args.iter().position(|a| a == "-append").unwrap();
+        assert!(args[append + 1].contains("root=/dev/pmem0p1"));
+        assert!(args[append + 1].contains("console=hvc0"));
+
+        let tail: Vec<&str> = args[args.len() - 6..].iter().map(|s| s.as_str()).collect();
+        let object = format!(
+            "memory-backend-file,id=mem-image,mem-path={},size=4096,readonly=on",
+            image_path
+        );
+        assert_eq!(
+            tail,
+            vec![
+                "-device",
+                "pci-bridge,bus=pci.0,id=pci-bridge-0,chassis_nr=1,shpc=off",
+                "-object",
+                object.as_str(),
+                "-device",
+                "nvdimm,id=nv-image,memdev=mem-image,unarmed=on",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cmdline_vm_template() {
+        let mut config = base_config();
+        config.boot_info.initrd = "/usr/share/kata-containers/kata-initrd.img".to_string();
+        config.vm_template.boot_to_be_template = true;
+        config.vm_template.memory_path = "/run/vc/vm/template/memory".to_string();
+
+        let args = QemuCmdLine::new("sb5", "/run/kata/sb5", &config)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(args.contains(
+            &"memory-backend-file,id=dimm1,size=2048M,mem-path=/run/vc/vm/template/memory,share=on"
+                .to_string()
+        ));
+        assert!(!args.contains(&"-incoming".to_string()));
+
+        config.vm_template.boot_to_be_template = false;
+        config.vm_template.boot_from_template = true;
+        let args = QemuCmdLine::new("sb5", "/run/kata/sb5", &config)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(args.contains(
+            &"memory-backend-file,id=dimm1,size=2048M,mem-path=/run/vc/vm/template/memory"
+                .to_string()
+        ));
+        assert_eq!(args[args.len() - 2..], ["-incoming", "defer"]);
+    }
+
+    #[test]
+    fn test_cmdline_numa() {
+        let mut config = base_config();
+        config.boot_info.initrd = "/usr/share/kata-containers/kata-initrd.img".to_string();
+        let topology = NumaTopology {
+            nodes: vec![
+                NumaNode {
+                    guest_node_id: 0,
+                    host_node_id: 0,
+                    host_cpus: vec![0, 1],
+                    vcpus: vec![0, 1],
+                    memory_mb: 1024,
+                },
+                NumaNode {
+                    guest_node_id: 1,
+                    host_node_id: 1,
+                    host_cpus: vec![2, 3],
+                    vcpus: vec![2, 3],
+                    memory_mb: 1024,
+                },
+            ],
+        };
+
+        let mut cmdline = QemuCmdLine::new("sb7", "/run/kata/sb7", &config).unwrap();
+        cmdline.set_numa_topology(&topology).unwrap();
+        let args = cmdline.build().unwrap();
+        let expected = [
+            "-object",
+            "memory-backend-ram,id=dimm1-node0,size=1024M,host-nodes=0,policy=bind",
+            "-numa",
+            "node,nodeid=0,cpus=0-1,memdev=dimm1-node0",
+            "-object",
+            "memory-backend-ram,id=dimm1-node1,size=1024M,host-nodes=1,policy=bind",
+            "-numa",
+            "node,nodeid=1,cpus=2-3,memdev=dimm1-node1",
+        ];
+        assert!(args.windows(expected.len()).any(|w| w == expected));
+
+        config.vm_template.boot_from_template = true;
+        let mut cmdline = QemuCmdLine::new("sb7", "/run/kata/sb7", &config).unwrap();
+        assert!(cmdline.set_numa_topology(&topology).is_err());
+    }
+
+    #[test]
+    fn test_cmdline_pvpanic() {
+        let mut config = base_config();
+        config.boot_info.initrd = "/usr/share/kata-containers/kata-initrd.img".to_string();
+        let args = QemuCmdLine::new("sb6", "/run/kata/sb6", &config)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(!args.contains(&"pvpanic".to_string()));
+
+        config.debug_info.guest_memory_dump_path = "/var/crash/kata".to_string();
+        let args = QemuCmdLine::new("sb6", "/run/kata/sb6", &config)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            args[args.len() - 3..],
+            ["-device", "pvpanic", "-no-shutdown"]
+        );
+    }
+
+    #[test]
+    fn test_cmdline_virt() {
+        let mut config = base_config();
+        config.boot_info.initrd = "/usr/share/kata-containers/kata-initrd.img".to_string();
+        config.machine_info.machine_type = "virt".to_string();
+        config.device_info.pcie_root_port = 2;
+        config.device_info.default_bridges = 1;
+
+        let mut cmdline = QemuCmdLine::new("sb8", "/run/kata/sb8", &config).unwrap();
+        cmdline.add_vsock(3, 10).unwrap();
+
+        assert_args(
+            cmdline.build().unwrap(),
+            &[
+                "-name", "sandbox-sb8",
+                "-machine", "virt,accel=kvm,kernel_irqchip=on",
+                "-cpu", "host",
+                "-qmp", "unix:/run/kata/sb8/qmp.sock,server=on,wait=off",
+                "-smp", "1,cores=1,threads=1,sockets=4,maxcpus=4",
+                "-m", "2048M",
+                "-kernel", "/usr/share/kata-containers/vmlinuz",
+                "-initrd", "/usr/share/kata-containers/kata-initrd.img",
+                "-append", "reboot=k earlyprintk=ttyS0 initcall_debug panic=1 systemd.unit=kata-containers.target systemd.mask=systemd-networkd.service",
+                "-no-user-config", "-nodefaults", "-nographic", "-vga", "none",
+                "-rtc", "base=utc,driftfix=slew,clock=host",
+                "-device", "virtio-serial-pci,id=serial0",
+                "-device", "virtconsole,chardev=charconsole0,id=console0",
+                "-chardev", "socket,id=charconsole0,path=/run/kata/sb8/console.sock,server=on,wait=off",
+                "-device", "vhost-vsock-pci,id=vsock-3,guest-cid=3,vhostfd=10",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_cmdline_errors() {
+        let mut config = base_config();
+        config.blockdev_info.block_device_driver = "virtio-scsi".to_string();
+        let mut cmdline = QemuCmdLine::new("sb4", "/run/kata/sb4", &config).unwrap();
+        assert!(cmdline.add_block_device("d1", "/dev/null", true).is_err());
+        // no shared memory backend
+        assert!(cmdline
+            .add_virtiofs_share("/run/kata/sb4/vhost-fs.sock", "kataShared", 0)
+            .is_err());
+    }
+}
@@ -3,126 +3,741 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
 
-use crate::{HypervisorConfig, VcpuThreadIds};
+use anyhow::{anyhow, Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sched::{setns, CloneFlags};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde_json::json;
+use shim_interface::KATA_PATH;
+
+use super::cmdline_generator::{
+    memory_backend_kind, qemu_device_id, qemu_netdev_id, qemu_node_name, MemoryBackendKind,
+    QemuCmdLine,
+};
+use super::qmp::Qmp;
+use crate::hypervisor_persist::HypervisorState;
+use crate::numa::{self, NumaTopology};
+use crate::utils::{
+    self, get_host_memory_mb, get_memory_hotplug_size_mb, get_resized_vcpus, MIB_TO_B,
+};
+use crate::{
+    HypervisorConfig, MemoryHotplugInfo, VcpuThreadIds, VmmState, VsockDevice, HYPERVISOR_QEMU,
+};
 use kata_types::capabilities::{Capabilities, CapabilityBits};
 
 const VSOCK_SCHEME: &str = "vsock";
-const VSOCK_AGENT_CID: u32 = 3;
 const VSOCK_AGENT_PORT: u32 = 1024;
 
+pub(crate) const QMP_SOCKET_NAME: &str = "qmp.sock";
+const QEMU_STATE_FILE: &str = "state";
+const QEMU_JAILER_DIR: &str = "root";
+
+/// Migration capability skipping shared memory, which is how the template
+/// memory is handed over to the VMs cloned from it.
+const MIGRATION_IGNORE_SHARED: &str = "x-ignore-shared";
+
+/// Number of milliseconds to wait before retrying a QMP operation.
+const QMP_POLL_TIME_MS: u64 = 50;
+/// Number of seconds to wait for QEMU to exit once asked to quit.
+const QEMU_STOP_TIMEOUT_SECS: u64 = 10;
+/// Number of seconds to wait for a migration to or from a state file.
+const QEMU_MIGRATION_TIMEOUT_SECS: u64 = 60;
+/// Number of seconds to wait for the guest memory to be dumped.
+const QEMU_DUMP_TIMEOUT_SECS: u64 = 300;
+
 pub struct QemuInner {
+    /// sandbox id
+    id: String,
+
+    /// vm path
+    vm_path: String,
+
+    /// netns the VM is started in
+    netns: Option<String>,
+
     config: HypervisorConfig,
+
+    state: VmmState,
+
+    /// QEMU process, only known for a VM started by this instance
+    qemu_process: Option<Child>,
+
+    /// QEMU pid, also available for a restored VM
+    pid: Option<u32>,
+
+    /// QMP connection, established lazily
+    qmp: Option<Qmp>,
+
+    /// devices added before the VM is running
+    pending_devices: Vec<DeviceType>,
+
+    /// guest vsock CID, reserved in prepare_vm()
+    vsock_cid: Option<u32>,
+
+    /// guest memory hot-added since boot, in MiB
+    hotplugged_memory_mb: u32,
+
+    /// number of DIMMs hot-added since boot
+    hotplugged_memory_slots: u32,
+
+    /// ids of the CPUs hot-plugged since boot, in plug order
+    hotplugged_vcpus: Vec<String>,
+
+    /// guest NUMA topology mirroring the host NUMA nodes of the sandbox
+    numa_topology: Option<NumaTopology>,
 }
 
 impl QemuInner {
     pub fn new() -> QemuInner {
         QemuInner {
+            id: String::default(),
+            vm_path: String::default(),
+            netns: None,
             config: Default::default(),
+            state: VmmState::NotReady,
+            qemu_process: None,
+            pid: None,
+            qmp: None,
+            pending_devices: vec![],
+            vsock_cid: None,
+            hotplugged_memory_mb: 0,
+            hotplugged_memory_slots: 0,
+            hotplugged_vcpus: vec![],
+            numa_topology: None,
         }
     }
 
-    pub(crate) async fn prepare_vm(&mut self, _id: &str, _netns: Option<String>) -> Result<()> {
+    fn qmp_socket_path(&self) -> String {
+        [self.vm_path.as_str(), QMP_SOCKET_NAME].join("/")
+    }
+
+    // Return the QMP connection, (re)connecting if needed. A restored
+    // sandbox only knows the socket path.
+    async fn qmp(&mut self) -> Result<&mut Qmp> {
+        if self.qmp.is_none() {
+            let qmp = Qmp::new(&self.qmp_socket_path())
+                .await
+                .context("connect QMP")?;
+            self.qmp = Some(qmp);
+        }
+        self.qmp
+            .as_mut()
+            .ok_or_else(|| anyhow!("no QMP connection"))
+    }
+
+    pub(crate) async fn prepare_vm(&mut self, id: &str, netns: Option<String>) -> Result<()> {
         info!(sl!(), "Preparing QEMU VM");
+        self.id = id.to_string();
+        self.vm_path = [KATA_PATH, id].join("/");
+        self.netns = netns;
+        self.state = VmmState::NotReady;
+
+        create_dir_all(&self.vm_path)
+            .with_context(|| format!("failed to create dir {}", self.vm_path))?;
+
+        // The CID stays reserved as long as a vhost-vsock fd it was set on
+        // is open, the one handed over to QEMU outlives this process'.
+        let vsock = VsockDevice::new(format!("vsock-{}", self.id))
+            .await
+            .context("allocate vsock CID")?;
+        self.vsock_cid = Some(vsock.config.guest_cid);
+        self.add_device(DeviceType::Vsock(vsock))
+            .await
+            .context("add vsock device")?;
+
         Ok(())
     }
 
-    pub(crate) async fn start_vm(&mut self, _timeout: i32) -> Result<()> {
+    fn build_command(&self) -> Result<Command> {
+        let mut cmdline = QemuCmdLine::new(&self.id, &self.vm_path, &self.config)
+            .context("build QEMU command line")?;
+        if let Some(topology) = &self.numa_topology {
+            cmdline.set_numa_topology(topology)?;
+        }
+        let mut inherited_fds = vec![];
+
+        // Devices added before start are cold-plugged, in the order they
+        // were added. A VM cloned from a template must have the same devices
+        // as the template VM, only the vsock device, so the other ones are
+        // hotplugged once its state is loaded.
+        for device in self.pending_devices.iter().rev() {
+            if self.config.vm_template.boot_from_template && !is_vsock(device) {
+                continue;
+            }
+            match device {
+                DeviceType::Block(block) => cmdline.add_block_device(
+                    &block.device_id,
+                    &block.config.path_on_host,
+                    block.config.is_readonly,
+                )?,
+                DeviceType::Network(network) => {
+                    cmdline.add_network_device(&network.id, &network.config)?;
+                    inherited_fds.extend_from_slice(&network.config.queue_fds);
+                }
+                DeviceType::ShareFs(share_fs) => cmdline.add_virtiofs_share(
+                    &share_fs.config.sock_path,
+                    &share_fs.config.mount_tag,
+                    share_fs.config.queue_size,
+                )?,
+                DeviceType::Vsock(vsock) => {
+                    let vhost_fd = vsock.config.vhost_fd.as_raw_fd();
+                    cmdline.add_vsock(vsock.config.guest_cid, vhost_fd)?;
+                    inherited_fds.push(vhost_fd);
+                }
+                _ => return Err(anyhow!("QEMU doesn't support adding device {}", device)),
+            }
+        }
+
+        let mut command = Command::new(&self.config.path);
+        command.args(cmdline.build()?);
+        // Safe because fcntl() is async-signal-safe and the fds are owned by
+        // the pending devices, which outlive the spawn.
+        unsafe {
+            command.pre_exec(move || {
+                for fd in &inherited_fds {
+                    fcntl(*fd, FcntlArg::F_SETFD(FdFlag::empty()))
+                        .map_err(|e| std::io::Error::from_raw_os_error(e as i32))?;
+                }
+                Ok(())
+            });
+        }
+        Ok(command)
+    }
+
+    pub(crate) async fn start_vm(&mut self, timeout: i32) -> Result<()> {
         info!(sl!(), "Starting QEMU VM");
 
-        let mut command = std::process::Command::new(&self.config.path);
+        if self.qemu_process.is_some() {
+            return Err(anyhow!("QEMU VM {} is already started", self.id));
+        }
+        if timeout < 0 {
+            return Err(anyhow!("Invalid param timeout {}", timeout));
+        }
 
+        let mut command = self.build_command()?;
         command
-            .arg("-kernel")
-            .arg(&self.config.boot_info.kernel)
-            .arg("-m")
-            .arg(format!("{}M", &self.config.memory_info.default_memory))
-            .arg("-initrd")
-            .arg(&self.config.boot_info.initrd)
-            .arg("-vga")
-            .arg("none")
-            .arg("-nodefaults")
-            .arg("-nographic");
-
-        command.spawn()?;
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+
+        // QEMU opens tap devices by name, so it must live in the sandbox netns.
+        let netns_file = match &self.netns {
+            Some(netns) => {
+                Some(File::open(netns).with_context(|| format!("open netns {}", netns))?)
+            }
+            None => None,
+        };
+        if let Some(netns_fd) = netns_file.as_ref().map(|f| f.as_raw_fd()) {
+            // Safe because setns() is async-signal-safe and the fd is kept
+            // open by `netns_file` until spawn() returns.
+            unsafe {
+                command.pre_exec(move || {
+                    setns(netns_fd, CloneFlags::CLONE_NEWNET)
+                        .map_err(|e| std::io::Error::from_raw_os_error(e as i32))
+                });
+            }
+        }
+        self.spawn(command)?;
+        drop(netns_file);
+
+        if let Err(e) = self.wait_qmp_ready(timeout).await {
+            error!(sl!(), "QEMU VM not ready: {:?}", e);
+            if let Err(err) = self.stop_vm().await {
+                error!(sl!(), "failed to stop QEMU VM: {:?}", err);
+            }
+            return Err(e);
+        }
+
+        if self.config.vm_template.boot_from_template {
+            if let Err(e) = self.boot_from_template().await {
+                error!(sl!(), "failed to clone QEMU VM from template: {:?}", e);
+                if let Err(err) = self.stop_vm().await {
+                    error!(sl!(), "failed to stop QEMU VM: {:?}", err);
+                }
+                return Err(e);
+            }
+        } else {
+            self.state = VmmState::VmRunning;
+            self.pending_devices.clear();
+        }
+        self.pin_vcpus().await;
+
+        Ok(())
+    }
+
+    pub(crate) fn set_numa_topology(&mut self, cpus: &str) -> Result<()> {
+        let template = &self.config.vm_template;
+        if template.boot_to_be_template || template.boot_from_template {
+            // The template VM memory layout is shared by all its clones.
+            warn!(sl!(), "NUMA topology is ignored for VM templates");
+            return Ok(());
+        }
+        self.numa_topology =
+            numa::get_numa_topology(&self.config, cpus).context("get numa topology")?;
+        Ok(())
+    }
+
+    // Pin the vCPU threads to the host CPUs of their NUMA node, if the guest
+    // mirrors the host NUMA nodes. Failing to do so only hurts performance.
+    pub(crate) async fn pin_vcpus(&mut self) {
+        if self.numa_topology.is_none() {
+            return;
+        }
+        let tids = self.get_thread_ids().await;
+        if let Some(topology) = self.numa_topology.as_ref() {
+            if let Err(e) = tids.and_then(|tids| topology.pin_vcpus(&tids)) {
+                warn!(sl!(), "failed to pin vcpus to host NUMA nodes: {:?}", e);
+            }
+        }
+    }
+
+    // Load the device state of the template VM, the guest memory is mapped
+    // from the template memory file already, then hotplug the devices which
+    // weren't cold-plugged.
+    async fn boot_from_template(&mut self) -> Result<()> {
+        info!(sl!(), "Cloning QEMU VM from template");
+        let state_file = self.config.vm_template.devices_state_path.clone();
+        let qmp = self.qmp().await?;
+        qmp.migrate_set_capability(MIGRATION_IGNORE_SHARED, true)
+            .await
+            .context("set migration capability")?;
+        qmp.migrate_incoming(&format!("exec:cat {}", state_file))
+            .await
+            .context("migrate from template")?;
+        self.wait_migration(&state_file).await?;
 
+        // QEMU resumes the VM once the incoming migration is done.
+        self.state = VmmState::VmRunning;
+        let devices = std::mem::take(&mut self.pending_devices);
+        for device in devices.into_iter().rev() {
+            if !is_vsock(&device) {
+                self.add_device(device).await?;
+            }
+        }
         Ok(())
     }
 
-    pub(crate) fn stop_vm(&mut self) -> Result<()> {
+    async fn wait_migration(&mut self, state_file: &str) -> Result<()> {
+        let qmp = self.qmp().await?;
+        let time_start = Instant::now();
+        loop {
+            let status = qmp.query_migrate().await.context("query migration")?;
+            match status.status.as_str() {
+                "completed" => return Ok(()),
+                "failed" | "cancelled" => {
+                    return Err(anyhow!("migration of {} {}", state_file, status.status))
+                }
+                _ if time_start.elapsed() > Duration::from_secs(QEMU_MIGRATION_TIMEOUT_SECS) => {
+                    return Err(anyhow!(
+                        "migration of {} timed out, last status {}",
+                        state_file,
+                        status.status
+                    ))
+                }
+                _ => tokio::time::sleep(Duration::from_millis(QMP_POLL_TIME_MS)).await,
+            }
+        }
+    }
+
+    fn spawn(&mut self, mut command: Command) -> Result<()> {
+        info!(sl!(), "QEMU command {:?}", command);
+        let mut child = command.spawn().context("spawn QEMU")?;
+
+        if let Some(stderr) = child.stderr.take() {
+            std::thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().flatten() {
+                    info!(sl!(), "{}", line; "stream" => "stderr");
+                }
+            });
+        }
+
+        self.pid = Some(child.id());
+        self.qemu_process = Some(child);
+        Ok(())
+    }
+
+    // QEMU creates the QMP socket once it's done parsing its command line,
+    // retry connecting until then.
+    async fn wait_qmp_ready(&mut self, timeout: i32) -> Result<()> {
+        let time_start = Instant::now();
+        loop {
+            if let Some(child) = self.qemu_process.as_mut() {
+                if let Some(status) = child.try_wait().context("wait QEMU")? {
+                    return Err(anyhow!("QEMU exited prematurely: {}", status));
+                }
+            }
+
+            match self.qmp().await {
+                Ok(_) => return Ok(()),
+                Err(err) => {
+                    if time_start.elapsed().as_millis() > timeout as u128 {
+                        return Err(err.context(format!("QMP not ready after {}ms", timeout)));
+                    }
+                    tokio::time::sleep(Duration::from_millis(QMP_POLL_TIME_MS)).await;
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn stop_vm(&mut self) -> Result<()> {
         info!(sl!(), "Stopping QEMU VM");
-        todo!()
+
+        let quit = match self.qmp().await {
+            Ok(qmp) => qmp.quit().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = quit {
+            warn!(sl!(), "failed to quit QEMU through QMP: {:?}", e);
+        }
+        self.qmp = None;
+        self.state = VmmState::NotReady;
+        self.pending_devices.clear();
+
+        if let Some(mut child) = self.qemu_process.take() {
+            let time_start = Instant::now();
+            while child.try_wait().context("wait QEMU")?.is_none() {
+                if time_start.elapsed() > Duration::from_secs(QEMU_STOP_TIMEOUT_SECS) {
+                    warn!(sl!(), "QEMU didn't exit in time, killing it");
+                    // kill() only sends SIGKILL, reap the process as well.
+                    child.kill().context("kill QEMU")?;
+                    child.wait().context("wait QEMU")?;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(QMP_POLL_TIME_MS)).await;
+            }
+        } else if let Some(pid) = self.pid {
+            // Not our child, so all we can do is poll for its disappearance.
+            let pid = Pid::from_raw(pid as i32);
+            let time_start = Instant::now();
+            while kill(pid, None).is_ok() {
+                if time_start.elapsed() > Duration::from_secs(QEMU_STOP_TIMEOUT_SECS) {
+                    warn!(sl!(), "QEMU didn't exit in time, killing it");
+                    kill(pid, Signal::SIGKILL).context("kill QEMU")?;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(QMP_POLL_TIME_MS)).await;
+            }
+        }
+        self.pid = None;
+
+        Ok(())
     }
 
-    pub(crate) fn pause_vm(&self) -> Result<()> {
+    pub(crate) async fn pause_vm(&mut self) -> Result<()> {
         info!(sl!(), "Pausing QEMU VM");
-        todo!()
+        self.qmp().await?.stop().await.context("pause vm")
     }
 
-    pub(crate) fn resume_vm(&self) -> Result<()> {
+    pub(crate) async fn resume_vm(&mut self) -> Result<()> {
         info!(sl!(), "Resuming QEMU VM");
-        todo!()
+        self.qmp().await?.cont().await.context("resume vm")
+    }
+
+    // Save the device state of the VM to the sandbox directory by migrating
+    // it to a file. Guest memory is migrated as well unless it's shared.
+    // A template VM saves its device state to the template directory, its
+    // memory being in the template memory file already.
+    pub(crate) async fn save_vm(&mut self) -> Result<()> {
+        info!(sl!(), "Saving QEMU VM");
+        let to_be_template = self.config.vm_template.boot_to_be_template;
+        let state_file = if to_be_template {
+            self.config.vm_template.devices_state_path.clone()
+        } else {
+            [self.vm_path.as_str(), QEMU_STATE_FILE].join("/")
+        };
+        let qmp = self.qmp().await?;
+
+        if to_be_template {
+            qmp.migrate_set_capability(MIGRATION_IGNORE_SHARED, true)
+                .await
+                .context("set migration capability")?;
+        }
+        qmp.migrate(&format!("exec:cat>{}", state_file))
+            .await
+            .context("migrate to file")?;
+        self.wait_migration(&state_file).await
+    }
+
+    // dump_guest_memory saves the hypervisor config and state, then pauses
+    // the VM and dumps its memory into the guest memory dump directory of the
+    // sandbox. QEMU writes the dump in the background, poll for its
+    // completion like for migrations.
+    pub(crate) async fn dump_guest_memory(&mut self) -> Result<()> {
+        let dump_path = &self.config.debug_info.guest_memory_dump_path;
+        if dump_path.is_empty() {
+            return Ok(());
+        }
+        let dump_dir = utils::get_guest_memory_dump_dir(dump_path, &self.id);
+        info!(sl!(), "dump guest memory to {}", &dump_dir);
+
+        utils::save_guest_memory_dump_info(&dump_dir, &self.save())
+            .context("save guest memory dump info")?;
+        let memory_mb = self.config.memory_info.default_memory + self.hotplugged_memory_mb;
+        utils::check_guest_memory_dump_space(&dump_dir, memory_mb as u64)?;
+
+        let dump_file = utils::get_guest_memory_dump_file(&dump_dir);
+        let paging = self.config.debug_info.guest_memory_dump_paging;
+        let qmp = self.qmp().await?;
+        qmp.stop().await.context("pause vm")?;
+        qmp.dump_guest_memory(&dump_file, paging)
+            .await
+            .context("dump guest memory")?;
+        self.wait_dump(&dump_file).await
     }
 
-    pub(crate) async fn save_vm(&self) -> Result<()> {
-        todo!()
+    // QEMU has no command to cancel a dump, so a dump still running at the
+    // deadline is abandoned: its partial file is removed and QEMU goes on
+    // writing to it until the VM is torn down.
+    async fn wait_dump(&mut self, dump_file: &str) -> Result<()> {
+        let qmp = self.qmp().await?;
+        let time_start = Instant::now();
+        loop {
+            let status = qmp.query_dump().await.context("query dump")?;
+            match status.status.as_str() {
+                "completed" => return Ok(()),
+                "failed" => return Err(anyhow!("guest memory dump to {} failed", dump_file)),
+                _ if time_start.elapsed() > Duration::from_secs(QEMU_DUMP_TIMEOUT_SECS) => {
+                    if let Err(e) = std::fs::remove_file(dump_file) {
+                        warn!(sl!(), "failed to remove dump file {}: {:?}", dump_file, e);
+                    }
+                    return Err(anyhow!(
+                        "guest memory dump to {} timed out, last status {} ({}/{} bytes)",
+                        dump_file,
+                        status.status,
+                        status.completed,
+                        status.total
+                    ));
+                }
+                _ => tokio::time::sleep(Duration::from_millis(QMP_POLL_TIME_MS)).await,
+            }
+        }
     }
 
-    /// TODO: using a single hardcoded CID is clearly not adequate in the long
-    /// run. Use the recently added VsockConfig infrastructure to fix this.
     pub(crate) async fn get_agent_socket(&self) -> Result<String> {
         info!(sl!(), "QemuInner::get_agent_socket()");
+        let guest_cid = self
+            .vsock_cid
+            .ok_or_else(|| anyhow!("no vsock CID allocated for {}", self.id))?;
         Ok(format!(
             "{}://{}:{}",
-            VSOCK_SCHEME, VSOCK_AGENT_CID, VSOCK_AGENT_PORT
+            VSOCK_SCHEME, guest_cid, VSOCK_AGENT_PORT
         ))
     }
 
     pub(crate) async fn disconnect(&mut self) {
         info!(sl!(), "QemuInner::disconnect()");
-        todo!()
+        self.qmp = None;
+        self.state = VmmState::NotReady;
     }
 
-    pub(crate) async fn get_thread_ids(&self) -> Result<VcpuThreadIds> {
+    pub(crate) async fn get_thread_ids(&mut self) -> Result<VcpuThreadIds> {
         info!(sl!(), "QemuInner::get_thread_ids()");
-        todo!()
+        let cpus = self
+            .qmp()
+            .await?
+            .query_cpus_fast()
+            .await
+            .context("query vcpu threads")?;
+
+        let vcpus: HashMap<u32, u32> = cpus
+            .into_iter()
+            .map(|cpu| (cpu.cpu_index, cpu.thread_id))
+            .collect();
+        info!(sl!(), "get thread ids {:?}", vcpus);
+
+        Ok(VcpuThreadIds { vcpus })
     }
 
     pub(crate) async fn get_vmm_master_tid(&self) -> Result<u32> {
         info!(sl!(), "QemuInner::get_vmm_master_tid()");
-        todo!()
+        self.pid
+            .ok_or_else(|| anyhow!("could not get vmm master tid"))
     }
 
     pub(crate) async fn get_ns_path(&self) -> Result<String> {
         info!(sl!(), "QemuInner::get_ns_path()");
-        todo!()
+        let pid = self.pid.ok_or_else(|| anyhow!("could not get ns path"))?;
+        Ok(format!("/proc/{}/ns", pid))
     }
 
     pub(crate) async fn cleanup(&self) -> Result<()> {
         info!(sl!(), "QemuInner::cleanup()");
-        todo!()
+        if self.vm_path.is_empty() {
+            return Ok(());
+        }
+
+        std::fs::remove_dir_all(&self.vm_path)
+            .map_err(|err| {
+                error!(sl!(), "failed to remove dir all for {}", &self.vm_path);
+                err
+            })
+            .ok();
+        Ok(())
     }
 
     pub(crate) async fn get_pids(&self) -> Result<Vec<u32>> {
         info!(sl!(), "QemuInner::get_pids()");
-        todo!()
+        Ok(self.pid.into_iter().collect())
     }
 
-    pub(crate) async fn check(&self) -> Result<()> {
-        todo!()
+    pub(crate) async fn check(&mut self) -> Result<()> {
+        let status = self
+            .qmp()
+            .await?
+            .query_status()
+            .await
+            .context("query status")?;
+        if status.status == "internal-error" || status.status == "guest-panicked" {
+            return Err(anyhow!("QEMU VM is in {} state", status.status));
+        }
+        Ok(())
     }
 
     pub(crate) async fn get_jailer_root(&self) -> Result<String> {
-        todo!()
+        let root_path = [self.vm_path.as_str(), QEMU_JAILER_DIR].join("/");
+        create_dir_all(&root_path).with_context(|| format!("failed to create {}", root_path))?;
+        Ok(root_path)
     }
 
     pub(crate) async fn capabilities(&self) -> Result<Capabilities> {
         let mut caps = Capabilities::default();
-        caps.set(CapabilityBits::FsSharingSupport);
+        caps.set(
+            CapabilityBits::BlockDeviceSupport
+                | CapabilityBits::BlockDeviceHotplugSupport
+                | CapabilityBits::FsSharingSupport
+                | CapabilityBits::NetQueueFdsSupport,
+        );
         Ok(caps)
     }
 
+    // resize_memory hot-adds a DIMM, backed like the boot memory, holding
+    // the memory missing to reach new_mem_mb. The guest is notified
+    // through ACPI.
+    pub(crate) async fn resize_memory(
+        &mut self,
+        new_mem_mb: u32,
+    ) -> Result<(u32, MemoryHotplugInfo)> {
+        let memory_info = &self.config.memory_info;
+        let current_mem_mb = memory_info.default_memory + self.hotplugged_memory_mb;
+
+        let max_mem_mb = get_host_memory_mb()
+            .context("get host memory")?
+            .min(u32::MAX as u64) as u32;
+        let size_mb = get_memory_hotplug_size_mb(current_mem_mb, new_mem_mb, max_mem_mb);
+        if size_mb == 0 {
+            return Ok((current_mem_mb, MemoryHotplugInfo::default()));
+        }
+        if self.hotplugged_memory_slots >= memory_info.memory_slots {
+            return Err(anyhow!(
+                "no memory slot left, {} slots already used",
+                self.hotplugged_memory_slots
+            ));
+        }
+
+        let mut props = json!({ "size": size_mb as u64 * MIB_TO_B });
+        let qom_type = match memory_backend_kind(&self.config) {
+            Some(MemoryBackendKind::File { path, share }) => {
+                props["mem-path"] = json!(path);
+                props["share"] = json!(share);
+                "memory-backend-file"
+            }
+            Some(MemoryBackendKind::Ram) | None => "memory-backend-ram",
+        };
+        if memory_info.enable_mem_prealloc {
+            props["prealloc"] = json!(true);
+        }
+
+        let slot = self.hotplugged_memory_slots;
+        let backend_id = format!("mem-hp{}", slot);
+        let dimm_id = format!("dimm-hp{}", slot);
+        info!(
+            sl!(),
+            "hot-add {} MiB of memory as {}, {} MiB in total",
+            size_mb,
+            dimm_id,
+            current_mem_mb + size_mb
+        );
+
+        let qmp = self.qmp().await?;
+        qmp.object_add(&backend_id, qom_type, props)
+            .await
+            .context("add memory backend")?;
+        if let Err(e) = qmp
+            .device_add(&dimm_id, "pc-dimm", json!({ "memdev": backend_id }))
+            .await
+        {
+            qmp.object_del(&backend_id).await.ok();
+            return Err(e).context("add memory device");
+        }
+
+        self.hotplugged_memory_mb += size_mb;
+        self.hotplugged_memory_slots += 1;
+
+        Ok((
+            current_mem_mb + size_mb,
+            MemoryHotplugInfo {
+                size_mb,
+                ..Default::default()
+            },
+        ))
+    }
+
+    // resize_vcpu plugs CPUs into the free slots reported by QEMU, and
+    // unplugs the last plugged ones first.
+    pub(crate) async fn resize_vcpu(&mut self, new_vcpus: u32) -> Result<(u32, u32)> {
+        let cpu_info = &self.config.cpu_info;
+        let boot_vcpus = cpu_info.default_vcpus.max(1) as u32;
+        let old_vcpus = boot_vcpus + self.hotplugged_vcpus.len() as u32;
+        let new_vcpus = get_resized_vcpus(new_vcpus, cpu_info);
+        if new_vcpus == old_vcpus {
+            return Ok((old_vcpus, new_vcpus));
+        }
+        info!(sl!(), "resize vcpu from {} to {}", old_vcpus, new_vcpus);
+
+        if new_vcpus < old_vcpus {
+            while boot_vcpus + (self.hotplugged_vcpus.len() as u32) > new_vcpus {
+                let id = self.hotplugged_vcpus[self.hotplugged_vcpus.len() - 1].clone();
+                self.qmp()
+                    .await?
+                    .device_del(&id)
+                    .await
+                    .with_context(|| format!("unplug cpu {}", id))?;
+                self.hotplugged_vcpus.pop();
+            }
+            return Ok((old_vcpus, new_vcpus));
+        }
+
+        let free_cpus: Vec<_> = self
+            .qmp()
+            .await?
+            .query_hotpluggable_cpus()
+            .await
+            .context("query hotpluggable cpus")?
+            .into_iter()
+            .filter(|cpu| cpu.qom_path.is_none() && cpu.vcpus_count == 1)
+            .collect();
+        for cpu in free_cpus.into_iter().take((new_vcpus - old_vcpus) as usize) {
+            let id = format!("cpu-hp{}", self.hotplugged_vcpus.len());
+            self.qmp()
+                .await?
+                .device_add(&id, &cpu.driver, cpu.props)
+                .await
+                .with_context(|| format!("plug cpu {}", id))?;
+            self.hotplugged_vcpus.push(id);
+        }
+
+        Ok((old_vcpus, boot_vcpus + self.hotplugged_vcpus.len() as u32))
+    }
+
     pub fn set_hypervisor_config(&mut self, config: HypervisorConfig) {
         self.config = config;
     }
@@ -131,6 +746,38 @@ impl QemuInner {
         info!(sl!(), "QemuInner::hypervisor_config()");
         self.config.clone()
     }
+
+    pub(crate) fn save(&self) -> HypervisorState {
+        HypervisorState {
+            hypervisor_type: HYPERVISOR_QEMU.to_string(),
+            pid: self.pid.map(|pid| pid as i32),
+            id: self.id.clone(),
+            vm_path: self.vm_path.clone(),
+            netns: self.netns.clone(),
+            config: self.hypervisor_config(),
+            run_dir: self.vm_path.clone(),
+            vsock_cid: self.vsock_cid,
+            hotplugged_memory_mb: self.hotplugged_memory_mb,
+            hotplugged_memory_slots: self.hotplugged_memory_slots,
+            hotplugged_vcpus: self.hotplugged_vcpus.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn restore(hypervisor_state: HypervisorState) -> Self {
+        QemuInner {
+            id: hypervisor_state.id,
+            vm_path: hypervisor_state.vm_path,
+            netns: hypervisor_state.netns,
+            config: hypervisor_state.config,
+            pid: hypervisor_state.pid.map(|pid| pid as u32),
+            vsock_cid: hypervisor_state.vsock_cid,
+            hotplugged_memory_mb: hypervisor_state.hotplugged_memory_mb,
+            hotplugged_memory_slots: hypervisor_state.hotplugged_memory_slots,
+            hotplugged_vcpus: hypervisor_state.hotplugged_vcpus,
+            ..QemuInner::new()
+        }
+    }
 }
 
 use crate::device::DeviceType;
@@ -139,11 +786,279 @@ use crate::device::DeviceType;
 impl QemuInner {
     pub(crate) async fn add_device(&mut self, device: DeviceType) -> Result<()> {
         info!(sl!(), "QemuInner::add_device() {}", device);
-        todo!()
+
+        if self.state == VmmState::NotReady {
+            info!(sl!(), "VMM not ready, queueing device {}", device);
+
+            // add the pending device by reverse order, build_command()
+            // walks them backwards to cold-plug them in the right order.
+            self.pending_devices.insert(0, device);
+            return Ok(());
+        }
+
+        let direct = self.config.blockdev_info.block_device_cache_direct;
+        let no_flush = self.config.blockdev_info.block_device_cache_noflush;
+        let queues = self.config.network_info.network_queues;
+        let qmp = self.qmp().await?;
+
+        match device {
+            DeviceType::Block(block) => {
+                let node_name = qemu_node_name(&block.device_id);
+                qmp.blockdev_add(
+                    &node_name,
+                    &block.config.path_on_host,
+                    block.config.is_readonly,
+                    direct,
+                    no_flush,
+                )
+                .await
+                .context("add block backend")?;
+
+                if let Err(e) = qmp
+                    .device_add(
+                        &qemu_device_id(&block.device_id),
+                        "virtio-blk-pci",
+                        json!({ "drive": node_name }),
+                    )
+                    .await
+                {
+                    qmp.blockdev_del(&node_name).await.ok();
+                    return Err(e).context("add block device");
+                }
+                Ok(())
+            }
+            DeviceType::Network(network) => {
+                let netdev_id = qemu_netdev_id(&network.id);
+                let queues = if network.config.queue_fds.is_empty() {
+                    qmp.netdev_add_tap(&netdev_id, &network.config.host_dev_name, queues)
+                        .await
+                        .context("add network backend")?;
+                    queues
+                } else {
+                    qmp.netdev_add_tap_fds(&netdev_id, &network.config.queue_fds)
+                        .await
+                        .context("add network backend")?;
+                    network.config.queue_fds.len() as u32
+                };
+
+                let mut args = json!({ "netdev": netdev_id });
+                if let Some(mac) = &network.config.guest_mac {
+                    args["mac"] = json!(format!("{:?}", mac));
+                }
+                if queues > 1 {
+                    args["mq"] = json!(true);
+                    args["vectors"] = json!(2 * queues + 2);
+                }
+                if let Err(e) = qmp
+                    .device_add(&qemu_device_id(&network.id), "virtio-net-pci", args)
+                    .await
+                {
+                    qmp.netdev_del(&netdev_id).await.ok();
+                    return Err(e).context("add network device");
+                }
+                Ok(())
+            }
+            _ => Err(anyhow!("QEMU doesn't support adding device {}", device)),
+        }
     }
 
     pub(crate) async fn remove_device(&mut self, device: DeviceType) -> Result<()> {
         info!(sl!(), "QemuInner::remove_device() {} ", device);
-        todo!()
+        let qmp = self.qmp().await?;
+
+        match device {
+            DeviceType::Block(block) => {
+                qmp.device_del(&qemu_device_id(&block.device_id))
+                    .await
+                    .context("remove block device")?;
+                qmp.blockdev_del(&qemu_node_name(&block.device_id))
+                    .await
+                    .context("remove block backend")
+            }
+            DeviceType::Network(network) => {
+                qmp.device_del(&qemu_device_id(&network.id))
+                    .await
+                    .context("remove network device")?;
+                qmp.netdev_del(&qemu_netdev_id(&network.id))
+                    .await
+                    .context("remove network backend")
+            }
+            _ => Err(anyhow!("QEMU doesn't support removing device {}", device)),
+        }
+    }
+}
+
+fn is_vsock(device: &DeviceType) -> bool {
+    matches!(device, DeviceType::Vsock(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qemu::qmp::tests::fake_qmp_server;
+
+    #[actix_rt::test]
+    async fn test_qemu_inner_qmp() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut qemu = QemuInner::new();
+        qemu.vm_path = dir.path().to_str().unwrap().to_string();
+        qemu.state = VmmState::VmRunning;
+
+        let server = fake_qmp_server(&qemu.qmp_socket_path(), |command, _| match command {
+            "query-cpus-fast" => vec![r#"{"return": [
+                {"thread-id": 2001, "qom-path": "/machine/unattached/device[0]", "cpu-index": 0},
+                {"thread-id": 2002, "qom-path": "/machine/unattached/device[2]", "cpu-index": 1}]}"#
+                .replace('\n', "")],
+            "query-status" => {
+                vec![r#"{"return": {"status": "guest-panicked", "running": false}}"#.to_string()]
+            }
+            _ => vec![r#"{"return": {}}"#.to_string()],
+        });
+
+        let ids = qemu.get_thread_ids().await.unwrap();
+        assert_eq!(ids.vcpus.len(), 2);
+        assert_eq!(ids.vcpus.get(&0), Some(&2001));
+        assert_eq!(ids.vcpus.get(&1), Some(&2002));
+
+        qemu.pause_vm().await.unwrap();
+        qemu.resume_vm().await.unwrap();
+        assert!(qemu.check().await.is_err());
+
+        qemu.disconnect().await;
+        let commands = server.join().unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                "qmp_capabilities",
+                "query-cpus-fast",
+                "stop",
+                "cont",
+                "query-status"
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_qemu_inner_resize_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut qemu = QemuInner::new();
+        qemu.vm_path = dir.path().to_str().unwrap().to_string();
+        qemu.state = VmmState::VmRunning;
+        qemu.config.memory_info.default_memory = 128;
+        qemu.config.memory_info.memory_slots = 1;
+
+        let server = fake_qmp_server(&qemu.qmp_socket_path(), |command, args| {
+            match command {
+                "object-add" => {
+                    assert_eq!(args["qom-type"], "memory-backend-ram");
+                    assert_eq!(args["id"], "mem-hp0");
+                    assert_eq!(args["size"], 256 * MIB_TO_B);
+                }
+                "device_add" => {
+                    assert_eq!(args["driver"], "pc-dimm");
+                    assert_eq!(args["memdev"], "mem-hp0");
+                }
+                _ => {}
+            }
+            vec![r#"{"return": {}}"#.to_string()]
+        });
+
+        // Shrinking is a no-op.
+        let (mem_mb, info) = qemu.resize_memory(64).await.unwrap();
+        assert_eq!(mem_mb, 128);
+        assert_eq!(info, MemoryHotplugInfo::default());
+
+        let (mem_mb, info) = qemu.resize_memory(300).await.unwrap();
+        assert_eq!(mem_mb, 384);
+        assert_eq!(info.size_mb, 256);
+        assert!(!info.probe);
+
+        // The only memory slot is used.
+        assert!(qemu.resize_memory(1024).await.is_err());
+
+        qemu.disconnect().await;
+        let commands = server.join().unwrap();
+        assert_eq!(
+            commands,
+            vec!["qmp_capabilities", "object-add", "device_add"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_qemu_inner_resize_vcpu() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut qemu = QemuInner::new();
+        qemu.vm_path = dir.path().to_str().unwrap().to_string();
+        qemu.state = VmmState::VmRunning;
+        qemu.config.cpu_info.default_vcpus = 1;
+        qemu.config.cpu_info.default_maxvcpus = 3;
+
+        let server = fake_qmp_server(&qemu.qmp_socket_path(), |command, args| {
+            match command {
+            "query-hotpluggable-cpus" => vec![r#"{"return": [
+                {"props": {"core-id": 2, "thread-id": 0, "socket-id": 0}, "vcpus-count": 1, "type": "host-x86_64-cpu"},
+                {"props": {"core-id": 1, "thread-id": 0, "socket-id": 0}, "vcpus-count": 1, "type": "host-x86_64-cpu"},
+                {"props": {"core-id": 0, "thread-id": 0, "socket-id": 0}, "vcpus-count": 1, "qom-path": "/machine/unattached/device[0]", "type": "host-x86_64-cpu"}]}"#
+                .replace('\n', "")],
+            "device_add" => {
+                assert_eq!(args["driver"], "host-x86_64-cpu");
+                assert_eq!(args["core-id"], 2);
+                vec![r#"{"return": {}}"#.to_string()]
+            }
+            "device_del" => vec![
+                r#"{"return": {}}"#.to_string(),
+                format!(
+                    r#"{{"event": "DEVICE_DELETED", "data": {{"device": {}}}}}"#,
+                    args["id"]
+                ),
+            ],
+            _ => vec![r#"{"return": {}}"#.to_string()],
+        }
+        });
+
+        // The boot vCPU can't be unplugged.
+        assert_eq!(qemu.resize_vcpu(0).await.unwrap(), (1, 1));
+        assert_eq!(qemu.resize_vcpu(2).await.unwrap(), (1, 2));
+        assert_eq!(qemu.hotplugged_vcpus, vec!["cpu-hp0"]);
+        assert_eq!(qemu.resize_vcpu(1).await.unwrap(), (2, 1));
+        assert!(qemu.hotplugged_vcpus.is_empty());
+
+        qemu.disconnect().await;
+        let commands = server.join().unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                "qmp_capabilities",
+                "query-hotpluggable-cpus",
+                "device_add",
+                "device_del"
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_qemu_inner_vsock_cid_persist() {
+        let mut qemu = QemuInner::new();
+        assert!(qemu.get_agent_socket().await.is_err());
+
+        qemu.vsock_cid = Some(42);
+        let restored = QemuInner::restore(qemu.save());
+        assert_eq!(
+            restored.get_agent_socket().await.unwrap(),
+            "vsock://42:1024"
+        );
+    }
+
+    #[test]
+    fn test_qemu_inner_hotplug_persist() {
+        let mut qemu = QemuInner::new();
+        qemu.hotplugged_memory_mb = 512;
+        qemu.hotplugged_memory_slots = 2;
+        qemu.hotplugged_vcpus = vec!["cpu-hp0".to_string()];
+
+        let restored = QemuInner::restore(qemu.save());
+        assert_eq!(restored.hotplugged_memory_mb, 512);
+        assert_eq!(restored.hotplugged_memory_slots, 2);
+        assert_eq!(restored.hotplugged_vcpus, vec!["cpu-hp0"]);
     }
 }
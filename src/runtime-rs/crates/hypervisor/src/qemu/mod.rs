@@ -3,16 +3,19 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+mod cmdline_generator;
 mod inner;
+mod qmp;
 
 use crate::device::DeviceType;
 use crate::hypervisor_persist::HypervisorState;
 use crate::Hypervisor;
-use crate::{HypervisorConfig, VcpuThreadIds};
+use crate::{BalloonStats, HypervisorConfig, MemoryHotplugInfo, VcpuThreadIds};
 use inner::QemuInner;
 use kata_types::capabilities::Capabilities;
+use persist::sandbox_persist::Persist;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 
 use std::sync::Arc;
@@ -55,21 +58,21 @@ impl Hypervisor for Qemu {
 
     async fn stop_vm(&self) -> Result<()> {
         let mut inner = self.inner.write().await;
-        inner.stop_vm()
+        inner.stop_vm().await
     }
 
     async fn pause_vm(&self) -> Result<()> {
-        let inner = self.inner.read().await;
-        inner.pause_vm()
+        let mut inner = self.inner.write().await;
+        inner.pause_vm().await
     }
 
     async fn resume_vm(&self) -> Result<()> {
-        let inner = self.inner.read().await;
-        inner.resume_vm()
+        let mut inner = self.inner.write().await;
+        inner.resume_vm().await
     }
 
     async fn save_vm(&self) -> Result<()> {
-        let inner = self.inner.read().await;
+        let mut inner = self.inner.write().await;
         inner.save_vm().await
     }
 
@@ -83,6 +86,32 @@ impl Hypervisor for Qemu {
         inner.remove_device(device).await
     }
 
+    async fn resize_memory(&self, new_mem_mb: u32) -> Result<(u32, MemoryHotplugInfo)> {
+        let mut inner = self.inner.write().await;
+        inner.resize_memory(new_mem_mb).await
+    }
+
+    async fn resize_vcpu(&self, new_vcpus: u32) -> Result<(u32, u32)> {
+        let mut inner = self.inner.write().await;
+        let vcpus = inner.resize_vcpu(new_vcpus).await?;
+        // hot-plugged vCPUs are pinned like the boot ones
+        inner.pin_vcpus().await;
+        Ok(vcpus)
+    }
+
+    async fn set_numa_topology(&self, cpus: &str) -> Result<()> {
+        let mut inner = self.inner.write().await;
+        inner.set_numa_topology(cpus)
+    }
+
+    async fn get_balloon_stats(&self) -> Result<BalloonStats> {
+        Err(anyhow!("balloon statistics are not supported by qemu"))
+    }
+
+    async fn get_hypervisor_metrics(&self) -> Result<String> {
+        Err(anyhow!("hypervisor metrics are not supported by qemu"))
+    }
+
     async fn get_agent_socket(&self) -> Result<String> {
         let inner = self.inner.read().await;
         inner.get_agent_socket().await
@@ -99,7 +128,7 @@ impl Hypervisor for Qemu {
     }
 
     async fn get_thread_ids(&self) -> Result<VcpuThreadIds> {
-        let inner = self.inner.read().await;
+        let mut inner = self.inner.write().await;
         inner.get_thread_ids().await
     }
 
@@ -124,7 +153,7 @@ impl Hypervisor for Qemu {
     }
 
     async fn check(&self) -> Result<()> {
-        let inner = self.inner.read().await;
+        let mut inner = self.inner.write().await;
         inner.check().await
     }
 
@@ -134,11 +163,39 @@ impl Hypervisor for Qemu {
     }
 
     async fn save_state(&self) -> Result<HypervisorState> {
-        todo!()
+        self.save().await
     }
 
     async fn capabilities(&self) -> Result<Capabilities> {
         let inner = self.inner.read().await;
         inner.capabilities().await
     }
+
+    async fn dump_guest_memory(&self) -> Result<()> {
+        let mut inner = self.inner.write().await;
+        inner.dump_guest_memory().await
+    }
+}
+
+#[async_trait]
+impl Persist for Qemu {
+    type State = HypervisorState;
+    type ConstructorArgs = ();
+
+    /// Save a state of the component.
+    async fn save(&self) -> Result<Self::State> {
+        let inner = self.inner.read().await;
+        Ok(inner.save())
+    }
+
+    /// Restore a component from a specified state.
+    async fn restore(
+        _hypervisor_args: Self::ConstructorArgs,
+        hypervisor_state: Self::State,
+    ) -> Result<Self> {
+        let inner = QemuInner::restore(hypervisor_state);
+        Ok(Self {
+            inner: Arc::new(RwLock::new(inner)),
+        })
+    }
 }
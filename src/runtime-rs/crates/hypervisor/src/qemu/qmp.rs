@@ -0,0 +1,710 @@
+// Copyright (c) 2023 Red Hat
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::io::{ErrorKind, IoSlice};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags, UnixAddr};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Interest};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use tokio::time::{timeout_at, Instant};
+
+// Upper bound for a single QMP command round trip. Commands which may take
+// longer (e.g. migration) are polled by the caller instead.
+const QMP_COMMAND_TIMEOUT_MS: u64 = 5000;
+
+// Upper bound for the guest to release a device once asked to.
+const QMP_DEVICE_DEL_TIMEOUT_MS: u64 = 10000;
+
+// Number of asynchronous events kept around for wait_event().
+const QMP_MAX_PENDING_EVENTS: usize = 64;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct QmpVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub micro: u32,
+}
+
+/// Response entry of the `query-cpus-fast` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CpuInfoFast {
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: u32,
+    #[serde(rename = "thread-id")]
+    pub thread_id: u32,
+    #[serde(rename = "qom-path", default)]
+    pub qom_path: String,
+}
+
+/// Response entry of the `query-hotpluggable-cpus` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HotpluggableCpu {
+    #[serde(rename = "type")]
+    pub driver: String,
+    #[serde(rename = "vcpus-count")]
+    pub vcpus_count: u32,
+    /// Topology properties (socket-id, core-id...) to plug the CPU with.
+    pub props: Value,
+    /// Set once the CPU is plugged.
+    #[serde(rename = "qom-path")]
+    pub qom_path: Option<String>,
+}
+
+/// Response of the `query-status` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusInfo {
+    pub running: bool,
+    pub status: String,
+}
+
+/// Response of the `query-migrate` command.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MigrationStatus {
+    #[serde(default)]
+    pub status: String,
+}
+
+/// Response of the `query-dump` command.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DumpStatus {
+    #[serde(default)]
+    pub status: String,
+    /// Number of bytes written so far.
+    #[serde(default)]
+    pub completed: u64,
+    /// Number of bytes to write.
+    #[serde(default)]
+    pub total: u64,
+}
+
+/// A QMP client talking to QEMU over its unix socket.
+///
+/// Commands are executed one at a time: the reply to a command is the first
+/// `return` or `error` message following it, asynchronous events received
+/// in between are kept so that they can be waited for later on.
+pub struct Qmp {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    version: QmpVersion,
+    events: Vec<Value>,
+}
+
+impl std::fmt::Debug for Qmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Qmp")
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+impl Qmp {
+    /// Connect to the QMP socket at `path`, consume the greeting and enter
+    /// command mode.
+    pub async fn new(path: &str) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("connect to QMP socket {}", path))?;
+        let (reader, writer) = stream.into_split();
+
+        let mut qmp = Qmp {
+            reader: BufReader::new(reader),
+            writer,
+            version: QmpVersion::default(),
+            events: vec![],
+        };
+
+        let greeting = qmp
+            .read_message(command_deadline())
+            .await
+            .context("read QMP greeting")?;
+        let version = greeting
+            .pointer("/QMP/version/qemu")
+            .ok_or_else(|| anyhow!("invalid QMP greeting {}", greeting))?;
+        qmp.version = serde_json::from_value(version.clone()).context("parse QMP version")?;
+        info!(sl!(), "connected to QMP, qemu version {:?}", qmp.version);
+
+        qmp.execute("qmp_capabilities", None)
+            .await
+            .context("negotiate QMP capabilities")?;
+
+        Ok(qmp)
+    }
+
+    // Read the next message, giving up at `deadline`. A message partially
+    // read by then is lost, the connection shouldn't be used anymore.
+    async fn read_message(&mut self, deadline: Instant) -> Result<Value> {
+        let mut line = String::new();
+        let len = timeout_at(deadline, self.reader.read_line(&mut line))
+            .await
+            .map_err(|_| anyhow!(std::io::Error::from(ErrorKind::TimedOut)))
+            .and_then(|ret| ret.map_err(|e| anyhow!(e)))
+            .context("read QMP")?;
+        if len == 0 {
+            return Err(anyhow!(std::io::Error::from(ErrorKind::UnexpectedEof))
+                .context("QMP connection closed"));
+        }
+        serde_json::from_str(&line).with_context(|| format!("parse QMP message {:?}", line))
+    }
+
+    fn request(command: &str, arguments: Option<Value>) -> Result<Vec<u8>> {
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+        debug!(sl!(), "QMP request {}", request);
+
+        let mut buf = serde_json::to_vec(&request)?;
+        buf.push(b'\n');
+        Ok(buf)
+    }
+
+    /// Execute `command` with optional `arguments` and return the content
+    /// of the `return` member of the reply.
+    pub async fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        let buf = Self::request(command, arguments)?;
+        self.writer
+            .write_all(&buf)
+            .await
+            .with_context(|| format!("send QMP command {}", command))?;
+        self.wait_reply(command).await
+    }
+
+    /// Execute `command` like execute(), passing `fd` along with it.
+    async fn execute_with_fd(
+        &mut self,
+        command: &str,
+        arguments: Option<Value>,
+        fd: RawFd,
+    ) -> Result<Value> {
+        let buf = Self::request(command, arguments)?;
+        let fds = [fd];
+        let stream: &UnixStream = self.writer.as_ref();
+        let sent = loop {
+            stream
+                .writable()
+                .await
+                .with_context(|| format!("send QMP command {}", command))?;
+            let ret = stream.try_io(Interest::WRITABLE, || {
+                sendmsg::<UnixAddr>(
+                    stream.as_raw_fd(),
+                    &[IoSlice::new(&buf)],
+                    &[ControlMessage::ScmRights(&fds)],
+                    MsgFlags::empty(),
+                    None,
+                )
+                .map_err(std::io::Error::from)
+            });
+            match ret {
+                Ok(sent) => break sent,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e).with_context(|| format!("send QMP command {}", command)),
+            }
+        };
+        // the fd went along with the first bytes
+        self.writer
+            .write_all(&buf[sent..])
+            .await
+            .with_context(|| format!("send QMP command {}", command))?;
+        self.wait_reply(command).await
+    }
+
+    async fn wait_reply(&mut self, command: &str) -> Result<Value> {
+        let deadline = command_deadline();
+        loop {
+            let message = self
+                .read_message(deadline)
+                .await
+                .with_context(|| format!("wait for reply to QMP command {}", command))?;
+
+            if let Some(ret) = message.get("return") {
+                return Ok(ret.clone());
+            }
+            if let Some(error) = message.get("error") {
+                let class = error.get("class").and_then(Value::as_str).unwrap_or("");
+                let desc = error.get("desc").and_then(Value::as_str).unwrap_or("");
+                return Err(anyhow!(
+                    "QMP command {} failed: {}: {}",
+                    command,
+                    class,
+                    desc
+                ));
+            }
+            if message.get("event").is_some() {
+                self.push_event(message);
+                continue;
+            }
+
+            warn!(sl!(), "unexpected QMP message {}", message);
+        }
+    }
+
+    fn push_event(&mut self, event: Value) {
+        debug!(sl!(), "QMP event {}", event);
+        if self.events.len() >= QMP_MAX_PENDING_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    /// Wait for an event called `name` whose `data` member satisfies
+    /// `matches`, either already received or arriving within `timeout`.
+    pub async fn wait_event<F>(
+        &mut self,
+        name: &str,
+        matches: F,
+        timeout: Duration,
+    ) -> Result<Value>
+    where
+        F: Fn(&Value) -> bool,
+    {
+        let is_match = |event: &Value| event["event"] == name && matches(&event["data"]);
+
+        if let Some(pos) = self.events.iter().position(is_match) {
+            return Ok(self.events.remove(pos));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let message = self
+                .read_message(deadline)
+                .await
+                .with_context(|| format!("wait for QMP event {}", name))?;
+            if is_match(&message) {
+                return Ok(message);
+            }
+            if message.get("event").is_some() {
+                self.push_event(message);
+            } else {
+                warn!(sl!(), "unexpected QMP message {}", message);
+            }
+        }
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        self.execute("stop", None).await.map(|_| ())
+    }
+
+    pub async fn cont(&mut self) -> Result<()> {
+        self.execute("cont", None).await.map(|_| ())
+    }
+
+    /// Ask QEMU to exit. QEMU may close the connection before or right after
+    /// acknowledging the command, both are treated as success.
+    pub async fn quit(&mut self) -> Result<()> {
+        match self.execute("quit", None).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_eof(&e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn query_status(&mut self) -> Result<StatusInfo> {
+        let ret = self.execute("query-status", None).await?;
+        serde_json::from_value(ret).context("parse query-status reply")
+    }
+
+    pub async fn query_cpus_fast(&mut self) -> Result<Vec<CpuInfoFast>> {
+        let ret = self.execute("query-cpus-fast", None).await?;
+        serde_json::from_value(ret).context("parse query-cpus-fast reply")
+    }
+
+    pub async fn query_hotpluggable_cpus(&mut self) -> Result<Vec<HotpluggableCpu>> {
+        let ret = self.execute("query-hotpluggable-cpus", None).await?;
+        serde_json::from_value(ret).context("parse query-hotpluggable-cpus reply")
+    }
+
+    pub async fn blockdev_add(
+        &mut self,
+        node_name: &str,
+        path: &str,
+        read_only: bool,
+        direct: bool,
+        no_flush: bool,
+    ) -> Result<()> {
+        let file_driver = match tokio::fs::metadata(path).await {
+            Ok(m) if std::os::unix::fs::FileTypeExt::is_block_device(&m.file_type()) => {
+                "host_device"
+            }
+            _ => "file",
+        };
+        let args = json!({
+            "node-name": node_name,
+            "driver": "raw",
+            "read-only": read_only,
+            "cache": {
+                "direct": direct,
+                "no-flush": no_flush,
+            },
+            "file": {
+                "driver": file_driver,
+                "filename": path,
+            },
+        });
+        self.execute("blockdev-add", Some(args)).await.map(|_| ())
+    }
+
+    pub async fn blockdev_del(&mut self, node_name: &str) -> Result<()> {
+        self.execute("blockdev-del", Some(json!({ "node-name": node_name })))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn netdev_add_tap(
+        &mut self,
+        netdev_id: &str,
+        ifname: &str,
+        queues: u32,
+    ) -> Result<()> {
+        let mut args = json!({
+            "type": "tap",
+            "id": netdev_id,
+            "ifname": ifname,
+            "script": "no",
+            "downscript": "no",
+            "vhost": true,
+        });
+        if queues > 1 {
+            args["queues"] = json!(queues);
+        }
+        self.execute("netdev_add", Some(args)).await.map(|_| ())
+    }
+
+    /// Hand `fd` over to QEMU, it can then be referred to as `fd_name`.
+    pub async fn getfd(&mut self, fd_name: &str, fd: RawFd) -> Result<()> {
+        self.execute_with_fd("getfd", Some(json!({ "fdname": fd_name })), fd)
+            .await
+            .map(|_| ())
+    }
+
+    /// Add a tap backend using the already opened queues `fds`.
+    pub async fn netdev_add_tap_fds(&mut self, netdev_id: &str, fds: &[RawFd]) -> Result<()> {
+        let mut fd_names = vec![];
+        for (i, fd) in fds.iter().enumerate() {
+            let fd_name = format!("{}-fd{}", netdev_id, i);
+            self.getfd(&fd_name, *fd)
+                .await
+                .with_context(|| format!("pass queue fd {}", fd))?;
+            fd_names.push(fd_name);
+        }
+        let args = json!({
+            "type": "tap",
+            "id": netdev_id,
+            "fds": fd_names.join(":"),
+            "vhost": true,
+        });
+        self.execute("netdev_add", Some(args)).await.map(|_| ())
+    }
+
+    pub async fn netdev_del(&mut self, netdev_id: &str) -> Result<()> {
+        self.execute("netdev_del", Some(json!({ "id": netdev_id })))
+            .await
+            .map(|_| ())
+    }
+
+    /// Add the guest side of a device. `args` holds the driver specific
+    /// properties, `id` and `driver` are filled in here.
+    pub async fn device_add(&mut self, id: &str, driver: &str, mut args: Value) -> Result<()> {
+        args["id"] = json!(id);
+        args["driver"] = json!(driver);
+        self.execute("device_add", Some(args)).await.map(|_| ())
+    }
+
+    /// Remove a device and wait for the guest to release it, which is
+    /// signalled by the DEVICE_DELETED event.
+    pub async fn device_del(&mut self, id: &str) -> Result<()> {
+        self.execute("device_del", Some(json!({ "id": id })))
+            .await?;
+        self.wait_event(
+            "DEVICE_DELETED",
+            |data| data["device"] == id,
+            Duration::from_millis(QMP_DEVICE_DEL_TIMEOUT_MS),
+        )
+        .await
+        .with_context(|| format!("wait for device {} to be released", id))
+        .map(|_| ())
+    }
+
+    /// Create a QOM object, e.g. a memory backend. `props` holds the object
+    /// specific properties, `id` and `qom-type` are filled in here.
+    pub async fn object_add(&mut self, id: &str, qom_type: &str, mut props: Value) -> Result<()> {
+        props["id"] = json!(id);
+        props["qom-type"] = json!(qom_type);
+        self.execute("object-add", Some(props)).await.map(|_| ())
+    }
+
+    pub async fn object_del(&mut self, id: &str) -> Result<()> {
+        self.execute("object-del", Some(json!({ "id": id })))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn migrate(&mut self, uri: &str) -> Result<()> {
+        self.execute("migrate", Some(json!({ "uri": uri })))
+            .await
+            .map(|_| ())
+    }
+
+    /// Start an incoming migration in a QEMU started with `-incoming defer`.
+    pub async fn migrate_incoming(&mut self, uri: &str) -> Result<()> {
+        self.execute("migrate-incoming", Some(json!({ "uri": uri })))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn migrate_set_capability(&mut self, capability: &str, state: bool) -> Result<()> {
+        let args = json!({
+            "capabilities": [{ "capability": capability, "state": state }],
+        });
+        self.execute("migrate-set-capabilities", Some(args))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn query_migrate(&mut self) -> Result<MigrationStatus> {
+        let ret = self.execute("query-migrate", None).await?;
+        serde_json::from_value(ret).context("parse query-migrate reply")
+    }
+
+    /// Start dumping the guest memory into the ELF core file at `path` in
+    /// the background, its progress is reported by `query-dump`. With
+    /// `paging`, the guest page tables are used to fill in the virtual
+    /// addresses of the segments.
+    pub async fn dump_guest_memory(&mut self, path: &str, paging: bool) -> Result<()> {
+        let args = json!({
+            "paging": paging,
+            "protocol": format!("file:{}", path),
+            "format": "elf",
+            "detach": true,
+        });
+        self.execute("dump-guest-memory", Some(args))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn query_dump(&mut self) -> Result<DumpStatus> {
+        let ret = self.execute("query-dump", None).await?;
+        serde_json::from_value(ret).context("parse query-dump reply")
+    }
+}
+
+fn command_deadline() -> Instant {
+    Instant::now() + Duration::from_millis(QMP_COMMAND_TIMEOUT_MS)
+}
+
+fn is_eof(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .map_or(false, |io| io.kind() == ErrorKind::UnexpectedEof)
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread::JoinHandle;
+
+    /// A fake QMP server handling a single connection. `handler` maps a
+    /// command name and its arguments to the raw reply lines to send back.
+    pub(crate) fn fake_qmp_server<F>(path: &str, mut handler: F) -> JoinHandle<Vec<String>>
+    where
+        F: FnMut(&str, &Value) -> Vec<String> + Send + 'static,
+    {
+        let listener = UnixListener::bind(path).unwrap();
+        std::thread::spawn(move || {
+            let mut commands = vec![];
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            writeln!(
+                writer,
+                r#"{{"QMP": {{"version": {{"qemu": {{"micro": 1, "minor": 2, "major": 7}}, "package": ""}}, "capabilities": ["oob"]}}}}"#
+            )
+            .unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let command = request["execute"].as_str().unwrap().to_string();
+                let replies = if command == "qmp_capabilities" {
+                    vec![r#"{"return": {}}"#.to_string()]
+                } else {
+                    handler(&command, &request["arguments"])
+                };
+                commands.push(command);
+                for reply in replies {
+                    writeln!(writer, "{}", reply).unwrap();
+                }
+            }
+            commands
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_qmp_handshake_and_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qmp.sock");
+        let path = path.to_str().unwrap();
+
+        let server = fake_qmp_server(path, |command, args| {
+            match command {
+            "query-status" => vec![
+                r#"{"event": "RESUME", "data": {}, "timestamp": {"seconds": 1, "microseconds": 2}}"#
+                    .to_string(),
+                r#"{"return": {"status": "running", "singlestep": false, "running": true}}"#
+                    .to_string(),
+            ],
+            "query-cpus-fast" => vec![r#"{"return": [
+                {"thread-id": 1001, "props": {"core-id": 0}, "qom-path": "/machine/unattached/device[0]", "cpu-index": 0, "target": "x86_64"},
+                {"thread-id": 1002, "props": {"core-id": 1}, "qom-path": "/machine/unattached/device[1]", "cpu-index": 1, "target": "x86_64"}]}"#
+                .replace('\n', "")],
+            "query-dump" => vec![
+                r#"{"return": {"status": "active", "completed": 1024, "total": 4096}}"#.to_string(),
+            ],
+            "device_del" if args["id"] == "missing" => {
+                vec![r#"{"error": {"class": "DeviceNotFound", "desc": "Device 'missing' not found"}}"#
+                    .to_string()]
+            }
+            "device_del" => vec![
+                r#"{"return": {}}"#.to_string(),
+                r#"{"event": "DEVICE_DELETED", "data": {"path": "/machine/peripheral/other/virtio-backend"}}"#
+                    .to_string(),
+                r#"{"event": "DEVICE_DELETED", "data": {"device": "other", "path": "/machine/peripheral/other"}}"#
+                    .to_string(),
+                r#"{"event": "DEVICE_DELETED", "data": {"device": "virtio-1", "path": "/machine/peripheral/virtio-1"}}"#
+                    .to_string(),
+            ],
+            _ => vec![r#"{"return": {}}"#.to_string()],
+        }
+        });
+
+        let mut qmp = Qmp::new(path).await.unwrap();
+        assert_eq!(
+            qmp.version,
+            QmpVersion {
+                major: 7,
+                minor: 2,
+                micro: 1
+            }
+        );
+
+        let status = qmp.query_status().await.unwrap();
+        assert!(status.running);
+        assert_eq!(status.status, "running");
+
+        let cpus = qmp.query_cpus_fast().await.unwrap();
+        assert_eq!(cpus.len(), 2);
+        assert_eq!(cpus[1].cpu_index, 1);
+        assert_eq!(cpus[1].thread_id, 1002);
+
+        let dump = qmp.query_dump().await.unwrap();
+        assert_eq!(dump.status, "active");
+        assert_eq!((dump.completed, dump.total), (1024, 4096));
+
+        let err = qmp.device_del("missing").await.unwrap_err();
+        assert!(format!("{}", err).contains("DeviceNotFound"));
+        qmp.device_del("virtio-1").await.unwrap();
+
+        qmp.stop().await.unwrap();
+        qmp.cont().await.unwrap();
+
+        let file = tempfile::tempfile().unwrap();
+        qmp.netdev_add_tap_fds("netdev-n1", &[file.as_raw_fd(), file.as_raw_fd()])
+            .await
+            .unwrap();
+        drop(qmp);
+
+        let commands = server.join().unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                "qmp_capabilities",
+                "query-status",
+                "query-cpus-fast",
+                "query-dump",
+                "device_del",
+                "device_del",
+                "stop",
+                "cont",
+                "getfd",
+                "getfd",
+                "netdev_add"
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_qmp_wait_event_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qmp.sock");
+        let path = path.to_str().unwrap();
+
+        // the guest never releases the device
+        let server = fake_qmp_server(path, |_, _| vec![r#"{"return": {}}"#.to_string()]);
+
+        let mut qmp = Qmp::new(path).await.unwrap();
+        qmp.execute("device_del", Some(json!({ "id": "virtio-1" })))
+            .await
+            .unwrap();
+        let err = qmp
+            .wait_event(
+                "DEVICE_DELETED",
+                |data| data["device"] == "virtio-1",
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.chain().any(|cause| cause
+            .downcast_ref::<std::io::Error>()
+            .map_or(false, |io| io.kind() == ErrorKind::TimedOut)));
+        drop(qmp);
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["qmp_capabilities", "device_del"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_qmp_quit_connection_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qmp.sock");
+        let path = path.to_str().unwrap();
+
+        // QEMU may go away without acknowledging "quit".
+        let listener = UnixListener::bind(path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            writeln!(
+                writer,
+                r#"{{"QMP": {{"version": {{"qemu": {{"micro": 0, "minor": 0, "major": 8}}}}, "capabilities": []}}}}"#
+            )
+            .unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            writeln!(writer, r#"{{"return": {{}}}}"#).unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.contains("quit"));
+        });
+
+        let mut qmp = Qmp::new(path).await.unwrap();
+        qmp.quit().await.unwrap();
+        server.join().unwrap();
+    }
+}
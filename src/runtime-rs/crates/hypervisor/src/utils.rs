@@ -5,6 +5,25 @@
 //
 
 use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use kata_types::config::hypervisor::CpuInfo;
+
+use crate::hypervisor_persist::HypervisorState;
+
+pub const MIB_TO_B: u64 = 1024 * 1024;
+
+// Guest memory is hot-added in blocks of this size, the memory block size of
+// x86_64 and aarch64 Linux guests.
+pub const MEMORY_BLOCK_SIZE_MB: u32 = 128;
+
+// The network rate limiters refill their token bucket every second.
+pub const NET_RATE_LIMITER_REFILL_TIME_MS: u64 = 1000;
+
+// Files of the guest memory dump directory describing the dumped VM.
+const GUEST_MEMORY_DUMP_CONFIG_FILE: &str = "hypervisor.json";
+const GUEST_MEMORY_DUMP_STATE_FILE: &str = "state.json";
 
 pub fn get_child_threads(pid: u32) -> HashSet<u32> {
     let mut result = HashSet::new();
@@ -25,3 +44,155 @@ pub fn get_child_threads(pid: u32) -> HashSet<u32> {
     }
     result
 }
+
+pub fn get_host_memory_mb() -> Result<u64> {
+    let info = nix::sys::sysinfo::sysinfo().context("get host memory size")?;
+    Ok(info.ram_total() / MIB_TO_B)
+}
+
+// Size in MiB of the memory to hot-add to grow the guest memory from
+// `current_mb` to at least `new_mb`, in memory blocks, without exceeding
+// `max_mb`. Returns 0 when the guest memory isn't to grow.
+pub fn get_memory_hotplug_size_mb(current_mb: u32, new_mb: u32, max_mb: u32) -> u32 {
+    if new_mb <= current_mb {
+        return 0;
+    }
+    let blocks = (new_mb - current_mb + MEMORY_BLOCK_SIZE_MB - 1) / MEMORY_BLOCK_SIZE_MB;
+    let max_blocks = max_mb.saturating_sub(current_mb) / MEMORY_BLOCK_SIZE_MB;
+    blocks.min(max_blocks) * MEMORY_BLOCK_SIZE_MB
+}
+
+// Number of vCPUs to resize the guest to for `new_vcpus` requested: at least
+// the boot vCPUs, which can't be unplugged, and at most the maximum vCPUs.
+pub fn get_resized_vcpus(new_vcpus: u32, cpu_info: &CpuInfo) -> u32 {
+    let boot_vcpus = cpu_info.default_vcpus.max(1) as u32;
+    new_vcpus.clamp(boot_vcpus, cpu_info.default_maxvcpus.max(boot_vcpus))
+}
+
+/// Get the size in bytes of the token bucket limiting the network bandwidth to `rate` bits/sec,
+/// refilled every NET_RATE_LIMITER_REFILL_TIME_MS, `None` for an unlimited bandwidth.
+pub fn get_net_rate_limiter_bucket_size(rate: u64) -> Option<u64> {
+    if rate == 0 {
+        return None;
+    }
+    // at least a byte, for the limiter to be enabled
+    Some((rate / 8 * NET_RATE_LIMITER_REFILL_TIME_MS / 1000).max(1))
+}
+
+// Directory to dump the guest memory of sandbox `id` into, under the
+// guest_memory_dump_path of the hypervisor config.
+pub fn get_guest_memory_dump_dir(dump_path: &str, id: &str) -> String {
+    [dump_path, id].join("/")
+}
+
+// Path of a new core file in the guest memory dump directory `dump_dir`.
+pub fn get_guest_memory_dump_file(dump_dir: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format!("{}/vmcore-{}.elf", dump_dir, timestamp)
+}
+
+// Save the hypervisor config and state into the guest memory dump directory
+// `dump_dir`, creating it if needed, for the core file to be analyzed along
+// with them.
+pub fn save_guest_memory_dump_info(dump_dir: &str, state: &HypervisorState) -> Result<()> {
+    std::fs::create_dir_all(dump_dir)
+        .with_context(|| format!("create guest memory dump dir {}", dump_dir))?;
+
+    let config = serde_json::to_vec_pretty(&state.config).context("serialize config")?;
+    let path = [dump_dir, GUEST_MEMORY_DUMP_CONFIG_FILE].join("/");
+    std::fs::write(&path, config).with_context(|| format!("write {}", path))?;
+
+    let state = serde_json::to_vec_pretty(state).context("serialize state")?;
+    let path = [dump_dir, GUEST_MEMORY_DUMP_STATE_FILE].join("/");
+    std::fs::write(&path, state).with_context(|| format!("write {}", path))?;
+
+    Ok(())
+}
+
+// Check that `dump_dir` has room for a dump of `memory_mb` MiB of guest
+// memory. Twice the guest memory size is required, so that a single dump
+// can't fill up the filesystem.
+pub fn check_guest_memory_dump_space(dump_dir: &str, memory_mb: u64) -> Result<()> {
+    let stat =
+        nix::sys::statvfs::statvfs(dump_dir).with_context(|| format!("statvfs {}", dump_dir))?;
+    let available = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    let required = memory_mb * MIB_TO_B * 2;
+    if available < required {
+        return Err(anyhow!(
+            "not enough space in {} to dump guest memory, {} bytes required but only {} available",
+            dump_dir,
+            required,
+            available
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_memory_hotplug_size_mb() {
+        // shrinking isn't supported
+        assert_eq!(get_memory_hotplug_size_mb(2048, 1024, 8192), 0);
+        assert_eq!(get_memory_hotplug_size_mb(2048, 2048, 8192), 0);
+        // aligned up to the memory block size
+        assert_eq!(get_memory_hotplug_size_mb(2048, 2049, 8192), 128);
+        assert_eq!(get_memory_hotplug_size_mb(2048, 2560, 8192), 512);
+        // capped by the maximum memory
+        assert_eq!(get_memory_hotplug_size_mb(2048, 16384, 8192), 6144);
+        assert_eq!(get_memory_hotplug_size_mb(2048, 4096, 2100), 0);
+    }
+
+    #[test]
+    fn test_get_net_rate_limiter_bucket_size() {
+        assert_eq!(get_net_rate_limiter_bucket_size(0), None);
+        assert_eq!(get_net_rate_limiter_bucket_size(4), Some(1));
+        assert_eq!(get_net_rate_limiter_bucket_size(8_000_000), Some(1_000_000));
+    }
+
+    #[test]
+    fn test_get_resized_vcpus() {
+        let cpu_info = CpuInfo {
+            default_vcpus: 2,
+            default_maxvcpus: 8,
+            ..Default::default()
+        };
+        assert_eq!(get_resized_vcpus(4, &cpu_info), 4);
+        assert_eq!(get_resized_vcpus(1, &cpu_info), 2);
+        assert_eq!(get_resized_vcpus(16, &cpu_info), 8);
+    }
+
+    #[test]
+    fn test_guest_memory_dump_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump_dir = get_guest_memory_dump_dir(dir.path().to_str().unwrap(), "sandbox-1");
+        assert!(get_guest_memory_dump_file(&dump_dir).starts_with(&format!("{}/vmcore-", dump_dir)));
+
+        let mut state = HypervisorState {
+            id: "sandbox-1".to_string(),
+            hypervisor_type: "qemu".to_string(),
+            ..Default::default()
+        };
+        state.config.memory_info.default_memory = 2048;
+        save_guest_memory_dump_info(&dump_dir, &state).unwrap();
+
+        let config: serde_json::Value = serde_json::from_slice(
+            &std::fs::read([&dump_dir, GUEST_MEMORY_DUMP_CONFIG_FILE].join("/")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(config["default_memory"], 2048);
+        let saved: HypervisorState = serde_json::from_slice(
+            &std::fs::read([&dump_dir, GUEST_MEMORY_DUMP_STATE_FILE].join("/")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved.id, "sandbox-1");
+
+        check_guest_memory_dump_space(&dump_dir, 0).unwrap();
+        check_guest_memory_dump_space(&dump_dir, u64::MAX / MIB_TO_B / 2).unwrap_err();
+    }
+}
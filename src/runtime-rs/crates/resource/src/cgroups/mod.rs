@@ -15,11 +15,15 @@ use std::{
     sync::Arc,
 };
 
+use agent::{Agent, GetGuestDetailsRequest, MemHotplugByProbeRequest, OnlineCPUMemRequest};
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use cgroup_persist::CgroupState;
-use cgroups_rs::{cgroup_builder::CgroupBuilder, Cgroup, CgroupPid, CpuResources, Resources};
-use hypervisor::Hypervisor;
+use cgroups_rs::{
+    cgroup_builder::CgroupBuilder, Cgroup, CgroupPid, CpuResources, HugePageResource,
+    HugePageResources, MemoryResources, Resources,
+};
+use hypervisor::{Hypervisor, MIB_TO_B};
 use kata_sys_util::spec::load_oci_spec;
 use kata_types::config::TomlConfig;
 use oci::LinuxResources;
@@ -62,6 +66,9 @@ pub struct CgroupsResource {
     cgroup_manager: Cgroup,
     overhead_cgroup_manager: Option<Cgroup>,
     cgroup_config: CgroupConfig,
+    // The sandbox resources are fixed, the guest memory isn't resized to fit
+    // the containers.
+    static_resource_mgmt: bool,
 }
 
 impl CgroupsResource {
@@ -106,6 +113,7 @@ impl CgroupsResource {
             resources: Arc::new(RwLock::new(HashMap::new())),
             overhead_cgroup_manager,
             cgroup_config: config,
+            static_resource_mgmt: toml_config.runtime.static_sandbox_resource_mgmt,
         })
     }
 
@@ -150,6 +158,7 @@ impl CgroupsResource {
         cid: &str,
         linux_resources: Option<&LinuxResources>,
         h: &dyn Hypervisor,
+        agent: &dyn Agent,
     ) -> Result<()> {
         let resource = self.calc_resource(linux_resources);
         let changed = self.update_resources(cid, resource).await;
@@ -158,9 +167,150 @@ impl CgroupsResource {
             return Ok(());
         }
 
+        if !self.static_resource_mgmt {
+            self.update_sandbox_vcpus(h, agent)
+                .await
+                .context("update sandbox vcpus")?;
+            self.update_sandbox_memory(h, agent)
+                .await
+                .context("update sandbox memory")?;
+        }
+
+        self.do_update_cgroups(h).await
+    }
+
+    /// delete_cgroups drops the resources of a removed container, shrinking
+    /// the sandbox vCPUs accordingly. The guest memory isn't shrunk.
+    pub async fn delete_cgroups(
+        &self,
+        cid: &str,
+        h: &dyn Hypervisor,
+        agent: &dyn Agent,
+    ) -> Result<()> {
+        if self.resources.write().await.remove(cid).is_none() {
+            return Ok(());
+        }
+
+        if !self.static_resource_mgmt {
+            self.update_sandbox_vcpus(h, agent)
+                .await
+                .context("update sandbox vcpus")?;
+        }
+
         self.do_update_cgroups(h).await
     }
 
+    /// update_sandbox_vcpus resizes the guest to fit the cpu quotas of all
+    /// the containers, on top of the default vCPUs.
+    async fn update_sandbox_vcpus(&self, h: &dyn Hypervisor, agent: &dyn Agent) -> Result<()> {
+        let default_vcpus = h.hypervisor_config().await.cpu_info.default_vcpus.max(0) as u32;
+        let new_vcpus = default_vcpus + self.total_vcpus().await;
+
+        let (old_vcpus, new_vcpus) = h.resize_vcpu(new_vcpus).await.context("resize vcpu")?;
+        if new_vcpus <= old_vcpus {
+            return Ok(());
+        }
+        info!(
+            sl!(),
+            "hot-plugged {} vcpus, guest has {} vcpus",
+            new_vcpus - old_vcpus,
+            new_vcpus
+        );
+
+        agent
+            .online_cpu_mem(OnlineCPUMemRequest {
+                wait: false,
+                nb_cpus: new_vcpus - old_vcpus,
+                cpu_only: true,
+            })
+            .await
+            .context("online hot-plugged vcpus")?;
+
+        Ok(())
+    }
+
+    // Number of vCPUs needed by the cpu quotas of the containers, rounded up.
+    async fn total_vcpus(&self) -> u32 {
+        let resources = self.resources.read().await;
+        let vcpus: f64 = resources
+            .values()
+            .filter_map(|r| match (r.cpu.quota, r.cpu.period) {
+                (Some(quota), Some(period)) if quota > 0 && period > 0 => {
+                    Some(quota as f64 / period as f64)
+                }
+                _ => None,
+            })
+            .sum();
+        vcpus.ceil() as u32
+    }
+
+    /// update_sandbox_memory grows the guest memory to fit the memory and
+    /// hugepage limits of all the containers, on top of the default memory.
+    async fn update_sandbox_memory(&self, h: &dyn Hypervisor, agent: &dyn Agent) -> Result<()> {
+        let default_memory = h.hypervisor_config().await.memory_info.default_memory;
+        let limits_mb = (self.total_memory_limit().await + MIB_TO_B - 1) / MIB_TO_B;
+        let new_mem_mb = (default_memory as u64 + limits_mb).min(u32::MAX as u64) as u32;
+
+        let (mem_mb, info) = h.resize_memory(new_mem_mb).await.context("resize memory")?;
+        if info.size_mb == 0 {
+            return Ok(());
+        }
+        info!(
+            sl!(),
+            "hot-added {} MiB of memory, guest memory is {} MiB", info.size_mb, mem_mb
+        );
+
+        if info.probe {
+            let details = agent
+                .get_guest_details(GetGuestDetailsRequest {
+                    mem_block_size: true,
+                    mem_hotplug_probe: true,
+                })
+                .await
+                .context("get guest details")?;
+            if !details.support_mem_hotplug_probe {
+                return Err(anyhow!("guest doesn't support memory hotplug by probe"));
+            }
+            let block_size = details.mem_block_size_bytes;
+            if block_size == 0 {
+                return Err(anyhow!("invalid guest memory block size 0"));
+            }
+
+            let blocks = info.size_mb as u64 * MIB_TO_B / block_size;
+            agent
+                .mem_hotplug_by_probe(MemHotplugByProbeRequest {
+                    mem_hotplug_probe_addr: (0..blocks)
+                        .map(|i| info.addr + i * block_size)
+                        .collect(),
+                })
+                .await
+                .context("probe hot-added memory")?;
+        }
+
+        agent
+            .online_cpu_mem(OnlineCPUMemRequest {
+                wait: false,
+                nb_cpus: 0,
+                cpu_only: false,
+            })
+            .await
+            .context("online hot-added memory")?;
+
+        Ok(())
+    }
+
+    async fn total_memory_limit(&self) -> u64 {
+        let resources = self.resources.read().await;
+        resources
+            .values()
+            .map(|r| {
+                let memory = r.memory.memory_hard_limit.unwrap_or(0).max(0) as u64;
+                let hugepages: u64 = r.hugepages.limits.iter().map(|l| l.limit).sum();
+                memory + hugepages
+            })
+            .sum()
+    }
+
     async fn update_resources(&self, cid: &str, new_resource: Resources) -> bool {
         let mut resources = self.resources.write().await;
         let old_resource = resources.insert(cid.to_owned(), new_resource.clone());
@@ -236,14 +386,49 @@ impl CgroupsResource {
 
         CpuResources {
             cpus: cpu.clone().map(|cpu| cpu.cpus),
-            mems: cpu.map(|cpu| cpu.mems),
+            mems: cpu.clone().map(|cpu| cpu.mems),
+            quota: cpu.as_ref().and_then(|cpu| cpu.quota),
+            period: cpu.and_then(|cpu| cpu.period),
+            ..Default::default()
+        }
+    }
+
+    fn calc_memory_resources(&self, linux_resources: Option<&LinuxResources>) -> MemoryResources {
+        let memory = || -> Option<oci::LinuxMemory> { linux_resources.as_ref()?.memory.clone() }();
+
+        MemoryResources {
+            memory_hard_limit: memory.and_then(|memory| memory.limit),
             ..Default::default()
         }
     }
 
+    fn calc_hugepage_resources(
+        &self,
+        linux_resources: Option<&LinuxResources>,
+    ) -> HugePageResources {
+        HugePageResources {
+            limits: linux_resources
+                .map(|r| {
+                    r.hugepage_limits
+                        .iter()
+                        .map(|l| HugePageResource {
+                            size: l.page_size.clone(),
+                            limit: l.limit,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    // Only the cpusets are applied to the sandbox cgroup, the cpu quotas
+    // size the guest vCPUs and the memory and hugepage limits the guest
+    // memory.
     fn calc_resource(&self, linux_resources: Option<&LinuxResources>) -> Resources {
         Resources {
             cpu: self.calc_cpu_resources(linux_resources),
+            memory: self.calc_memory_resources(linux_resources),
+            hugepages: self.calc_hugepage_resources(linux_resources),
             ..Default::default()
         }
     }
@@ -275,6 +460,7 @@ impl Persist for CgroupsResource {
             resources: Arc::new(RwLock::new(HashMap::new())),
             overhead_cgroup_manager: None,
             cgroup_config: config,
+            static_resource_mgmt: cgroup_args.config.runtime.static_sandbox_resource_mgmt,
         })
     }
 }
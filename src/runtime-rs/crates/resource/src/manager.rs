@@ -4,12 +4,12 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use crate::network::NetworkConfig;
+use crate::network::{LinkWatcher, NetworkConfig};
 use crate::resource_persist::ResourceState;
 use crate::{manager_inner::ResourceManagerInner, rootfs::Rootfs, volume::Volume, ResourceConfig};
 use agent::types::Device;
 use agent::{Agent, Storage};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use hypervisor::device::device_manager::DeviceManager;
 use hypervisor::Hypervisor;
@@ -17,9 +17,13 @@ use kata_types::config::TomlConfig;
 use kata_types::mount::Mount;
 use oci::{Linux, LinuxResources};
 use persist::sandbox_persist::Persist;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::sync::RwLock;
 
+// Delay of the rescan of the network after a link change, for the link to be
+// set up.
+const NETWORK_WATCH_DELAY: Duration = Duration::from_secs(1);
+
 pub struct ManagerArgs {
     pub sid: String,
     pub agent: Arc<dyn Agent>,
@@ -68,6 +72,33 @@ impl ResourceManager {
         inner.handle_network(network_config).await
     }
 
+    /// Rescan the sandbox network for links added or removed since it has been set up.
+    pub async fn rescan_network(&self) -> Result<()> {
+        let inner = self.inner.read().await;
+        inner.rescan_network().await
+    }
+
+    /// Rescan the sandbox network whenever a link is added to or removed from `netns_path`.
+    pub async fn watch_network(&self, netns_path: &str) -> Result<()> {
+        let mut watcher = LinkWatcher::new(netns_path).context("new link watcher")?;
+        let inner = self.inner.clone();
+        let handle = tokio::spawn(async move {
+            while watcher.changed().await {
+                // coalesce the events of a link being set up
+                tokio::time::sleep(NETWORK_WATCH_DELAY).await;
+                watcher.clear();
+
+                let inner = inner.read().await;
+                if let Err(e) = inner.rescan_network().await {
+                    warn!(sl!(), "failed to rescan network: {:?}", e);
+                }
+            }
+        });
+        let mut inner = self.inner.write().await;
+        inner.set_network_watcher(handle);
+        Ok(())
+    }
+
     pub async fn setup_after_start_vm(&self) -> Result<()> {
         let mut inner = self.inner.write().await;
         inner.setup_after_start_vm().await
@@ -119,6 +150,16 @@ impl ResourceManager {
         inner.update_cgroups(cid, linux_resources).await
     }
 
+    pub async fn delete_cgroups(&self, cid: &str) -> Result<()> {
+        let inner = self.inner.read().await;
+        inner.delete_cgroups(cid).await
+    }
+
+    pub async fn remove_devices(&self, cid: &str) -> Result<()> {
+        let inner = self.inner.read().await;
+        inner.remove_devices(cid).await
+    }
+
     pub async fn cleanup(&self) -> Result<()> {
         let inner = self.inner.read().await;
         inner.cleanup().await
@@ -4,7 +4,7 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::{sync::Arc, thread, vec};
+use std::{collections::HashMap, path::Path, sync::Arc, thread, time::Duration, vec};
 
 use crate::{network::NetworkConfig, resource_persist::ResourceState};
 use agent::{types::Device, Agent, Storage};
@@ -12,24 +12,32 @@ use anyhow::{anyhow, Context, Ok, Result};
 use async_trait::async_trait;
 use hypervisor::{
     device::{device_manager::DeviceManager, DeviceConfig},
-    BlockConfig, Hypervisor,
+    BlockConfig, Hypervisor, VfioConfig, KATA_VFIO_PCI_DEV_TYPE, KATA_VFIO_PCI_GK_DEV_TYPE,
 };
 use kata_types::config::TomlConfig;
 use kata_types::mount::Mount;
 use oci::{Linux, LinuxResources};
 use persist::sandbox_persist::Persist;
-use tokio::{runtime, sync::RwLock};
+use tokio::{runtime, sync::RwLock, task::JoinHandle};
 
 use crate::{
     cgroups::{CgroupArgs, CgroupsResource},
     manager::ManagerArgs,
-    network::{self, Network},
+    network::{self, EndpointState, Network, NetworkChanges},
     rootfs::{RootFsResource, Rootfs},
     share_fs::{self, ShareFs},
     volume::{Volume, VolumeResource},
     ResourceConfig,
 };
 
+const VFIO_DEV_PATH: &str = "/dev/vfio/";
+// The VFIO container node, present next to the group nodes, isn't a device.
+const VFIO_CONTAINER_DEV_PATH: &str = "/dev/vfio/vfio";
+const VFIO_MODE_VFIO: &str = "vfio";
+// The guest may take a while to probe a hotplugged network device.
+const UPDATE_INTERFACE_RETRIES: u32 = 10;
+const UPDATE_INTERFACE_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
 pub(crate) struct ResourceManagerInner {
     sid: String,
     toml_config: Arc<TomlConfig>,
@@ -37,7 +45,12 @@ pub(crate) struct ResourceManagerInner {
     hypervisor: Arc<dyn Hypervisor>,
     device_manager: Arc<RwLock<DeviceManager>>,
     network: Option<Arc<dyn Network>>,
+    netns: Option<String>,
+    // endpoints of a restored sandbox, whose host resources are released on cleanup
+    restored_endpoints: Vec<EndpointState>,
+    network_watcher: Option<JoinHandle<()>>,
     share_fs: Option<Arc<dyn ShareFs>>,
+    container_devices: ContainerDevices,
 
     pub rootfs_resource: RootFsResource,
     pub volume_resource: VolumeResource,
@@ -64,7 +77,11 @@ impl ResourceManagerInner {
             hypervisor,
             device_manager: Arc::new(RwLock::new(dev_manager)),
             network: None,
+            netns: None,
+            restored_endpoints: vec![],
+            network_watcher: None,
             share_fs: None,
+            container_devices: ContainerDevices::default(),
             rootfs_resource: RootFsResource::new(),
             volume_resource: VolumeResource::new(),
             cgroups_resource,
@@ -114,6 +131,14 @@ impl ResourceManagerInner {
     }
 
     pub async fn handle_network(&mut self, network_config: NetworkConfig) -> Result<()> {
+        let NetworkConfig::NetworkResourceWithNetNs(config) = &network_config;
+        network::check_network_model(&config.network_model, self.hypervisor.as_ref())
+            .await
+            .context("check network model")?;
+        if !config.netns_path.is_empty() {
+            self.netns = Some(config.netns_path.clone());
+        }
+
         // 1. When using Rust asynchronous programming, we use .await to
         //    allow other task to run instead of waiting for the completion of the current task.
         // 2. Also, when handling the pod network, we need to set the shim threads
@@ -186,6 +211,69 @@ impl ResourceManagerInner {
         Ok(())
     }
 
+    // Rescan the network, and push the changes of the endpoints to the guest.
+    pub async fn rescan_network(&self) -> Result<()> {
+        let network = match self.network.as_ref() {
+            Some(network) => network.clone(),
+            None => return Ok(()),
+        };
+
+        // Rescan the netns in a dedicated thread, as `handle_network` does.
+        let hypervisor = self.hypervisor.clone();
+        let n = network.clone();
+        let changes = thread::spawn(move || -> Result<NetworkChanges> {
+            let rt = runtime::Builder::new_current_thread().enable_io().build()?;
+            rt.block_on(n.rescan(hypervisor.as_ref()))
+        })
+        .join()
+        .map_err(|e| anyhow!("{:?}", e))
+        .context("Couldn't join on the associated thread")?
+        .context("failed to rescan network")?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+        info!(sl!(), "network changes {:?}", changes);
+
+        for i in changes.added_interfaces {
+            self.update_hotplugged_interface(i)
+                .await
+                .context("update hotplugged interface")?;
+        }
+        if !changes.added_neighs.is_empty() {
+            self.agent
+                .add_arp_neighbors(agent::AddArpNeighborRequest {
+                    neighbors: Some(agent::ARPNeighbors {
+                        neighbors: changes.added_neighs,
+                    }),
+                })
+                .await
+                .context("update neighbors")?;
+        }
+        self.handle_routes(network.as_ref())
+            .await
+            .context("handle routes")
+    }
+
+    async fn update_hotplugged_interface(&self, interface: agent::Interface) -> Result<()> {
+        let mut retries = 0;
+        loop {
+            match self
+                .agent
+                .update_interface(agent::UpdateInterfaceRequest {
+                    interface: Some(interface.clone()),
+                })
+                .await
+            {
+                Err(e) if retries < UPDATE_INTERFACE_RETRIES => {
+                    debug!(sl!(), "interface {} not ready: {:?}", interface.name, e);
+                    retries += 1;
+                    tokio::time::sleep(UPDATE_INTERFACE_RETRY_INTERVAL).await;
+                }
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+
     pub async fn setup_after_start_vm(&mut self) -> Result<()> {
         if let Some(share_fs) = self.share_fs.as_ref() {
             share_fs
@@ -245,6 +333,7 @@ impl ResourceManagerInner {
         self.volume_resource
             .handler_volumes(
                 &self.share_fs,
+                self.agent.clone(),
                 cid,
                 spec,
                 self.device_manager.as_ref(),
@@ -253,7 +342,7 @@ impl ResourceManagerInner {
             .await
     }
 
-    pub async fn handler_devices(&self, _cid: &str, linux: &Linux) -> Result<Vec<Device>> {
+    pub async fn handler_devices(&self, cid: &str, linux: &Linux) -> Result<Vec<Device>> {
         let mut devices = vec![];
         for d in linux.devices.iter() {
             match d.r#type.as_str() {
@@ -277,6 +366,7 @@ impl ResourceManagerInner {
                         .try_add_device(&device_id)
                         .await
                         .context("failed to add deivce")?;
+                    self.container_devices.add(cid, &device_id).await;
 
                     // get complete device information
                     let dev_info = self
@@ -299,6 +389,9 @@ impl ResourceManagerInner {
                         devices.push(agent_device);
                     }
                 }
+                "c" if is_vfio_group_device(&d.path) => {
+                    devices.push(self.handle_vfio_device(cid, d).await?);
+                }
                 _ => {
                     // TODO enable other devices type
                     continue;
@@ -308,17 +401,110 @@ impl ResourceManagerInner {
         Ok(devices)
     }
 
+    async fn handle_vfio_device(&self, cid: &str, d: &oci::LinuxDevice) -> Result<Device> {
+        let dev_type = match self.toml_config.runtime.vfio_mode.as_str() {
+            VFIO_MODE_VFIO => KATA_VFIO_PCI_DEV_TYPE,
+            _ => KATA_VFIO_PCI_GK_DEV_TYPE,
+        };
+        let device_info = DeviceConfig::VfioCfg(VfioConfig {
+            host_path: d.path.clone(),
+            dev_type: dev_type.to_string(),
+            ..Default::default()
+        });
+        let device_id = self
+            .device_manager
+            .write()
+            .await
+            .new_device(&device_info)
+            .await
+            .context("failed to create vfio device")?;
+
+        self.device_manager
+            .write()
+            .await
+            .try_add_device(&device_id)
+            .await
+            .context("failed to add vfio device")?;
+        self.container_devices.add(cid, &device_id).await;
+
+        let dev_info = self
+            .device_manager
+            .read()
+            .await
+            .get_device_info(&device_id)
+            .await
+            .context("failed to get device info")?;
+
+        // the agent finds each device from its guest PCI path
+        match dev_info {
+            DeviceConfig::VfioCfg(config) => Ok(Device {
+                id: config.iommu_group,
+                container_path: d.path.clone(),
+                field_type: config.dev_type,
+                options: config
+                    .devices
+                    .iter()
+                    .map(|dev| format!("{}={}", dev.bus_slot_func, dev.guest_pci_path))
+                    .collect(),
+                ..Default::default()
+            }),
+            _ => Err(anyhow!(
+                "unexpected device info for vfio device {}",
+                device_id
+            )),
+        }
+    }
+
     pub async fn update_cgroups(
         &self,
         cid: &str,
         linux_resources: Option<&LinuxResources>,
     ) -> Result<()> {
         self.cgroups_resource
-            .update_cgroups(cid, linux_resources, self.hypervisor.as_ref())
+            .update_cgroups(
+                cid,
+                linux_resources,
+                self.hypervisor.as_ref(),
+                self.agent.as_ref(),
+            )
             .await
     }
 
+    pub async fn delete_cgroups(&self, cid: &str) -> Result<()> {
+        self.cgroups_resource
+            .delete_cgroups(cid, self.hypervisor.as_ref(), self.agent.as_ref())
+            .await
+    }
+
+    /// remove_devices detaches the devices hotplugged for a removed
+    /// container, the ones shared with other containers stay attached.
+    pub async fn remove_devices(&self, cid: &str) -> Result<()> {
+        let mut result = Ok(());
+        for device_id in self.container_devices.take(cid).await {
+            if let Err(e) = self
+                .device_manager
+                .write()
+                .await
+                .try_remove_device(&device_id)
+                .await
+            {
+                warn!(sl!(), "failed to remove device {}: {:?}", device_id, e);
+                result = Err(e).with_context(|| format!("remove device {}", device_id));
+            }
+        }
+        result
+    }
+
+    pub(crate) fn set_network_watcher(&mut self, watcher: JoinHandle<()>) {
+        if let Some(old) = self.network_watcher.replace(watcher) {
+            old.abort();
+        }
+    }
+
     pub async fn cleanup(&self) -> Result<()> {
+        if let Some(watcher) = self.network_watcher.as_ref() {
+            watcher.abort();
+        }
         // clean up cgroup
         self.cgroups_resource
             .delete()
@@ -332,10 +518,39 @@ impl ResourceManagerInner {
                 .await
                 .context("failed to cleanup host path")?;
         }
+        self.cleanup_restored_endpoints()
+            .context("cleanup restored endpoints")?;
         // TODO cleanup other resources
         Ok(())
     }
 
+    // The endpoints are restored in the netns in a dedicated thread, as
+    // `handle_network` does.
+    fn cleanup_restored_endpoints(&self) -> Result<()> {
+        let netns = match self.netns.clone() {
+            Some(netns) if !self.restored_endpoints.is_empty() => netns,
+            _ => return Ok(()),
+        };
+        // The netns not created by the runtime may be removed already, along
+        // with its endpoints.
+        if !Path::new(&netns).exists() {
+            info!(
+                sl!(),
+                "skip cleaning up endpoints of removed netns {}", netns
+            );
+            return Ok(());
+        }
+        let states = self.restored_endpoints.clone();
+        thread::spawn(move || -> Result<()> {
+            let _netns_guard = network::NetnsGuard::new(&netns).context("new netns guard")?;
+            let rt = runtime::Builder::new_current_thread().enable_io().build()?;
+            rt.block_on(network::cleanup_endpoints(&states))
+        })
+        .join()
+        .map_err(|e| anyhow!("{:?}", e))
+        .context("Couldn't join on the associated thread")?
+    }
+
     pub async fn dump(&self) {
         self.rootfs_resource.dump().await;
         self.volume_resource.dump().await;
@@ -358,6 +573,7 @@ impl Persist for ResourceManagerInner {
         let cgroup_state = self.cgroups_resource.save().await?;
         Ok(ResourceState {
             endpoint: endpoint_state,
+            netns: self.netns.clone(),
             cgroup_state: Some(cgroup_state),
         })
     }
@@ -377,7 +593,11 @@ impl Persist for ResourceManagerInner {
             hypervisor: resource_args.hypervisor.clone(),
             device_manager: Arc::new(RwLock::new(DeviceManager::new(resource_args.hypervisor)?)),
             network: None,
+            netns: resource_state.netns,
+            restored_endpoints: resource_state.endpoint,
+            network_watcher: None,
             share_fs: None,
+            container_devices: ContainerDevices::default(),
             rootfs_resource: RootFsResource::new(),
             volume_resource: VolumeResource::new(),
             cgroups_resource: CgroupsResource::restore(
@@ -389,3 +609,53 @@ impl Persist for ResourceManagerInner {
         })
     }
 }
+
+// The devices hotplugged for each container, by container id.
+#[derive(Default)]
+struct ContainerDevices(RwLock<HashMap<String, Vec<String>>>);
+
+impl ContainerDevices {
+    async fn add(&self, cid: &str, device_id: &str) {
+        self.0
+            .write()
+            .await
+            .entry(cid.to_string())
+            .or_default()
+            .push(device_id.to_string());
+    }
+
+    async fn take(&self, cid: &str) -> Vec<String> {
+        self.0.write().await.remove(cid).unwrap_or_default()
+    }
+}
+
+// Only the /dev/vfio/<group> nodes stand for devices to pass through.
+fn is_vfio_group_device(path: &str) -> bool {
+    path.starts_with(VFIO_DEV_PATH) && path != VFIO_CONTAINER_DEV_PATH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_vfio_group_device() {
+        assert!(is_vfio_group_device("/dev/vfio/12"));
+        assert!(!is_vfio_group_device("/dev/vfio/vfio"));
+        assert!(!is_vfio_group_device("/dev/null"));
+    }
+
+    #[actix_rt::test]
+    async fn test_container_devices() {
+        let devices = ContainerDevices::default();
+        devices.add("c1", "vfio1").await;
+        devices.add("c2", "vfio1").await;
+        devices.add("c1", "blk1").await;
+
+        assert_eq!(devices.take("c1").await, vec!["vfio1", "blk1"]);
+        // the devices are detached only once
+        assert!(devices.take("c1").await.is_empty());
+        assert_eq!(devices.take("c2").await, vec!["vfio1"]);
+        assert!(devices.take("c3").await.is_empty());
+    }
+}
@@ -15,10 +15,28 @@ pub struct PhysicalEndpointState {
     pub hard_addr: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct MacvtapModelState {
+    /// name of the macvtap interface created on top of the virt interface
+    pub tap_name: String,
+    pub virt_name: String,
+    /// MAC address of the virt interface, taken over by the macvtap interface
+    pub hard_addr: String,
+    /// addresses of the virt interface in CIDR notation, taken over by the guest
+    pub addrs: Vec<String>,
+    pub queues: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct NetworkModelState {
+    pub macvtap_model: Option<MacvtapModelState>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct MacvlanEndpointState {
     pub if_name: String,
     pub network_qos: bool,
+    pub network_model: Option<NetworkModelState>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -31,6 +49,7 @@ pub struct VlanEndpointState {
 pub struct VethEndpointState {
     pub if_name: String,
     pub network_qos: bool,
+    pub network_model: Option<NetworkModelState>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -39,6 +58,19 @@ pub struct IpVlanEndpointState {
     pub network_qos: bool,
 }
 
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TapEndpointState {
+    pub if_name: String,
+    pub hard_addr: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct VhostUserEndpointState {
+    pub if_name: String,
+    pub hard_addr: String,
+    pub socket_path: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct EndpointState {
     pub physical_endpoint: Option<PhysicalEndpointState>,
@@ -46,5 +78,7 @@ pub struct EndpointState {
     pub ipvlan_endpoint: Option<IpVlanEndpointState>,
     pub macvlan_endpoint: Option<MacvlanEndpointState>,
     pub vlan_endpoint: Option<VlanEndpointState>,
+    pub tap_endpoint: Option<TapEndpointState>,
+    pub vhost_user_endpoint: Option<VhostUserEndpointState>,
     // TODO : other endpoint
 }
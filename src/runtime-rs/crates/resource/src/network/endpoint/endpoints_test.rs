@@ -13,14 +13,23 @@ mod tests {
     use std::sync::Arc;
 
     use crate::network::{
-        endpoint::{IPVlanEndpoint, MacVlanEndpoint, VlanEndpoint},
+        endpoint::{
+            self,
+            endpoint_persist::{
+                EndpointState, MacvtapModelState, NetworkModelState, VethEndpointState,
+            },
+            Endpoint, IPVlanEndpoint, MacVlanEndpoint, TapEndpoint, VhostUserEndpoint,
+            VlanEndpoint,
+        },
         network_model::{
             self,
             tc_filter_model::{fetch_index, TcFilterModel},
             NetworkModelType, TC_FILTER_NET_MODEL_STR,
         },
-        network_pair::{NetworkInterface, NetworkPair, TapInterface},
-        utils::link::net_test_utils::delete_link,
+        network_pair::{
+            create_link, get_link_by_name, NetworkInterface, NetworkPair, TapInterface,
+        },
+        utils::{get_mac_addr, link::net_test_utils::delete_link},
     };
 
     // this unit test tests the integrity of MacVlanEndpoint::new()
@@ -204,7 +213,7 @@ mod tests {
                                     hard_addr: mac_addr.clone(),
                                     ..Default::default()
                                 },
-                                model: network_model::new(model_str)
+                                model: network_model::new(model_str, 1)
                                     .expect("failed to create new network model"),
                                 network_qos: false,
                             },
@@ -355,4 +364,89 @@ mod tests {
             }
         }
     }
+
+    // this unit test tests the integrity of TapEndpoint::new() on an existing TAP interface
+    #[actix_rt::test]
+    async fn test_tap_construction() {
+        let tap_iface_name = String::from("kata_tap_8196");
+
+        if let Ok((conn, handle, _)) =
+            rtnetlink::new_connection().context("failed to create netlink connection")
+        {
+            let thread_handler = tokio::spawn(conn);
+            defer!({
+                thread_handler.abort();
+            });
+
+            // mock the network plugin creating the TAP interface
+            if let Ok(link) = create_link(&handle, &tap_iface_name, 1).await {
+                let hard_addr = get_mac_addr(&link.attrs().hardware_addr).unwrap();
+                let link = get_link_by_name(&handle, &tap_iface_name).await.unwrap();
+                assert_eq!(link.r#type(), "tuntap");
+                assert_eq!(link.attrs().link_layer_type, libc::ARPHRD_ETHER);
+
+                let endpoint =
+                    TapEndpoint::new(&link.attrs().name, &link.attrs().hardware_addr).unwrap();
+                assert_eq!(endpoint.name().await, tap_iface_name);
+                assert_eq!(endpoint.hardware_addr().await, hard_addr);
+
+                let state = endpoint.save().await.unwrap().tap_endpoint.unwrap();
+                assert_eq!(state.if_name, tap_iface_name);
+                assert_eq!(state.hard_addr, hard_addr);
+
+                assert!(delete_link(&handle, &tap_iface_name).await.is_ok());
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_vhost_user_construction() {
+        let mac = [0x02, 0x78, 0xca, 0xfe, 0x00, 0x05];
+        assert!(VhostUserEndpoint::new("eth0", &mac[..3], "/run/vhostuser/eth0.sock").is_err());
+
+        let endpoint = VhostUserEndpoint::new("eth0", &mac, "/run/vhostuser/eth0.sock").unwrap();
+        assert_eq!(endpoint.name().await, "eth0");
+        assert_eq!(endpoint.hardware_addr().await, "02:78:ca:fe:00:05");
+
+        let state = endpoint.save().await.unwrap();
+        assert!(state.tap_endpoint.is_none());
+        let state = state.vhost_user_endpoint.unwrap();
+        assert_eq!(state.if_name, "eth0");
+        assert_eq!(state.hard_addr, "02:78:ca:fe:00:05");
+        assert_eq!(state.socket_path, "/run/vhostuser/eth0.sock");
+    }
+
+    #[actix_rt::test]
+    async fn test_endpoint_restore() {
+        // the endpoints without network model state hold no host resources
+        let mut state = EndpointState {
+            veth_endpoint: Some(VethEndpointState {
+                if_name: "eth0".to_string(),
+                network_qos: false,
+                network_model: None,
+            }),
+            ..Default::default()
+        };
+        assert!(endpoint::restore(&state).is_none());
+        assert!(endpoint::restore(&EndpointState::default()).is_none());
+
+        let model_state = MacvtapModelState {
+            tap_name: "tap0_kata".to_string(),
+            virt_name: "eth0".to_string(),
+            hard_addr: "02:42:ac:11:00:02".to_string(),
+            addrs: vec!["172.17.0.2/16".to_string()],
+            queues: 2,
+        };
+        state.veth_endpoint.as_mut().unwrap().network_model = Some(NetworkModelState {
+            macvtap_model: Some(model_state.clone()),
+        });
+        let endpoint = endpoint::restore(&state).unwrap();
+        assert_eq!(endpoint.name().await, "eth0");
+
+        // the restored endpoint saves the state of its network model again
+        let state = endpoint.save().await.unwrap().veth_endpoint.unwrap();
+        let saved = state.network_model.unwrap().macvtap_model.unwrap();
+        assert_eq!(saved.tap_name, model_state.tap_name);
+        assert_eq!(saved.addrs, model_state.addrs);
+    }
 }
@@ -48,6 +48,7 @@ impl IPVlanEndpoint {
         Ok(NetworkConfig {
             host_dev_name: iface.name.clone(),
             guest_mac: Some(guest_mac),
+            queue_fds: self.net_pair.queue_fds(),
         })
     }
 }
@@ -67,6 +68,10 @@ impl Endpoint for IPVlanEndpoint {
             .add_network_model()
             .await
             .context("error adding network model")?;
+        self.net_pair
+            .add_rate_limiter(h)
+            .await
+            .context("add rate limiter")?;
         let config = self.get_network_config().context("get network config")?;
         h.add_device(DeviceType::Network(NetworkDevice {
             id: self.net_pair.virt_iface.name.clone(),
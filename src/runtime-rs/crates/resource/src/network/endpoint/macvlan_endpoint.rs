@@ -34,6 +34,14 @@ impl MacVlanEndpoint {
         Ok(MacVlanEndpoint { net_pair })
     }
 
+    /// Restore the endpoint saved in `state`, `None` if its network model
+    /// holds no host resources.
+    pub(crate) fn restore(state: &MacvlanEndpointState) -> Option<Self> {
+        let model = state.network_model.as_ref()?;
+        let net_pair = NetworkPair::restore(&state.if_name, state.network_qos, model)?;
+        Some(MacVlanEndpoint { net_pair })
+    }
+
     fn get_network_config(&self) -> Result<NetworkConfig> {
         let iface = &self.net_pair.tap.tap_iface;
         let guest_mac = utils::parse_mac(&iface.hard_addr).ok_or_else(|| {
@@ -45,6 +53,7 @@ impl MacVlanEndpoint {
         Ok(NetworkConfig {
             host_dev_name: iface.name.clone(),
             guest_mac: Some(guest_mac),
+            queue_fds: self.net_pair.queue_fds(),
         })
     }
 }
@@ -64,6 +73,10 @@ impl Endpoint for MacVlanEndpoint {
             .add_network_model()
             .await
             .context("add network model")?;
+        self.net_pair
+            .add_rate_limiter(h)
+            .await
+            .context("add rate limiter")?;
         let config = self.get_network_config().context("get network config")?;
         h.add_device(DeviceType::Network(NetworkDevice {
             id: self.net_pair.virt_iface.name.clone(),
@@ -96,8 +109,16 @@ impl Endpoint for MacVlanEndpoint {
             macvlan_endpoint: Some(MacvlanEndpointState {
                 if_name: self.net_pair.virt_iface.name.clone(),
                 network_qos: self.net_pair.network_qos,
+                network_model: self.net_pair.save_network_model(),
             }),
             ..Default::default()
         })
     }
+
+    async fn cleanup(&self) -> Result<()> {
+        self.net_pair
+            .del_network_model()
+            .await
+            .context("del network model")
+    }
 }
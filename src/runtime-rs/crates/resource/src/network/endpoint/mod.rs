@@ -14,9 +14,16 @@ mod vlan_endpoint;
 pub use vlan_endpoint::VlanEndpoint;
 mod macvlan_endpoint;
 pub use macvlan_endpoint::MacVlanEndpoint;
+mod tap_endpoint;
+pub use tap_endpoint::TapEndpoint;
+mod vhost_user_endpoint;
+pub(crate) use vhost_user_endpoint::find_vhost_user_socket;
+pub use vhost_user_endpoint::VhostUserEndpoint;
 pub mod endpoint_persist;
 mod endpoints_test;
 
+use std::sync::Arc;
+
 use anyhow::Result;
 use async_trait::async_trait;
 use hypervisor::Hypervisor;
@@ -30,4 +37,22 @@ pub trait Endpoint: std::fmt::Debug + Send + Sync {
     async fn attach(&self, hypervisor: &dyn Hypervisor) -> Result<()>;
     async fn detach(&self, hypervisor: &dyn Hypervisor) -> Result<()>;
     async fn save(&self) -> Option<EndpointState>;
+
+    /// Release the host resources of a restored endpoint, whose hypervisor is
+    /// gone.
+    async fn cleanup(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Restore the endpoint saved in `state`, `None` if it holds no host
+/// resources to release.
+pub(crate) fn restore(state: &EndpointState) -> Option<Arc<dyn Endpoint>> {
+    if let Some(state) = &state.veth_endpoint {
+        return VethEndpoint::restore(state).map(|e| Arc::new(e) as _);
+    }
+    if let Some(state) = &state.macvlan_endpoint {
+        return MacVlanEndpoint::restore(state).map(|e| Arc::new(e) as _);
+    }
+    None
 }
@@ -117,7 +117,9 @@ impl Endpoint for PhysicalEndpoint {
                 bus_slot_func: self.bdf.clone(),
                 mode: driver::VfioBusMode::new(mode)
                     .with_context(|| format!("new vfio bus mode {:?}", mode))?,
+                ..Default::default()
             },
+            ..Default::default()
         });
         hypervisor.add_device(d).await.context("add device")?;
         Ok(())
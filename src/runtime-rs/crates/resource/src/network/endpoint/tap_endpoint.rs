@@ -0,0 +1,91 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::io::{self, Error};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use hypervisor::device::DeviceType;
+use hypervisor::NetworkDevice;
+use hypervisor::{device::driver::NetworkConfig, Hypervisor};
+
+use super::endpoint_persist::{EndpointState, TapEndpointState};
+use super::Endpoint;
+use crate::network::utils;
+
+// TapEndpoint is an existing TAP interface of the network namespace, handed
+// to the VM as is.
+#[derive(Debug)]
+pub struct TapEndpoint {
+    pub(crate) if_name: String,
+    pub(crate) hard_addr: String,
+}
+
+impl TapEndpoint {
+    pub fn new(name: &str, hardware_addr: &[u8]) -> Result<Self> {
+        Ok(Self {
+            if_name: name.to_string(),
+            hard_addr: utils::get_mac_addr(hardware_addr).context("get mac addr")?,
+        })
+    }
+
+    fn get_network_config(&self) -> Result<NetworkConfig> {
+        let guest_mac = utils::parse_mac(&self.hard_addr).ok_or_else(|| {
+            Error::new(
+                io::ErrorKind::InvalidData,
+                format!("hard_addr {}", &self.hard_addr),
+            )
+        })?;
+        Ok(NetworkConfig {
+            host_dev_name: self.if_name.clone(),
+            guest_mac: Some(guest_mac),
+            queue_fds: vec![],
+        })
+    }
+}
+
+#[async_trait]
+impl Endpoint for TapEndpoint {
+    async fn name(&self) -> String {
+        self.if_name.clone()
+    }
+
+    async fn hardware_addr(&self) -> String {
+        self.hard_addr.clone()
+    }
+
+    async fn attach(&self, h: &dyn Hypervisor) -> Result<()> {
+        let config = self.get_network_config().context("get network config")?;
+        h.add_device(DeviceType::Network(NetworkDevice {
+            id: self.if_name.clone(),
+            config,
+        }))
+        .await
+        .context("error adding device by hypervisor")?;
+        Ok(())
+    }
+
+    // The TAP interface isn't ours, it is left in the network namespace.
+    async fn detach(&self, h: &dyn Hypervisor) -> Result<()> {
+        let config = self.get_network_config().context("get network config")?;
+        h.remove_device(DeviceType::Network(NetworkDevice {
+            id: self.if_name.clone(),
+            config,
+        }))
+        .await
+        .context("error removing device by hypervisor")?;
+        Ok(())
+    }
+
+    async fn save(&self) -> Option<EndpointState> {
+        Some(EndpointState {
+            tap_endpoint: Some(TapEndpointState {
+                if_name: self.if_name.clone(),
+                hard_addr: self.hard_addr.clone(),
+            }),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::{
+    convert::TryFrom,
+    net::IpAddr,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use hypervisor::device::DeviceType;
+use hypervisor::{Hypervisor, VhostUserConfig, VhostUserDevice, VHOST_USER_NET};
+
+use super::endpoint_persist::{EndpointState, VhostUserEndpointState};
+use super::Endpoint;
+use crate::network::utils::{self, address::Address};
+
+// The network plugin, e.g. an OVS-DPDK one, marks an interface of the network
+// namespace as backed by a vhost-user net backend with the alias
+// vhost-user:<socket path> of the interface.
+const VHOST_USER_ALIAS_PREFIX: &str = "vhost-user:";
+// Without such an alias, the socket of an interface is looked for at
+// /tmp/vhostuser_<IPv4 address>/vhu.sock, the convention of the Go runtime.
+const VHOST_USER_SOCKET_SEARCH_DIR: &str = "/tmp";
+const VHOST_USER_SOCKET_NAME: &str = "vhu.sock";
+
+// VhostUserEndpoint is an interface of the network namespace backed by a
+// vhost-user net backend, the VM is connected to the backend socket.
+#[derive(Debug)]
+pub struct VhostUserEndpoint {
+    pub(crate) if_name: String,
+    pub(crate) hard_addr: String,
+    pub(crate) socket_path: String,
+}
+
+impl VhostUserEndpoint {
+    pub fn new(name: &str, hardware_addr: &[u8], socket_path: &str) -> Result<Self> {
+        Ok(Self {
+            if_name: name.to_string(),
+            hard_addr: utils::get_mac_addr(hardware_addr).context("get mac addr")?,
+            socket_path: socket_path.to_string(),
+        })
+    }
+
+    fn get_device(&self) -> VhostUserDevice {
+        let config = VhostUserConfig {
+            dev_id: self.if_name.clone(),
+            socket_path: self.socket_path.clone(),
+            mac_address: self.hard_addr.clone(),
+            device_type: VHOST_USER_NET.to_string(),
+            ..Default::default()
+        };
+        VhostUserDevice::new(self.if_name.clone(), config)
+    }
+}
+
+#[async_trait]
+impl Endpoint for VhostUserEndpoint {
+    async fn name(&self) -> String {
+        self.if_name.clone()
+    }
+
+    async fn hardware_addr(&self) -> String {
+        self.hard_addr.clone()
+    }
+
+    async fn attach(&self, h: &dyn Hypervisor) -> Result<()> {
+        let capabilities = h.capabilities().await.context("get capabilities")?;
+        if !capabilities.is_vhost_user_net_supported() {
+            return Err(anyhow!(
+                "the hypervisor doesn't support vhost-user network devices, can't attach {}",
+                self.if_name
+            ));
+        }
+        let device = self.get_device();
+        device.config.check_socket().context("check socket")?;
+        h.add_device(DeviceType::VhostUser(device))
+            .await
+            .context("error adding device by hypervisor")?;
+        Ok(())
+    }
+
+    async fn detach(&self, h: &dyn Hypervisor) -> Result<()> {
+        h.remove_device(DeviceType::VhostUser(self.get_device()))
+            .await
+            .context("error removing device by hypervisor")?;
+        Ok(())
+    }
+
+    async fn save(&self) -> Option<EndpointState> {
+        Some(EndpointState {
+            vhost_user_endpoint: Some(VhostUserEndpointState {
+                if_name: self.if_name.clone(),
+                hard_addr: self.hard_addr.clone(),
+                socket_path: self.socket_path.clone(),
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+/// Find the vhost-user socket of the link of index `index`, from its alias
+/// `alias`, or else from its IPv4 addresses. `None` if the link isn't backed
+/// by a vhost-user net backend.
+pub(crate) async fn find_vhost_user_socket(
+    handle: &rtnetlink::Handle,
+    index: u32,
+    alias: &str,
+) -> Result<Option<String>> {
+    if let Some(socket_path) = alias_socket_path(alias)? {
+        return Ok(Some(socket_path));
+    }
+
+    let mut addr_msg_list = handle
+        .address()
+        .get()
+        .set_link_index_filter(index)
+        .execute();
+    let mut addrs = vec![];
+    while let Some(addr_msg) = addr_msg_list.try_next().await? {
+        let addr = Address::try_from(addr_msg).context("get address from msg")?;
+        addrs.push(addr.addr);
+    }
+    Ok(probe_socket_path(
+        Path::new(VHOST_USER_SOCKET_SEARCH_DIR),
+        &addrs,
+    ))
+}
+
+fn alias_socket_path(alias: &str) -> Result<Option<String>> {
+    let socket_path = match alias.strip_prefix(VHOST_USER_ALIAS_PREFIX) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    if !Path::new(socket_path).is_absolute() {
+        return Err(anyhow!("invalid vhost-user socket path {:?}", socket_path));
+    }
+    Ok(Some(socket_path.to_string()))
+}
+
+fn probe_socket_path(search_dir: &Path, addrs: &[IpAddr]) -> Option<String> {
+    addrs
+        .iter()
+        .filter(|addr| addr.is_ipv4())
+        .map(|addr| -> PathBuf {
+            search_dir
+                .join(format!("vhostuser_{}", addr))
+                .join(VHOST_USER_SOCKET_NAME)
+        })
+        .find(|path| {
+            std::fs::metadata(path).map_or(false, |metadata| metadata.file_type().is_socket())
+        })
+        .map(|path| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_alias_socket_path() {
+        assert_eq!(alias_socket_path("").unwrap(), None);
+        assert_eq!(alias_socket_path("uplink").unwrap(), None);
+        assert_eq!(
+            alias_socket_path("vhost-user:/run/vhostuser/eth0.sock").unwrap(),
+            Some("/run/vhostuser/eth0.sock".to_string())
+        );
+        assert!(alias_socket_path("vhost-user:").is_err());
+        assert!(alias_socket_path("vhost-user:eth0.sock").is_err());
+    }
+
+    #[test]
+    fn test_probe_socket_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let addrs: Vec<IpAddr> = vec!["fe80::1".parse().unwrap(), "172.16.0.2".parse().unwrap()];
+        assert!(probe_socket_path(dir.path(), &addrs).is_none());
+
+        // not a socket
+        let socket_dir = dir.path().join("vhostuser_172.16.0.2");
+        std::fs::create_dir(&socket_dir).unwrap();
+        std::fs::write(socket_dir.join(VHOST_USER_SOCKET_NAME), "").unwrap();
+        assert!(probe_socket_path(dir.path(), &addrs).is_none());
+
+        std::fs::remove_file(socket_dir.join(VHOST_USER_SOCKET_NAME)).unwrap();
+        let _listener = UnixListener::bind(socket_dir.join(VHOST_USER_SOCKET_NAME)).unwrap();
+        assert_eq!(
+            probe_socket_path(dir.path(), &addrs),
+            Some(
+                socket_dir
+                    .join(VHOST_USER_SOCKET_NAME)
+                    .display()
+                    .to_string()
+            )
+        );
+    }
+}
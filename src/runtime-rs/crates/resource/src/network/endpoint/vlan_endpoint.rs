@@ -45,6 +45,7 @@ impl VlanEndpoint {
         Ok(NetworkConfig {
             host_dev_name: iface.name.clone(),
             guest_mac: Some(guest_mac),
+            queue_fds: self.net_pair.queue_fds(),
         })
     }
 }
@@ -64,6 +65,10 @@ impl Endpoint for VlanEndpoint {
             .add_network_model()
             .await
             .context("error adding network model")?;
+        self.net_pair
+            .add_rate_limiter(h)
+            .await
+            .context("add rate limiter")?;
         let config = self.get_network_config().context("get network config")?;
         h.add_device(DeviceType::Network(NetworkDevice {
             id: self.net_pair.virt_iface.name.clone(),
@@ -11,10 +11,12 @@ mod network_entity;
 mod network_info;
 pub use network_info::NetworkInfo;
 mod network_model;
-pub use network_model::NetworkModel;
+pub use network_model::{check as check_network_model, NetworkModel};
 mod network_with_netns;
 pub use network_with_netns::NetworkWithNetNsConfig;
 use network_with_netns::NetworkWithNetns;
+mod network_watcher;
+pub(crate) use network_watcher::LinkWatcher;
 mod network_pair;
 use network_pair::NetworkPair;
 mod utils;
@@ -31,6 +33,23 @@ pub enum NetworkConfig {
     NetworkResourceWithNetNs(NetworkWithNetNsConfig),
 }
 
+/// Changes of the network endpoints found by a rescan of the network.
+#[derive(Debug, Default)]
+pub struct NetworkChanges {
+    /// Interfaces of the endpoints hotplugged into the guest.
+    pub added_interfaces: Vec<agent::Interface>,
+    /// ARP neighbors of the hotplugged endpoints.
+    pub added_neighs: Vec<agent::ARPNeighbor>,
+    /// Names of the endpoints unplugged from the guest.
+    pub removed: Vec<String>,
+}
+
+impl NetworkChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added_interfaces.is_empty() && self.removed.is_empty()
+    }
+}
+
 #[async_trait]
 pub trait Network: Send + Sync {
     async fn setup(&self, h: &dyn Hypervisor) -> Result<()>;
@@ -39,6 +58,9 @@ pub trait Network: Send + Sync {
     async fn neighs(&self) -> Result<Vec<agent::ARPNeighbor>>;
     async fn save(&self) -> Option<Vec<EndpointState>>;
     async fn remove(&self, h: &dyn Hypervisor) -> Result<()>;
+    /// Scan the network again, hotplugging the endpoints of the new links and unplugging the
+    /// endpoints whose link is gone.
+    async fn rescan(&self, h: &dyn Hypervisor) -> Result<NetworkChanges>;
 }
 
 pub async fn new(config: &NetworkConfig) -> Result<Arc<dyn Network>> {
@@ -50,3 +72,17 @@ pub async fn new(config: &NetworkConfig) -> Result<Arc<dyn Network>> {
         )),
     }
 }
+
+/// Release the host resources of the endpoints saved in `states` by a sandbox
+/// whose runtime is gone. Must run in the network namespace of the sandbox.
+pub async fn cleanup_endpoints(states: &[EndpointState]) -> Result<()> {
+    for state in states {
+        if let Some(e) = endpoint::restore(state) {
+            let name = e.name().await;
+            e.cleanup()
+                .await
+                .with_context(|| format!("cleanup endpoint {}", name))?;
+        }
+    }
+    Ok(())
+}
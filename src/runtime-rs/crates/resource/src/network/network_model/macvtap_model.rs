@@ -0,0 +1,421 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::{
+    fs::File,
+    net::IpAddr,
+    os::unix::io::{AsRawFd, RawFd},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use netlink_packet_route::{MACVLAN_MODE_BRIDGE, RT_SCOPE_UNIVERSE};
+use rand::Rng;
+use rtnetlink::Handle;
+use scopeguard::defer;
+
+use super::{tc_filter_model::fetch_index, NetworkModel, NetworkModelType};
+use crate::network::{
+    endpoint::endpoint_persist::{MacvtapModelState, NetworkModelState},
+    network_pair::get_link_by_name,
+    utils::{self, link},
+    NetworkPair,
+};
+
+#[derive(Debug)]
+struct MacvtapInner {
+    state: MacvtapModelState,
+    // queues of the macvtap interface, handed to the hypervisor
+    files: Vec<File>,
+}
+
+/// The macvtap network model connects the VM to the virt interface through a
+/// macvtap interface created on top of it, instead of a TAP interface.
+#[derive(Debug)]
+pub(crate) struct MacvtapModel {
+    queues: usize,
+    inner: Mutex<Option<MacvtapInner>>,
+}
+
+impl MacvtapModel {
+    pub fn new(queues: usize) -> Result<Self> {
+        Ok(Self {
+            queues,
+            inner: Mutex::new(None),
+        })
+    }
+
+    /// Restore the model saved in `state`, to delete the macvtap interface
+    /// of a sandbox whose runtime is gone. The queues of the macvtap
+    /// interface aren't reopened: each open adds a queue to the interface,
+    /// and the restored model doesn't hand them to any hypervisor.
+    pub(crate) fn restore(state: &MacvtapModelState) -> Self {
+        Self {
+            queues: state.queues,
+            inner: Mutex::new(Some(MacvtapInner {
+                state: state.clone(),
+                files: vec![],
+            })),
+        }
+    }
+
+    // Hand the MAC address `hard_addr` and the addresses of the virt interface
+    // over to the macvtap interface `state.tap_name`, and open its queues.
+    async fn setup(
+        &self,
+        handle: &Handle,
+        state: &MacvtapModelState,
+        hard_addr: &[u8],
+        virt_index: u32,
+        mtu: u32,
+    ) -> Result<Vec<File>> {
+        let tap_index = fetch_index(handle, &state.tap_name)
+            .await
+            .context("fetch macvtap index")?;
+
+        // The guest takes over the MAC address and the addresses of the virt
+        // interface: the virt interface gets a random MAC address and loses
+        // its addresses, so that it doesn't answer the ARP requests for them.
+        handle
+            .link()
+            .set(virt_index)
+            .address(random_mac_addr().to_vec())
+            .execute()
+            .await
+            .context("set virt link random MAC address")?;
+        handle
+            .link()
+            .set(tap_index)
+            .address(hard_addr.to_vec())
+            .mtu(mtu)
+            .up()
+            .execute()
+            .await
+            .context("set macvtap up")?;
+        flush_addresses(handle, virt_index)
+            .await
+            .context("flush virt link addresses")?;
+
+        link::open_macvtap_queues(tap_index, self.queues).context("open macvtap queues")
+    }
+}
+
+#[async_trait]
+impl NetworkModel for MacvtapModel {
+    fn model_type(&self) -> NetworkModelType {
+        NetworkModelType::Macvtap
+    }
+
+    async fn add(&self, pair: &NetworkPair) -> Result<()> {
+        let (connection, handle, _) = rtnetlink::new_connection().context("new connection")?;
+        let thread_handler = tokio::spawn(connection);
+        defer!({
+            thread_handler.abort();
+        });
+
+        let state = MacvtapModelState {
+            tap_name: pair.tap.tap_iface.name.clone(),
+            virt_name: pair.virt_iface.name.clone(),
+            hard_addr: pair.tap.tap_iface.hard_addr.clone(),
+            addrs: pair
+                .virt_iface
+                .addrs
+                .iter()
+                // the link local addresses come with the link
+                .filter(|addr| addr.scope == RT_SCOPE_UNIVERSE)
+                .map(|addr| format!("{}/{}", addr.addr, addr.perfix_len))
+                .collect(),
+            queues: self.queues,
+        };
+        let hard_addr = utils::parse_mac(&state.hard_addr)
+            .ok_or_else(|| anyhow!("invalid MAC address {}", state.hard_addr))?;
+
+        let virt_link = get_link_by_name(&handle, &state.virt_name)
+            .await
+            .context("get virt link")?;
+        let virt_index = virt_link.attrs().index;
+        let mtu = virt_link.attrs().mtu;
+
+        handle
+            .link()
+            .add()
+            .macvtap(state.tap_name.clone(), virt_index, MACVLAN_MODE_BRIDGE)
+            .execute()
+            .await
+            .context("add macvtap")?;
+        // From now on, del() deletes the macvtap interface and restores the
+        // virt interface, which is how a failed setup is rolled back.
+        *self.inner.lock().unwrap() = Some(MacvtapInner {
+            state: state.clone(),
+            files: vec![],
+        });
+
+        let files = match self
+            .setup(&handle, &state, &hard_addr.0, virt_index, mtu)
+            .await
+        {
+            Ok(files) => files,
+            Err(e) => {
+                if let Err(err) = self.del(pair).await {
+                    warn!(sl!(), "failed to roll back macvtap: {:?}", err);
+                }
+                return Err(e);
+            }
+        };
+        info!(
+            sl!(),
+            "macvtap {} created on {} with {} queues",
+            state.tap_name,
+            state.virt_name,
+            files.len()
+        );
+        if let Some(inner) = self.inner.lock().unwrap().as_mut() {
+            inner.files = files;
+        }
+        Ok(())
+    }
+
+    async fn del(&self, _pair: &NetworkPair) -> Result<()> {
+        // Closes the queues of the macvtap interface.
+        let inner = self.inner.lock().unwrap().take();
+        let state = match inner {
+            Some(inner) => inner.state,
+            None => return Ok(()),
+        };
+
+        let (connection, handle, _) = rtnetlink::new_connection().context("new connection")?;
+        let thread_handler = tokio::spawn(connection);
+        defer!({
+            thread_handler.abort();
+        });
+
+        match fetch_index(&handle, &state.tap_name).await {
+            Ok(tap_index) => handle
+                .link()
+                .del(tap_index)
+                .execute()
+                .await
+                .context("delete macvtap")?,
+            Err(e) => warn!(sl!(), "skip deleting macvtap: {:?}", e),
+        }
+
+        // Give the MAC address and the addresses back to the virt interface,
+        // unless it is gone already.
+        let virt_index = match fetch_index(&handle, &state.virt_name).await {
+            Ok(index) => index,
+            Err(e) => {
+                warn!(sl!(), "skip restoring virt link: {:?}", e);
+                return Ok(());
+            }
+        };
+        let hard_addr = utils::parse_mac(&state.hard_addr)
+            .ok_or_else(|| anyhow!("invalid MAC address {}", state.hard_addr))?;
+        handle
+            .link()
+            .set(virt_index)
+            .address(hard_addr.0.to_vec())
+            .execute()
+            .await
+            .context("restore virt link MAC address")?;
+        for addr in &state.addrs {
+            let (ip, prefix_len) = parse_cidr(addr)?;
+            handle
+                .address()
+                .add(virt_index, ip, prefix_len)
+                .execute()
+                .await
+                .with_context(|| format!("restore address {}", addr))?;
+        }
+        Ok(())
+    }
+
+    fn queue_fds(&self) -> Vec<RawFd> {
+        match self.inner.lock().unwrap().as_ref() {
+            Some(inner) => inner.files.iter().map(|f| f.as_raw_fd()).collect(),
+            None => vec![],
+        }
+    }
+
+    fn save(&self) -> Option<NetworkModelState> {
+        self.inner
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|inner| NetworkModelState {
+                macvtap_model: Some(inner.state.clone()),
+            })
+    }
+}
+
+async fn flush_addresses(handle: &Handle, index: u32) -> Result<()> {
+    let mut addr_msgs = handle
+        .address()
+        .get()
+        .set_link_index_filter(index)
+        .execute();
+    let mut msgs = vec![];
+    while let Some(msg) = addr_msgs.try_next().await? {
+        msgs.push(msg);
+    }
+    for msg in msgs {
+        handle.address().del(msg).execute().await?;
+    }
+    Ok(())
+}
+
+// Generate a random unicast, locally administered, MAC address.
+fn random_mac_addr() -> [u8; 6] {
+    let mut addr: [u8; 6] = rand::thread_rng().gen();
+    addr[0] = (addr[0] & 0xfe) | 0x02;
+    addr
+}
+
+fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8)> {
+    let (ip, prefix_len) = cidr
+        .split_once('/')
+        .ok_or_else(|| anyhow!("invalid address {}", cidr))?;
+    let ip = ip
+        .parse::<IpAddr>()
+        .with_context(|| format!("invalid IP address {}", ip))?;
+    let prefix_len = prefix_len
+        .parse::<u8>()
+        .with_context(|| format!("invalid prefix length {}", prefix_len))?;
+    Ok((ip, prefix_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use test_utils::skip_if_not_root;
+
+    use super::*;
+    use crate::network::{
+        network_pair::{NetworkInterface, TapInterface},
+        utils::link::net_test_utils::delete_link,
+    };
+
+    #[test]
+    fn test_random_mac_addr() {
+        for _ in 0..16 {
+            let addr = random_mac_addr();
+            assert_eq!(addr[0] & 0x01, 0);
+            assert_eq!(addr[0] & 0x02, 0x02);
+        }
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(
+            parse_cidr("172.16.0.2/24").unwrap(),
+            ("172.16.0.2".parse().unwrap(), 24)
+        );
+        assert_eq!(
+            parse_cidr("fe80::1/64").unwrap(),
+            ("fe80::1".parse().unwrap(), 64)
+        );
+        assert!(parse_cidr("172.16.0.2").is_err());
+        assert!(parse_cidr("172.16.0/24").is_err());
+    }
+
+    #[test]
+    fn test_macvtap_model_state() {
+        let model = MacvtapModel::new(2).unwrap();
+        assert!(model.queue_fds().is_empty());
+        assert!(model.save().is_none());
+
+        let file = tempfile::tempfile().unwrap();
+        let fd = file.as_raw_fd();
+        *model.inner.lock().unwrap() = Some(MacvtapInner {
+            state: MacvtapModelState {
+                tap_name: "tap0_kata".to_string(),
+                virt_name: "eth0".to_string(),
+                hard_addr: "02:42:ac:11:00:02".to_string(),
+                addrs: vec!["172.17.0.2/16".to_string()],
+                queues: 2,
+            },
+            files: vec![file],
+        });
+        assert_eq!(model.queue_fds(), vec![fd]);
+        let state = model.save().unwrap().macvtap_model.unwrap();
+        assert_eq!(state.tap_name, "tap0_kata");
+        assert_eq!(state.addrs, vec!["172.17.0.2/16".to_string()]);
+    }
+
+    #[test]
+    fn test_macvtap_model_restore() {
+        let state = MacvtapModelState {
+            tap_name: "tap0_kata".to_string(),
+            virt_name: "eth0".to_string(),
+            hard_addr: "02:42:ac:11:00:02".to_string(),
+            addrs: vec![],
+            queues: 4,
+        };
+        let model = MacvtapModel::restore(&state);
+        assert_eq!(model.queues, 4);
+        // the queues of the macvtap interface aren't reopened
+        assert!(model.queue_fds().is_empty());
+        let state = model.save().unwrap().macvtap_model.unwrap();
+        assert_eq!(state.tap_name, "tap0_kata");
+        assert_eq!(state.virt_name, "eth0");
+    }
+
+    #[actix_rt::test]
+    async fn test_macvtap_model_add_rollback() {
+        skip_if_not_root!();
+
+        let (connection, handle, _) = rtnetlink::new_connection().unwrap();
+        let thread_handler = tokio::spawn(connection);
+        defer!({
+            thread_handler.abort();
+        });
+
+        let virt_name = "kata_mvt_virt";
+        let tap_name = "kata_mvt_tap";
+        handle
+            .link()
+            .add()
+            .dummy(virt_name.to_string())
+            .execute()
+            .await
+            .unwrap();
+        let virt_link = get_link_by_name(&handle, virt_name).await.unwrap();
+        let hard_addr = utils::get_mac_addr(&virt_link.attrs().hardware_addr).unwrap();
+
+        // a macvtap interface takes 256 queues at most, opening more fails
+        // once the macvtap interface is set up
+        let model = Arc::new(MacvtapModel::new(257).unwrap());
+        let pair = NetworkPair {
+            tap: TapInterface {
+                tap_iface: NetworkInterface {
+                    name: tap_name.to_string(),
+                    hard_addr: hard_addr.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            virt_iface: NetworkInterface {
+                name: virt_name.to_string(),
+                ..Default::default()
+            },
+            model: model.clone(),
+            network_qos: false,
+        };
+        assert!(model.add(&pair).await.is_err());
+
+        // the macvtap interface is deleted and the virt interface restored
+        assert!(get_link_by_name(&handle, tap_name).await.is_err());
+        let virt_link = get_link_by_name(&handle, virt_name).await.unwrap();
+        assert_eq!(
+            utils::get_mac_addr(&virt_link.attrs().hardware_addr).unwrap(),
+            hard_addr
+        );
+        assert!(model.save().is_none());
+
+        delete_link(&handle, virt_name).await.unwrap();
+    }
+}
@@ -4,21 +4,26 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+pub mod macvtap_model;
 pub mod none_model;
+pub(crate) mod rate_limiter;
 pub mod tc_filter_model;
 pub mod test_network_model;
-use std::sync::Arc;
+use std::{os::unix::io::RawFd, sync::Arc};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use hypervisor::Hypervisor;
 
-use super::NetworkPair;
+use super::{endpoint::endpoint_persist::NetworkModelState, NetworkPair};
 
 pub(crate) const TC_FILTER_NET_MODEL_STR: &str = "tcfilter";
+pub(crate) const MACVTAP_NET_MODEL_STR: &str = "macvtap";
 
 pub enum NetworkModelType {
     NoneModel,
     TcFilter,
+    Macvtap,
 }
 
 #[async_trait]
@@ -26,15 +31,53 @@ pub trait NetworkModel: std::fmt::Debug + Send + Sync {
     fn model_type(&self) -> NetworkModelType;
     async fn add(&self, net_pair: &NetworkPair) -> Result<()>;
     async fn del(&self, net_pair: &NetworkPair) -> Result<()>;
+
+    /// Queues of the TAP interface opened by the model, handed to the hypervisor.
+    fn queue_fds(&self) -> Vec<RawFd> {
+        vec![]
+    }
+
+    /// Save the state of the model, for the models with host resources to restore.
+    fn save(&self) -> Option<NetworkModelState> {
+        None
+    }
 }
 
-pub fn new(model: &str) -> Result<Arc<dyn NetworkModel>> {
+pub fn new(model: &str, queues: usize) -> Result<Arc<dyn NetworkModel>> {
     match model {
         TC_FILTER_NET_MODEL_STR => Ok(Arc::new(
             tc_filter_model::TcFilterModel::new().context("new tc filter model")?,
         )),
+        MACVTAP_NET_MODEL_STR => Ok(Arc::new(
+            macvtap_model::MacvtapModel::new(queues).context("new macvtap model")?,
+        )),
         _ => Ok(Arc::new(
             none_model::NoneModel::new().context("new none model")?,
         )),
     }
 }
+
+/// Restore the network model saved in `state`, `None` if it holds no host
+/// resources to release.
+pub fn restore(state: &NetworkModelState) -> Option<Arc<dyn NetworkModel>> {
+    state
+        .macvtap_model
+        .as_ref()
+        .map(|state| Arc::new(macvtap_model::MacvtapModel::restore(state)) as _)
+}
+
+/// Check that the hypervisor can back the network devices of the network
+/// model `model`, before any endpoint is set up.
+pub async fn check(model: &str, h: &dyn Hypervisor) -> Result<()> {
+    if model != MACVTAP_NET_MODEL_STR {
+        return Ok(());
+    }
+    let capabilities = h.capabilities().await.context("get capabilities")?;
+    if !capabilities.is_net_queue_fds_supported() {
+        return Err(anyhow!(
+            "the hypervisor doesn't support the {} network model, it can't use the macvtap queues opened by the runtime",
+            model
+        ));
+    }
+    Ok(())
+}
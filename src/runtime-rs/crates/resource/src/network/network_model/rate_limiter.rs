@@ -0,0 +1,102 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! tc based rate limiting of the TAP interface of a network pair, for the
+//! hypervisors without a native network rate limiter.
+
+use anyhow::{anyhow, Context, Result};
+use tokio::process::Command;
+
+const TC_BIN: &str = "tc";
+// Time window of the ingress policer burst, in milliseconds.
+const POLICER_BURST_MS: u64 = 10;
+// Smallest burst size accepted by the policer, in bytes.
+const MIN_POLICER_BURST: u64 = 1600;
+
+/// Limit the traffic going through the TAP interface `tap_name`, the rates are in bits per
+/// second and 0 means unlimited.
+///
+/// The receive rate of the guest is the egress rate of the TAP interface, it is shaped by an HTB
+/// qdisc. The transmit rate of the guest is the ingress rate of the TAP interface, which can only
+/// be policed: the packets above the rate are dropped.
+pub(crate) async fn add_rate_limiter(tap_name: &str, rx_rate: u64, tx_rate: u64) -> Result<()> {
+    for args in tc_commands(tap_name, rx_rate, tx_rate) {
+        run_tc(&args).await?;
+    }
+    Ok(())
+}
+
+fn tc_commands(tap_name: &str, rx_rate: u64, tx_rate: u64) -> Vec<Vec<String>> {
+    let mut commands = vec![];
+    if rx_rate > 0 {
+        commands.push(format!(
+            "qdisc add dev {} root handle 1: htb default 1",
+            tap_name
+        ));
+        commands.push(format!(
+            "class add dev {} parent 1: classid 1:1 htb rate {}bit",
+            tap_name, rx_rate
+        ));
+    }
+    if tx_rate > 0 {
+        // The ingress qdisc may have been added by the tc filter network
+        // model already, the conforming packets continue to its redirect
+        // filter.
+        let burst = (tx_rate / 8 * POLICER_BURST_MS / 1000).max(MIN_POLICER_BURST);
+        commands.push(format!("qdisc replace dev {} ingress", tap_name));
+        commands.push(format!(
+            "filter add dev {} parent ffff: protocol all prio 1 u32 match u32 0 0 \
+             police rate {}bit burst {} conform-exceed drop/continue",
+            tap_name, tx_rate, burst
+        ));
+    }
+    commands
+        .iter()
+        .map(|command| command.split_whitespace().map(String::from).collect())
+        .collect()
+}
+
+async fn run_tc(args: &[String]) -> Result<()> {
+    let output = Command::new(TC_BIN)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("run {} {}", TC_BIN, args.join(" ")))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} {} failed: {}",
+            TC_BIN,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tc_commands() {
+        assert!(tc_commands("tap0_kata", 0, 0).is_empty());
+
+        let commands = tc_commands("tap0_kata", 8_000_000, 0);
+        assert_eq!(commands.len(), 2);
+        assert_eq!(
+            commands[1].join(" "),
+            "class add dev tap0_kata parent 1: classid 1:1 htb rate 8000000bit"
+        );
+
+        let commands = tc_commands("tap0_kata", 0, 8_000_000);
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].join(" "), "qdisc replace dev tap0_kata ingress");
+        assert_eq!(
+            commands[1].join(" "),
+            "filter add dev tap0_kata parent ffff: protocol all prio 1 u32 match u32 0 0 \
+             police rate 8000000bit burst 10000 conform-exceed drop/continue"
+        );
+    }
+}
@@ -89,7 +89,15 @@ impl NetworkModel for TcFilterModel {
         defer!({
             thread_handler.abort();
         });
-        let virt_index = fetch_index(&handle, &pair.virt_iface.name).await?;
+        // The link, and its qdisc, may be gone already when unplugging the
+        // endpoint of a link removed from the netns.
+        let virt_index = match fetch_index(&handle, &pair.virt_iface.name).await {
+            Ok(index) => index,
+            Err(e) => {
+                warn!(sl!(), "skip deleting qdisc: {:?}", e);
+                return Ok(());
+            }
+        };
         handle.qdisc().del(virt_index as i32).execute().await?;
         Ok(())
     }
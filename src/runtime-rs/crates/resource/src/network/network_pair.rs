@@ -4,13 +4,15 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::{convert::TryFrom, sync::Arc, usize};
+use std::{convert::TryFrom, os::unix::io::RawFd, sync::Arc, usize};
 
 use anyhow::{anyhow, Context, Result};
 use futures::stream::TryStreamExt;
+use hypervisor::Hypervisor;
 
 use super::{
-    network_model,
+    endpoint::endpoint_persist::NetworkModelState,
+    network_model::{self, NetworkModelType},
     utils::{self, address::Address, link},
 };
 
@@ -48,12 +50,13 @@ impl NetworkPair {
         queues: usize,
     ) -> Result<Self> {
         let unique_id = kata_sys_util::rand::UUID::new();
-        let model = network_model::new(model).context("new network model")?;
+        let model = network_model::new(model, queues).context("new network model")?;
         let tap_iface_name = format!("tap{}{}", idx, TAP_SUFFIX);
-        let virt_iface_name = format!("eth{}", idx);
-        let tap_link = create_link(handle, &tap_iface_name, queues)
-            .await
-            .context("create link")?;
+        let virt_iface_name = if name.is_empty() {
+            format!("eth{}", idx)
+        } else {
+            String::from(name)
+        };
 
         let virt_link = get_link_by_name(handle, virt_iface_name.clone().as_str())
             .await
@@ -79,29 +82,39 @@ impl NetworkPair {
         let tap_hard_addr =
             utils::get_mac_addr(&virt_link.attrs().hardware_addr).context("get mac addr")?;
 
-        // Save the TAP Mac address to the virt_iface so that it can later updated
-        // the guest's gateway IP's mac as this TAP device. This MAC address has
-        // to be inside the VM in order to the network reach to the gateway.
-        let virt_hard_addr =
-            utils::get_mac_addr(&tap_link.attrs().hardware_addr).context("get mac addr")?;
+        let virt_hard_addr = match model.model_type() {
+            // The macvtap network model creates the TAP interface, as a
+            // macvtap interface taking over the virt_iface MAC address.
+            NetworkModelType::Macvtap => tap_hard_addr.clone(),
+            _ => {
+                let tap_link = create_link(handle, &tap_iface_name, queues)
+                    .await
+                    .context("create link")?;
 
-        handle
-            .link()
-            .set(tap_link.attrs().index)
-            .mtu(virt_link.attrs().mtu)
-            .execute()
-            .await
-            .context("set link mtu")?;
+                handle
+                    .link()
+                    .set(tap_link.attrs().index)
+                    .mtu(virt_link.attrs().mtu)
+                    .execute()
+                    .await
+                    .context("set link mtu")?;
 
-        handle
-            .link()
-            .set(tap_link.attrs().index)
-            .up()
-            .execute()
-            .await
-            .context("set link up")?;
+                handle
+                    .link()
+                    .set(tap_link.attrs().index)
+                    .up()
+                    .execute()
+                    .await
+                    .context("set link up")?;
 
-        let mut net_pair = NetworkPair {
+                // Save the TAP Mac address to the virt_iface so that it can later updated
+                // the guest's gateway IP's mac as this TAP device. This MAC address has
+                // to be inside the VM in order to the network reach to the gateway.
+                utils::get_mac_addr(&tap_link.attrs().hardware_addr).context("get mac addr")?
+            }
+        };
+
+        let net_pair = NetworkPair {
             tap: TapInterface {
                 id: String::from(&unique_id),
                 name: format!("br{}{}", idx, TAP_SUFFIX),
@@ -120,19 +133,65 @@ impl NetworkPair {
             network_qos: false,
         };
 
-        if !name.is_empty() {
-            net_pair.virt_iface.name = String::from(name);
-        }
-
         Ok(net_pair)
     }
 
+    /// Restore the pair of an endpoint whose network model saved `state`, to
+    /// release the host resources of the model. `None` if the model holds no
+    /// host resources.
+    pub(crate) fn restore(
+        virt_name: &str,
+        network_qos: bool,
+        state: &NetworkModelState,
+    ) -> Option<Self> {
+        let model = network_model::restore(state)?;
+        Some(NetworkPair {
+            // the TAP interface is known by the model
+            tap: TapInterface::default(),
+            virt_iface: NetworkInterface {
+                name: virt_name.to_string(),
+                ..Default::default()
+            },
+            model,
+            network_qos,
+        })
+    }
+
     pub(crate) async fn add_network_model(&self) -> Result<()> {
         let model = self.model.clone();
         model.add(self).await.context("add")?;
         Ok(())
     }
 
+    /// Queues of the TAP interface opened by the network model, if any.
+    pub(crate) fn queue_fds(&self) -> Vec<RawFd> {
+        self.model.queue_fds()
+    }
+
+    pub(crate) fn save_network_model(&self) -> Option<NetworkModelState> {
+        self.model.save()
+    }
+
+    /// Rate limit the TAP interface with tc when the hypervisor can't rate limit the network
+    /// device by itself.
+    pub(crate) async fn add_rate_limiter(&self, h: &dyn Hypervisor) -> Result<()> {
+        let network_info = h.hypervisor_config().await.network_info;
+        let (rx_rate, tx_rate) = (
+            network_info.rx_rate_limiter_max_rate,
+            network_info.tx_rate_limiter_max_rate,
+        );
+        if rx_rate == 0 && tx_rate == 0 {
+            return Ok(());
+        }
+        let capabilities = h.capabilities().await.context("get capabilities")?;
+        if capabilities.is_net_rate_limiter_supported() {
+            return Ok(());
+        }
+        network_model::rate_limiter::add_rate_limiter(&self.tap.tap_iface.name, rx_rate, tx_rate)
+            .await
+            .context("add tc rate limiter")
+    }
+
     pub(crate) async fn del_network_model(&self) -> Result<()> {
         let model = self.model.clone();
         model.del(self).await.context("del")?;
@@ -140,6 +199,11 @@ impl NetworkPair {
     }
 }
 
+/// Check whether `name` is a TAP interface created for a network pair.
+pub(crate) fn is_tap_iface(name: &str) -> bool {
+    name.starts_with("tap") && name.ends_with(TAP_SUFFIX)
+}
+
 pub async fn create_link(
     handle: &rtnetlink::Handle,
     name: &str,
@@ -218,6 +282,13 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_is_tap_iface() {
+        assert!(is_tap_iface(&format!("tap0{}", TAP_SUFFIX)));
+        assert!(!is_tap_iface("eth0"));
+        assert!(!is_tap_iface("tap0"));
+    }
+
     #[actix_rt::test]
     async fn test_network_pair() {
         let idx = 123456;
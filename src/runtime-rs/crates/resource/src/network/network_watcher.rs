@@ -0,0 +1,62 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use anyhow::{Context, Result};
+use futures::{channel::mpsc::UnboundedReceiver, StreamExt};
+use netlink_packet_route::{NetlinkMessage, NetlinkPayload, RtnlMessage, RTMGRP_LINK};
+use netlink_sys::{AsyncSocket, SocketAddr};
+use tokio::task::JoinHandle;
+
+use super::utils::netns;
+
+/// Watch the links added to or removed from a network namespace.
+pub(crate) struct LinkWatcher {
+    connection: JoinHandle<()>,
+    messages: UnboundedReceiver<(NetlinkMessage<RtnlMessage>, SocketAddr)>,
+}
+
+impl LinkWatcher {
+    pub(crate) fn new(netns_path: &str) -> Result<Self> {
+        // The netlink socket stays in the netns it has been created in, no
+        // need to stay in the netns to use it.
+        let (mut connection, _, messages) = {
+            let _netns_guard = netns::NetnsGuard::new(netns_path).context("net netns guard")?;
+            rtnetlink::new_connection().context("new connection")?
+        };
+        connection
+            .socket_mut()
+            .socket_mut()
+            .bind(&SocketAddr::new(0, RTMGRP_LINK))
+            .context("bind link multicast group")?;
+
+        Ok(Self {
+            connection: tokio::spawn(connection),
+            messages,
+        })
+    }
+
+    /// Wait for a link to be added or removed, return false once the watch is over.
+    pub(crate) async fn changed(&mut self) -> bool {
+        while let Some((message, _)) = self.messages.next().await {
+            if let NetlinkPayload::InnerMessage(RtnlMessage::NewLink(_))
+            | NetlinkPayload::InnerMessage(RtnlMessage::DelLink(_)) = message.payload
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Discard the changes received so far.
+    pub(crate) fn clear(&mut self) {
+        while let Ok(Some(_)) = self.messages.try_next() {}
+    }
+}
+
+impl Drop for LinkWatcher {
+    fn drop(&mut self) {
+        self.connection.abort();
+    }
+}
@@ -5,6 +5,7 @@
 //
 
 use std::{
+    collections::HashMap,
     fs,
     sync::{
         atomic::{AtomicU32, Ordering},
@@ -23,16 +24,18 @@ use tokio::sync::RwLock;
 
 use super::{
     endpoint::{
-        Endpoint, IPVlanEndpoint, MacVlanEndpoint, PhysicalEndpoint, VethEndpoint, VlanEndpoint,
+        find_vhost_user_socket, Endpoint, IPVlanEndpoint, MacVlanEndpoint, PhysicalEndpoint,
+        TapEndpoint, VethEndpoint, VhostUserEndpoint, VlanEndpoint,
     },
     network_entity::NetworkEntity,
     network_info::network_info_from_link::NetworkInfoFromLink,
+    network_pair::is_tap_iface,
     utils::{link, netns},
-    Network,
+    Network, NetworkChanges,
 };
 use crate::network::NetworkInfo;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NetworkWithNetNsConfig {
     pub network_model: String,
     pub netns_path: String,
@@ -41,9 +44,10 @@ pub struct NetworkWithNetNsConfig {
 }
 
 struct NetworkWithNetnsInner {
-    netns_path: String,
+    config: NetworkWithNetNsConfig,
     entity_list: Vec<NetworkEntity>,
-    network_created: bool,
+    // index of the next endpoint, naming its TAP interface
+    next_idx: u32,
 }
 
 impl NetworkWithNetnsInner {
@@ -58,9 +62,9 @@ impl NetworkWithNetnsInner {
                 .context("get entity from netns")?
         };
         Ok(Self {
-            netns_path: config.netns_path.to_string(),
+            config: config.clone(),
+            next_idx: entity_list.len() as u32,
             entity_list,
-            network_created: config.network_created,
         })
     }
 }
@@ -81,7 +85,8 @@ impl NetworkWithNetns {
 impl Network for NetworkWithNetns {
     async fn setup(&self, h: &dyn Hypervisor) -> Result<()> {
         let inner = self.inner.read().await;
-        let _netns_guard = netns::NetnsGuard::new(&inner.netns_path).context("net netns guard")?;
+        let _netns_guard =
+            netns::NetnsGuard::new(&inner.config.netns_path).context("net netns guard")?;
         for e in &inner.entity_list {
             e.endpoint.attach(h).await.context("attach")?;
         }
@@ -132,21 +137,106 @@ impl Network for NetworkWithNetns {
         let inner = self.inner.read().await;
         // The network namespace would have been deleted at this point
         // if it has not been created by virtcontainers.
-        if !inner.network_created {
+        if !inner.config.network_created {
             return Ok(());
         }
         {
             let _netns_guard =
-                netns::NetnsGuard::new(&inner.netns_path).context("net netns guard")?;
+                netns::NetnsGuard::new(&inner.config.netns_path).context("net netns guard")?;
             for e in &inner.entity_list {
                 e.endpoint.detach(h).await.context("detach")?;
             }
         }
-        let netns = get_from_path(inner.netns_path.clone())?;
+        let netns = get_from_path(inner.config.netns_path.clone())?;
         netns.remove()?;
-        fs::remove_dir_all(inner.netns_path.clone()).context("failed to remove netns path")?;
+        fs::remove_dir_all(inner.config.netns_path.clone())
+            .context("failed to remove netns path")?;
         Ok(())
     }
+
+    async fn rescan(&self, h: &dyn Hypervisor) -> Result<NetworkChanges> {
+        let mut inner = self.inner.write().await;
+        let mut changes = NetworkChanges::default();
+        if inner.config.netns_path.is_empty() {
+            return Ok(changes);
+        }
+
+        let _netns_guard =
+            netns::NetnsGuard::new(&inner.config.netns_path).context("net netns guard")?;
+        let (connection, handle, _) = rtnetlink::new_connection().context("new connection")?;
+        let thread_handler = tokio::spawn(connection);
+        defer!({
+            thread_handler.abort();
+        });
+        let mut links = get_links(&handle).await.context("get links")?;
+
+        // unplug the endpoints whose link is gone, but the physical ones,
+        // whose link is gone once bound to vfio
+        let mut i = 0;
+        while i < inner.entity_list.len() {
+            let endpoint = inner.entity_list[i].endpoint.clone();
+            let name = endpoint.name().await;
+            if links.remove(&name).is_some() || is_physical_endpoint(endpoint.as_ref()).await {
+                i += 1;
+                continue;
+            }
+            info!(sl!(), "network interface {} removed", name);
+            endpoint
+                .detach(h)
+                .await
+                .with_context(|| format!("detach {}", name))?;
+            inner.entity_list.remove(i);
+            changes.removed.push(name);
+        }
+
+        // hotplug the endpoints of the new links
+        let mut links: Vec<Box<dyn link::Link>> = links.into_values().collect();
+        links.sort_by_key(|link| link.attrs().index);
+        for link in links {
+            let idx = inner.next_idx;
+            inner.next_idx += 1;
+            let (endpoint, network_info) =
+                create_endpoint(&handle, link.as_ref(), idx, &inner.config)
+                    .await
+                    .context("create endpoint")?;
+            endpoint.attach(h).await.context("attach")?;
+
+            changes
+                .added_interfaces
+                .push(network_info.interface().await.context("interface")?);
+            changes
+                .added_neighs
+                .append(&mut network_info.neighs().await.context("neighs")?);
+            inner
+                .entity_list
+                .push(NetworkEntity::new(endpoint, network_info));
+        }
+
+        Ok(changes)
+    }
+}
+
+async fn is_physical_endpoint(endpoint: &dyn Endpoint) -> bool {
+    endpoint
+        .save()
+        .await
+        .map_or(false, |state| state.physical_endpoint.is_some())
+}
+
+// Get the links of the current netns by name, but the loopback and the TAP
+// interfaces created for the endpoints.
+async fn get_links(handle: &rtnetlink::Handle) -> Result<HashMap<String, Box<dyn link::Link>>> {
+    let mut links = HashMap::new();
+    let mut link_msgs = handle.link().get().execute();
+    while let Some(link) = link_msgs.try_next().await? {
+        let link = link::get_link_from_message(link);
+        let attrs = link.attrs();
+        if (attrs.flags & libc::IFF_LOOPBACK as u32) != 0 || is_tap_iface(&attrs.name) {
+            continue;
+        }
+        links.insert(attrs.name.clone(), link);
+    }
+    Ok(links)
 }
 
 async fn get_entity_from_netns(config: &NetworkWithNetNsConfig) -> Result<Vec<NetworkEntity>> {
@@ -209,6 +299,17 @@ async fn create_endpoint(
         let t = PhysicalEndpoint::new(&attrs.name, &attrs.hardware_addr)
             .context("new physical endpoint")?;
         Arc::new(t)
+    } else if let Some(socket_path) = find_vhost_user_socket(handle, attrs.index, &attrs.alias)
+        .await
+        .context("find vhost-user socket")?
+    {
+        info!(
+            sl!(),
+            "vhost-user network interface found: {} {}", &attrs.name, &socket_path
+        );
+        let t = VhostUserEndpoint::new(&attrs.name, &attrs.hardware_addr, &socket_path)
+            .context("new vhost-user endpoint")?;
+        Arc::new(t)
     } else {
         info!(
             sl!(),
@@ -251,6 +352,12 @@ async fn create_endpoint(
                 .context("macvlan endpoint")?;
                 Arc::new(ret)
             }
+            // only the TAP mode of the tuntap interfaces is supported
+            "tuntap" if attrs.link_layer_type == libc::ARPHRD_ETHER => {
+                let ret =
+                    TapEndpoint::new(&attrs.name, &attrs.hardware_addr).context("tap endpoint")?;
+                Arc::new(ret)
+            }
             _ => return Err(anyhow!("unsupported link type: {}", link_type)),
         }
     };
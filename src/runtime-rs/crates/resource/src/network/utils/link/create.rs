@@ -73,6 +73,7 @@ impl CreateLinkReq {
 }
 
 const DEVICE_PATH: &str = "/dev/net/tun";
+const MACVTAP_DEVICE_PATH_PREFIX: &str = "/dev/tap";
 
 ioctl_write_ptr!(tun_set_iff, b'T', 202, libc::c_int);
 ioctl_write_ptr!(tun_set_persist, b'T', 203, libc::c_int);
@@ -117,6 +118,33 @@ pub fn create_link(name: &str, link_type: LinkType, queues: usize) -> Result<()>
     Ok(())
 }
 
+/// Open `queues` queues of the macvtap interface of index `index`, each open
+/// of its character device adds a queue.
+pub fn open_macvtap_queues(index: u32, queues: usize) -> Result<Vec<File>> {
+    let path = format!("{}{}", MACVTAP_DEVICE_PATH_PREFIX, index);
+    let mut flags = libc::IFF_TAP | libc::IFF_NO_PI | libc::IFF_VNET_HDR;
+    let queues = if queues == 0 { 1 } else { queues };
+    if queues > 1 {
+        flags |= libc::IFF_MULTI_QUEUE;
+    }
+
+    let mut files = vec![];
+    for _ in 0..queues {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("open {}", path))?;
+        let mut req = CreateLinkReq::from_name("")?;
+        unsafe {
+            req.set_raw_flags(flags as libc::c_short);
+            tun_set_iff(file.as_raw_fd(), &mut req as *mut _ as *mut _).context("tun set iff")?;
+        };
+        files.push(file);
+    }
+    Ok(files)
+}
+
 fn create_queue(name: &str, flags: libc::c_int) -> Result<(File, String)> {
     let path = Path::new(DEVICE_PATH);
     let file = OpenOptions::new().read(true).write(true).open(path)?;
@@ -5,7 +5,7 @@
 //
 
 mod create;
-pub use create::{create_link, LinkType};
+pub use create::{create_link, open_macvtap_queues, LinkType};
 mod driver_info;
 pub use driver_info::{get_driver_info, DriverInfo};
 mod macros;
@@ -11,5 +11,7 @@ use crate::cgroups::cgroup_persist::CgroupState;
 #[derive(Serialize, Deserialize, Default)]
 pub struct ResourceState {
     pub endpoint: Vec<EndpointState>,
+    /// network namespace of the endpoints
+    pub netns: Option<String>,
     pub cgroup_state: Option<CgroupState>,
 }
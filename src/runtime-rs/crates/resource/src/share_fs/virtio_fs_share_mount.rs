@@ -15,8 +15,8 @@ use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
-const WATCHABLE_PATH_NAME: &str = "watchable";
-const WATCHABLE_BIND_DEV_TYPE: &str = "watchable-bind";
+pub(crate) const WATCHABLE_PATH_NAME: &str = "watchable";
+pub(crate) const WATCHABLE_BIND_DEV_TYPE: &str = "watchable-bind";
 pub const EPHEMERAL_PATH: &str = "/run/kata-containers/sandbox/ephemeral";
 
 use super::{
@@ -7,10 +7,12 @@
 mod block_volume;
 mod default_volume;
 pub mod hugepage;
+mod share_copy;
 mod share_fs_volume;
 mod shm_volume;
 use async_trait::async_trait;
 
+use agent::Agent;
 use anyhow::{Context, Result};
 use hypervisor::device::device_manager::DeviceManager;
 use std::{sync::Arc, vec::Vec};
@@ -48,6 +50,7 @@ impl VolumeResource {
     pub async fn handler_volumes(
         &self,
         share_fs: &Option<Arc<dyn ShareFs>>,
+        agent: Arc<dyn Agent>,
         cid: &str,
         spec: &oci::Spec,
         d: &RwLock<DeviceManager>,
@@ -85,7 +88,7 @@ impl VolumeResource {
                 )
             } else if share_fs_volume::is_share_fs_volume(m) {
                 Arc::new(
-                    share_fs_volume::ShareFsVolume::new(share_fs, m, cid, read_only)
+                    share_fs_volume::ShareFsVolume::new(share_fs, agent.clone(), m, cid, read_only)
                         .await
                         .with_context(|| format!("new share fs volume {:?}", m))?,
                 )
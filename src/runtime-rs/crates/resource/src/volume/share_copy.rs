@@ -0,0 +1,523 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Volume sharing by copy, used when no shared filesystem is configured.
+//!
+//! The files of the volume are pushed to the guest through the `CopyFile` agent RPC, and copied
+//! again when changed on the host, as reported by inotify. Files removed on the host are kept in
+//! the guest, the agent can't remove them.
+//!
+//! The copies live in the guest memory, so volumes with more than `MAX_COPIED_ENTRIES` entries or
+//! `MAX_COPIED_SIZE` bytes of files can't be shared by copy.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::Read,
+    os::unix::{
+        fs::MetadataExt,
+        io::{AsRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use agent::{Agent, CopyFileRequest, Storage};
+use anyhow::{anyhow, Context, Result};
+use kata_types::k8s::is_watchable_mount;
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use tokio::task::JoinHandle;
+
+use crate::share_fs::{KATA_GUEST_SHARE_DIR, WATCHABLE_BIND_DEV_TYPE, WATCHABLE_PATH_NAME};
+
+// Size of the data sent by each CopyFile request, far below the ttrpc
+// message size limit.
+const COPY_FILE_CHUNK_SIZE: usize = 1024 * 1024;
+// Mode of the guest directories created for the copied files.
+const COPY_DIR_MODE: u32 = 0o750;
+const LOCAL_DEV_TYPE: &str = "local";
+// Interval of the checks for inotify events, which are coalesced until then.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+// Maximum number of files and directories of a volume shared by copy.
+const MAX_COPIED_ENTRIES: usize = 1024;
+// Maximum total size of the files of a volume shared by copy.
+const MAX_COPIED_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The volume source exceeds the limits of the volumes shared by copy.
+#[derive(Debug)]
+struct SourceTooLarge(PathBuf);
+
+impl fmt::Display for SourceTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} has more than {} entries or {} bytes, too large to be copied to the guest",
+            self.0, MAX_COPIED_ENTRIES, MAX_COPIED_SIZE
+        )
+    }
+}
+
+impl std::error::Error for SourceTooLarge {}
+
+// Identity of the content of a file: a file rewritten in place changes its
+// modification time or size, a file replaced changes its inode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl From<&fs::Metadata> for FileStamp {
+    fn from(metadata: &fs::Metadata) -> Self {
+        FileStamp {
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        }
+    }
+}
+
+/// The files of a host volume source, a regular file or a directory tree, copied to a guest path.
+#[derive(Debug)]
+pub(crate) struct CopiedSource {
+    source: PathBuf,
+    guest_path: PathBuf,
+    // relative path of the files copied so far -> stamp when copied
+    files: HashMap<PathBuf, FileStamp>,
+}
+
+impl CopiedSource {
+    pub(crate) fn new(source: &Path, guest_path: &Path) -> Self {
+        CopiedSource {
+            source: source.to_path_buf(),
+            guest_path: guest_path.to_path_buf(),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Copy the files changed since the last sync to the guest, returning the directories of the
+    /// source to watch.
+    pub(crate) async fn sync(&mut self, agent: &dyn Agent) -> Result<Vec<PathBuf>> {
+        // The walk may be long, keep it off the async runtime threads.
+        let source = self.source.clone();
+        let files = std::mem::take(&mut self.files);
+        let (files, scanned) = tokio::task::spawn_blocking(move || {
+            let scanned = scan(&source, &files);
+            (files, scanned)
+        })
+        .await
+        .context("join scan task")?;
+        self.files = files;
+
+        let (changed, dirs) = scanned?;
+        for (relative, stamp) in changed {
+            let (source, guest_path) = if relative.as_os_str().is_empty() {
+                (self.source.clone(), self.guest_path.clone())
+            } else {
+                (self.source.join(&relative), self.guest_path.join(&relative))
+            };
+            copy_file(agent, &source, &guest_path).await?;
+            self.files.insert(relative, stamp);
+        }
+        Ok(dirs)
+    }
+}
+
+// Walk `source` and return the files changed since their stamps in `files`,
+// along with their new stamps, and the directories of the source. Symlinks are
+// followed, entries starting with ".." are skipped, they're the internals of
+// the atomic updates of the kubernetes configmap and secret volumes.
+fn scan(
+    source: &Path,
+    files: &HashMap<PathBuf, FileStamp>,
+) -> Result<(Vec<(PathBuf, FileStamp)>, Vec<PathBuf>)> {
+    let mut changed = vec![];
+    let mut dirs = vec![];
+    let mut pending = vec![PathBuf::new()];
+    let mut entries = 0;
+    let mut size = 0;
+
+    while let Some(relative) = pending.pop() {
+        let path = source.join(&relative);
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            // removed since listed, or a dangling symlink
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("stat {:?}", path)),
+        };
+
+        if metadata.is_dir() {
+            for entry in fs::read_dir(&path).with_context(|| format!("read dir {:?}", path))? {
+                let name = entry.context("read dir entry")?.file_name();
+                if !name.to_string_lossy().starts_with("..") {
+                    pending.push(relative.join(name));
+                }
+            }
+            dirs.push(path);
+        } else if metadata.is_file() {
+            size += metadata.size();
+            let stamp = FileStamp::from(&metadata);
+            if files.get(&relative) != Some(&stamp) {
+                changed.push((relative, stamp));
+            }
+        } else {
+            continue;
+        }
+
+        entries += 1;
+        if entries > MAX_COPIED_ENTRIES || size > MAX_COPIED_SIZE {
+            return Err(SourceTooLarge(source.to_path_buf()).into());
+        }
+    }
+
+    Ok((changed, dirs))
+}
+
+/// Result of sharing a volume by copy.
+pub(crate) struct ShareCopyResult {
+    /// Guest path to bind mount into the container.
+    pub(crate) guest_path: String,
+    pub(crate) storages: Vec<Storage>,
+    pub(crate) watcher: CopyWatcher,
+}
+
+/// Copy the volume source `source` to the guest share directory as `target`, and keep it in
+/// sync. Watchable mounts (configmaps, secrets, ...) are bound through a `watchable-bind`
+/// storage, for the container to see the updates of a file replaced by a new copy.
+pub(crate) async fn share_volume_by_copy(
+    agent: Arc<dyn Agent>,
+    source: &Path,
+    target: &str,
+    mount_options: &[String],
+) -> Result<ShareCopyResult> {
+    let guest_path = Path::new(KATA_GUEST_SHARE_DIR).join(target);
+    let mut copied = CopiedSource::new(source, &guest_path);
+    let dirs = copied
+        .sync(agent.as_ref())
+        .await
+        .with_context(|| format!("copy {:?} to guest", source))?;
+
+    let mut guest_path = guest_path
+        .into_os_string()
+        .into_string()
+        .map_err(|e| anyhow!("invalid guest path {:?}", e))?;
+    let mut storages = vec![];
+    if !dirs.is_empty() {
+        // the directory may have no file to copy
+        storages.push(local_storage(&guest_path, COPY_DIR_MODE));
+    }
+
+    if is_watchable_mount(source) {
+        // path: /run/kata-containers/shared/containers/watchable/config-map-name
+        let watchable_dir = Path::new(KATA_GUEST_SHARE_DIR).join(WATCHABLE_PATH_NAME);
+        let watchable_guest_mount = watchable_dir
+            .join(target)
+            .into_os_string()
+            .into_string()
+            .map_err(|e| anyhow!("failed to get watchable guest mount path {:?}", e))?;
+        // The agent only creates the watchable mounts in an existing
+        // watchable directory.
+        storages.push(local_storage(
+            watchable_dir.to_str().context("watchable dir")?,
+            COPY_DIR_MODE,
+        ));
+        storages.push(Storage {
+            driver: String::from(WATCHABLE_BIND_DEV_TYPE),
+            driver_options: Vec::new(),
+            source: guest_path,
+            fs_type: String::from("bind"),
+            fs_group: None,
+            options: mount_options.to_vec(),
+            mount_point: watchable_guest_mount.clone(),
+        });
+        guest_path = watchable_guest_mount;
+    }
+
+    let watcher = CopyWatcher::new(agent, copied, dirs).context("watch volume")?;
+    Ok(ShareCopyResult {
+        guest_path,
+        storages,
+        watcher,
+    })
+}
+
+// A storage making the agent create the directory `path` with `mode`.
+fn local_storage(path: &str, mode: u32) -> Storage {
+    Storage {
+        driver: String::from(LOCAL_DEV_TYPE),
+        driver_options: Vec::new(),
+        source: String::from(LOCAL_DEV_TYPE),
+        fs_type: String::from(LOCAL_DEV_TYPE),
+        fs_group: None,
+        options: vec![format!("mode={:o}", mode)],
+        mount_point: path.to_string(),
+    }
+}
+
+/// Copy the host file `source` to `guest_path` with the agent.
+pub(crate) async fn copy_file(agent: &dyn Agent, source: &Path, guest_path: &Path) -> Result<()> {
+    debug!(sl!(), "copy file {:?} to guest {:?}", source, guest_path);
+    let (source_path, guest) = (source.to_path_buf(), guest_path.to_path_buf());
+    let requests = tokio::task::spawn_blocking(move || copy_file_requests(&source_path, &guest))
+        .await
+        .context("join read task")??;
+
+    for req in requests {
+        let path = req.path.clone();
+        agent
+            .copy_file(req)
+            .await
+            .with_context(|| format!("copy {:?} to guest {}", source, path))?;
+    }
+
+    Ok(())
+}
+
+// Read the host file `source` into the CopyFile requests copying it to
+// `guest_path`, the file size is bounded by the limits of the copied volumes.
+fn copy_file_requests(source: &Path, guest_path: &Path) -> Result<Vec<CopyFileRequest>> {
+    let mut file = File::open(source).with_context(|| format!("open {:?}", source))?;
+    let metadata = file
+        .metadata()
+        .with_context(|| format!("stat {:?}", source))?;
+    if metadata.len() > MAX_COPIED_SIZE {
+        return Err(SourceTooLarge(source.to_path_buf()).into());
+    }
+    let file_size = metadata.len() as i64;
+    let path = guest_path
+        .to_str()
+        .with_context(|| format!("invalid guest path {:?}", guest_path))?
+        .to_string();
+
+    let mut requests = vec![];
+    let mut offset = 0;
+    loop {
+        let mut data = Vec::with_capacity(COPY_FILE_CHUNK_SIZE);
+        (&mut file)
+            .take(COPY_FILE_CHUNK_SIZE as u64)
+            .read_to_end(&mut data)
+            .with_context(|| format!("read {:?}", source))?;
+        let len = data.len() as i64;
+
+        requests.push(CopyFileRequest {
+            path: path.clone(),
+            file_size,
+            file_mode: metadata.mode(),
+            dir_mode: COPY_DIR_MODE,
+            uid: metadata.uid() as i32,
+            gid: metadata.gid() as i32,
+            offset,
+            data,
+        });
+
+        offset += len;
+        // The agent only installs the file once it got all of it, even an
+        // empty one.
+        if len == 0 || offset >= file_size {
+            break;
+        }
+    }
+
+    Ok(requests)
+}
+
+// Inotify instance of a watcher, closed when the watcher is stopped.
+struct InotifyFd(Inotify);
+
+impl Drop for InotifyFd {
+    fn drop(&mut self) {
+        let fd: RawFd = self.0.as_raw_fd();
+        let _ = nix::unistd::close(fd);
+    }
+}
+
+/// Keeps the files copied to the guest in sync with the host volume source.
+pub(crate) struct CopyWatcher {
+    handle: JoinHandle<()>,
+}
+
+impl CopyWatcher {
+    /// Watch the source of `copied`, whose directories are `dirs`, and copy its changed files.
+    pub(crate) fn new(
+        agent: Arc<dyn Agent>,
+        mut copied: CopiedSource,
+        dirs: Vec<PathBuf>,
+    ) -> Result<Self> {
+        let inotify = InotifyFd(
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+                .context("init inotify")?,
+        );
+        let flags = AddWatchFlags::IN_MODIFY
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_DELETE_SELF
+            | AddWatchFlags::IN_MOVE_SELF;
+        let add_watches = move |inotify: &InotifyFd, source: &Path, dirs: &[PathBuf]| {
+            // a file is watched itself, a directory along its subdirectories
+            let paths = if dirs.is_empty() {
+                vec![source.to_path_buf()]
+            } else {
+                dirs.to_vec()
+            };
+            for path in paths {
+                if let Err(e) = inotify.0.add_watch(path.as_path(), flags) {
+                    warn!(sl!(), "failed to watch {:?}: {:?}", path, e);
+                }
+            }
+        };
+        add_watches(&inotify, &copied.source, &dirs);
+
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                match inotify.0.read_events() {
+                    Ok(events) if !events.is_empty() => {}
+                    Ok(_) | Err(Errno::EAGAIN) => continue,
+                    Err(e) => {
+                        error!(sl!(), "failed to read inotify events: {:?}", e);
+                        break;
+                    }
+                }
+
+                match copied.sync(agent.as_ref()).await {
+                    Ok(dirs) => add_watches(&inotify, &copied.source, &dirs),
+                    Err(e) if e.downcast_ref::<SourceTooLarge>().is_some() => {
+                        error!(sl!(), "stop syncing copied volume: {:?}", e);
+                        break;
+                    }
+                    Err(e) => warn!(sl!(), "failed to sync {:?}: {:?}", copied.source, e),
+                }
+            }
+        });
+
+        Ok(CopyWatcher { handle })
+    }
+
+    /// Stop syncing the copied files.
+    pub(crate) fn stop(&self) {
+        self.handle.abort();
+    }
+}
+
+impl Drop for CopyWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    // Scan the source of `copied` as if the changed files were copied.
+    fn scan_copied(copied: &mut CopiedSource) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let (changed, dirs) = scan(&copied.source, &copied.files).unwrap();
+        let mut changed: Vec<PathBuf> = changed
+            .into_iter()
+            .map(|(relative, stamp)| {
+                copied.files.insert(relative.clone(), stamp);
+                relative
+            })
+            .collect();
+        changed.sort();
+        (changed, dirs)
+    }
+
+    #[test]
+    fn test_copied_source_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path();
+        fs::create_dir_all(source.join("..2023_01_01/sub")).unwrap();
+        fs::write(source.join("..2023_01_01/key"), "value").unwrap();
+        symlink("..2023_01_01", source.join("..data")).unwrap();
+        symlink("..data/key", source.join("key")).unwrap();
+        fs::create_dir(source.join("dir")).unwrap();
+        fs::write(source.join("dir/file"), "content").unwrap();
+
+        let mut copied = CopiedSource::new(source, Path::new("/run/guest/volume"));
+        let (changed, dirs) = scan_copied(&mut copied);
+        assert_eq!(
+            changed,
+            vec![PathBuf::from("dir/file"), PathBuf::from("key")]
+        );
+        assert_eq!(dirs.len(), 2);
+        assert!(scan_copied(&mut copied).0.is_empty());
+
+        // atomic update of the kubernetes volume
+        fs::create_dir(source.join("..2023_01_02")).unwrap();
+        fs::write(source.join("..2023_01_02/key"), "new value").unwrap();
+        symlink("..2023_01_02", source.join("..data_tmp")).unwrap();
+        fs::rename(source.join("..data_tmp"), source.join("..data")).unwrap();
+        assert_eq!(scan_copied(&mut copied).0, vec![PathBuf::from("key")]);
+
+        // a regular file source
+        let file = source.join("dir/file");
+        let mut copied = CopiedSource::new(&file, Path::new("/run/guest/file"));
+        let (changed, dirs) = scan_copied(&mut copied);
+        assert_eq!(changed, vec![PathBuf::new()]);
+        assert!(dirs.is_empty());
+    }
+
+    #[test]
+    fn test_copied_source_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path();
+        for i in 0..MAX_COPIED_ENTRIES {
+            fs::write(source.join(i.to_string()), "").unwrap();
+        }
+        let err = scan(source, &HashMap::new()).unwrap_err();
+        assert!(err.downcast_ref::<SourceTooLarge>().is_some());
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path();
+        let file = File::create(source.join("big")).unwrap();
+        file.set_len(MAX_COPIED_SIZE).unwrap();
+        assert!(scan(source, &HashMap::new()).is_ok());
+        file.set_len(MAX_COPIED_SIZE + 1).unwrap();
+        let err = scan(source, &HashMap::new()).unwrap_err();
+        assert!(err.downcast_ref::<SourceTooLarge>().is_some());
+        let err = copy_file_requests(&source.join("big"), Path::new("/run/guest/big")).unwrap_err();
+        assert!(err.downcast_ref::<SourceTooLarge>().is_some());
+    }
+
+    #[test]
+    fn test_copy_file_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("file");
+        let content: Vec<u8> = (0..COPY_FILE_CHUNK_SIZE + 16).map(|i| i as u8).collect();
+        fs::write(&source, &content).unwrap();
+
+        let requests = copy_file_requests(&source, Path::new("/run/guest/file")).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].offset, 0);
+        assert_eq!(requests[0].data, content[..COPY_FILE_CHUNK_SIZE]);
+        assert_eq!(requests[1].offset, COPY_FILE_CHUNK_SIZE as i64);
+        assert_eq!(requests[1].data, content[COPY_FILE_CHUNK_SIZE..]);
+        for req in requests.iter() {
+            assert_eq!(req.path, "/run/guest/file");
+            assert_eq!(req.file_size, content.len() as i64);
+            assert_eq!(req.dir_mode, COPY_DIR_MODE);
+        }
+
+        // an empty file is copied with a single empty request
+        fs::write(&source, "").unwrap();
+        let requests = copy_file_requests(&source, Path::new("/run/guest/file")).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].file_size, 0);
+        assert!(requests[0].data.is_empty());
+    }
+}
@@ -10,19 +10,20 @@ use std::{
     sync::Arc,
 };
 
+use agent::Agent;
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use hypervisor::device::device_manager::DeviceManager;
 use tokio::sync::RwLock;
 
-use super::Volume;
+use super::{share_copy, Volume};
 use crate::share_fs::{MountedInfo, ShareFs, ShareFsVolumeConfig};
 use kata_types::mount;
 
 const SYS_MOUNT_PREFIX: [&str; 2] = ["/proc", "/sys"];
 
-// copy file to container's rootfs if filesystem sharing is not supported, otherwise
-// bind mount it in the shared directory.
+// copy files to the guest share directory if filesystem sharing is not supported,
+// otherwise bind mount it in the shared directory.
 // Ignore /dev, directories and all other device files. We handle
 // only regular files in /dev. It does not make sense to pass the host
 // device nodes to the guest.
@@ -31,11 +32,14 @@ pub(crate) struct ShareFsVolume {
     share_fs: Option<Arc<dyn ShareFs>>,
     mounts: Vec<oci::Mount>,
     storages: Vec<agent::Storage>,
+    // keeps the copied files in sync if filesystem sharing is not supported
+    copy_watcher: Option<share_copy::CopyWatcher>,
 }
 
 impl ShareFsVolume {
     pub(crate) async fn new(
         share_fs: &Option<Arc<dyn ShareFs>>,
+        agent: Arc<dyn Agent>,
         m: &oci::Mount,
         cid: &str,
         readonly: bool,
@@ -48,6 +52,7 @@ impl ShareFsVolume {
             share_fs: share_fs.as_ref().map(Arc::clone),
             mounts: vec![],
             storages: vec![],
+            copy_watcher: None,
         };
         match share_fs {
             None => {
@@ -61,9 +66,19 @@ impl ShareFsVolume {
                     Ok(src) => src,
                 };
 
-                if src.is_file() {
-                    // TODO: copy file
-                    debug!(sl!(), "FIXME: copy file {}", &m.source);
+                if src.is_file() || src.is_dir() {
+                    let result =
+                        share_copy::share_volume_by_copy(agent, &src, &file_name, &m.options)
+                            .await
+                            .context("share volume by copy")?;
+                    volume.storages = result.storages;
+                    volume.copy_watcher = Some(result.watcher);
+                    volume.mounts.push(oci::Mount {
+                        destination: m.destination.clone(),
+                        r#type: "bind".to_string(),
+                        source: result.guest_path,
+                        options: m.options.clone(),
+                    });
                 } else {
                     debug!(
                         sl!(),
@@ -161,6 +176,10 @@ impl Volume for ShareFsVolume {
     }
 
     async fn cleanup(&self, _device_manager: &RwLock<DeviceManager>) -> Result<()> {
+        if let Some(watcher) = self.copy_watcher.as_ref() {
+            watcher.stop();
+        }
+
         let share_fs = match self.share_fs.as_ref() {
             Some(fs) => fs,
             None => return Ok(()),
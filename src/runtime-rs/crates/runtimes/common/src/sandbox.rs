@@ -34,4 +34,9 @@ pub trait Sandbox: Send + Sync {
     async fn get_iptables(&self, is_ipv6: bool) -> Result<Vec<u8>>;
     async fn direct_volume_stats(&self, volume_path: &str) -> Result<String>;
     async fn direct_volume_resize(&self, resize_req: agent::ResizeVolumeRequest) -> Result<()>;
+    async fn rescan_network(&self) -> Result<()>;
+
+    // metrics function
+    async fn agent_metrics(&self) -> Result<String>;
+    async fn hypervisor_metrics(&self) -> Result<String>;
 }
@@ -0,0 +1,67 @@
+// Copyright (c) 2019-2023 Alibaba Cloud
+// Copyright (c) 2019-2023 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use prometheus::{Encoder, IntCounter, Registry, TextEncoder};
+use std::sync::Mutex;
+
+const NAMESPACE_KATA_SHIM: &str = "kata_shim";
+
+lazy_static! {
+    static ref REGISTERED: Mutex<bool> = Mutex::new(false);
+
+    // custom registry
+    static ref REGISTRY: Registry = Registry::new();
+
+    static ref SHIM_SCRAPE_COUNT: IntCounter =
+    IntCounter::new(format!("{}_{}", NAMESPACE_KATA_SHIM, "scrape_count"), "Metrics scrape count").unwrap();
+}
+
+/// get prometheus metrics of the shim process
+pub fn get_shim_metrics() -> Result<String> {
+    let mut registered = REGISTERED
+        .lock()
+        .map_err(|e| anyhow!("failed to check shim metrics register status {:?}", e))?;
+
+    if !(*registered) {
+        register_shim_metrics().context("register shim metrics")?;
+        *registered = true;
+    }
+
+    SHIM_SCRAPE_COUNT.inc();
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .context("encode shim metrics")?;
+    String::from_utf8(buffer).context("shim metrics to string")
+}
+
+fn register_shim_metrics() -> Result<()> {
+    REGISTRY.register(Box::new(SHIM_SCRAPE_COUNT.clone()))?;
+
+    // cpu, memory and file descriptor usage of the shim process
+    let process_collector = prometheus::process_collector::ProcessCollector::for_self();
+    REGISTRY.register(Box::new(process_collector))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_shim_metrics() {
+        let metrics = get_shim_metrics().unwrap();
+        assert!(metrics.contains("kata_shim_scrape_count 1"));
+        assert!(metrics.contains("process_cpu_seconds_total"));
+
+        let metrics = get_shim_metrics().unwrap();
+        assert!(metrics.contains("kata_shim_scrape_count 2"));
+    }
+}
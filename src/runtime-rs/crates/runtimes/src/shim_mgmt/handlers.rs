@@ -16,9 +16,11 @@ use url::Url;
 
 use shim_interface::shim_mgmt::{
     AGENT_URL, DIRECT_VOLUME_PATH_KEY, DIRECT_VOLUME_RESIZE_URL, DIRECT_VOLUME_STATS_URL,
-    IP6_TABLE_URL, IP_TABLE_URL,
+    IP6_TABLE_URL, IP_TABLE_URL, METRICS_URL, NETWORK_RESCAN_URL,
 };
 
+use crate::shim_metrics::get_shim_metrics;
+
 // main router for response, this works as a multiplexer on
 // http arrival which invokes the corresponding handler function
 pub(crate) async fn handler_mux(
@@ -43,6 +45,8 @@ pub(crate) async fn handler_mux(
         (&Method::POST, DIRECT_VOLUME_RESIZE_URL) => {
             direct_volume_resize_handler(sandbox, req).await
         }
+        (&Method::GET, METRICS_URL) => metrics_url_handler(sandbox, req).await,
+        (&Method::PUT, NETWORK_RESCAN_URL) => network_rescan_handler(sandbox, req).await,
         _ => Ok(not_found(req).await),
     }
 }
@@ -146,3 +150,39 @@ async fn direct_volume_resize_handler(
         _ => Err(anyhow!("handler: Failed to resize volume")),
     }
 }
+
+/// rescans the sandbox network, hotplugging or unplugging the interfaces added or removed
+async fn network_rescan_handler(
+    sandbox: Arc<dyn Sandbox>,
+    _req: Request<Body>,
+) -> Result<Response<Body>> {
+    sandbox
+        .rescan_network()
+        .await
+        .context("handler: failed to rescan network")?;
+    Ok(Response::new(Body::from("")))
+}
+
+/// returns the metrics of the shim, the hypervisor and the agent in the prometheus text format,
+/// a failure of one of them is logged and the others are still returned
+async fn metrics_url_handler(
+    sandbox: Arc<dyn Sandbox>,
+    _req: Request<Body>,
+) -> Result<Response<Body>> {
+    let mut metrics = String::new();
+
+    match get_shim_metrics() {
+        Ok(shim_metrics) => metrics.push_str(&shim_metrics),
+        Err(e) => warn!(sl!(), "failed to get shim metrics: {:?}", e),
+    }
+    match sandbox.hypervisor_metrics().await {
+        Ok(hypervisor_metrics) => metrics.push_str(&hypervisor_metrics),
+        Err(e) => warn!(sl!(), "failed to get hypervisor metrics: {:?}", e),
+    }
+    match sandbox.agent_metrics().await {
+        Ok(agent_metrics) => metrics.push_str(&agent_metrics),
+        Err(e) => warn!(sl!(), "failed to get agent metrics: {:?}", e),
+    }
+
+    Ok(Response::new(Body::from(metrics)))
+}
@@ -40,8 +40,6 @@ impl MgmtServer {
     }
 
     // TODO(when metrics is supported): write metric addresses to fs
-    // TODO(when metrics is supported): register shim metrics
-    // TODO(when metrics is supported): register sandbox metrics
     // running management http server in an infinite loop, able to serve concurrent requests
     pub async fn run(self: Arc<Self>) {
         let listener = listener_from_path(self.s_addr.clone()).await.unwrap();
@@ -139,6 +139,21 @@ impl ContainerManager for VirtContainerManager {
                     poststop_hook_states.execute_hooks(&hooks.poststop, Some(state))?;
                 }
 
+                // Give the resources of the container back, failing to do
+                // so doesn't prevent it from being deleted.
+                if let Err(e) = self.resource_manager.delete_cgroups(container_id).await {
+                    warn!(
+                        logger_with_process(process),
+                        "failed to delete container cgroups: {:?}", e
+                    );
+                }
+                if let Err(e) = self.resource_manager.remove_devices(container_id).await {
+                    warn!(
+                        logger_with_process(process),
+                        "failed to remove container devices: {:?}", e
+                    );
+                }
+
                 c.state_process(process).await.context("state process")
             }
             ProcessType::Exec => {
@@ -0,0 +1,328 @@
+// Copyright (c) 2019-2023 Alibaba Cloud
+// Copyright (c) 2019-2023 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! VM factory: sandbox VMs cloned from a template VM.
+//!
+//! The template VM is booted once, and paused once the guest is up. Its
+//! memory is backed by a file shared with the host and its device state is
+//! saved next to the memory file. New VMs map the memory file privately, so
+//! that the template memory is shared copy-on-write, and load the device
+//! state instead of booting the guest kernel. Only QEMU supports it.
+
+use std::path::Path;
+use std::time::Duration;
+
+use agent::{kata::KataAgent, AgentManager, CheckRequest, HealthService};
+use anyhow::{anyhow, Context, Result};
+use hypervisor::{qemu::Qemu, Hypervisor, Param, HYPERVISOR_QEMU, MIB_TO_B};
+use kata_types::config::{
+    hypervisor::Hypervisor as HypervisorConfig, Agent as AgentConfig, Factory, TomlConfig,
+    VmTemplateInfo, TEMPLATE_HYPERVISORS,
+};
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+
+/// Sandbox id of the template VM.
+const TEMPLATE_VM_ID: &str = "template";
+/// Milliseconds to wait for the template VM to start.
+const TEMPLATE_VM_START_TIMEOUT_MS: i32 = 10_000;
+/// Extra room in the template tmpfs for the device state.
+const TEMPLATE_STATE_SIZE_MB: u32 = 8;
+/// Once disconnected from, the agent needs some time to listen on its vsock
+/// port again. Waiting for it in the template saves that time in every VM
+/// cloned from it.
+const TEMPLATE_WAIT_FOR_AGENT: Duration = Duration::from_secs(2);
+
+/// The VM template of a factory.
+pub struct Template {
+    factory: Factory,
+    hypervisor_name: String,
+    hypervisor_config: HypervisorConfig,
+}
+
+impl Template {
+    pub fn new(
+        factory: Factory,
+        hypervisor_name: &str,
+        hypervisor_config: HypervisorConfig,
+    ) -> Self {
+        Template {
+            factory,
+            hypervisor_name: hypervisor_name.to_string(),
+            hypervisor_config,
+        }
+    }
+
+    /// Whether VM templating is supported by the hypervisor.
+    pub fn is_supported(hypervisor_name: &str) -> bool {
+        TEMPLATE_HYPERVISORS.contains(&hypervisor_name)
+    }
+
+    /// Create the template of the hypervisor and factory configured in
+    /// `toml_config`.
+    pub fn from_toml_config(toml_config: &TomlConfig) -> Result<Self> {
+        let hypervisor_name = &toml_config.runtime.hypervisor_name;
+        let mut hypervisor_config = toml_config
+            .hypervisor
+            .get(hypervisor_name)
+            .ok_or_else(|| anyhow!("failed to get hypervisor for {}", hypervisor_name))?
+            .clone();
+
+        // The cloned VMs run the kernel booted by the template VM, which
+        // needs the agent parameters of a sandbox VM.
+        let params = toml_config
+            .get_agent_kernel_params()
+            .context("get agent kernel params")?
+            .iter()
+            .filter_map(|(k, v)| Param::new(k, v).to_string().ok())
+            .collect();
+        hypervisor_config.boot_info.add_kernel_params(params);
+
+        Ok(Self::new(
+            toml_config.factory.clone(),
+            hypervisor_name,
+            hypervisor_config,
+        ))
+    }
+
+    pub fn template_path(&self) -> &str {
+        &self.factory.template_path
+    }
+
+    /// Whether the template VM has been created.
+    pub fn exists(&self) -> bool {
+        self.memory_size().is_some()
+    }
+
+    // Size of the guest memory of the template VM, None if there's no
+    // template.
+    fn memory_size(&self) -> Option<u64> {
+        match self.hypervisor_name.as_str() {
+            HYPERVISOR_QEMU => {
+                if !Path::new(&self.factory.template_state_path()).is_file() {
+                    return None;
+                }
+                let memory = std::fs::metadata(self.factory.template_memory_path()).ok()?;
+                Some(memory.len()).filter(|_| memory.is_file())
+            }
+            _ => None,
+        }
+    }
+
+    /// Boot the template VM, wait for the agent to be up and save the VM to
+    /// the template directory.
+    pub async fn create(&self, agent_config: AgentConfig) -> Result<()> {
+        if !Self::is_supported(&self.hypervisor_name) {
+            return Err(anyhow!(
+                "VM templating is not supported by hypervisor {}",
+                self.hypervisor_name
+            ));
+        }
+        if self.exists() {
+            return Err(anyhow!(
+                "template {} already exists",
+                self.factory.template_path
+            ));
+        }
+
+        self.prepare_template_files()
+            .context("prepare template files")?;
+        if let Err(e) = self.create_template_vm(agent_config).await {
+            if let Err(err) = self.destroy() {
+                warn!(sl!(), "failed to clean up template: {:?}", err);
+            }
+            return Err(e).context("create template vm");
+        }
+
+        info!(sl!(), "created template {}", self.factory.template_path);
+        Ok(())
+    }
+
+    // The template lives in a tmpfs, so that the VMs cloned from it don't
+    // page their memory in from disk.
+    fn prepare_template_files(&self) -> Result<()> {
+        let template_path = &self.factory.template_path;
+        std::fs::create_dir_all(template_path)
+            .with_context(|| format!("create dir {}", template_path))?;
+
+        let size = self.hypervisor_config.memory_info.default_memory + TEMPLATE_STATE_SIZE_MB;
+        mount(
+            Some("tmpfs"),
+            template_path.as_str(),
+            Some("tmpfs"),
+            MsFlags::empty(),
+            Some(format!("size={}M", size).as_str()),
+        )
+        .with_context(|| format!("mount tmpfs on {}", template_path))?;
+        Ok(())
+    }
+
+    async fn create_template_vm(&self, agent_config: AgentConfig) -> Result<()> {
+        let mut config = self.hypervisor_config.clone();
+        config.vm_template = VmTemplateInfo::to_be_template(&self.factory);
+
+        let hypervisor: Box<dyn Hypervisor> = match self.hypervisor_name.as_str() {
+            HYPERVISOR_QEMU => {
+                let mut qemu = Qemu::new();
+                qemu.set_hypervisor_config(config).await;
+                Box::new(qemu)
+            }
+            name => {
+                return Err(anyhow!(
+                    "VM templating is not supported by hypervisor {}",
+                    name
+                ))
+            }
+        };
+        hypervisor
+            .prepare_vm(TEMPLATE_VM_ID, None)
+            .await
+            .context("prepare vm")?;
+        let result = Self::save_template_vm(hypervisor.as_ref(), agent_config).await;
+
+        if let Err(e) = hypervisor.stop_vm().await {
+            warn!(sl!(), "failed to stop template vm: {:?}", e);
+        }
+        if let Err(e) = hypervisor.cleanup().await {
+            warn!(sl!(), "failed to clean up template vm: {:?}", e);
+        }
+        result
+    }
+
+    async fn save_template_vm(
+        hypervisor: &dyn Hypervisor,
+        agent_config: AgentConfig,
+    ) -> Result<()> {
+        hypervisor
+            .start_vm(TEMPLATE_VM_START_TIMEOUT_MS)
+            .await
+            .context("start vm")?;
+
+        // The guest is ready to be cloned once the agent answers.
+        let address = hypervisor
+            .get_agent_socket()
+            .await
+            .context("get agent socket")?;
+        let agent = KataAgent::new(agent_config);
+        agent.start(&address).await.context("connect agent")?;
+        let check = agent.check(CheckRequest::new("")).await;
+        agent.stop().await;
+        check.context("check agent")?;
+        tokio::time::sleep(TEMPLATE_WAIT_FOR_AGENT).await;
+
+        hypervisor.pause_vm().await.context("pause vm")?;
+        hypervisor.save_vm().await.context("save vm")
+    }
+
+    /// Remove the template.
+    pub fn destroy(&self) -> Result<()> {
+        let template_path = &self.factory.template_path;
+        if !Path::new(template_path).exists() {
+            return Ok(());
+        }
+
+        if let Err(e) = umount2(template_path.as_str(), MntFlags::MNT_DETACH) {
+            // EINVAL: not a mount point, i.e. the tmpfs mount failed.
+            if e != nix::Error::EINVAL {
+                return Err(e).with_context(|| format!("umount {}", template_path));
+            }
+        }
+        std::fs::remove_dir_all(template_path)
+            .with_context(|| format!("remove dir {}", template_path))?;
+
+        info!(sl!(), "destroyed template {}", template_path);
+        Ok(())
+    }
+
+    /// Set up `config` to clone the VM from the template. The VM is booted
+    /// from scratch, and false returned, if there's no usable template.
+    pub fn apply(&self, config: &mut HypervisorConfig) -> Result<bool> {
+        if !Self::is_supported(&self.hypervisor_name) {
+            warn!(
+                sl!(),
+                "VM templating is not supported by hypervisor {}, booting vm from scratch",
+                self.hypervisor_name
+            );
+            return Ok(false);
+        }
+        let size = match self.memory_size() {
+            Some(size) => size,
+            None => {
+                warn!(
+                    sl!(),
+                    "template {} not found, booting vm from scratch", self.factory.template_path
+                );
+                return Ok(false);
+            }
+        };
+
+        // The template memory size is fixed, VMs of another size can't be
+        // cloned from it.
+        let expected = config.memory_info.default_memory as u64 * MIB_TO_B;
+        if size != expected {
+            warn!(
+                sl!(),
+                "template memory size {} doesn't match vm memory size {}, booting vm from scratch",
+                size,
+                expected
+            );
+            return Ok(false);
+        }
+
+        config.vm_template = VmTemplateInfo::from_template(&self.factory);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::skip_if_not_root;
+
+    #[test]
+    fn test_template_apply() {
+        skip_if_not_root!();
+
+        let dir = tempfile::tempdir().unwrap();
+        let factory = Factory {
+            enable_template: true,
+            template_path: dir.path().display().to_string(),
+            ..Default::default()
+        };
+        let mut config = HypervisorConfig::default();
+        config.memory_info.default_memory = 1;
+        let template = Template::new(factory.clone(), HYPERVISOR_QEMU, config.clone());
+
+        assert!(!template.exists());
+        assert!(!template.apply(&mut config).unwrap());
+        assert!(!config.vm_template.boot_from_template);
+
+        let memory = std::fs::File::create(factory.template_memory_path()).unwrap();
+        memory.set_len(MIB_TO_B * 2).unwrap();
+        std::fs::write(factory.template_state_path(), "state").unwrap();
+        assert!(template.exists());
+        assert!(!template.apply(&mut config).unwrap());
+
+        memory.set_len(MIB_TO_B).unwrap();
+        assert!(template.apply(&mut config).unwrap());
+        assert!(config.vm_template.boot_from_template);
+        assert_eq!(
+            config.vm_template.devices_state_path,
+            factory.template_state_path()
+        );
+
+        // The template is ignored by hypervisors not supporting templating.
+        let mut config = HypervisorConfig::default();
+        config.memory_info.default_memory = 1;
+        let other = Template::new(factory.clone(), "cloud-hypervisor", config.clone());
+        assert!(!other.exists());
+        assert!(!other.apply(&mut config).unwrap());
+        assert!(!config.vm_template.boot_from_template);
+
+        template.destroy().unwrap();
+        assert!(!dir.path().exists());
+        template.destroy().unwrap();
+    }
+}
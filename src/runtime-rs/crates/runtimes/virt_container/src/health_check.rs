@@ -8,6 +8,7 @@ use std::sync::Arc;
 
 use agent::Agent;
 use anyhow::Context;
+use hypervisor::Hypervisor;
 use tokio::sync::{mpsc, Mutex};
 
 /// monitor check interval 30s
@@ -37,7 +38,7 @@ impl HealthCheck {
         }
     }
 
-    pub fn start(&self, id: &str, agent: Arc<dyn Agent>) {
+    pub fn start(&self, id: &str, agent: Arc<dyn Agent>, hypervisor: Arc<dyn Hypervisor>) {
         if !self.keep_alive {
             return;
         }
@@ -49,6 +50,7 @@ impl HealthCheck {
         let keep_abnormal = self.keep_abnormal;
         tokio::spawn(async move {
             let mut version_check_threshold_count = 0;
+            let mut guest_memory_dumped = false;
 
             loop {
                 tokio::time::sleep(std::time::Duration::from_secs(HEALTH_CHECK_TIMER_INTERVAL))
@@ -87,7 +89,21 @@ impl HealthCheck {
                                 error!(sl!(), "failed to do {} agent health check: {}", id, e);
                                 if let Err(mpsc::error::TryRecvError::Empty) = stop_rx.try_recv() {
                                     error!(sl!(), "failed to receive stop monitor signal");
+                                    // the guest is unhealthy, keep its memory for
+                                    // post-mortem analysis before tearing it down
+                                    if !guest_memory_dumped {
+                                        guest_memory_dumped = true;
+                                        if let Err(e) = hypervisor.dump_guest_memory().await {
+                                            error!(
+                                                sl!(),
+                                                "failed to dump {} guest memory: {:?}", id, e
+                                            );
+                                        }
+                                    }
                                     if !keep_abnormal {
+                                        if let Err(e) = hypervisor.stop_vm().await {
+                                            error!(sl!(), "failed to stop {} vm: {:?}", id, e);
+                                        }
                                         ::std::process::exit(1);
                                     }
                                 } else {
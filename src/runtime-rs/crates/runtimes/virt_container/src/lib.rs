@@ -10,6 +10,7 @@ extern crate slog;
 logging::logger_with_subsystem!(sl, "virt-container");
 
 mod container_manager;
+pub mod factory;
 pub mod health_check;
 pub mod sandbox;
 pub mod sandbox_persist;
@@ -118,6 +119,17 @@ async fn new_hypervisor(toml_config: &TomlConfig) -> Result<Arc<dyn Hypervisor>>
         .ok_or_else(|| anyhow!("failed to get hypervisor for {}", &hypervisor_name))
         .context("get hypervisor")?;
 
+    let mut hypervisor_config = hypervisor_config.clone();
+    if toml_config.factory.enable_template && factory::Template::is_supported(hypervisor_name) {
+        factory::Template::new(
+            toml_config.factory.clone(),
+            hypervisor_name,
+            hypervisor_config.clone(),
+        )
+        .apply(&mut hypervisor_config)
+        .context("apply vm template")?;
+    }
+
     // TODO: support other hypervisor
     // issue: https://github.com/kata-containers/kata-containers/issues/4634
     match hypervisor_name.as_str() {
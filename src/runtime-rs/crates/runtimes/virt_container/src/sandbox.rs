@@ -7,8 +7,8 @@
 use std::sync::Arc;
 
 use agent::{
-    self, kata::KataAgent, types::KernelModule, Agent, GetIPTablesRequest, SetIPTablesRequest,
-    VolumeStatsRequest,
+    self, kata::KataAgent, types::KernelModule, Agent, GetIPTablesRequest, GetMetricsRequest,
+    SetIPTablesRequest, VolumeStatsRequest,
 };
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
@@ -18,6 +18,7 @@ use common::{
 };
 use containerd_shim_protos::events::task::TaskOOM;
 use hypervisor::{dragonball::Dragonball, Hypervisor, HYPERVISOR_DRAGONBALL};
+use hypervisor::{qemu::Qemu, HYPERVISOR_QEMU};
 use kata_sys_util::hooks::HookStates;
 use kata_types::config::TomlConfig;
 use resource::{
@@ -195,6 +196,19 @@ impl Sandbox for VirtSandbox {
             .await
             .context("prepare vm")?;
 
+        // mirror the host NUMA nodes of the pod cpuset into the guest
+        let cpus = spec
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.resources.as_ref())
+            .and_then(|resources| resources.cpu.as_ref())
+            .map(|cpu| cpu.cpus.as_str())
+            .unwrap_or_default();
+        self.hypervisor
+            .set_numa_topology(cpus)
+            .await
+            .context("set numa topology")?;
+
         // generate device and setup before start vm
         // should after hypervisor.prepare_vm
         let resources = self
@@ -220,7 +234,6 @@ impl Sandbox for VirtSandbox {
         // 1. if there are pre-start hook functions, network config might have been changed.
         //    We need to rescan the netns to handle the change.
         // 2. Do not scan the netns if we want no network for the VM.
-        // TODO In case of vm factory, scan the netns to hotplug interfaces after the VM is started.
         if self.has_prestart_hooks(prestart_hooks, create_runtime_hooks)
             && !self
                 .resource_manager
@@ -229,7 +242,7 @@ impl Sandbox for VirtSandbox {
                 .runtime
                 .disable_new_netns
         {
-            if let Some(netns_path) = network_env.netns {
+            if let Some(netns_path) = network_env.netns.clone() {
                 let network_resource = self
                     .prepare_network_config(netns_path, network_env.network_created)
                     .await;
@@ -281,6 +294,22 @@ impl Sandbox for VirtSandbox {
             .await
             .context("create sandbox")?;
 
+        // hotplug the network interfaces of the links added to the netns later on
+        if self
+            .resource_manager
+            .config()
+            .await
+            .runtime
+            .enable_network_watcher
+        {
+            if let Some(netns_path) = network_env.netns.as_ref() {
+                self.resource_manager
+                    .watch_network(netns_path)
+                    .await
+                    .context("watch network")?;
+            }
+        }
+
         inner.state = SandboxState::Running;
         let agent = self.agent.clone();
         let sender = self.msg_sender.clone();
@@ -315,7 +344,8 @@ impl Sandbox for VirtSandbox {
                 }
             }
         });
-        self.monitor.start(id, self.agent.clone());
+        self.monitor
+            .start(id, self.agent.clone(), self.hypervisor.clone());
         self.save().await.context("save state")?;
         Ok(())
     }
@@ -389,6 +419,13 @@ impl Sandbox for VirtSandbox {
         Ok(())
     }
 
+    async fn rescan_network(&self) -> Result<()> {
+        self.resource_manager
+            .rescan_network()
+            .await
+            .context("sandbox: failed to rescan network")
+    }
+
     async fn set_iptables(&self, is_ipv6: bool, data: Vec<u8>) -> Result<Vec<u8>> {
         info!(sl!(), "sb: set_iptables invoked");
         let req = SetIPTablesRequest { is_ipv6, data };
@@ -410,6 +447,22 @@ impl Sandbox for VirtSandbox {
             .context("sandbox: failed to get iptables")?;
         Ok(resp.data)
     }
+
+    async fn agent_metrics(&self) -> Result<String> {
+        let resp = self
+            .agent
+            .get_metrics(GetMetricsRequest {})
+            .await
+            .context("sandbox: failed to get agent metrics")?;
+        Ok(resp.metrics)
+    }
+
+    async fn hypervisor_metrics(&self) -> Result<String> {
+        self.hypervisor
+            .get_hypervisor_metrics()
+            .await
+            .context("sandbox: failed to get hypervisor metrics")
+    }
 }
 
 #[async_trait]
@@ -435,9 +488,10 @@ impl Persist for VirtSandbox {
         let config = sandbox_args.toml_config;
         let r = sandbox_state.resource.unwrap_or_default();
         let h = sandbox_state.hypervisor.unwrap_or_default();
-        let hypervisor = match h.hypervisor_type.as_str() {
+        let hypervisor: Arc<dyn Hypervisor> = match h.hypervisor_type.as_str() {
             // TODO support other hypervisors
-            HYPERVISOR_DRAGONBALL => Ok(Arc::new(Dragonball::restore((), h).await?)),
+            HYPERVISOR_DRAGONBALL => Ok(Arc::new(Dragonball::restore((), h).await?) as _),
+            HYPERVISOR_QEMU => Ok(Arc::new(Qemu::restore((), h).await?) as _),
             _ => Err(anyhow!("Unsupported hypervisor {}", &h.hypervisor_type)),
         }?;
         let agent = Arc::new(KataAgent::new(kata_types::config::Agent::default()));
@@ -29,7 +29,8 @@ pub enum Commands {
     Exec(ExecArguments),
 
     /// Manage VM factory
-    Factory,
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    Factory(FactoryCommand),
 
     /// Manage guest VM iptables
     Iptables(IptablesCommand),
@@ -103,6 +104,24 @@ pub enum IpTablesArguments {
     Metrics,
 }
 
+#[derive(Debug, Args)]
+pub struct FactoryCommand {
+    #[clap(subcommand)]
+    pub factory_cmd: FactorySubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FactorySubcommand {
+    /// Create the VM template
+    Init,
+
+    /// Display the VM factory status
+    Status,
+
+    /// Destroy the VM template
+    Destroy,
+}
+
 #[derive(Debug, Args)]
 pub struct DirectVolumeCommand {
     #[clap(subcommand)]
@@ -16,11 +16,11 @@ use std::process::exit;
 
 use args::{Commands, KataCtlCli};
 
-use ops::check_ops::{
-    handle_check, handle_factory, handle_iptables, handle_metrics, handle_version,
-};
+use ops::check_ops::{handle_check, handle_iptables, handle_metrics, handle_version};
 use ops::env_ops::handle_env;
 use ops::exec_ops::handle_exec;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use ops::factory_ops::handle_factory;
 use ops::volume_ops::handle_direct_volume;
 
 fn real_main() -> Result<()> {
@@ -31,7 +31,8 @@ fn real_main() -> Result<()> {
         Commands::DirectVolume(args) => handle_direct_volume(args),
         Commands::Exec(args) => handle_exec(args),
         Commands::Env(args) => handle_env(args),
-        Commands::Factory => handle_factory(),
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        Commands::Factory(args) => handle_factory(args),
         Commands::Iptables(args) => handle_iptables(args),
         Commands::Metrics(args) => handle_metrics(args),
         Commands::Version => handle_version(),
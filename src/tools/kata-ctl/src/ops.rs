@@ -6,5 +6,7 @@
 pub mod check_ops;
 pub mod env_ops;
 pub mod exec_ops;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod factory_ops;
 pub mod version;
 pub mod volume_ops;
@@ -110,10 +110,6 @@ pub fn handle_check(checkcmd: CheckArgument) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_factory() -> Result<()> {
-    Ok(())
-}
-
 pub fn handle_iptables(_args: IptablesCommand) -> Result<()> {
     Ok(())
 }
@@ -0,0 +1,62 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Management of the VM factory, i.e. of the template VMs are cloned from.
+
+use crate::args::{FactoryCommand, FactorySubcommand};
+
+use anyhow::{anyhow, Context, Result};
+use common::RuntimeHandler;
+use kata_types::config::TomlConfig;
+use virt_container::{factory::Template, VirtContainer};
+
+pub fn handle_factory(factory_cmd: FactoryCommand) -> Result<()> {
+    if !nix::unistd::Uid::effective().is_root() {
+        return Err(anyhow!(
+            "super-user privileges are required for the factory subcommand"
+        ));
+    }
+
+    // The hypervisor plugins must be registered to load the configuration.
+    VirtContainer::init().context("init virt container")?;
+    let (toml_config, _) = TomlConfig::load_from_file("").context("load toml config")?;
+    toml_config.validate().context("validate toml config")?;
+
+    if !toml_config.factory.enable_template {
+        println!("vm factory is not enabled");
+        return Ok(());
+    }
+    let template = Template::from_toml_config(&toml_config)?;
+
+    match factory_cmd.factory_cmd {
+        FactorySubcommand::Init => {
+            let agent_name = &toml_config.runtime.agent_name;
+            let agent_config = toml_config
+                .agent
+                .get(agent_name)
+                .ok_or_else(|| anyhow!("failed to get agent for {}", agent_name))?
+                .clone();
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(template.create(agent_config))
+                .context("create vm template")?;
+            println!("vm factory initialized");
+        }
+        FactorySubcommand::Status => {
+            if template.exists() {
+                println!("vm factory is on, template {}", template.template_path());
+            } else {
+                println!("vm factory is off");
+            }
+        }
+        FactorySubcommand::Destroy => {
+            template.destroy().context("destroy vm template")?;
+            println!("vm factory destroyed");
+        }
+    }
+
+    Ok(())
+}